@@ -0,0 +1,50 @@
+#![feature(test)]
+
+extern crate loa;
+extern crate test;
+
+use loa::bytecode::Instruction;
+use loa::optimization::Optimizable;
+use loa::vm::VM;
+use test::Bencher;
+
+/// Compiles the PolymorphicDispatch fixture, whose hot loop alternates
+/// between two receiver classes at the same call sites.
+fn program() -> Vec<Instruction> {
+    let mut sources = loa::Source::files("src/__fixtures__/PolymorphicDispatch/**/*.loa").unwrap();
+    sources.extend(loa::Source::stdlib().unwrap());
+    sources.push(loa::Source::main("PolymorphicDispatch/Main"));
+
+    let modules = sources
+        .into_iter()
+        .map(loa::syntax::Parser::new)
+        .map(loa::syntax::Parser::parse)
+        .map(|(tree, _)| (tree.source.uri.clone(), tree))
+        .collect();
+    let mut analysis = loa::semantics::Analysis::new(loa::Arc::new(modules));
+    let mut assembly = loa::generation::Generator::new(&mut analysis)
+        .generate_all()
+        .unwrap();
+    assembly.optimize();
+    assembly.into()
+}
+
+fn run(b: &mut Bencher, inline_caches: bool) {
+    let program = program();
+    b.iter(|| {
+        let mut vm = VM::new();
+        vm.set_inline_caches(inline_caches);
+        let result = vm.eval_pop::<()>(program.clone()).unwrap();
+        assert_eq!(result.to_string(), "35000");
+    });
+}
+
+#[bench]
+fn dispatch_with_inline_caches(b: &mut Bencher) {
+    run(b, true);
+}
+
+#[bench]
+fn dispatch_without_inline_caches(b: &mut Bencher) {
+    run(b, false);
+}
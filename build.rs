@@ -0,0 +1,17 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    // The header is checked in, so that C programs can be built against the
+    // library without cbindgen. Building with the c-header feature brings it
+    // up to date with src/lib/ffi.rs.
+    #[cfg(feature = "c-header")]
+    {
+        println!("cargo:rerun-if-changed=cbindgen.toml");
+        println!("cargo:rerun-if-changed=src/lib/ffi.rs");
+
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        cbindgen::generate(&crate_dir)
+            .expect("failed to generate the C header")
+            .write_to_file(format!("{}/include/loa.h", crate_dir));
+    }
+}
@@ -0,0 +1,137 @@
+/* Generated from src/lib/ffi.rs by cbindgen. Run `cargo build --features c-header` to update. */
+
+#ifndef LOA_H
+#define LOA_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+typedef enum {
+  LOA_VALUE_KIND_OBJECT,
+  LOA_VALUE_KIND_BOOLEAN,
+  LOA_VALUE_KIND_STRING,
+  LOA_VALUE_KIND_CHARACTER,
+  LOA_VALUE_KIND_SYMBOL,
+  LOA_VALUE_KIND_INTEGER,
+  LOA_VALUE_KIND_FLOAT,
+} LoaValueKind;
+
+/**
+ * A VM, and the program loaded into it.
+ *
+ * Functions that can fail return null or false, and leave a message to be
+ * read with `loa_vm_last_error`.
+ */
+typedef struct LoaVM LoaVM;
+
+/**
+ * An object passed to, or returned from, a program. Every value returned
+ * by this library must be released with `loa_value_free`.
+ */
+typedef struct LoaValue LoaValue;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+void loa_string_free(char *s);
+
+/**
+ * Reads a boolean into `out`. Returns false if the value isn't a boolean.
+ */
+bool loa_value_as_bool(const LoaValue *value, bool *out);
+
+/**
+ * Reads a number of any kind into `out`, rounded to the nearest double.
+ * Returns false if the value isn't a number.
+ */
+bool loa_value_as_f64(const LoaValue *value, double *out);
+
+/**
+ * Reads an integer of any kind into `out`. Returns false if the value isn't
+ * an integer, or doesn't fit.
+ */
+bool loa_value_as_i64(const LoaValue *value, int64_t *out);
+
+/**
+ * Like `loa_value_as_i64`, for integers that fit in a `uint64_t`.
+ */
+bool loa_value_as_u64(const LoaValue *value, uint64_t *out);
+
+/**
+ * Creates a boolean. Like the other values, it can only be created once the
+ * loaded program has declared its class.
+ */
+LoaValue *loa_value_bool(LoaVM *vm, bool value);
+
+/**
+ * Creates a `Loa/Float64`.
+ */
+LoaValue *loa_value_f64(LoaVM *vm, double value);
+
+void loa_value_free(LoaValue *value);
+
+/**
+ * Creates a `Loa/Int64`.
+ */
+LoaValue *loa_value_i64(LoaVM *vm, int64_t value);
+
+LoaValueKind loa_value_kind(const LoaValue *value);
+
+/**
+ * Creates a `Loa/String` from a UTF-8 string, which is copied.
+ */
+LoaValue *loa_value_string(LoaVM *vm, const char *value);
+
+/**
+ * Formats the value like the VM prints results. Release the string with
+ * `loa_string_free`.
+ */
+char *loa_value_to_string(const LoaValue *value);
+
+/**
+ * Creates a `Loa/UInt64`.
+ */
+LoaValue *loa_value_u64(LoaVM *vm, uint64_t value);
+
+/**
+ * An instance of the class with the qualified name, like
+ * `"Example/Calculator"`, to send messages to.
+ */
+LoaValue *loa_vm_class(LoaVM *vm, const char *qualified_name);
+
+void loa_vm_free(LoaVM *vm);
+
+/**
+ * The message of the last error, or null if the last call succeeded. The
+ * message is owned by the VM, and lives until the next call.
+ */
+const char *loa_vm_last_error(const LoaVM *vm);
+
+/**
+ * Loads a program from the contents of a `.loabin` file, and runs its
+ * entry point. Returns the result of the entry point, or null if the
+ * program doesn't have one, or failed.
+ */
+LoaValue *loa_vm_load(LoaVM *vm, const uint8_t *bytecode, uintptr_t length);
+
+/**
+ * Creates a VM without a program. Release it with `loa_vm_free`.
+ */
+LoaVM *loa_vm_new(void);
+
+/**
+ * Sends a message, like `"add:to:"`, to the receiver, and returns the
+ * result. The arguments are not released.
+ */
+LoaValue *loa_vm_send(LoaVM *vm,
+                      const LoaValue *receiver,
+                      const char *selector,
+                      const LoaValue *const *arguments,
+                      uintptr_t argument_count);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* LOA_H */
@@ -0,0 +1,342 @@
+mod protocol;
+pub use self::protocol::*;
+
+mod session;
+pub use self::session::*;
+
+use loa::bytecode::{Instruction, SourceMap};
+use loa::vm::{Breakpoint, VM};
+use log::error;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::Arc;
+
+struct Launch {
+    instructions: Vec<Instruction>,
+    source_map: SourceMap,
+    stop_on_entry: bool,
+    arguments: Vec<String>,
+}
+
+struct Session {
+    messages: Sender<SessionMessage>,
+    interrupt: Arc<AtomicBool>,
+}
+
+/// Serves the Debug Adapter Protocol over stdio, so that Loa programs can be
+/// debugged from editors. The program runs on a separate thread, with an
+/// `AdapterDebugger` attached that the requests are forwarded to.
+pub fn debug_adapter(default_main: &str) {
+    let stdin = io::stdin();
+    serve(&mut stdin.lock(), default_main);
+}
+
+fn serve<R: BufRead>(input: &mut R, default_main: &str) {
+    let mut launch = None;
+    let mut session: Option<Session> = None;
+    let mut breakpoints: HashMap<String, Vec<Breakpoint>> = HashMap::new();
+
+    loop {
+        let request = match read_message(input) {
+            Ok(Some(request)) => request,
+            Ok(None) => break,
+            Err(e) => {
+                error!("{}", e);
+                break;
+            }
+        };
+
+        match request["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                MessageSender.respond(
+                    &request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsTerminateRequest": true,
+                    }),
+                );
+                MessageSender.event("initialized", json!({}));
+            }
+
+            "launch" => match compile(&request["arguments"], default_main) {
+                Ok(l) => {
+                    launch = Some(l);
+                    MessageSender.respond(&request, json!({}));
+                }
+                Err(e) => MessageSender.respond_error(&request, e),
+            },
+
+            "setBreakpoints" => {
+                let path = request["arguments"]["source"]["path"]
+                    .as_str()
+                    .unwrap_or("")
+                    .to_string();
+                let lines: Vec<_> = request["arguments"]["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|b| b["line"].as_u64())
+                    .collect();
+
+                let uri = relative_path(path.as_ref());
+                breakpoints.insert(
+                    path,
+                    lines
+                        .iter()
+                        .map(|line| Breakpoint::new(uri.as_str(), *line))
+                        .collect(),
+                );
+                MessageSender.respond(
+                    &request,
+                    json!({
+                        "breakpoints": lines
+                            .iter()
+                            .map(|line| json!({ "verified": true, "line": line }))
+                            .collect::<Vec<_>>(),
+                    }),
+                );
+
+                if let Some(ref session) = session {
+                    let all = breakpoints.values().flatten().cloned().collect();
+                    if session
+                        .messages
+                        .send(SessionMessage::SetBreakpoints(all))
+                        .is_ok()
+                    {
+                        session.interrupt.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+
+            "setExceptionBreakpoints" => MessageSender.respond(&request, json!({})),
+
+            "threads" => MessageSender.respond(
+                &request,
+                json!({ "threads": [{ "id": 1, "name": "main" }] }),
+            ),
+
+            "configurationDone" => match launch.take() {
+                None => MessageSender.respond_error(&request, "the program was not launched"),
+                Some(launch) => {
+                    MessageSender.respond(&request, json!({}));
+                    session = Some(start(
+                        launch,
+                        breakpoints.values().flatten().cloned().collect(),
+                    ));
+                }
+            },
+
+            "disconnect" | "terminate" => {
+                MessageSender.respond(&request, json!({}));
+                break;
+            }
+
+            _ => match session {
+                Some(ref session) => {
+                    let interrupt = request["command"] == "pause";
+                    if session
+                        .messages
+                        .send(SessionMessage::Request(request.clone()))
+                        .is_err()
+                    {
+                        MessageSender.respond_error(&request, "the program has exited");
+                    } else if interrupt {
+                        session.interrupt.store(true, Ordering::SeqCst);
+                    }
+                }
+                None => MessageSender.respond_error(&request, "the program is not running"),
+            },
+        }
+    }
+}
+
+fn compile(arguments: &Value, default_main: &str) -> Result<Launch, String> {
+    if let Some(cwd) = arguments["cwd"].as_str() {
+        std::env::set_current_dir(cwd).map_err(|e| e.to_string())?;
+    }
+    let main = arguments["main"].as_str().unwrap_or(default_main);
+    let no_stdlib = arguments["noStdlib"].as_bool().unwrap_or(false);
+
+    let (diagnostics, mut analysis) = crate::parse(Some(main), no_stdlib);
+    if loa::Diagnostic::failed(&diagnostics) {
+        return Err(diagnostics
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n"));
+    }
+
+    let mut generator = loa::generation::Generator::new(&mut analysis);
+    let assembly = generator.generate_all().map_err(|e| format!("{:?}", e))?;
+
+    Ok(Launch {
+        source_map: assembly.source_map(&analysis.navigator.sources(), false),
+        instructions: assembly.into(),
+        stop_on_entry: arguments["stopOnEntry"].as_bool().unwrap_or(false),
+        arguments: arguments["args"]
+            .as_array()
+            .map(|a| {
+                a.iter()
+                    .filter_map(Value::as_str)
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default(),
+    })
+}
+
+fn start(launch: Launch, breakpoints: Vec<Breakpoint>) -> Session {
+    let (sender, receiver) = channel();
+    let (interrupt_sender, interrupt_receiver) = channel();
+    let cwd = std::env::current_dir().unwrap_or_default();
+
+    std::thread::spawn(move || {
+        let mut vm = VM::new();
+        let _ = interrupt_sender.send(vm.interrupt_handle());
+        vm.attach_debugger(AdapterDebugger::new(receiver, cwd, launch.stop_on_entry));
+        vm.set_source_map(launch.source_map);
+        vm.set_arguments(launch.arguments);
+        for breakpoint in breakpoints {
+            vm.add_breakpoint(breakpoint);
+        }
+        if launch.stop_on_entry {
+            vm.pause();
+        }
+
+        // Make sure the client hears about the program ending, even when the
+        // VM itself crashes.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            vm.eval_pop::<AdapterRuntime>(launch.instructions)
+        }))
+        .unwrap_or(None);
+        if let Some(ref result) = result {
+            MessageSender.output("stdout", format!("{}\n", result));
+        }
+        let exit_code = match vm.exit_code() {
+            Some(code) => code,
+            None if result.is_some() => 0,
+            None => 1,
+        };
+        MessageSender.event("exited", json!({ "exitCode": exit_code }));
+        MessageSender.event("terminated", json!({}));
+    });
+
+    Session {
+        messages: sender,
+        interrupt: interrupt_receiver.recv().unwrap(),
+    }
+}
+
+/// Source URIs are relative to the project root, while editors send absolute
+/// paths.
+fn relative_path(path: &Path) -> String {
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| path.strip_prefix(cwd).ok().map(PathBuf::from))
+        .unwrap_or_else(|| path.to_path_buf());
+    relative.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn serve_requests(requests: Vec<Value>) -> Vec<Value> {
+        let mut input = vec![];
+        for (seq, mut request) in requests.into_iter().enumerate() {
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            let content = request.to_string();
+            input.extend(format!("Content-Length: {}\r\n\r\n{}", content.len(), content).bytes());
+        }
+        serve(&mut Cursor::new(input), "Main.loa");
+        sent()
+    }
+
+    #[test]
+    fn initialize() {
+        assert_eq!(
+            serve_requests(vec![json!({ "command": "initialize", "arguments": {} })]),
+            vec![
+                json!({
+                    "type": "response",
+                    "request_seq": 1,
+                    "command": "initialize",
+                    "success": true,
+                    "body": {
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsTerminateRequest": true,
+                    },
+                }),
+                json!({ "type": "event", "event": "initialized", "body": {} }),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_breakpoints() {
+        let path = std::env::current_dir().unwrap().join("Main.loa");
+        assert_eq!(
+            serve_requests(vec![json!({
+                "command": "setBreakpoints",
+                "arguments": {
+                    "source": { "path": path },
+                    "breakpoints": [{ "line": 3 }, { "line": 7 }],
+                },
+            })]),
+            vec![json!({
+                "type": "response",
+                "request_seq": 1,
+                "command": "setBreakpoints",
+                "success": true,
+                "body": {
+                    "breakpoints": [
+                        { "verified": true, "line": 3 },
+                        { "verified": true, "line": 7 },
+                    ],
+                },
+            })]
+        );
+    }
+
+    #[test]
+    fn requests_before_launch() {
+        assert_eq!(
+            serve_requests(vec![
+                json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+                json!({ "command": "configurationDone" }),
+                json!({ "command": "disconnect" }),
+                json!({ "command": "initialize" }),
+            ]),
+            vec![
+                json!({
+                    "type": "response",
+                    "request_seq": 1,
+                    "command": "stackTrace",
+                    "success": false,
+                    "message": "the program is not running",
+                }),
+                json!({
+                    "type": "response",
+                    "request_seq": 2,
+                    "command": "configurationDone",
+                    "success": false,
+                    "message": "the program was not launched",
+                }),
+                json!({
+                    "type": "response",
+                    "request_seq": 3,
+                    "command": "disconnect",
+                    "success": true,
+                    "body": {},
+                }),
+            ]
+        );
+    }
+}
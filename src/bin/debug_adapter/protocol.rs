@@ -0,0 +1,135 @@
+use serde_json::{json, Value};
+#[cfg(test)]
+use std::cell::RefCell;
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const CONTENT_LENGTH: &str = "Content-Length:";
+
+/// Reads a single Debug Adapter Protocol message, framed by a
+/// `Content-Length` header, from the input.
+pub fn read_message<R: BufRead>(r: &mut R) -> io::Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if r.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if header.starts_with(CONTENT_LENGTH) {
+            content_length = header[CONTENT_LENGTH.len()..].trim().parse::<usize>().ok();
+        }
+    }
+
+    let content_length = match content_length {
+        None => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "missing Content-Length header",
+            ))
+        }
+        Some(l) => l,
+    };
+    let mut content = vec![0u8; content_length];
+    r.read_exact(&mut content)?;
+    Ok(Some(serde_json::from_slice(content.as_slice())?))
+}
+
+static SEQ: AtomicU64 = AtomicU64::new(1);
+
+#[cfg(test)]
+thread_local! {
+    static SENT: RefCell<Vec<Value>> = RefCell::new(vec![]);
+}
+
+/// Writes responses and events to the client. Messages are numbered from a
+/// global sequence, so that the thread reading requests and the thread
+/// running the VM can both talk to the client.
+#[derive(Clone, Copy)]
+pub struct MessageSender;
+
+impl MessageSender {
+    fn send(self, mut message: Value) {
+        message["seq"] = json!(SEQ.fetch_add(1, Ordering::SeqCst));
+        deliver(message);
+    }
+
+    pub fn respond(self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    pub fn respond_error<S: Into<String>>(self, request: &Value, message: S) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message.into(),
+        }));
+    }
+
+    pub fn event(self, event: &str, body: Value) {
+        self.send(json!({
+            "type": "event",
+            "event": event,
+            "body": body,
+        }));
+    }
+
+    pub fn output<S: Into<String>>(self, category: &str, output: S) {
+        self.event(
+            "output",
+            json!({
+                "category": category,
+                "output": output.into(),
+            }),
+        );
+    }
+}
+
+#[cfg(not(test))]
+fn deliver(message: Value) {
+    use std::io::Write;
+
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let content = message.to_string();
+    let _ = write!(
+        stdout,
+        "Content-Length: {}\r\n\r\n{}",
+        content.len(),
+        content
+    );
+    let _ = stdout.flush();
+}
+
+/// Tests keep the messages sent on their own thread, instead of writing them
+/// to stdout, so that they can be checked with `sent`.
+#[cfg(test)]
+fn deliver(message: Value) {
+    SENT.with(|sent| sent.borrow_mut().push(message));
+}
+
+/// Takes the messages sent on this thread so far, without their sequence
+/// numbers, which are shared by all threads.
+#[cfg(test)]
+pub fn sent() -> Vec<Value> {
+    SENT.with(|sent| {
+        sent.borrow_mut()
+            .drain(..)
+            .map(|mut message| {
+                message.as_object_mut().unwrap().remove("seq");
+                message
+            })
+            .collect()
+    })
+}
@@ -0,0 +1,407 @@
+use super::MessageSender;
+use loa::bytecode::Instruction;
+use loa::vm::{
+    frame_name, Breakpoint, CallStack, Capabilities, DebugCommand, Debugger, Object, PauseReason,
+    Runtime, SourceCodeLocation, MAIN_FRAME, VM,
+};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+pub enum SessionMessage {
+    Request(Value),
+    SetBreakpoints(Vec<Breakpoint>),
+}
+
+/// Reports panics to the client instead of the terminal, since stdout is
+/// occupied by the protocol. For the same reason, programs may use neither
+/// stdin nor stdout.
+pub struct AdapterRuntime;
+
+impl Runtime for AdapterRuntime {
+    fn capabilities() -> Capabilities {
+        Capabilities {
+            stdout: false,
+            stdin: false,
+            ..Capabilities::all()
+        }
+    }
+
+    fn print_panic(message: String, call_stack: CallStack) {
+        let call_stack: Vec<_> = call_stack.into();
+        let mut output = format!("PANIC: {}\n", message);
+        for frame in call_stack {
+            output.push_str(&format!("{}\n  ({})\n", frame_name(&frame), frame.callsite));
+        }
+        MessageSender.output("stderr", output);
+    }
+}
+
+enum Handle {
+    Frame(usize),
+    Object(Arc<Object>),
+}
+
+/// Runs on the VM thread. While the VM is paused, it answers the requests
+/// forwarded from the main thread, until one of them resumes execution.
+pub struct AdapterDebugger {
+    messages: Receiver<SessionMessage>,
+    cwd: PathBuf,
+    handles: Vec<Handle>,
+    resume: DebugCommand,
+    entry: bool,
+}
+
+impl AdapterDebugger {
+    pub fn new(
+        messages: Receiver<SessionMessage>,
+        cwd: PathBuf,
+        stop_on_entry: bool,
+    ) -> AdapterDebugger {
+        AdapterDebugger {
+            messages,
+            cwd,
+            handles: vec![],
+            resume: DebugCommand::Continue,
+            entry: stop_on_entry,
+        }
+    }
+
+    fn handle(&mut self, handle: Handle) -> usize {
+        self.handles.push(handle);
+        self.handles.len()
+    }
+
+    fn source(&self, location: &SourceCodeLocation) -> Value {
+        let SourceCodeLocation(uri, line, character) = location;
+        // Source maps hold paths relative to the project root.
+        let path = if uri.starts_with("file://") {
+            Some(self.cwd.join(&uri["file://".len()..]))
+        } else if self.cwd.join(uri).is_file() {
+            Some(self.cwd.join(uri))
+        } else {
+            None
+        };
+        if let Some(path) = path {
+            json!({
+                "source": {
+                    "name": path.file_name().and_then(|n| n.to_str()).unwrap_or(uri),
+                    "path": path.to_str(),
+                },
+                "line": line,
+                "column": character,
+            })
+        } else {
+            json!({
+                "source": { "name": uri },
+                "line": line,
+                "column": character,
+            })
+        }
+    }
+
+    fn stack_trace(&mut self, vm: &VM) -> Value {
+        let frames = vm.call_stack().frames();
+        let mut location = match vm.instruction_at(vm.pc()) {
+            Some(Instruction::CallMethod(_, uri, line, character))
+            | Some(Instruction::TailCallMethod(_, uri, line, character)) => {
+                Some(SourceCodeLocation(uri.clone(), *line, *character))
+            }
+            _ => vm
+                .source_location(vm.pc())
+                .map(|l| SourceCodeLocation(l.uri, l.line, l.character)),
+        };
+
+        let mut stack_frames = vec![];
+        for depth in 0..=frames.len() {
+            let name = frames
+                .get(depth)
+                .map(|f| frame_name(f))
+                .unwrap_or_else(|| MAIN_FRAME.into());
+            let id = self.handle(Handle::Frame(depth));
+
+            let mut stack_frame = match location {
+                Some(ref location) => self.source(location),
+                None => json!({ "line": 0, "column": 0 }),
+            };
+            stack_frame["id"] = json!(id);
+            stack_frame["name"] = json!(name);
+            stack_frames.push(stack_frame);
+
+            location = frames.get(depth).map(|f| f.callsite.clone());
+        }
+
+        json!({
+            "stackFrames": stack_frames,
+            "totalFrames": frames.len() + 1,
+        })
+    }
+
+    fn variables(&mut self, vm: &VM, reference: usize) -> Value {
+        let variables = match self.handles.get(reference.wrapping_sub(1)) {
+            None => vec![],
+            Some(Handle::Frame(depth)) => vm.named_locals(*depth),
+            Some(Handle::Object(object)) => object.instance_variables(),
+        };
+
+        let variables: Vec<_> = variables
+            .into_iter()
+            .map(|(name, value)| {
+                let reference = if value.instance_variables().is_empty() {
+                    0
+                } else {
+                    self.handle(Handle::Object(value.clone()))
+                };
+                json!({
+                    "name": name,
+                    "value": value.to_string(),
+                    "type": value.class.as_ref().map(|c| c.name.clone()),
+                    "variablesReference": reference,
+                })
+            })
+            .collect();
+
+        json!({ "variables": variables })
+    }
+
+    fn stop(&mut self, vm: &mut VM, reason: &str) -> DebugCommand {
+        self.handles.clear();
+        MessageSender.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": 1,
+                "allThreadsStopped": true,
+            }),
+        );
+
+        while let Ok(message) = self.messages.recv() {
+            let request = match message {
+                SessionMessage::SetBreakpoints(breakpoints) => {
+                    set_breakpoints(vm, breakpoints);
+                    continue;
+                }
+                SessionMessage::Request(request) => request,
+            };
+
+            let command = match request["command"].as_str().unwrap_or("") {
+                "continue" => {
+                    MessageSender.respond(&request, json!({ "allThreadsContinued": true }));
+                    DebugCommand::Continue
+                }
+                "next" => {
+                    MessageSender.respond(&request, json!({}));
+                    DebugCommand::StepOver
+                }
+                "stepIn" => {
+                    MessageSender.respond(&request, json!({}));
+                    DebugCommand::StepInstruction
+                }
+                "stepOut" => {
+                    MessageSender.respond(&request, json!({}));
+                    DebugCommand::StepOut
+                }
+                "pause" => {
+                    MessageSender.respond(&request, json!({}));
+                    continue;
+                }
+                "stackTrace" => {
+                    let body = self.stack_trace(vm);
+                    MessageSender.respond(&request, body);
+                    continue;
+                }
+                "scopes" => {
+                    let reference = request["arguments"]["frameId"].as_u64().unwrap_or(0);
+                    MessageSender.respond(
+                        &request,
+                        json!({
+                            "scopes": [{
+                                "name": "Locals",
+                                "variablesReference": reference,
+                                "expensive": false,
+                            }],
+                        }),
+                    );
+                    continue;
+                }
+                "variables" => {
+                    let reference = request["arguments"]["variablesReference"]
+                        .as_u64()
+                        .unwrap_or(0);
+                    let body = self.variables(vm, reference as usize);
+                    MessageSender.respond(&request, body);
+                    continue;
+                }
+                command => {
+                    MessageSender.respond_error(&request, format!("{} is not supported", command));
+                    continue;
+                }
+            };
+            self.resume = command;
+            return command;
+        }
+
+        // The client went away.
+        DebugCommand::Terminate
+    }
+}
+
+impl Debugger for AdapterDebugger {
+    fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> DebugCommand {
+        match reason {
+            PauseReason::Breakpoint(_) => self.stop(vm, "breakpoint"),
+
+            // Stepping is done by instruction in the VM, but by source line
+            // in an editor, so keep going until we reach a message send.
+            PauseReason::Step => match vm.instruction_at(vm.pc()) {
+                Some(Instruction::CallMethod(..))
+                | Some(Instruction::TailCallMethod(..))
+                | None => self.stop(vm, "step"),
+                Some(_) if self.resume == DebugCommand::StepOut => DebugCommand::StepOver,
+                Some(_) => self.resume,
+            },
+
+            // The main thread interrupts the VM whenever it has something to
+            // say, which is either a pause request or a change of breakpoints.
+            PauseReason::Pause => {
+                let mut reason = if self.entry { Some("entry") } else { None };
+                self.entry = false;
+                while let Ok(message) = self.messages.try_recv() {
+                    match message {
+                        SessionMessage::SetBreakpoints(breakpoints) => {
+                            set_breakpoints(vm, breakpoints)
+                        }
+                        SessionMessage::Request(ref request) if request["command"] == "pause" => {
+                            MessageSender.respond(request, json!({}));
+                            reason = reason.or(Some("pause"));
+                        }
+                        SessionMessage::Request(request) => {
+                            MessageSender.respond_error(&request, "the program is running")
+                        }
+                    }
+                }
+                match reason {
+                    Some(reason) => self.stop(vm, reason),
+                    None => self.resume,
+                }
+            }
+        }
+    }
+}
+
+fn set_breakpoints(vm: &mut VM, breakpoints: Vec<Breakpoint>) {
+    vm.clear_breakpoints();
+    for breakpoint in breakpoints {
+        vm.add_breakpoint(breakpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debug_adapter::sent;
+    use loa::assembly::Parser;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn stack_trace_at_breakpoint() {
+        let instructions: Vec<Instruction> = Parser::new()
+            .parse(
+                r#"
+                @A$methods
+                  DeclareMethod "a" @A#a
+                  DeclareMethod "b" @A#b
+
+                @A
+                  DeclareClass "A"
+                  UseMethod @A#a
+                  UseMethod @A#b
+
+                LoadObject @A
+                CallMethod @A#a "Main.loa" 3 1
+                Halt
+
+                @A#a
+                  LoadLocal 0
+                  CallMethod @A#b "Main.loa" 5 3
+                  Return 1
+
+                @A#b
+                  LoadLocal 0
+                  Return 1
+                "#,
+            )
+            .unwrap()
+            .into();
+
+        let (sender, receiver) = channel();
+        for (seq, command) in ["stackTrace", "continue"].iter().enumerate() {
+            sender
+                .send(SessionMessage::Request(json!({
+                    "seq": seq + 1,
+                    "type": "request",
+                    "command": command,
+                    "arguments": { "threadId": 1 },
+                })))
+                .unwrap();
+        }
+
+        // The VM runs on the test thread, so that its messages are kept.
+        let mut vm = VM::new();
+        vm.attach_debugger(AdapterDebugger::new(
+            receiver,
+            PathBuf::from("/nonexistent"),
+            false,
+        ));
+        vm.add_breakpoint(Breakpoint::new("Main.loa", 5));
+        assert!(vm.eval_pop::<AdapterRuntime>(instructions).is_some());
+
+        assert_eq!(
+            sent(),
+            vec![
+                json!({
+                    "type": "event",
+                    "event": "stopped",
+                    "body": {
+                        "reason": "breakpoint",
+                        "threadId": 1,
+                        "allThreadsStopped": true,
+                    },
+                }),
+                json!({
+                    "type": "response",
+                    "request_seq": 1,
+                    "command": "stackTrace",
+                    "success": true,
+                    "body": {
+                        "stackFrames": [
+                            {
+                                "id": 1,
+                                "name": "A#a",
+                                "source": { "name": "Main.loa" },
+                                "line": 5,
+                                "column": 3,
+                            },
+                            {
+                                "id": 2,
+                                "name": "<main>",
+                                "source": { "name": "Main.loa" },
+                                "line": 3,
+                                "column": 1,
+                            },
+                        ],
+                        "totalFrames": 2,
+                    },
+                }),
+                json!({
+                    "type": "response",
+                    "request_seq": 2,
+                    "command": "continue",
+                    "success": true,
+                    "body": { "allThreadsContinued": true },
+                }),
+            ]
+        );
+    }
+}
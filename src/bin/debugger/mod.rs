@@ -0,0 +1,24 @@
+mod terminal_debugger;
+pub use self::terminal_debugger::*;
+
+use crate::runtime::ServerRuntime;
+use loa::assembly::Assembly;
+use loa::vm::{Breakpoint, VM};
+use loa::{Arc, Source};
+
+pub fn debug(assembly: Assembly, sources: &[Arc<Source>], breakpoints: Vec<Breakpoint>) {
+    let mut vm = VM::new();
+    vm.attach_debugger(TerminalDebugger::new());
+    vm.set_source_map(assembly.source_map(sources, true));
+
+    if breakpoints.is_empty() {
+        vm.pause();
+    }
+    for breakpoint in breakpoints {
+        vm.add_breakpoint(breakpoint);
+    }
+
+    if let Some(result) = vm.eval_pop::<ServerRuntime>(assembly.into()) {
+        println!("{}", result);
+    }
+}
@@ -0,0 +1,147 @@
+use colored::Colorize;
+use loa::bytecode::Instruction;
+use loa::vm::{frame_name, Breakpoint, DebugCommand, Debugger, PauseReason, MAIN_FRAME, VM};
+use rustyline::Editor;
+
+const HELP: &str = "\
+break FILE:LINE    (b)   Pause when a message is sent from FILE:LINE
+delete FILE:LINE   (d)   Remove a breakpoint
+breakpoints              List all breakpoints
+continue           (c)   Run until the next breakpoint
+step               (s)   Execute a single instruction
+next               (n)   Step over method calls
+out                (o)   Run until the current method returns
+backtrace          (bt)  Show the call stack
+locals [FRAME]     (l)   Show the receiver, arguments and temporaries of a frame
+stack                    Show the entire value stack
+quit               (q)   Terminate the program";
+
+pub struct TerminalDebugger {
+    editor: Editor<()>,
+}
+
+impl TerminalDebugger {
+    pub fn new() -> TerminalDebugger {
+        TerminalDebugger {
+            editor: Editor::new(),
+        }
+    }
+
+    fn print_location(&self, vm: &VM, reason: &PauseReason) {
+        match reason {
+            PauseReason::Breakpoint(b) => println!("{} {}", "Breakpoint".red(), b),
+            PauseReason::Pause => println!("{}", "Paused".yellow()),
+            PauseReason::Step => {}
+        }
+
+        let method = vm
+            .call_stack()
+            .top()
+            .map(|frame| frame_name(frame))
+            .unwrap_or_else(|| MAIN_FRAME.into());
+
+        match vm.instruction_at(vm.pc()) {
+            Some(Instruction::CallMethod(_, uri, line, character))
+            | Some(Instruction::TailCallMethod(_, uri, line, character)) => println!(
+                "{} {:X} {}\n  {}",
+                method.yellow(),
+                vm.pc(),
+                format!("{:?}", vm.instruction_at(vm.pc()).unwrap()).bright_black(),
+                format!("({}:{}:{})", uri, line, character).bright_black()
+            ),
+            Some(instruction) => println!(
+                "{} {:X} {}",
+                method.yellow(),
+                vm.pc(),
+                format!("{:?}", instruction).bright_black()
+            ),
+            None => println!("{} {:X}", method.yellow(), vm.pc()),
+        }
+
+        if let Some(location) = vm.source_location(vm.pc()) {
+            println!("  {} {}", "-->".bright_black(), location);
+            if let Some(excerpt) = location.excerpt() {
+                println!("{}", excerpt);
+            }
+        }
+    }
+
+    fn print_backtrace(&self, vm: &VM) {
+        for (i, frame) in vm.call_stack().frames().iter().enumerate() {
+            println!(
+                "#{} {}\n  {}",
+                i,
+                frame_name(frame).yellow(),
+                format!("({})", frame.callsite).bright_black()
+            );
+        }
+    }
+
+    fn print_locals(&self, vm: &VM, depth: usize) {
+        if vm.call_stack().frames().get(depth).is_none() {
+            eprintln!("No frame #{}", depth);
+            return;
+        }
+        for (name, value) in vm.named_locals(depth) {
+            println!("{} = {}", name.blue(), value);
+        }
+    }
+}
+
+impl Debugger for TerminalDebugger {
+    fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> DebugCommand {
+        self.print_location(vm, &reason);
+
+        loop {
+            let line = match self.editor.readline(&"(loa) ".bright_black().to_string()) {
+                Ok(line) => line,
+                Err(_) => return DebugCommand::Terminate,
+            };
+            self.editor.add_history_entry(line.as_str());
+
+            let mut words = line.split_whitespace();
+            let command = words.next().unwrap_or("");
+            let argument = words.next();
+
+            match (command, argument) {
+                ("", _) => {}
+                ("c", _) | ("continue", _) => return DebugCommand::Continue,
+                ("s", _) | ("step", _) => return DebugCommand::StepInstruction,
+                ("n", _) | ("next", _) => return DebugCommand::StepOver,
+                ("o", _) | ("out", _) => return DebugCommand::StepOut,
+                ("q", _) | ("quit", _) => return DebugCommand::Terminate,
+
+                ("b", Some(location)) | ("break", Some(location)) => {
+                    match location.parse::<Breakpoint>() {
+                        Ok(b) => vm.add_breakpoint(b),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                ("d", Some(location)) | ("delete", Some(location)) => {
+                    match location.parse::<Breakpoint>() {
+                        Ok(b) => vm.remove_breakpoint(&b),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                }
+                ("breakpoints", _) => {
+                    for b in vm.breakpoints() {
+                        println!("{}", b);
+                    }
+                }
+
+                ("bt", _) | ("backtrace", _) => self.print_backtrace(vm),
+                ("l", depth) | ("locals", depth) => {
+                    match depth.map(str::parse::<usize>).unwrap_or(Ok(0)) {
+                        Ok(depth) => self.print_locals(vm, depth),
+                        Err(_) => eprintln!("Invalid frame number"),
+                    }
+                }
+                ("stack", _) => vm.print_stack(),
+                ("w", _) | ("where", _) => self.print_location(vm, &PauseReason::Step),
+
+                ("h", _) | ("help", _) => println!("{}", HELP),
+                (c, _) => eprintln!("Unknown command {}. Type help for a list of commands.", c),
+            }
+        }
+    }
+}
@@ -21,26 +21,30 @@ extern crate simple_logging;
 extern crate tar;
 extern crate tee;
 
+mod debug_adapter;
+mod debugger;
 mod docs;
 mod repl;
 mod reporting;
 mod server_handler;
 pub use self::reporting::*;
 mod runtime;
-use self::runtime::ServerRuntime;
+use self::runtime::{finish_tracing, trace_from_env, ServerRuntime};
 use colored::Colorize;
 
 mod pkg;
 
 use crate::docs::{Docs, Versions};
 use crate::pkg::ManifestFile;
-use loa::bytecode::BytecodeEncoding;
-use loa::bytecode::Instruction;
+use loa::assembly::{Disassembler, Linker, Object, ObjectError};
+use loa::bytecode::{Binary, BinaryError, Instruction, SourceMap};
 use loa::optimization::Optimizable;
-use loa::vm::VM;
+use loa::vm::{Recording, VMLimits, VM};
 use log::LevelFilter;
+use std::collections::HashSet;
 use std::convert::identity;
 use std::io::stdout;
+use std::path::PathBuf;
 use std::process::exit;
 use std::str::FromStr;
 
@@ -86,6 +90,19 @@ fn main() -> Result<(), clap::Error> {
         .long("no-stdlib")
         .help("Don't include the standard library.");
 
+    let limits_option = clap::Arg::with_name("limits")
+        .help("Resource limits for the VM, as a comma separated list of instructions=N, call-depth=N, stack-size=N, lazy-depth=N and timeout=SECONDS.")
+        .long("limits")
+        .short("l")
+        .takes_value(true)
+        .value_name("LIMITS");
+
+    let arguments_option = clap::Arg::with_name("arguments")
+        .help("Arguments for the program, which it reads through Loa/Process.")
+        .multiple(true)
+        .last(true)
+        .value_name("ARGUMENTS");
+
     let mut config_file = dirs::config_dir().unwrap();
     config_file.push("loa");
     std::fs::create_dir_all(&config_file).expect("need write permission to config directory");
@@ -98,8 +115,9 @@ fn main() -> Result<(), clap::Error> {
             clap::SubCommand::with_name("server")
                 .about("Starts a Language Server using STDIO. Used by editors to provide an integrated experience for Loa development."),
             clap::SubCommand::with_name("repl")
-                .about("Starts an interactive Read-Eval-Print-Loop that can be used to quickly explore APIs and make quick calculations.")
-                .arg(no_stdlib_option.clone()),
+                .about("Starts an interactive Read-Eval-Print-Loop that can be used to quickly explore APIs and make quick calculations. A session can be saved to an image with :save <file>, and resumed in the same project with :load <file>.")
+                .arg(no_stdlib_option.clone())
+                .arg(limits_option.clone()),
             clap::SubCommand::with_name("build")
                 .about("Builds the current project into a Loa VM bytecode file, that can be executed in many different environments using the Loa VM.")
                 .arg(
@@ -116,12 +134,73 @@ fn main() -> Result<(), clap::Error> {
                         .long("assembly")
                         .short("s"),
                 )
+                .arg(
+                    clap::Arg::with_name("target")
+                        .help("Build Loa VM bytecode, or a WebAssembly module that exports its memory and a `main` function.")
+                        .long("target")
+                        .takes_value(true)
+                        .possible_values(&["vm", "wasm"])
+                        .default_value("vm"),
+                )
+                .arg(
+                    clap::Arg::with_name("embed_sources")
+                        .help("Embed the code of the sources in the source map of the bytecode file, so that panics show excerpts of the code without access to the sources.")
+                        .long("embed-sources"),
+                )
                 .arg(no_stdlib_option.clone())
                 .arg(main_class_option.clone()),
             clap::SubCommand::with_name("run")
                 .about("Builds and immediately runs the current project. This is not suitable for a production environment, but handy for quickly running your program.")
+                .arg(
+                    clap::Arg::with_name("profile")
+                        .help("Profile the program, writing its call stacks in the collapsed format used by flamegraph tools to this file, and a summary of the time spent in each method to stderr.")
+                        .long("profile")
+                        .takes_value(true)
+                        .value_name("FILE"),
+                )
+                .arg(
+                    clap::Arg::with_name("parallel")
+                        .help("Evaluate independent lazy values on this many worker threads. The output of the program is the same as without workers.")
+                        .long("parallel")
+                        .short("j")
+                        .takes_value(true)
+                        .value_name("WORKERS"),
+                )
+                .arg(
+                    clap::Arg::with_name("record")
+                        .help("Record every call to a native method, with the objects passed to it and what it returned, to this file.")
+                        .long("record")
+                        .takes_value(true)
+                        .value_name("FILE"),
+                )
+                .arg(
+                    clap::Arg::with_name("replay")
+                        .help("Replay the native calls recorded to this file with --record, instead of making them, and report where the program stops matching the recording.")
+                        .long("replay")
+                        .takes_value(true)
+                        .conflicts_with("record")
+                        .value_name("FILE"),
+                )
+                .arg(no_stdlib_option.clone())
+                .arg(limits_option.clone())
+                .arg(main_class_option.clone())
+                .arg(arguments_option.clone()),
+            clap::SubCommand::with_name("debug")
+                .about("Builds and runs the current project in an interactive step-through debugger.")
+                .arg(
+                    clap::Arg::with_name("break")
+                        .help("Pause when a message is sent from this source location. Without any breakpoints, the program is paused before the first instruction.")
+                        .long("break")
+                        .short("b")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("FILE:LINE"),
+                )
                 .arg(no_stdlib_option)
                 .arg(main_class_option),
+            clap::SubCommand::with_name("debug-adapter")
+                .about("Starts a Debug Adapter using STDIO. Used by editors to debug Loa programs."),
             clap::SubCommand::with_name("exec")
                 .about("Executes a bytecode file using the Loa VM.")
                 .arg(
@@ -129,6 +208,17 @@ fn main() -> Result<(), clap::Error> {
                     .help("The path to the bytecode file (.loabin).")
                     .takes_value(true)
                     .value_name("BINARY_FILE"),
+            )
+                .arg(limits_option)
+                .arg(arguments_option),
+            clap::SubCommand::with_name("disasm")
+                .about("Prints the assembly of a bytecode file. The output can be saved to a .loaasm file, which `loa exec` runs like a bytecode file.")
+                .arg(
+                clap::Arg::with_name("loabin")
+                    .help("The path to the bytecode file (.loabin).")
+                    .takes_value(true)
+                    .required(true)
+                    .value_name("BINARY_FILE"),
             ),
             clap::SubCommand::with_name("format")
                 .about("Runs the Loa code formatter on the provided files. Outputs to stdout, and does not modify the files themselves.")
@@ -243,7 +333,7 @@ fn main() -> Result<(), clap::Error> {
     match cli.subcommand() {
         ("repl", Some(matches)) => {
             log_to_stderr();
-            repl::repl(!matches.is_present("no_stdlib"))
+            repl::repl(!matches.is_present("no_stdlib"), vm_limits(matches))
         }
 
         ("server", _) => {
@@ -276,44 +366,174 @@ fn main() -> Result<(), clap::Error> {
             }
             Some(file) => {
                 log_to_stderr();
-                let instructions = if file.ends_with(".loaasm") {
+                let (instructions, source_map) = if file.ends_with(".loaasm") {
                     let assembly_code = std::fs::read_to_string(file).unwrap();
                     let assembly = loa::assembly::Parser::new()
                         .parse(assembly_code.as_ref())
                         .unwrap();
-                    assembly.into()
+                    (assembly.into(), SourceMap::new())
                 } else {
-                    std::fs::read(file)
-                        .map(|bytes| Vec::<_>::deserialize(bytes.as_slice()).unwrap())?
+                    match std::fs::File::open(file)
+                        .map_err(BinaryError::from)
+                        .and_then(Binary::read)
+                    {
+                        Ok(binary) => (binary.instructions, binary.source_map),
+                        Err(e) => {
+                            eprintln!("{}: {}", file, e);
+                            exit(1);
+                        }
+                    }
                 };
+                if let Err(e) = loa::bytecode::verify(&instructions) {
+                    eprintln!("{}: {}", file, e);
+                    exit(1);
+                }
 
                 let mut vm = VM::new();
+                vm.set_limits(vm_limits(matches));
+                vm.set_source_map(source_map);
+                vm.set_arguments(program_arguments(matches));
+                trace_from_env(&mut vm);
                 if let Some(result) = vm.eval_pop::<ServerRuntime>(instructions) {
                     println!("{}", result);
                 }
+                finish_tracing(&mut vm);
+                if let Some(code) = vm.exit_code() {
+                    exit(code);
+                }
             }
         },
 
+        ("disasm", Some(matches)) => {
+            log_to_stderr();
+            let file = matches.value_of("loabin").unwrap();
+            let binary = match std::fs::File::open(file)
+                .map_err(BinaryError::from)
+                .and_then(Binary::read)
+            {
+                Ok(binary) => binary,
+                Err(e) => {
+                    eprintln!("{}: {}", file, e);
+                    exit(1);
+                }
+            };
+            match Disassembler::new()
+                .with_source_map(&binary.source_map)
+                .disassemble(&binary.instructions)
+            {
+                Ok(assembly) => print!("{:?}", assembly),
+                Err(e) => {
+                    eprintln!("{}: {}", file, e);
+                    exit(1);
+                }
+            }
+        }
+
         ("run", Some(matches)) => {
             log_to_stderr();
-            let assembly = build(
+            let (assembly, sources) = build(
                 matches.value_of("main").unwrap(),
                 matches.is_present("no_stdlib"),
             );
 
-            if let Some(result) = loa::vm::VM::new().eval_pop::<ServerRuntime>(assembly.into()) {
+            let mut vm = VM::new();
+            vm.set_limits(vm_limits(matches));
+            vm.set_source_map(assembly.source_map(&sources, true));
+            vm.set_arguments(program_arguments(matches));
+            if let Some(workers) = matches.value_of("parallel") {
+                match workers.parse() {
+                    Ok(workers) => vm.set_parallel_workers(workers),
+                    Err(e) => {
+                        eprintln!("--parallel {}: {}", workers, e);
+                        exit(1);
+                    }
+                }
+            }
+            if matches.is_present("profile") {
+                vm.start_profiling();
+            }
+            if matches.is_present("record") {
+                vm.start_recording();
+            }
+            if let Some(file) = matches.value_of("replay") {
+                match std::fs::File::open(file).and_then(Recording::read) {
+                    Ok(recording) => vm.start_replaying(recording),
+                    Err(e) => {
+                        eprintln!("{}: {}", file, e);
+                        exit(1);
+                    }
+                }
+            }
+            trace_from_env(&mut vm);
+            if let Some(result) = vm.eval_pop::<ServerRuntime>(assembly.into()) {
                 println!("{}", result);
             }
+            finish_tracing(&mut vm);
+
+            if let (Some(file), Some(profile)) = (matches.value_of("profile"), vm.stop_profiling())
+            {
+                eprint!("{}", profile);
+                if let Err(e) = std::fs::File::create(file).and_then(|f| profile.write_collapsed(f))
+                {
+                    eprintln!("{}: {}", file, e);
+                    exit(1);
+                }
+            }
+            if let (Some(file), Some(recording)) = (matches.value_of("record"), vm.stop_recording())
+            {
+                if let Err(e) = std::fs::File::create(file).and_then(|f| recording.write(f)) {
+                    eprintln!("{}: {}", file, e);
+                    exit(1);
+                }
+            }
+            match vm.stop_replaying() {
+                Some(0) | None => {}
+                Some(left) => {
+                    eprintln!(
+                        "replay diverged: the program ended with {} recorded native calls left",
+                        left
+                    );
+                    exit(1);
+                }
+            }
+            if let Some(code) = vm.exit_code() {
+                exit(code);
+            }
         }
 
-        ("build", Some(matches)) => {
+        ("debug", Some(matches)) => {
             log_to_stderr();
-            let output_assembly = matches.is_present("output_assembly");
-            let mut assembly = build(
+            let mut breakpoints = vec![];
+            for location in matches.values_of("break").into_iter().flatten() {
+                match location.parse() {
+                    Ok(b) => breakpoints.push(b),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit(1);
+                    }
+                }
+            }
+
+            let (assembly, sources) = build(
                 matches.value_of("main").unwrap(),
                 matches.is_present("no_stdlib"),
             );
 
+            debugger::debug(assembly, &sources, breakpoints);
+        }
+
+        ("debug-adapter", _) => {
+            log_to_file();
+            debug_adapter::debug_adapter(default_main.as_ref())
+        }
+
+        ("build", Some(matches)) => {
+            log_to_stderr();
+            let output_assembly = matches.is_present("output_assembly");
+            let output_wasm = !output_assembly && matches.value_of("target") == Some("wasm");
+            let main = matches.value_of("main").unwrap();
+            let (mut assembly, sources) = build(main, matches.is_present("no_stdlib"));
+
             assembly.optimize();
 
             let mut write: Box<dyn std::io::Write> = match matches.value_of("out") {
@@ -326,6 +546,9 @@ fn main() -> Result<(), clap::Error> {
                         outfile.pop();
                         outfile.pop();
                         outfile.push_str("asm");
+                    } else if output_wasm && outfile == default_out {
+                        outfile.truncate(outfile.len() - "loabin".len());
+                        outfile.push_str("wasm");
                     }
 
                     println!("{} {}", "Building".bright_black(), outfile.green());
@@ -333,6 +556,7 @@ fn main() -> Result<(), clap::Error> {
                     let outfile_sink = std::fs::OpenOptions::new()
                         .create(true)
                         .write(true)
+                        .truncate(true)
                         .open(outfile)
                         .unwrap();
 
@@ -342,9 +566,21 @@ fn main() -> Result<(), clap::Error> {
 
             if output_assembly {
                 write.write(format!("{:?}", assembly).as_bytes())?;
+            } else if output_wasm {
+                match loa::wasm::Generator::new().generate(assembly) {
+                    Ok(module) => write.write_all(&module)?,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        exit(1);
+                    }
+                }
             } else {
+                let source_map = assembly.source_map(&sources, matches.is_present("embed_sources"));
                 let instructions: Vec<Instruction> = assembly.into();
-                instructions.serialize(write)?;
+                Binary::new(instructions)
+                    .with_entry_point(main)
+                    .with_source_map(source_map)
+                    .write(write)?;
             }
         }
 
@@ -426,6 +662,8 @@ fn main() -> Result<(), clap::Error> {
                         }
                     } {
                         eprintln!("{}", e)
+                    } else {
+                        precompile();
                     }
                 }
                 ("whoami", _) => match api.auth_email() {
@@ -516,15 +754,264 @@ fn parse_and_report(main: Option<&str>, load_stdlib: bool) -> loa::semantics::An
     analysis
 }
 
-fn build(main: &str, load_stdlib: bool) -> loa::assembly::Assembly {
+/// Builds the program, compiling only the packages whose objects aren't up
+/// to date.
+///
+/// All sources are still parsed and checked, even those of packages with an
+/// up to date object, because the checkers look at the whole program at once
+/// and the project is generated from the declarations of its dependencies.
+/// Only the generation of code is skipped for those packages.
+fn build(main: &str, load_stdlib: bool) -> (loa::assembly::Assembly, Vec<loa::Arc<loa::Source>>) {
     let mut analysis = parse_and_report(Some(main), load_stdlib);
 
-    let mut generator = loa::generation::Generator::new(&mut analysis);
-    match generator.generate_all() {
+    let mut linker = Linker::new();
+    for package in packages(analysis.navigator.sources()) {
+        match compile(&mut analysis, &package) {
+            Err(err) => {
+                eprintln!("{:?}", err);
+                exit(1);
+            }
+            Ok((object, kept)) => {
+                if let Err(err) = kept {
+                    log::warn!("{}", err);
+                }
+                linker.add(object)
+            }
+        }
+    }
+    match linker.link() {
         Err(err) => {
-            eprintln!("{:?}", err);
+            eprintln!("{}", err);
+            exit(1);
+        }
+        Ok(linked) => linked,
+    }
+}
+
+/// Generates code for the standard library and the installed dependencies
+/// ahead of time, so that building the project only has to generate code
+/// for its own sources. Every package is still parsed and checked by each
+/// build.
+fn precompile() {
+    let (diagnostics, mut analysis) = parse(None, false);
+    if loa::Diagnostic::failed(&diagnostics) {
+        <PrettyReporter as loa::Reporter>::report(diagnostics, &analysis.navigator);
+        return;
+    }
+
+    for package in packages(analysis.navigator.sources()) {
+        if package.object_file.is_none() {
+            continue;
+        }
+        match compile(&mut analysis, &package) {
+            Err(err) => eprintln!("{}: {:?}", package.name, err),
+            Ok((_, Err(err))) => eprintln!("{}", err),
+            Ok(_) => println!("{} {}", "Compiled".bright_black(), package.name.green()),
+        }
+    }
+}
+
+/// A set of sources that is compiled into an object of its own. The standard
+/// library and every installed dependency are packages, and so is the
+/// project itself.
+struct Package {
+    name: String,
+    sources: Vec<loa::Arc<loa::Source>>,
+
+    /// The sources that, if changed, require the package to be compiled
+    /// again. That's its own sources, and those of the packages it may
+    /// depend on.
+    dependent_sources: Vec<loa::Arc<loa::Source>>,
+
+    /// Where the compiled package is kept between builds. The project is
+    /// compiled every time, and so is the standard library when there's no
+    /// cache directory to keep it in.
+    object_file: Option<PathBuf>,
+}
+
+impl Package {
+    fn new(name: &str, object_file: Option<PathBuf>) -> Package {
+        Package {
+            name: name.into(),
+            sources: vec![],
+            dependent_sources: vec![],
+            object_file,
+        }
+    }
+
+    /// The object kept in the object file, if it was compiled from the
+    /// current sources. Dependencies may ship with their object file.
+    fn cached_object(&self) -> Option<Object> {
+        let file = self.object_file.as_ref()?;
+        let object = std::fs::File::open(file)
+            .map_err(ObjectError::from)
+            .and_then(Object::read)
+            .ok()?;
+        if object.is_fresh(&self.dependent_sources) {
+            Some(object)
+        } else {
+            None
+        }
+    }
+}
+
+/// Splits the sources into the standard library, the dependencies installed
+/// in .pkg, and the project, in the order they are to be linked in.
+fn packages(sources: Vec<loa::Arc<loa::Source>>) -> Vec<Package> {
+    let mut stdlib = Package::new("Loa", stdlib_object_file());
+    let mut dependencies: Vec<_> = ManifestFile::<pkg::config::Lockfile>::new(".pkg.lock")
+        .load()
+        .map(|lockfile| lockfile.0.keys().cloned().collect::<Vec<String>>())
+        .unwrap_or_else(|_| vec![])
+        .into_iter()
+        .map(|name| {
+            let mut package_dir = PathBuf::from(".pkg");
+            package_dir.extend(name.split("/"));
+            let object_file = package_dir.join("package.loaobj");
+            (package_dir, Package::new(&name, Some(object_file)))
+        })
+        .collect();
+    dependencies.sort_by(|(_, a), (_, b)| a.name.cmp(&b.name));
+    let dependencies = in_dependency_order(dependencies);
+    let mut project = Package::new("the project", None);
+
+    for source in sources {
+        let package = match source.uri {
+            loa::URI::Stdlib(_) => &mut stdlib,
+            loa::URI::File(ref path) => dependencies
+                .iter_mut()
+                .find(|(dir, _)| path.starts_with(dir))
+                .map(|(_, package)| package)
+                .unwrap_or(&mut project),
+            _ => &mut project,
+        };
+        package.sources.push(source);
+    }
+
+    stdlib.dependent_sources = stdlib.sources.clone();
+    let libraries: Vec<_> = stdlib
+        .sources
+        .iter()
+        .chain(dependencies.iter().flat_map(|(_, d)| d.sources.iter()))
+        .cloned()
+        .collect();
+    let mut packages = vec![stdlib];
+    for (_, mut dependency) in dependencies {
+        dependency.dependent_sources = libraries.clone();
+        packages.push(dependency);
+    }
+    packages.push(project);
+    packages.retain(|p| !p.sources.is_empty());
+    packages
+}
+
+/// Where the compiled standard library is kept. It's kept in the cache
+/// directory of the user rather than in the SDK, which may not be writable,
+/// and is compiled again when the SDK changes since its checksum won't match.
+fn stdlib_object_file() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("loa").join("std.loaobj"))
+}
+
+/// Orders the installed dependencies so that each one comes after the
+/// dependencies listed in its own pkg.yml. Packages that don't depend on
+/// each other keep their order, and a cycle is broken where it is found.
+fn in_dependency_order(dependencies: Vec<(PathBuf, Package)>) -> Vec<(PathBuf, Package)> {
+    let requirements: Vec<Vec<usize>> = dependencies
+        .iter()
+        .map(|(dir, _)| {
+            let pkgfile = ManifestFile::<pkg::config::Pkgfile>::new(
+                dir.join("pkg.yml").to_string_lossy().as_ref(),
+            )
+            .load()
+            .ok()
+            .and_then(|pkgfile| pkgfile.dependencies)
+            .unwrap_or_default();
+            let mut required: Vec<_> = dependencies
+                .iter()
+                .enumerate()
+                .filter(|(_, (_, d))| pkgfile.contains_key(&d.name))
+                .map(|(index, _)| index)
+                .collect();
+            required.sort();
+            required
+        })
+        .collect();
+
+    fn visit(index: usize, requirements: &[Vec<usize>], order: &mut Vec<usize>, seen: &mut [bool]) {
+        if seen[index] {
+            return;
+        }
+        seen[index] = true;
+        for required in requirements[index].iter() {
+            visit(*required, requirements, order, seen);
+        }
+        order.push(index);
+    }
+
+    let mut order = vec![];
+    let mut seen = vec![false; dependencies.len()];
+    for index in 0..dependencies.len() {
+        visit(index, &requirements, &mut order, &mut seen);
+    }
+
+    let mut dependencies: Vec<_> = dependencies.into_iter().map(Some).collect();
+    order
+        .into_iter()
+        .map(|index| dependencies[index].take().unwrap())
+        .collect()
+}
+
+/// Compiles the package into an object, unless an up to date one is kept in
+/// its object file. A newly compiled object is kept for the next build, and
+/// the object is returned along with whether it could be kept.
+fn compile(
+    analysis: &mut loa::semantics::Analysis,
+    package: &Package,
+) -> loa::generation::GenerationResult<(Object, Result<(), String>)> {
+    if let Some(object) = package.cached_object() {
+        return Ok((object, Ok(())));
+    }
+
+    let uris: HashSet<_> = package.sources.iter().map(|s| s.uri.to_string()).collect();
+    let assembly = loa::generation::Generator::new(analysis)
+        .generate_package(|source| uris.contains(&source.uri.to_string()))?;
+    let mut object = Object::new(package.name.as_str(), assembly, package.sources.clone());
+    object.checksum = Object::checksum(&package.dependent_sources);
+
+    let kept = match package.object_file {
+        None => Ok(()),
+        Some(ref file) => file
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::File::create(file))
+            .and_then(|f| object.write(f))
+            .map(|_| ())
+            .map_err(|e| {
+                format!(
+                    "Failed to keep {} in {}: {}",
+                    package.name,
+                    file.display(),
+                    e
+                )
+            }),
+    };
+    Ok((object, kept))
+}
+
+fn program_arguments(matches: &clap::ArgMatches) -> Vec<String> {
+    matches
+        .values_of("arguments")
+        .map(|a| a.map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn vm_limits(matches: &clap::ArgMatches) -> VMLimits {
+    match matches.value_of("limits").map(VMLimits::from_str) {
+        None => VMLimits::unlimited(),
+        Some(Ok(limits)) => limits,
+        Some(Err(e)) => {
+            eprintln!("{}", e);
             exit(1);
         }
-        Ok(i) => i,
     }
 }
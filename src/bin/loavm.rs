@@ -1,19 +1,68 @@
-use loa::bytecode::BytecodeEncodingRead;
-use loa::vm::VM;
+use loa::bytecode::{verify, Binary, BinaryError};
+use loa::vm::{VMLimits, VM};
 use std::env::args;
 use std::fs::File;
 use std::io::Result;
+use std::process::exit;
 mod runtime;
-use self::runtime::ServerRuntime;
+use self::runtime::{finish_tracing, trace_from_env, ServerRuntime};
 
 fn main() -> Result<()> {
-    for arg in args().skip(1) {
-        let instructions = File::open(arg)?.deserialize()?;
+    let mut limits = VMLimits::unlimited();
+    let mut files = vec![];
+
+    let mut args = args().skip(1);
+    while let Some(arg) = args.next() {
+        // Whatever follows `--` is passed on to the programs.
+        if arg == "--" {
+            break;
+        } else if arg == "--limits" || arg == "-l" {
+            match args.next().as_ref().map(|l| l.parse()) {
+                Some(Ok(l)) => limits = l,
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    exit(1);
+                }
+                None => {
+                    eprintln!("expected limits after {}", arg);
+                    exit(1);
+                }
+            }
+        } else {
+            files.push(arg);
+        }
+    }
+
+    let arguments: Vec<_> = args.collect();
+
+    for file in files {
+        let binary = match File::open(&file)
+            .map_err(BinaryError::from)
+            .and_then(Binary::read)
+        {
+            Ok(binary) => binary,
+            Err(e) => {
+                eprintln!("{}: {}", file, e);
+                exit(1);
+            }
+        };
+        if let Err(e) = verify(&binary.instructions) {
+            eprintln!("{}: {}", file, e);
+            exit(1);
+        }
 
         let mut vm = VM::new();
-        if let Some(result) = vm.eval_pop::<ServerRuntime>(instructions) {
+        vm.set_limits(limits.clone());
+        vm.set_source_map(binary.source_map);
+        vm.set_arguments(arguments.clone());
+        trace_from_env(&mut vm);
+        if let Some(result) = vm.eval_pop::<ServerRuntime>(binary.instructions) {
             println!("{}", result);
         }
+        finish_tracing(&mut vm);
+        if let Some(code) = vm.exit_code() {
+            exit(code);
+        }
     }
     Ok(())
 }
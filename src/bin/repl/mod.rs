@@ -1,8 +1,10 @@
 mod repl;
 pub use self::repl::*;
 
-pub fn repl(use_std: bool) {
-    let mut repl = repl::REPL::new::<crate::PrettyReporter>(use_std);
+use loa::vm::VMLimits;
+
+pub fn repl(use_std: bool, limits: VMLimits) {
+    let mut repl = repl::REPL::new::<crate::PrettyReporter>(use_std, limits);
 
     repl.start::<crate::PrettyReporter>();
 }
@@ -1,11 +1,12 @@
 use crate::*;
 use colored::{Color, Colorize};
 use loa::assembly::{Assembly, Cursor};
+use loa::bytecode::{BytecodeEncoding, ConstantPool, PooledDecoder, PooledEncoder};
 use loa::generation::REPLDirectives;
 use loa::semantics::Type;
 use loa::server::Server;
 use loa::syntax::{characters_to_string, string_to_characters, tokenize, TokenKind};
-use loa::vm::VM;
+use loa::vm::{Image, VMLimits, VM};
 use loa::*;
 use rustyline::completion::{Candidate, Completer};
 use rustyline::config::Configurer;
@@ -14,6 +15,8 @@ use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
 use rustyline::{CompletionType, Context, EditMode, Editor, Helper};
 use std::borrow::Cow::{Borrowed, Owned};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
 
 struct EditorHelper {
     pub server: Arc<Mutex<Server>>,
@@ -140,6 +143,8 @@ pub fn highlight(source: Arc<Source>, markers: Vec<(Color, Span)>) -> String {
                 | TokenKind::LetKeyword
                 | TokenKind::NativeKeyword
                 | TokenKind::PanicKeyword
+                | TokenKind::TryKeyword
+                | TokenKind::RescueKeyword
                 | TokenKind::InitKeyword
                 | TokenKind::VarKeyword => lexeme.blue().to_string(),
                 TokenKind::Dash => lexeme,
@@ -192,10 +197,13 @@ pub struct REPL {
     server: Arc<Mutex<Server>>,
     vm: VM,
     cursor: Cursor,
+
+    /// The lines evaluated so far, by their line number.
+    lines: Vec<(usize, String)>,
 }
 
 impl REPL {
-    pub fn new<R: Reporter>(use_stdlib: bool) -> REPL {
+    pub fn new<R: Reporter>(use_stdlib: bool, limits: VMLimits) -> REPL {
         let mut server = Server::new();
 
         let mut sources = Source::files("**/*.loa").unwrap_or(vec![]);
@@ -213,6 +221,7 @@ impl REPL {
         }
 
         let mut vm = VM::new();
+        vm.set_limits(limits);
         let mut cursor = Cursor::new();
 
         if failure {
@@ -241,6 +250,7 @@ impl REPL {
             server,
             vm,
             cursor,
+            lines: vec![],
         }
     }
 
@@ -260,6 +270,22 @@ impl REPL {
                 Ok(line) => line,
                 Err(_) => break,
             };
+
+            if line.is_empty() {
+                let mut words = addition.splitn(2, ' ');
+                let result = match (words.next(), words.next().map(str::trim)) {
+                    (Some(":save"), Some(path)) => Some(self.save(path, n)),
+                    (Some(":load"), Some(path)) => Some(self.load(path).map(|next| n = next)),
+                    _ => None,
+                };
+                if let Some(result) = result {
+                    self.editor.add_history_entry(&addition);
+                    if let Err(e) = result {
+                        eprintln!("{}", e.to_string().red());
+                    }
+                    continue;
+                }
+            }
             line.push_str(addition.as_str());
 
             let mut server = self.server.lock().unwrap();
@@ -319,6 +345,7 @@ impl REPL {
                     println!("{:?}", err)
                 }
                 Ok(_) => {
+                    self.lines.push((n - 1, line));
                     if is_expression {
                         if let Some(o) = self
                             .vm
@@ -330,10 +357,76 @@ impl REPL {
                         self.vm
                             .eval::<ServerRuntime>(assembly.compile(&mut self.cursor));
                     }
+                    if let Some(code) = self.vm.exit_code() {
+                        std::process::exit(code);
+                    }
                 }
             }
         }
     }
+
+    /// Saves an image of the VM, followed by the lines evaluated so far and
+    /// the labels they were compiled with, so that the session can be loaded
+    /// into another REPL started in the same project.
+    fn save(&self, path: &str, next_line: usize) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.vm.image().write(&mut file)?;
+
+        let mut e = PooledEncoder::new();
+        e.varint(next_line as u64)?;
+        e.varint(self.lines.len() as u64)?;
+        for (n, line) in self.lines.iter() {
+            e.varint(*n as u64)?;
+            e.string(line)?;
+        }
+        e.varint(self.cursor.end)?;
+        e.varint(self.cursor.labels.len() as u64)?;
+        for (label, offset) in self.cursor.labels.iter() {
+            e.string(label)?;
+            e.varint(*offset)?;
+        }
+        e.finish(&mut file)?;
+        file.flush()
+    }
+
+    /// Resumes a session saved with `save`, in place of the current one, and
+    /// returns the number of the next line. The lines of the session are
+    /// analysed again, so that the bindings and declarations they made can be
+    /// referred to, but they're not evaluated again.
+    fn load(&mut self, path: &str) -> io::Result<usize> {
+        let mut r = BufReader::new(File::open(path)?);
+        let image = Image::read(&mut r)?;
+        let pool = ConstantPool::deserialize(&mut r)?;
+        let mut d = PooledDecoder::new(r, &pool);
+
+        let next_line = d.varint()? as usize;
+        let mut lines = vec![];
+        for _ in 0..d.varint()? {
+            lines.push((d.varint()? as usize, d.string()?));
+        }
+        let mut cursor = Cursor::new();
+        cursor.end = d.varint()?;
+        for _ in 0..d.varint()? {
+            let label = d.string()?;
+            cursor.labels.insert(label, d.varint()?);
+        }
+
+        let mut vm = VM::from_image(image)?;
+        vm.set_limits(self.vm.limits().clone());
+
+        let mut server = self.server.lock().unwrap();
+        for (n, _) in self.lines.iter() {
+            server.remove(URI::REPLLine(*n));
+        }
+        for (n, line) in lines.iter() {
+            server.set(URI::REPLLine(*n), line.clone(), SourceKind::REPLLine);
+        }
+
+        self.vm = vm;
+        self.cursor = cursor;
+        self.lines = lines;
+        Ok(next_line)
+    }
 }
 
 struct REPLDirectivesImpl<'a> {
@@ -1,56 +1,104 @@
 extern crate atty;
 use colored::*;
-use loa::vm::{CallStack, Runtime, SourceCodeLocation, StackFrame};
+use loa::bytecode::SourceLocation;
+use loa::vm::{
+    CallStack, Capabilities, Runtime, SourceCodeLocation, StackFrame, TraceOptions, Tracer, VM,
+};
+use std::process::exit;
 
 pub struct ServerRuntime;
 
 impl Runtime for ServerRuntime {
+    fn capabilities() -> Capabilities {
+        Capabilities::all()
+    }
+
     fn print_panic(message: String, call_stack: CallStack) {
-        let call_stack: Vec<_> = call_stack.into();
         if atty::is(atty::Stream::Stdout) {
             eprint!("{} ", " PANIC ".bold().white().on_red());
             eprintln!("{}", message.red());
-            for frame in call_stack {
-                let StackFrame {
-                    receiver,
-                    method,
-                    callsite: SourceCodeLocation(uri, line, character),
-                    ..
-                } = frame.as_ref();
-                eprintln!(
-                    "{} {}\n  {}",
-                    receiver
-                        .class
-                        .as_ref()
-                        .map(|c| c.name.as_ref())
-                        .unwrap_or("?")
-                        .yellow(),
-                    method.name.yellow(),
-                    format!("({}:{}:{})", uri, line, character).bright_black(),
-                );
+        } else {
+            eprintln!("PANIC: {}", message);
+        }
+        print_call_stack(call_stack);
+    }
+
+    fn print_panic_at(message: String, call_stack: CallStack, location: SourceLocation) {
+        if atty::is(atty::Stream::Stdout) {
+            eprint!("{} ", " PANIC ".bold().white().on_red());
+            eprintln!("{}", message.red());
+            eprintln!("  {} {}", "-->".bright_black(), location);
+            if let Some(excerpt) = location.excerpt() {
+                eprintln!("{}", excerpt.bright_black());
             }
         } else {
             eprintln!("PANIC: {}", message);
-            for frame in call_stack {
-                let StackFrame {
-                    receiver,
-                    method,
-                    callsite: SourceCodeLocation(uri, line, character),
-                    ..
-                } = frame.as_ref();
-                eprintln!(
-                    "{} {}\n  ({}:{}:{})",
-                    receiver
-                        .class
-                        .as_ref()
-                        .map(|c| c.name.as_ref())
-                        .unwrap_or("?"),
-                    method.name,
-                    uri,
-                    line,
-                    character
-                );
+            eprintln!("  --> {}", location);
+            if let Some(excerpt) = location.excerpt() {
+                eprintln!("{}", excerpt);
+            }
+        }
+        print_call_stack(call_stack);
+    }
+}
+
+fn print_call_stack(call_stack: CallStack) {
+    let call_stack: Vec<_> = call_stack.into();
+    let colored = atty::is(atty::Stream::Stdout);
+    for frame in call_stack {
+        let StackFrame {
+            receiver,
+            method,
+            callsite: SourceCodeLocation(uri, line, character),
+            ..
+        } = frame.as_ref();
+        let class = receiver
+            .class
+            .as_ref()
+            .map(|c| c.name.as_ref())
+            .unwrap_or("?");
+        if colored {
+            eprintln!(
+                "{} {}\n  {}",
+                class.yellow(),
+                method.name.yellow(),
+                format!("({}:{}:{})", uri, line, character).bright_black(),
+            );
+        } else {
+            eprintln!(
+                "{} {}\n  ({}:{}:{})",
+                class, method.name, uri, line, character
+            );
+        }
+    }
+}
+
+/// Starts tracing the VM, if tracing has been turned on through the
+/// `LOA_TRACE` environment variable.
+pub fn trace_from_env(vm: &mut VM) {
+    match TraceOptions::from_env() {
+        None => {}
+        Some(Ok(options)) => match Tracer::create(options.clone()) {
+            Ok(tracer) => vm.start_tracing(tracer),
+            Err(e) => {
+                eprintln!("{}: {}", options.file.display(), e);
+                exit(1);
             }
+        },
+        Some(Err(e)) => {
+            eprintln!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Writes out whatever is left of the trace, if the VM is being traced.
+/// This has to happen before exiting, which skips the buffers' destructors.
+pub fn finish_tracing(vm: &mut VM) {
+    if let Some(tracer) = vm.stop_tracing() {
+        let file = tracer.options().file.clone();
+        if let Err(e) = tracer.finish() {
+            eprintln!("{}: {}", file.display(), e);
         }
     }
 }
@@ -1,4 +1,4 @@
-use crate::bytecode::Instruction as BytecodeInstruction;
+use crate::bytecode::{Instruction as BytecodeInstruction, SourceMap};
 use crate::vm::NativeMethod;
 use crate::HashMap;
 use crate::*;
@@ -57,6 +57,38 @@ impl Assembly {
         self
     }
 
+    /// Appends the sections of another assembly, keeping each kind of
+    /// section together, so that all methods and classes are declared
+    /// before any of the main sections run.
+    pub fn append(&mut self, other: Assembly) {
+        self.method_declaration_sections
+            .extend(other.method_declaration_sections);
+        self.class_declaration_sections
+            .extend(other.class_declaration_sections);
+        self.main_sections.extend(other.main_sections);
+        self.sections.extend(other.sections);
+    }
+
+    /// The sections of each kind, in the order they're laid out in.
+    pub(crate) fn groups(&self) -> Vec<&Vec<Section>> {
+        vec![
+            &self.method_declaration_sections,
+            &self.class_declaration_sections,
+            &self.main_sections,
+            &self.sections,
+        ]
+    }
+
+    pub(crate) fn group_mut(&mut self, group: usize) -> Option<&mut Vec<Section>> {
+        match group {
+            0 => Some(&mut self.method_declaration_sections),
+            1 => Some(&mut self.class_declaration_sections),
+            2 => Some(&mut self.main_sections),
+            3 => Some(&mut self.sections),
+            _ => None,
+        }
+    }
+
     pub fn last_main_section_mut(&mut self) -> &mut Section {
         if self.main_sections.is_empty() {
             self.main_sections.push(Section::unnamed());
@@ -112,6 +144,41 @@ impl Assembly {
         }
         instructions
     }
+
+    /// Maps the offsets the instructions will have once compiled back to the
+    /// spans they were generated from. Spans in files that aren't among
+    /// `sources` are left out, and the code of the files is only embedded in
+    /// the map if `embed_code` is set.
+    pub fn source_map(&self, sources: &[Arc<Source>], embed_code: bool) -> SourceMap {
+        let sources: HashMap<_, _> = sources.iter().map(|s| (&s.uri, s)).collect();
+        let mut files = HashMap::new();
+        let mut source_map = SourceMap::new();
+        let mut offset = 0;
+        for section in self.iter() {
+            for instruction in section.instructions.iter() {
+                if let Some(ref span) = instruction.source {
+                    if let Some(source) = sources.get(&span.start.uri) {
+                        let file = *files.entry(&source.uri).or_insert_with(|| {
+                            let code = if embed_code {
+                                Some(source.code.clone())
+                            } else {
+                                None
+                            };
+                            source_map.add_file(source.uri.to_relative_string(), code)
+                        });
+                        source_map.map(
+                            offset,
+                            file,
+                            (span.start.line as u64, span.start.character as u64),
+                            (span.end.line as u64, span.end.character as u64),
+                        );
+                    }
+                }
+                offset += 1;
+            }
+        }
+        source_map
+    }
 }
 
 impl PartialEq for Assembly {
@@ -225,6 +292,9 @@ impl PartialEq for Section {
 pub struct Instruction {
     pub leading_comment: Option<String>,
     pub kind: InstructionKind,
+
+    /// The span of the code this instruction was generated from, if any.
+    pub source: Option<Span>,
 }
 
 impl fmt::Debug for Instruction {
@@ -251,14 +321,22 @@ impl fmt::Debug for Instruction {
             CallMethod(ref label, ref uri, line, character) => {
                 write!(f, "CallMethod @{} {:?} {} {}", label, uri, line, character)
             }
+            TailCallMethod(ref label, ref uri, line, character) => {
+                write!(
+                    f,
+                    "TailCallMethod @{} {:?} {} {}",
+                    label, uri, line, character
+                )
+            }
             CallNative(ref native_method) => write!(f, "CallNative {}", native_method),
             LoadLocal(index) => write!(f, "LoadLocal {}", index),
             DropLocal(index) => write!(f, "DropLocal {}", index),
-            StoreGlobal(ref label) => write!(f, "StoreGlobal {}", label),
-            LoadGlobal(ref label) => write!(f, "LoadGlobal {}", label),
+            StoreGlobal(ref label) => write!(f, "StoreGlobal @{}", label),
+            LoadGlobal(ref label) => write!(f, "LoadGlobal @{}", label),
             LoadLazy(arity, ref label) => write!(f, "LoadLazy {} @{}", arity, label),
             Return(arity) => write!(f, "Return {}", arity),
             ReturnLazy(arity) => write!(f, "ReturnLazy {}", arity),
+            Rescue(arity, ref label) => write!(f, "Rescue {} @{}", arity, label),
 
             MarkClassTrue(ref label) => write!(f, "MarkClassTrue @{}", label),
             MarkClassFalse(ref label) => write!(f, "MarkClassFalse @{}", label),
@@ -283,8 +361,21 @@ impl fmt::Debug for Instruction {
             MarkClassFBig(ref label) => write!(f, "MarkClassFBig @{}", label),
 
             LoadConstString(ref value) => write!(f, "LoadConstString {:?}", value),
-            LoadConstCharacter(ref value) => write!(f, "LoadConstCharacter {:?}", value),
-            LoadConstSymbol(ref value) => write!(f, "LoadConstSymbol #'{}'", value),
+            LoadConstCharacter(value) => match std::char::from_u32(value as u32) {
+                Some(c) => write!(f, "LoadConstCharacter {:?}", c),
+                // Halves of surrogate pairs aren't chars on their own.
+                None => write!(f, "LoadConstCharacter '\\u{{{:x}}}'", value),
+            },
+            // Symbols that can't be told apart from what follows them are
+            // quoted, like strings.
+            LoadConstSymbol(ref value)
+                if value.is_empty()
+                    || value.starts_with('"')
+                    || value.chars().any(char::is_whitespace) =>
+            {
+                write!(f, "LoadConstSymbol #{:?}", value)
+            }
+            LoadConstSymbol(ref value) => write!(f, "LoadConstSymbol #{}", value),
             LoadConstU8(ref value) => write!(f, "LoadConstU8 {}", value),
             LoadConstU16(ref value) => write!(f, "LoadConstU16 {}", value),
             LoadConstU32(ref value) => write!(f, "LoadConstU32 {}", value),
@@ -320,6 +411,7 @@ pub enum InstructionKind {
     OverrideMethod(Label, Label),
     LoadObject(Label),
     CallMethod(Label, String, u64, u64),
+    TailCallMethod(Label, String, u64, u64),
     CallNative(NativeMethod),
     LoadLocal(u16),
     DropLocal(u16),
@@ -328,6 +420,7 @@ pub enum InstructionKind {
     LoadLazy(u16, Label),
     Return(u16),
     ReturnLazy(u16),
+    Rescue(u16, Label),
 
     MarkClassTrue(Label),
     MarkClassFalse(Label),
@@ -371,11 +464,54 @@ pub enum InstructionKind {
     LoadConstFBig(BigFraction),
 }
 
+impl InstructionKind {
+    /// The labels the instruction refers to.
+    pub fn labels(&self) -> Vec<&Label> {
+        use InstructionKind::*;
+        match self {
+            DeclareVariable(_, vl, gl, sl) => vec![vl, gl, sl],
+            OverrideMethod(s, t) => vec![s, t],
+            UseVariable(l)
+            | DeclareMethod(_, l)
+            | UseMethod(l)
+            | LoadObject(l)
+            | CallMethod(l, _, _, _)
+            | TailCallMethod(l, _, _, _)
+            | StoreGlobal(l)
+            | LoadGlobal(l)
+            | LoadLazy(_, l)
+            | Rescue(_, l)
+            | MarkClassTrue(l)
+            | MarkClassFalse(l)
+            | MarkClassString(l)
+            | MarkClassCharacter(l)
+            | MarkClassSymbol(l)
+            | MarkClassU8(l)
+            | MarkClassU16(l)
+            | MarkClassU32(l)
+            | MarkClassU64(l)
+            | MarkClassU128(l)
+            | MarkClassUBig(l)
+            | MarkClassI8(l)
+            | MarkClassI16(l)
+            | MarkClassI32(l)
+            | MarkClassI64(l)
+            | MarkClassI128(l)
+            | MarkClassIBig(l)
+            | MarkClassF32(l)
+            | MarkClassF64(l)
+            | MarkClassFBig(l) => vec![l],
+            _ => vec![],
+        }
+    }
+}
+
 impl Instruction {
     pub fn commented(comment: String, kind: InstructionKind) -> Instruction {
         Instruction {
             leading_comment: Some(comment),
             kind,
+            source: None,
         }
     }
 
@@ -383,6 +519,7 @@ impl Instruction {
         Instruction {
             leading_comment: None,
             kind,
+            source: None,
         }
     }
 
@@ -426,6 +563,14 @@ impl Instruction {
             InstructionKind::CallMethod(ref l, ref uri, line, character) => {
                 BytecodeInstruction::CallMethod(label!(l, "method"), uri.clone(), line, character)
             }
+            InstructionKind::TailCallMethod(ref l, ref uri, line, character) => {
+                BytecodeInstruction::TailCallMethod(
+                    label!(l, "method"),
+                    uri.clone(),
+                    line,
+                    character,
+                )
+            }
             InstructionKind::CallNative(ref m) => BytecodeInstruction::CallNative(m.clone()),
             InstructionKind::LoadLocal(i) => BytecodeInstruction::LoadLocal(i),
             InstructionKind::DropLocal(i) => BytecodeInstruction::DropLocal(i),
@@ -440,6 +585,9 @@ impl Instruction {
             }
             InstructionKind::Return(a) => BytecodeInstruction::Return(a),
             InstructionKind::ReturnLazy(a) => BytecodeInstruction::ReturnLazy(a),
+            InstructionKind::Rescue(a, ref l) => {
+                BytecodeInstruction::Rescue(a, label!(l, "rescue clause"))
+            }
 
             InstructionKind::MarkClassTrue(ref l) => {
                 BytecodeInstruction::MarkClassTrue(label!(l, "class"))
@@ -0,0 +1,359 @@
+use crate::assembly::*;
+use crate::bytecode::{Instruction as BytecodeInstruction, SourceMap};
+use crate::*;
+
+/// Turns compiled bytecode back into assembly.
+///
+/// Every address an instruction refers to gets a synthesized label, like
+/// `@L42` for the instruction at offset 42, and the program is split into
+/// sections at those labels. Compiling the result yields the exact same
+/// instructions again.
+pub struct Disassembler<'a> {
+    source_map: Option<&'a SourceMap>,
+}
+
+#[derive(Debug)]
+pub enum DisassemblyError {
+    /// The instruction at the offset refers to an address outside of the
+    /// program.
+    AddressOutOfRange { offset: usize, address: u64 },
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new() -> Disassembler<'a> {
+        Disassembler { source_map: None }
+    }
+
+    /// Annotates the instructions with the source locations they were
+    /// generated from, as comments.
+    pub fn with_source_map(mut self, source_map: &'a SourceMap) -> Disassembler<'a> {
+        self.source_map = Some(source_map);
+        self
+    }
+
+    pub fn disassemble(
+        &self,
+        instructions: &[BytecodeInstruction],
+    ) -> Result<Assembly, DisassemblyError> {
+        let mut targets = vec![];
+        for (offset, instruction) in instructions.iter().enumerate() {
+            for address in addresses(instruction) {
+                if address > instructions.len() as u64 {
+                    return Err(DisassemblyError::AddressOutOfRange { offset, address });
+                }
+                targets.push(address);
+            }
+        }
+        targets.sort();
+        targets.dedup();
+
+        let mut assembly = Assembly::new();
+        let mut section = Section::unnamed();
+        let mut targets = targets.into_iter().peekable();
+        let mut last_location = None;
+        for offset in 0..=instructions.len() {
+            if targets.peek() == Some(&(offset as u64)) {
+                targets.next();
+                if offset > 0 || section.label.is_some() {
+                    assembly.add_section(section);
+                }
+                section = Section::named(label(offset as u64));
+            }
+
+            let instruction = match instructions.get(offset) {
+                Some(instruction) => instruction,
+                None => break,
+            };
+
+            let location = self
+                .source_map
+                .and_then(|map| map.lookup(offset))
+                .map(|l| l.to_string());
+            let comment = if location.is_some() && location != last_location {
+                location.clone().map(|l| format!(" {}", l))
+            } else {
+                None
+            };
+            last_location = location;
+
+            section.instructions.push(Instruction {
+                leading_comment: comment,
+                kind: kind(instruction, label),
+                source: None,
+            });
+        }
+        if !section.is_empty() || section.label.is_some() {
+            assembly.add_section(section);
+        }
+        Ok(assembly)
+    }
+}
+
+impl fmt::Display for DisassemblyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DisassemblyError::AddressOutOfRange { offset, address } => write!(
+                f,
+                "instruction at offset {} refers to address {}, which is outside of the program",
+                offset, address
+            ),
+        }
+    }
+}
+
+impl Error for DisassemblyError {}
+
+fn label(address: u64) -> Label {
+    format!("L{}", address)
+}
+
+pub(crate) fn addresses(instruction: &BytecodeInstruction) -> Vec<u64> {
+    use BytecodeInstruction::*;
+    match *instruction {
+        DeclareVariable(_, vl, gl, sl) => vec![vl, gl, sl],
+        OverrideMethod(s, t) => vec![s, t],
+        UseVariable(a)
+        | DeclareMethod(_, a)
+        | UseMethod(a)
+        | LoadObject(a)
+        | CallMethod(a, _, _, _)
+        | TailCallMethod(a, _, _, _)
+        | StoreGlobal(a)
+        | LoadGlobal(a)
+        | LoadLazy(_, a)
+        | Rescue(_, a)
+        | MarkClassTrue(a)
+        | MarkClassFalse(a)
+        | MarkClassString(a)
+        | MarkClassCharacter(a)
+        | MarkClassSymbol(a)
+        | MarkClassU8(a)
+        | MarkClassU16(a)
+        | MarkClassU32(a)
+        | MarkClassU64(a)
+        | MarkClassU128(a)
+        | MarkClassUBig(a)
+        | MarkClassI8(a)
+        | MarkClassI16(a)
+        | MarkClassI32(a)
+        | MarkClassI64(a)
+        | MarkClassI128(a)
+        | MarkClassIBig(a)
+        | MarkClassF32(a)
+        | MarkClassF64(a)
+        | MarkClassFBig(a) => vec![a],
+        _ => vec![],
+    }
+}
+
+/// The assembly instruction for a bytecode instruction, with the addresses it
+/// refers to replaced by labels.
+pub(crate) fn kind<F: Fn(u64) -> Label>(
+    instruction: &BytecodeInstruction,
+    label: F,
+) -> InstructionKind {
+    use BytecodeInstruction as B;
+    use InstructionKind as K;
+    match *instruction {
+        B::Noop => K::Noop,
+        B::Halt => K::Halt,
+        B::Panic => K::Panic,
+        B::DumpStack => K::DumpStack,
+        B::DeclareClass(ref name) => K::DeclareClass(name.clone()),
+        B::DeclareVariable(ref name, vl, gl, sl) => {
+            K::DeclareVariable(name.clone(), label(vl), label(gl), label(sl))
+        }
+        B::UseVariable(a) => K::UseVariable(label(a)),
+        B::DeclareMethod(ref selector, a) => K::DeclareMethod(selector.clone(), label(a)),
+        B::UseMethod(a) => K::UseMethod(label(a)),
+        B::OverrideMethod(s, t) => K::OverrideMethod(label(s), label(t)),
+        B::LoadObject(a) => K::LoadObject(label(a)),
+        B::CallMethod(a, ref uri, line, character) => {
+            K::CallMethod(label(a), uri.clone(), line, character)
+        }
+        B::TailCallMethod(a, ref uri, line, character) => {
+            K::TailCallMethod(label(a), uri.clone(), line, character)
+        }
+        B::CallNative(ref method) => K::CallNative(method.clone()),
+        B::LoadLocal(i) => K::LoadLocal(i),
+        B::DropLocal(i) => K::DropLocal(i),
+        B::StoreGlobal(a) => K::StoreGlobal(label(a)),
+        B::LoadGlobal(a) => K::LoadGlobal(label(a)),
+        B::LoadLazy(arity, a) => K::LoadLazy(arity, label(a)),
+        B::Return(arity) => K::Return(arity),
+        B::ReturnLazy(arity) => K::ReturnLazy(arity),
+        B::Rescue(arity, a) => K::Rescue(arity, label(a)),
+
+        B::MarkClassTrue(a) => K::MarkClassTrue(label(a)),
+        B::MarkClassFalse(a) => K::MarkClassFalse(label(a)),
+
+        B::MarkClassString(a) => K::MarkClassString(label(a)),
+        B::MarkClassCharacter(a) => K::MarkClassCharacter(label(a)),
+        B::MarkClassSymbol(a) => K::MarkClassSymbol(label(a)),
+        B::MarkClassU8(a) => K::MarkClassU8(label(a)),
+        B::MarkClassU16(a) => K::MarkClassU16(label(a)),
+        B::MarkClassU32(a) => K::MarkClassU32(label(a)),
+        B::MarkClassU64(a) => K::MarkClassU64(label(a)),
+        B::MarkClassU128(a) => K::MarkClassU128(label(a)),
+        B::MarkClassUBig(a) => K::MarkClassUBig(label(a)),
+        B::MarkClassI8(a) => K::MarkClassI8(label(a)),
+        B::MarkClassI16(a) => K::MarkClassI16(label(a)),
+        B::MarkClassI32(a) => K::MarkClassI32(label(a)),
+        B::MarkClassI64(a) => K::MarkClassI64(label(a)),
+        B::MarkClassI128(a) => K::MarkClassI128(label(a)),
+        B::MarkClassIBig(a) => K::MarkClassIBig(label(a)),
+        B::MarkClassF32(a) => K::MarkClassF32(label(a)),
+        B::MarkClassF64(a) => K::MarkClassF64(label(a)),
+        B::MarkClassFBig(a) => K::MarkClassFBig(label(a)),
+
+        B::LoadConstString(ref v) => K::LoadConstString(v.clone()),
+        B::LoadConstCharacter(v) => K::LoadConstCharacter(v),
+        B::LoadConstSymbol(ref v) => K::LoadConstSymbol(v.clone()),
+        B::LoadConstU8(v) => K::LoadConstU8(v),
+        B::LoadConstU16(v) => K::LoadConstU16(v),
+        B::LoadConstU32(v) => K::LoadConstU32(v),
+        B::LoadConstU64(v) => K::LoadConstU64(v),
+        B::LoadConstU128(v) => K::LoadConstU128(v),
+        B::LoadConstUBig(ref v) => K::LoadConstUBig(v.clone()),
+        B::LoadConstI8(v) => K::LoadConstI8(v),
+        B::LoadConstI16(v) => K::LoadConstI16(v),
+        B::LoadConstI32(v) => K::LoadConstI32(v),
+        B::LoadConstI64(v) => K::LoadConstI64(v),
+        B::LoadConstI128(v) => K::LoadConstI128(v),
+        B::LoadConstIBig(ref v) => K::LoadConstIBig(v.clone()),
+        B::LoadConstF32(v) => K::LoadConstF32(v),
+        B::LoadConstF64(v) => K::LoadConstF64(v),
+        B::LoadConstFBig(ref v) => K::LoadConstFBig(v.clone()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::BytecodeEncoding;
+
+    fn serialize(instructions: &[BytecodeInstruction]) -> Vec<u8> {
+        let mut bytes = vec![];
+        instructions.to_vec().serialize(&mut bytes).unwrap();
+        bytes
+    }
+
+    fn assert_round_trips(code: &str) {
+        let instructions: Vec<BytecodeInstruction> = Parser::new().parse(code).unwrap().into();
+
+        let assembly = Disassembler::new().disassemble(&instructions).unwrap();
+        let text = format!("{:?}", assembly);
+        let reassembled: Vec<BytecodeInstruction> = Parser::new()
+            .parse(text.as_str())
+            .unwrap_or_else(|e| panic!("{:?} in:\n{}", e, text))
+            .into();
+
+        assert_eq!(
+            serialize(&reassembled),
+            serialize(&instructions),
+            "{}",
+            text
+        );
+    }
+
+    #[test]
+    fn synthesizes_labels() {
+        let instructions: Vec<BytecodeInstruction> = Parser::new()
+            .parse(
+                r#"
+                DeclareClass "A"
+                DeclareMethod "a" @A#a
+                LoadObject @A
+                CallMethod @A#a "test:" 1 1
+                Halt
+
+                @A
+                  DeclareClass "A"
+                  UseMethod @A#a
+
+                @A#a
+                  LoadConstU8 1
+                  Return 0
+                "#,
+            )
+            .unwrap()
+            .into();
+
+        let assembly = Disassembler::new().disassemble(&instructions).unwrap();
+        assert_eq!(
+            format!("{:?}", assembly),
+            r#"DeclareClass "A"
+DeclareMethod "a" @L7
+LoadObject @L5
+CallMethod @L7 "test:" 1 1
+Halt
+
+@L5
+  DeclareClass "A"
+  UseMethod @L7
+
+@L7
+  LoadConstU8 1
+  Return 0
+"#
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        assert_round_trips(
+            r#"
+            @Main
+              DeclareVariable "x" @x @x_get @x_set
+              UseVariable @x
+              LoadLazy 0 @lazy
+              StoreGlobal @global
+              LoadGlobal @global
+              OverrideMethod @Main @lazy
+              MarkClassTrue @Main
+              MarkClassFBig @Main
+              TailCallMethod @lazy "file.loa" 2 3
+              CallNative Loa/String#concat:
+              LoadLocal 1
+              DropLocal 1
+              ReturnLazy 0
+            @x
+              Noop
+            @x_get
+              Noop
+            @x_set
+              Noop
+            @global
+              Noop
+            @lazy
+              LoadConstString "a \"quoted\"\n\\ string"
+              LoadConstCharacter 'x'
+              LoadConstCharacter '\''
+              LoadConstCharacter '\n'
+              LoadConstCharacter '\u{d83d}'
+              LoadConstSymbol #hello:world:
+              LoadConstSymbol #"hello world"
+              LoadConstUBig 340282366920938463463374607431768211456
+              LoadConstIBig -340282366920938463463374607431768211456
+              LoadConstI8 -4
+              LoadConstF32 0.1
+              LoadConstF64 -2.5e-12
+              LoadConstFBig -1/3
+              LoadConstFBig NaN
+              Return 0
+            @end
+            "#,
+        );
+    }
+
+    #[test]
+    fn addresses_out_of_range() {
+        assert_matches!(
+            Disassembler::new().disassemble(&[BytecodeInstruction::LoadObject(2)]),
+            Err(DisassemblyError::AddressOutOfRange {
+                offset: 0,
+                address: 2
+            })
+        );
+    }
+}
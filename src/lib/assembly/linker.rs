@@ -0,0 +1,189 @@
+use crate::assembly::*;
+use crate::*;
+
+/// Combines objects that were compiled separately into a single program.
+///
+/// The objects are laid out in the order they were added in, so packages
+/// should be added after the packages they depend on. Each kind of section
+/// is kept together, so that the methods and classes of every package are
+/// declared before the main sections of any package run.
+pub struct Linker {
+    objects: Vec<Object>,
+}
+
+#[derive(Debug)]
+pub enum LinkError {
+    /// Two objects declare the same label.
+    DuplicateSymbol {
+        symbol: Label,
+        first: String,
+        second: String,
+    },
+
+    /// An object refers to a label that none of the objects declare.
+    UndefinedSymbol { symbol: Label, object: String },
+}
+
+impl Linker {
+    pub fn new() -> Linker {
+        Linker { objects: vec![] }
+    }
+
+    pub fn add(&mut self, object: Object) {
+        self.objects.push(object);
+    }
+
+    pub fn with_object(mut self, object: Object) -> Linker {
+        self.add(object);
+        self
+    }
+
+    /// Resolves the references between the objects, and returns the linked
+    /// assembly, which halts after the main sections, along with the sources
+    /// of all objects to build its source map from.
+    pub fn link(self) -> Result<(Assembly, Vec<Arc<Source>>), LinkError> {
+        let mut symbols = HashMap::new();
+        for (index, object) in self.objects.iter().enumerate() {
+            for symbol in object.exports() {
+                match symbols.insert(symbol, index) {
+                    Some(first) if first != index => {
+                        return Err(LinkError::DuplicateSymbol {
+                            symbol: symbol.clone(),
+                            first: self.objects[first].name.clone(),
+                            second: object.name.clone(),
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for object in self.objects.iter() {
+            if let Some(symbol) = object
+                .imports()
+                .into_iter()
+                .find(|symbol| !symbols.contains_key(symbol))
+            {
+                return Err(LinkError::UndefinedSymbol {
+                    symbol: symbol.clone(),
+                    object: object.name.clone(),
+                });
+            }
+        }
+
+        let mut assembly = Assembly::new();
+        let mut sources = vec![];
+        for object in self.objects {
+            assembly.append(object.assembly);
+            sources.extend(object.sources);
+        }
+        assembly
+            .last_main_section_mut()
+            .add_instruction(InstructionKind::Halt);
+
+        Ok((assembly, sources))
+    }
+}
+
+impl fmt::Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol {
+                symbol,
+                first,
+                second,
+            } => write!(f, "{} is declared by both {} and {}", symbol, first, second),
+            LinkError::UndefinedSymbol { symbol, object } => write!(
+                f,
+                "{} refers to {}, which none of the linked packages declare",
+                object, symbol
+            ),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Instruction as BytecodeInstruction;
+
+    fn object(name: &str, code: &str) -> Object {
+        let mut assembly = Assembly::new();
+        for section in Parser::new().parse(code).unwrap().into_iter() {
+            match section.label.as_ref().map(|l| l.ends_with("$class")) {
+                Some(true) => assembly.add_class_declaration_section(section),
+                Some(false) => assembly.add_section(section),
+                None => assembly.add_main_section(section),
+            }
+        }
+        Object::new(name, assembly, vec![])
+    }
+
+    fn library() -> Object {
+        object(
+            "Lib",
+            r#"
+            @Lib/A$class
+              DeclareClass "Lib/A"
+
+            @Lib/A#a
+              LoadConstString "a"
+              Return 0
+            "#,
+        )
+    }
+
+    #[test]
+    fn resolves_references_across_objects() {
+        let main = object(
+            "Main",
+            r#"
+            LoadObject @Lib/A$class
+            CallMethod @Lib/A#a "Main.loa" 1 1
+
+            @Main$lazy1
+              ReturnLazy 0
+            "#,
+        );
+
+        let (assembly, _) = Linker::new()
+            .with_object(library())
+            .with_object(main)
+            .link()
+            .unwrap();
+        let instructions: Vec<BytecodeInstruction> = assembly.into();
+
+        assert_eq!(
+            format!("{:?}", instructions),
+            concat!(
+                "[DeclareClass(\"Lib/A\"), ",
+                "LoadObject(0), ",
+                "CallMethod(4, \"Main.loa\", 1, 1), ",
+                "Halt, ",
+                "LoadConstString(\"a\"), ",
+                "Return(0), ",
+                "ReturnLazy(0)]"
+            )
+        );
+    }
+
+    #[test]
+    fn undefined_symbols() {
+        let main = object("Main", "LoadObject @Lib/B$class");
+
+        assert_matches!(
+            Linker::new().with_object(library()).with_object(main).link(),
+            Err(LinkError::UndefinedSymbol { ref symbol, ref object })
+                if symbol == "Lib/B$class" && object == "Main"
+        );
+    }
+
+    #[test]
+    fn duplicate_symbols() {
+        assert_matches!(
+            Linker::new().with_object(library()).with_object(library()).link(),
+            Err(LinkError::DuplicateSymbol { ref symbol, .. }) if symbol == "Lib/A$class"
+        );
+    }
+}
@@ -3,3 +3,12 @@ pub use self::assembly::*;
 
 mod parser;
 pub use self::parser::*;
+
+mod disassembler;
+pub use self::disassembler::*;
+
+mod object;
+pub use self::object::*;
+
+mod linker;
+pub use self::linker::*;
@@ -0,0 +1,412 @@
+use crate::assembly::*;
+use crate::bytecode::{
+    crc32, BytecodeEncoding, BytecodeEncodingRead, ConstantPool, PooledDecoder, PooledEncoder,
+    COMPILER_VERSION,
+};
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// Identifies a file as a compiled Loa package.
+pub const OBJECT_MAGIC: [u8; 4] = *b"\x7fLOO";
+
+/// The version of the object format. Objects with any other version are
+/// rejected, and have to be compiled again from their sources.
+pub const OBJECT_FORMAT_VERSION: u16 = 2;
+
+/// A package compiled on its own, like the standard library or a dependency,
+/// as stored in a `.loaobj` file.
+///
+/// The object holds the assembly generated from the package, so its
+/// sections keep their labels. Those are the symbols the package exports,
+/// and references to labels declared by other packages are left unresolved
+/// until the objects are combined by a `Linker`. The code of the sources is
+/// embedded, so that a linked program can be source mapped without them.
+#[derive(Clone)]
+pub struct Object {
+    pub name: String,
+    pub compiler_version: String,
+
+    /// A checksum of the sources the object was compiled from, to tell
+    /// whether it has to be compiled again.
+    pub checksum: u32,
+
+    pub assembly: Assembly,
+    pub sources: Vec<Arc<Source>>,
+}
+
+#[derive(Debug)]
+pub enum ObjectError {
+    Io(io::Error),
+    NotAnObject,
+    UnsupportedFormat(u16, String),
+    Invalid(io::Error),
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ObjectError::Io(e) => write!(f, "{}", e),
+            ObjectError::NotAnObject => write!(f, "not a compiled Loa package"),
+            ObjectError::UnsupportedFormat(version, compiler_version) => write!(
+                f,
+                "the object was built by loa {} using format version {}, but this compiler (loa {}) only supports format version {}",
+                compiler_version, version, COMPILER_VERSION, OBJECT_FORMAT_VERSION
+            ),
+            ObjectError::Invalid(e) => write!(f, "the object is invalid: {}", e),
+        }
+    }
+}
+
+impl Error for ObjectError {}
+
+impl From<io::Error> for ObjectError {
+    fn from(e: io::Error) -> ObjectError {
+        ObjectError::Io(e)
+    }
+}
+
+impl Object {
+    pub fn new<S: Into<String>>(name: S, assembly: Assembly, sources: Vec<Arc<Source>>) -> Object {
+        Object {
+            name: name.into(),
+            compiler_version: COMPILER_VERSION.into(),
+            checksum: Object::checksum(&sources),
+            assembly,
+            sources,
+        }
+    }
+
+    /// A checksum of the URIs and code of the sources, regardless of their
+    /// order.
+    pub fn checksum(sources: &[Arc<Source>]) -> u32 {
+        let mut files: Vec<_> = sources
+            .iter()
+            .map(|s| (s.uri.to_relative_string(), s.code.as_str()))
+            .collect();
+        files.sort();
+
+        let mut bytes = vec![];
+        for (uri, code) in files {
+            bytes.extend(uri.as_bytes());
+            bytes.push(0);
+            bytes.extend(code.as_bytes());
+            bytes.push(0);
+        }
+        crc32(&bytes)
+    }
+
+    /// Whether the object was compiled from these sources by this version of
+    /// the compiler, so that it doesn't have to be compiled again.
+    pub fn is_fresh(&self, sources: &[Arc<Source>]) -> bool {
+        self.compiler_version == COMPILER_VERSION && self.checksum == Object::checksum(sources)
+    }
+
+    /// The labels of the sections in the object.
+    pub fn exports(&self) -> Vec<&Label> {
+        self.assembly
+            .iter()
+            .filter_map(|section| section.label.as_ref())
+            .collect()
+    }
+
+    /// The labels the object refers to without declaring them itself.
+    pub fn imports(&self) -> Vec<&Label> {
+        let exports: HashSet<_> = self.exports().into_iter().collect();
+        let mut seen = HashSet::new();
+        let mut imports = vec![];
+        for section in self.assembly.iter() {
+            for instruction in section.instructions.iter() {
+                for label in instruction.kind.labels() {
+                    if !exports.contains(label) && seen.insert(label) {
+                        imports.push(label);
+                    }
+                }
+            }
+        }
+        imports
+    }
+
+    /// Writes the object, starting with the magic number, the format version
+    /// and the compiler version. Then follows a constant pool, holding every
+    /// string in the object, and the sources and sections referring to it.
+    /// Instructions are encoded like in a binary, but with the labels they
+    /// refer to in place of addresses.
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        let mut e = PooledEncoder::new();
+
+        let mut symbols = HashMap::new();
+        for section in self.assembly.iter() {
+            for instruction in section.instructions.iter() {
+                for label in instruction.kind.labels() {
+                    if !symbols.contains_key(label) {
+                        symbols.insert(label.clone(), e.pool.intern(label));
+                    }
+                }
+            }
+        }
+
+        e.string(&self.name)?;
+        self.checksum.serialize(&mut e.w)?;
+
+        e.varint(self.sources.len() as u64)?;
+        for source in self.sources.iter() {
+            e.string(&source.uri.to_relative_string())?;
+            e.string(&source.code)?;
+        }
+
+        for sections in self.assembly.groups() {
+            e.varint(sections.len() as u64)?;
+            for section in sections.iter() {
+                e.optional_string(&section.label)?;
+                e.optional_string(&section.leading_comment)?;
+                e.varint(section.instructions.len() as u64)?;
+                for instruction in section.instructions.iter() {
+                    e.optional_string(&instruction.leading_comment)?;
+                    encode_span(&mut e, &instruction.source, &self.sources)?;
+                    e.instruction(&instruction.compile(&symbols))?;
+                }
+            }
+        }
+
+        w.write_all(&OBJECT_MAGIC)?;
+        let written = OBJECT_MAGIC.len()
+            + OBJECT_FORMAT_VERSION.serialize(&mut w)?
+            + self.compiler_version.serialize(&mut w)?;
+        Ok(written + e.finish(w)?)
+    }
+
+    pub fn read<R: Read>(mut r: R) -> Result<Object, ObjectError> {
+        let mut bytes = vec![];
+        r.read_to_end(&mut bytes)?;
+        let mut r = bytes.as_slice();
+
+        let mut magic = [0u8; 4];
+        if r.read_exact(&mut magic).is_err() || magic != OBJECT_MAGIC {
+            return Err(ObjectError::NotAnObject);
+        }
+        let format_version: u16 = r.deserialize().map_err(ObjectError::Invalid)?;
+        let compiler_version: String = r.deserialize().map_err(ObjectError::Invalid)?;
+        if format_version != OBJECT_FORMAT_VERSION {
+            return Err(ObjectError::UnsupportedFormat(
+                format_version,
+                compiler_version,
+            ));
+        }
+
+        Object::read_contents(r, compiler_version).map_err(ObjectError::Invalid)
+    }
+
+    fn read_contents(mut r: &[u8], compiler_version: String) -> io::Result<Object> {
+        let pool = ConstantPool::deserialize(&mut r)?;
+        let mut d = PooledDecoder::new(r, &pool);
+
+        let name = d.string()?;
+        let checksum: u32 = d.r.deserialize()?;
+
+        let mut sources = vec![];
+        for _ in 0..d.varint()? {
+            let uri = d.string()?;
+            let code = d.string()?;
+            sources.push(Source::new(SourceKind::Module, URI::Exact(uri), code));
+        }
+
+        let mut assembly = Assembly::new();
+        for group in 0..assembly.groups().len() {
+            let mut sections = vec![];
+            for _ in 0..d.varint()? {
+                let mut section = Section::unnamed();
+                section.label = d.optional_string()?;
+                section.leading_comment = d.optional_string()?;
+                for _ in 0..d.varint()? {
+                    let leading_comment = d.optional_string()?;
+                    let source = decode_span(&mut d, &sources)?;
+                    let kind = decode_instruction(&mut d)?;
+                    section.instructions.push(Instruction {
+                        leading_comment,
+                        kind,
+                        source,
+                    });
+                }
+                sections.push(section);
+            }
+            if let Some(group) = assembly.group_mut(group) {
+                *group = sections;
+            }
+        }
+        if !d.r.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "unexpected data after the last section",
+            ));
+        }
+
+        Ok(Object {
+            name,
+            compiler_version,
+            checksum,
+            assembly,
+            sources,
+        })
+    }
+}
+
+/// Spans are encoded as zero if they're absent or in a file that isn't among
+/// the sources, and otherwise as the index of the file plus one followed by
+/// the offset, line and character of their start and end.
+fn encode_span(
+    e: &mut PooledEncoder,
+    span: &Option<Span>,
+    sources: &[Arc<Source>],
+) -> io::Result<()> {
+    let (span, file) = match span.as_ref().and_then(|span| {
+        sources
+            .iter()
+            .position(|s| s.uri == span.start.uri)
+            .map(|file| (span, file))
+    }) {
+        None => return e.varint(0),
+        Some(found) => found,
+    };
+    e.varint(file as u64 + 1)?;
+    for location in [&span.start, &span.end].iter() {
+        e.varint(location.offset as u64)?;
+        e.varint(location.line as u64)?;
+        e.varint(location.character as u64)?;
+    }
+    Ok(())
+}
+
+fn decode_span(d: &mut PooledDecoder<&[u8]>, sources: &[Arc<Source>]) -> io::Result<Option<Span>> {
+    let source = match d.varint()? {
+        0 => return Ok(None),
+        file => sources.get(file as usize - 1).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("span in source {}, which is not in the object", file - 1),
+            )
+        })?,
+    };
+    let mut location = || -> io::Result<Location> {
+        Ok(Location {
+            uri: source.uri.clone(),
+            offset: d.varint()? as usize,
+            line: d.varint()? as usize,
+            character: d.varint()? as usize,
+        })
+    };
+    let start = location()?;
+    let end = location()?;
+    Ok(Some(Span::new(start, end)))
+}
+
+/// Decodes an instruction, with the indices in the pool it refers to turned
+/// back into labels.
+fn decode_instruction(d: &mut PooledDecoder<&[u8]>) -> io::Result<InstructionKind> {
+    let instruction = d.instruction()?;
+    for symbol in addresses(&instruction) {
+        d.pool.get(symbol)?;
+    }
+    let pool = d.pool;
+    Ok(kind(&instruction, |symbol| {
+        pool.get(symbol).map(Clone::clone).unwrap_or_default()
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object() -> Object {
+        let source = Source::new(
+            SourceKind::Module,
+            URI::Exact("N/A.loa".into()),
+            "namespace N.\n\nclass A.\n".into(),
+        );
+        let mut assembly = Parser::new()
+            .parse(
+                r#"
+                @N/A$class
+                  DeclareClass "N/A"
+                  UseMethod @N/B#b
+
+                @N/A#a
+                  ; calls b
+                  CallMethod @N/B#b "N/A.loa" 3 1
+                  Return 0
+                "#,
+            )
+            .unwrap();
+        let instruction = &mut assembly.group_mut(3).unwrap()[1].instructions[0];
+        instruction.source = Some(Span::new(
+            Location {
+                uri: source.uri.clone(),
+                offset: 14,
+                line: 3,
+                character: 1,
+            },
+            Location {
+                uri: source.uri.clone(),
+                offset: 22,
+                line: 3,
+                character: 9,
+            },
+        ));
+        Object::new("N", assembly, vec![source])
+    }
+
+    #[test]
+    fn imports_and_exports() {
+        let object = object();
+
+        assert_eq!(object.exports(), vec!["N/A$class", "N/A#a"]);
+        assert_eq!(object.imports(), vec!["N/B#b"]);
+    }
+
+    #[test]
+    fn round_trip() {
+        let object = object();
+        let mut bytes = vec![];
+        object.write(&mut bytes).unwrap();
+        let read = Object::read(bytes.as_slice()).unwrap();
+
+        assert_eq!(read.name, "N");
+        assert_eq!(read.compiler_version, COMPILER_VERSION);
+        assert!(read.is_fresh(&object.sources));
+        assert_eq!(
+            format!("{:?}", read.assembly),
+            format!("{:?}", object.assembly)
+        );
+        assert_eq!(
+            read.assembly.source_map(&read.sources, true),
+            object.assembly.source_map(&object.sources, true)
+        );
+    }
+
+    #[test]
+    fn stale_objects() {
+        let object = object();
+        let changed = Source::new(
+            SourceKind::Module,
+            URI::Exact("N/A.loa".into()),
+            "namespace N.\n\nclass A.\nclass B.\n".into(),
+        );
+
+        assert!(!object.is_fresh(&[changed]));
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_matches!(
+            Object::read(b"\x7fLOA".as_ref()).err(),
+            Some(ObjectError::NotAnObject)
+        );
+
+        let mut bytes = vec![];
+        object().write(&mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert_matches!(
+            Object::read(bytes.as_slice()).err(),
+            Some(ObjectError::Invalid(_))
+        );
+    }
+}
@@ -1,5 +1,7 @@
 use crate::assembly::*;
 use crate::vm::NativeMethod;
+use crate::{BigFraction, BigUint};
+use num_bigint::ParseBigIntError;
 use std::num::{ParseFloatError, ParseIntError};
 
 pub struct Parser {
@@ -79,6 +81,7 @@ impl Parser {
                 code.drain(.."Noop".len());
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::Noop,
                 });
             }
@@ -87,6 +90,7 @@ impl Parser {
                 code.drain(.."Halt".len());
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::Halt,
                 });
             }
@@ -95,6 +99,7 @@ impl Parser {
                 code.drain(.."Panic".len());
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::Panic,
                 });
             }
@@ -103,6 +108,7 @@ impl Parser {
                 code.drain(.."DumpStack".len());
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::DumpStack,
                 });
             }
@@ -112,6 +118,7 @@ impl Parser {
                 let name = self.parse_string(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::DeclareClass(name),
                 });
             }
@@ -124,6 +131,7 @@ impl Parser {
                 let sl = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::DeclareVariable(name, vl, gl, sl),
                 });
             }
@@ -133,6 +141,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::UseVariable(label),
                 });
             }
@@ -143,6 +152,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::DeclareMethod(name, label),
                 });
             }
@@ -152,6 +162,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::UseMethod(label),
                 });
             }
@@ -162,6 +173,7 @@ impl Parser {
                 let target_label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::OverrideMethod(source_label, target_label),
                 });
             }
@@ -171,6 +183,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::LoadObject(label),
                 });
             }
@@ -183,15 +196,30 @@ impl Parser {
                 let character = self.parse_from_str(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::CallMethod(label, uri, line, character),
                 });
             }
+            // TailCallMethod <label> <string> <u46> <u64>
+            else if code.starts_with("TailCallMethod") {
+                code.drain(.."TailCallMethod".len());
+                let label = self.parse_label(code)?;
+                let uri = self.parse_string(code)?;
+                let line = self.parse_from_str(code)?;
+                let character = self.parse_from_str(code)?;
+                section.instructions.push(Instruction {
+                    leading_comment,
+                    source: None,
+                    kind: InstructionKind::TailCallMethod(label, uri, line, character),
+                });
+            }
             // CallNative <native method>
             else if code.starts_with("CallNative") {
                 code.drain(.."CallNative".len());
                 let method = self.parse_native_method(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::CallNative(method),
                 });
             }
@@ -201,6 +229,7 @@ impl Parser {
                 let index = self.parse_from_str(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::LoadLocal(index),
                 });
             }
@@ -210,6 +239,7 @@ impl Parser {
                 let index = self.parse_from_str(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::DropLocal(index),
                 });
             }
@@ -219,6 +249,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::StoreGlobal(label),
                 });
             }
@@ -228,6 +259,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::LoadGlobal(label),
                 });
             }
@@ -238,6 +270,7 @@ impl Parser {
                 let label = self.parse_label(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::LoadLazy(arity, label),
                 });
             }
@@ -247,6 +280,7 @@ impl Parser {
                 let arity = self.parse_from_str(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::ReturnLazy(arity),
                 });
             }
@@ -256,9 +290,21 @@ impl Parser {
                 let arity = self.parse_from_str(code)?;
                 section.instructions.push(Instruction {
                     leading_comment,
+                    source: None,
                     kind: InstructionKind::Return(arity),
                 });
             }
+            // Rescue <u16> <label>
+            else if code.starts_with("Rescue") {
+                code.drain(.."Rescue".len());
+                let arity = self.parse_from_str(code)?;
+                let label = self.parse_label(code)?;
+                section.instructions.push(Instruction {
+                    leading_comment,
+                    source: None,
+                    kind: InstructionKind::Rescue(arity, label),
+                });
+            }
             // MarkClass..
             else if code.starts_with("MarkClass") {
                 code.drain(.."MarkClass".len());
@@ -268,6 +314,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassFalse(label),
                     });
                 }
@@ -277,6 +324,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassTrue(label),
                     });
                 }
@@ -286,6 +334,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassString(label),
                     });
                 }
@@ -295,6 +344,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassCharacter(label),
                     });
                 }
@@ -304,6 +354,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassSymbol(label),
                     });
                 }
@@ -313,6 +364,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassU8(label),
                     });
                 }
@@ -322,6 +374,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassU16(label),
                     });
                 }
@@ -331,6 +384,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassU32(label),
                     });
                 }
@@ -340,6 +394,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassU64(label),
                     });
                 }
@@ -349,6 +404,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassU128(label),
                     });
                 }
@@ -358,6 +414,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassUBig(label),
                     });
                 }
@@ -367,6 +424,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassI8(label),
                     });
                 }
@@ -376,6 +434,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassI16(label),
                     });
                 }
@@ -385,6 +444,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassI32(label),
                     });
                 }
@@ -394,6 +454,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassI64(label),
                     });
                 }
@@ -403,6 +464,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassI128(label),
                     });
                 }
@@ -412,6 +474,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassIBig(label),
                     });
                 }
@@ -421,6 +484,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassF32(label),
                     });
                 }
@@ -430,6 +494,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassF64(label),
                     });
                 }
@@ -439,6 +504,7 @@ impl Parser {
                     let label = self.parse_label(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::MarkClassFBig(label),
                     });
                 }
@@ -456,6 +522,7 @@ impl Parser {
                     let value = self.parse_string(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstString(value),
                     });
                 }
@@ -465,6 +532,7 @@ impl Parser {
                     let value = self.parse_character(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstCharacter(value),
                     });
                 }
@@ -474,6 +542,7 @@ impl Parser {
                     let value = self.parse_symbol(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstSymbol(value),
                     });
                 }
@@ -483,6 +552,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstU8(value),
                     });
                 }
@@ -492,6 +562,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstU16(value),
                     });
                 }
@@ -501,6 +572,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstU32(value),
                     });
                 }
@@ -510,6 +582,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstU64(value),
                     });
                 }
@@ -519,16 +592,18 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstU128(value),
                     });
                 }
                 // ..UBig <integer>
                 else if code.starts_with("UBig") {
                     code.drain(.."UBig".len());
-                    let value: u128 = self.parse_from_str(code)?;
+                    let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
-                        kind: InstructionKind::LoadConstUBig(value.into()),
+                        source: None,
+                        kind: InstructionKind::LoadConstUBig(value),
                     });
                 }
                 // ..i8 <integer>
@@ -537,6 +612,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstI8(value),
                     });
                 }
@@ -546,6 +622,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstI16(value),
                     });
                 }
@@ -555,6 +632,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstI32(value),
                     });
                 }
@@ -564,6 +642,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstI64(value),
                     });
                 }
@@ -573,16 +652,18 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstI128(value),
                     });
                 }
                 // ..IBig <integer>
                 else if code.starts_with("IBig") {
                     code.drain(.."IBig".len());
-                    let value: i128 = self.parse_from_str(code)?;
+                    let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
-                        kind: InstructionKind::LoadConstIBig(value.into()),
+                        source: None,
+                        kind: InstructionKind::LoadConstIBig(value),
                     });
                 }
                 // ..F32 <float>
@@ -591,6 +672,7 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstF32(value),
                     });
                 }
@@ -600,16 +682,18 @@ impl Parser {
                     let value = self.parse_from_str(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
+                        source: None,
                         kind: InstructionKind::LoadConstF64(value),
                     });
                 }
-                // ..FBig <float>
+                // ..FBig <fraction>
                 else if code.starts_with("FBig") {
                     code.drain(.."FBig".len());
-                    let value: f64 = self.parse_from_str(code)?;
+                    let value = self.parse_fraction(code)?;
                     section.instructions.push(Instruction {
                         leading_comment,
-                        kind: InstructionKind::LoadConstFBig(value.into()),
+                        source: None,
+                        kind: InstructionKind::LoadConstFBig(value),
                     });
                 }
                 // ..
@@ -633,33 +717,67 @@ impl Parser {
             return Err(ParseError::ExpectedString(code.clone()));
         }
         code.drain(.."\"".len());
-        let mut result = String::new();
-        while code.len() > 0 && !code.starts_with("\"") {
-            result.push(code.remove(0));
-        }
-        if code.starts_with("\"") {
-            code.drain(.."\"".len());
+        Ok(self
+            .parse_escaped_until('"', code)?
+            .into_iter()
+            .map(|c| std::char::from_u32(c).unwrap_or(std::char::REPLACEMENT_CHARACTER))
+            .collect())
+    }
+
+    /// Parses the contents of a quoted literal, up to and including the
+    /// closing quote, resolving the same escape sequences that Rust's `{:?}`
+    /// formatting produces.
+    fn parse_escaped_until(&mut self, quote: char, code: &mut String) -> ParseResult<Vec<u32>> {
+        let mut result = vec![];
+        loop {
+            let c = match code.chars().next() {
+                None => break,
+                Some(c) => c,
+            };
+            code.drain(..c.len_utf8());
+            if c == quote {
+                break;
+            }
+            if c != '\\' {
+                result.push(c as u32);
+                continue;
+            }
+            let escaped = match code.chars().next() {
+                None => return Err(ParseError::InvalidEscape(code.clone())),
+                Some(c) => c,
+            };
+            code.drain(..escaped.len_utf8());
+            result.push(match escaped {
+                'n' => '\n' as u32,
+                'r' => '\r' as u32,
+                't' => '\t' as u32,
+                '0' => '\0' as u32,
+                '\\' | '"' | '\'' => escaped as u32,
+                'u' if code.starts_with("{") => {
+                    let end = code
+                        .find('}')
+                        .ok_or_else(|| ParseError::InvalidEscape(code.clone()))?;
+                    let value = u32::from_str_radix(&code[1..end], 16)
+                        .map_err(|_| ParseError::InvalidEscape(code.clone()))?;
+                    code.drain(..=end);
+                    value
+                }
+                _ => return Err(ParseError::InvalidEscape(code.clone())),
+            });
         }
         Ok(result)
     }
 
     fn parse_native_method(&mut self, code: &mut String) -> ParseResult<NativeMethod> {
         self.skip_leading_whitespace(code);
-        if code.starts_with("Loa/Number#+") {
-            code.drain(.."Loa/Number#+".len());
-            Ok(NativeMethod::Number_plus)
-        } else if code.starts_with("Loa/Number#-") {
-            code.drain(.."Loa/Number#-".len());
-            Ok(NativeMethod::Number_minus)
-        } else if code.starts_with("Loa/Object#==") {
-            code.drain(.."Loa/Object#==".len());
-            Ok(NativeMethod::Object_eq)
-        } else if code.starts_with("Loa/Object#asString") {
-            code.drain(.."Loa/Object#asString".len());
-            Ok(NativeMethod::Object_asString)
-        } else {
-            Err(ParseError::ExpectedNativeMethod(code.clone()))
+        let mut name = String::new();
+        while !code.is_empty() && !(code.as_bytes()[0] as char).is_whitespace() {
+            name.push(code.remove(0));
+        }
+        if name.is_empty() {
+            return Err(ParseError::ExpectedNativeMethod(code.clone()));
         }
+        Ok(name.into())
     }
 
     fn parse_character(&mut self, code: &mut String) -> ParseResult<u16> {
@@ -668,14 +786,17 @@ impl Parser {
             return Err(ParseError::ExpectedString(code.clone()));
         }
         code.drain(.."'".len());
-        let mut result = String::new();
-        while code.len() > 0 && !code.starts_with("'") {
-            result.push(code.remove(0));
-        }
-        if code.starts_with("'") {
-            code.drain(.."'".len());
+        let value = self.parse_escaped_until('\'', code)?;
+        match value.as_slice() {
+            // Lone surrogates can only be written as escapes.
+            [c] if *c <= 0xffff => Ok(*c as u16),
+            [c] => Ok(crate::syntax::string_to_characters(
+                std::char::from_u32(*c)
+                    .ok_or(ParseError::InvalidCharacter(*c))?
+                    .to_string(),
+            )[0]),
+            _ => Err(ParseError::ExpectedString(code.clone())),
         }
-        Ok(crate::syntax::string_to_characters(result)[0])
     }
 
     fn parse_comment(&mut self, code: &mut String) -> ParseResult<String> {
@@ -696,6 +817,9 @@ impl Parser {
             return Err(ParseError::ExpectedLabel(code.clone()));
         }
         code.drain(.."#".len());
+        if code.starts_with("\"") {
+            return self.parse_string(code);
+        }
         let mut symbol = String::new();
         while code.len() > 0 && !(code.as_bytes()[0] as char).is_whitespace() {
             symbol.push(code.remove(0));
@@ -716,6 +840,37 @@ impl Parser {
         Ok(label)
     }
 
+    /// Parses fractions as they are displayed, like `-1/3`, `5`, `NaN` or
+    /// `inf`, as well as decimal numbers.
+    fn parse_fraction(&mut self, code: &mut String) -> ParseResult<BigFraction> {
+        self.skip_leading_whitespace(code);
+        let mut fraction = String::new();
+        while code.len() > 0 && !(code.as_bytes()[0] as char).is_whitespace() {
+            fraction.push(code.remove(0));
+        }
+        let (negative, unsigned) = if fraction.starts_with("-") {
+            (true, &fraction[1..])
+        } else {
+            (false, fraction.trim_start_matches('+'))
+        };
+        let value = match unsigned {
+            "NaN" => return Ok(BigFraction::nan()),
+            "inf" if negative => return Ok(BigFraction::neg_infinity()),
+            "inf" => return Ok(BigFraction::infinity()),
+            _ if !unsigned.contains('/') => {
+                let float: f64 = unsigned.parse()?;
+                BigFraction::from(float)
+            }
+            _ => {
+                let mut parts = unsigned.splitn(2, '/');
+                let numerator: BigUint = parts.next().unwrap_or("").parse()?;
+                let denominator: BigUint = parts.next().unwrap_or("").parse()?;
+                BigFraction::new(numerator, denominator)
+            }
+        };
+        Ok(if negative { -value } else { value })
+    }
+
     fn parse_from_str<P: std::str::FromStr>(&mut self, code: &mut String) -> ParseResult<P>
     where
         P::Err: Into<ParseError>,
@@ -738,7 +893,10 @@ pub enum ParseError {
     ExpectedString(String),
     ExpectedLabel(String),
     ExpectedNativeMethod(String),
+    InvalidEscape(String),
+    InvalidCharacter(u32),
     InvalidInteger(ParseIntError),
+    InvalidBigInteger(ParseBigIntError),
     InvalidFloat(ParseFloatError),
 }
 
@@ -748,6 +906,12 @@ impl From<ParseIntError> for ParseError {
     }
 }
 
+impl From<ParseBigIntError> for ParseError {
+    fn from(e: ParseBigIntError) -> ParseError {
+        ParseError::InvalidBigInteger(e)
+    }
+}
+
 impl From<ParseFloatError> for ParseError {
     fn from(e: ParseFloatError) -> ParseError {
         ParseError::InvalidFloat(e)
@@ -0,0 +1,302 @@
+use crate::bytecode::*;
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// Identifies a file as a Loa binary.
+pub const MAGIC: [u8; 4] = *b"\x7fLOA";
+
+/// The version of the container format, and of the instruction encoding
+/// within it. Binaries with any other version are rejected.
+pub const FORMAT_VERSION: u16 = 5;
+
+/// The version of the compiler that produced a binary.
+pub const COMPILER_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// A compiled program, as stored in a `.loabin` file.
+///
+/// The file starts with the magic number, the format version and the
+/// compiler version, which are kept in place across format versions so that
+/// incompatible binaries can be explained. Then follows a checksum of the
+/// rest of the file, which holds the entry point followed by the constant
+/// pool, the instructions and the source map, each prefixed with its length.
+/// The instructions refer to the strings in the constant pool by index.
+#[derive(Debug, Clone)]
+pub struct Binary {
+    pub compiler_version: String,
+
+    /// The qualified name of the class the program was built to run.
+    pub entry_point: Option<String>,
+
+    pub instructions: Vec<Instruction>,
+
+    pub source_map: SourceMap,
+}
+
+#[derive(Debug)]
+pub enum BinaryError {
+    Io(io::Error),
+    NotABinary,
+    UnsupportedFormat(u16, String),
+    Truncated,
+    TrailingData(usize),
+    ChecksumMismatch,
+    InvalidInstructions(io::Error),
+    InvalidSourceMap(io::Error),
+}
+
+impl fmt::Display for BinaryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BinaryError::Io(e) => write!(f, "{}", e),
+            BinaryError::NotABinary => write!(f, "not a Loa binary"),
+            BinaryError::UnsupportedFormat(version, compiler_version) => write!(
+                f,
+                "the binary was built by loa {} using format version {}, but this VM (loa {}) only supports format version {}",
+                compiler_version, version, COMPILER_VERSION, FORMAT_VERSION
+            ),
+            BinaryError::Truncated => write!(f, "the binary is truncated"),
+            BinaryError::TrailingData(length) => {
+                write!(f, "the binary is followed by {} unexpected bytes", length)
+            }
+            BinaryError::ChecksumMismatch => {
+                write!(f, "the binary is corrupted (checksum mismatch)")
+            }
+            BinaryError::InvalidInstructions(e) => {
+                write!(f, "the binary contains invalid instructions: {}", e)
+            }
+            BinaryError::InvalidSourceMap(e) => {
+                write!(f, "the binary contains an invalid source map: {}", e)
+            }
+        }
+    }
+}
+
+impl Error for BinaryError {}
+
+impl From<io::Error> for BinaryError {
+    fn from(e: io::Error) -> BinaryError {
+        BinaryError::Io(e)
+    }
+}
+
+impl Binary {
+    pub fn new(instructions: Vec<Instruction>) -> Binary {
+        Binary {
+            compiler_version: COMPILER_VERSION.into(),
+            entry_point: None,
+            instructions,
+            source_map: SourceMap::new(),
+        }
+    }
+
+    pub fn with_entry_point<S: Into<String>>(mut self, entry_point: S) -> Binary {
+        self.entry_point = Some(entry_point.into());
+        self
+    }
+
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Binary {
+        self.source_map = source_map;
+        self
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        let mut pool = ConstantPool::new();
+        let mut instructions = vec![];
+        pool.encode(&self.instructions, &mut instructions)?;
+        let mut constants = vec![];
+        pool.serialize(&mut constants)?;
+        let mut source_map = vec![];
+        self.source_map.serialize(&mut source_map)?;
+
+        let mut checked = vec![];
+        self.entry_point
+            .clone()
+            .unwrap_or_default()
+            .serialize(&mut checked)?;
+        for part in vec![constants, instructions, source_map] {
+            (part.len() as u64).serialize(&mut checked)?;
+            checked.extend(part);
+        }
+
+        w.write_all(&MAGIC)?;
+        let written = MAGIC.len()
+            + FORMAT_VERSION.serialize(&mut w)?
+            + self.compiler_version.serialize(&mut w)?
+            + crc32(&checked).serialize(&mut w)?;
+        w.write_all(&checked)?;
+        Ok(written + checked.len())
+    }
+
+    pub fn read<R: Read>(mut r: R) -> Result<Binary, BinaryError> {
+        let mut bytes = vec![];
+        r.read_to_end(&mut bytes)?;
+        let mut r = bytes.as_slice();
+
+        let mut magic = [0u8; 4];
+        if r.read_exact(&mut magic).is_err() || magic != MAGIC {
+            return Err(BinaryError::NotABinary);
+        }
+        let format_version: u16 = r.deserialize().map_err(|_| BinaryError::Truncated)?;
+        let compiler_version: String = r.deserialize().map_err(|_| BinaryError::Truncated)?;
+        if format_version != FORMAT_VERSION {
+            return Err(BinaryError::UnsupportedFormat(
+                format_version,
+                compiler_version,
+            ));
+        }
+
+        let checksum: u32 = r.deserialize().map_err(|_| BinaryError::Truncated)?;
+        let checked = r;
+        let entry_point: String = r.deserialize().map_err(|_| BinaryError::Truncated)?;
+        let constants = section(&mut r)?;
+        let instructions = section(&mut r)?;
+        let source_map = section(&mut r)?;
+        if !r.is_empty() {
+            return Err(BinaryError::TrailingData(r.len()));
+        }
+        if crc32(checked) != checksum {
+            return Err(BinaryError::ChecksumMismatch);
+        }
+
+        let instructions = ConstantPool::deserialize(constants)
+            .and_then(|pool| pool.decode(instructions))
+            .map_err(BinaryError::InvalidInstructions)?;
+        let source_map =
+            SourceMap::deserialize(source_map).map_err(BinaryError::InvalidSourceMap)?;
+
+        Ok(Binary {
+            compiler_version,
+            entry_point: if entry_point.is_empty() {
+                None
+            } else {
+                Some(entry_point)
+            },
+            instructions,
+            source_map,
+        })
+    }
+}
+
+/// Splits a section, prefixed by its length, off the front of the input.
+fn section<'a>(r: &mut &'a [u8]) -> Result<&'a [u8], BinaryError> {
+    let length: u64 = r.deserialize().map_err(|_| BinaryError::Truncated)?;
+    if (r.len() as u64) < length {
+        return Err(BinaryError::Truncated);
+    }
+    let (section, rest) = r.split_at(length as usize);
+    *r = rest;
+    Ok(section)
+}
+
+/// CRC-32, as used by zlib and PNG.
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binary() -> Vec<u8> {
+        let mut source_map = SourceMap::new();
+        let main = source_map.add_file("Main.loa", Some("\"Hello\".".into()));
+        source_map.map(0, main, (1, 1), (1, 8));
+
+        let mut bytes = vec![];
+        Binary::new(vec![
+            Instruction::LoadConstString("Hello".into()),
+            Instruction::Halt,
+        ])
+        .with_entry_point("N/Main")
+        .with_source_map(source_map)
+        .write(&mut bytes)
+        .unwrap();
+        bytes
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let binary = Binary::read(binary().as_slice()).unwrap();
+
+        assert_eq!(binary.compiler_version, COMPILER_VERSION);
+        assert_eq!(binary.entry_point, Some("N/Main".into()));
+        assert_eq!(
+            format!("{:?}", binary.instructions),
+            r#"[LoadConstString("Hello"), Halt]"#
+        );
+        assert_eq!(
+            binary.source_map.lookup(0).unwrap().to_string(),
+            "Main.loa:1:1"
+        );
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert_matches!(
+            Binary::read(b"namespace N.".as_ref()),
+            Err(BinaryError::NotABinary)
+        );
+        assert_matches!(Binary::read(b"".as_ref()), Err(BinaryError::NotABinary));
+    }
+
+    #[test]
+    fn rejects_other_format_versions() {
+        let version = FORMAT_VERSION + 1;
+        let mut encoded = vec![];
+        version.serialize(&mut encoded).unwrap();
+        let mut bytes = binary();
+        bytes[MAGIC.len()..MAGIC.len() + encoded.len()].copy_from_slice(&encoded);
+
+        match Binary::read(bytes.as_slice()) {
+            Err(BinaryError::UnsupportedFormat(v, compiler_version)) if v == version => {
+                assert_eq!(compiler_version, COMPILER_VERSION)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_binaries() {
+        let bytes = binary();
+
+        for length in 4..bytes.len() {
+            assert_matches!(Binary::read(&bytes[..length]), Err(BinaryError::Truncated));
+        }
+    }
+
+    #[test]
+    fn rejects_corrupted_binaries() {
+        let mut bytes = binary();
+        let last = bytes.len() - 2;
+        bytes[last] ^= 1;
+
+        assert_matches!(
+            Binary::read(bytes.as_slice()),
+            Err(BinaryError::ChecksumMismatch)
+        );
+    }
+
+    #[test]
+    fn rejects_trailing_data() {
+        let mut bytes = binary();
+        bytes.extend(&[0, 1, 2]);
+
+        assert_matches!(
+            Binary::read(bytes.as_slice()),
+            Err(BinaryError::TrailingData(3))
+        );
+    }
+}
@@ -1,8 +1,9 @@
+use crate::bytecode::ConstantPool;
 use crate::vm::NativeMethod;
 use crate::*;
 use std::io::{self, Read, Write};
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub enum Instruction {
     Noop,
     Halt,
@@ -16,6 +17,7 @@ pub enum Instruction {
     OverrideMethod(u64, u64),
     LoadObject(u64),
     CallMethod(u64, String, u64, u64),
+    TailCallMethod(u64, String, u64, u64),
     CallNative(NativeMethod),
     LoadLocal(u16),
     DropLocal(u16),
@@ -24,6 +26,7 @@ pub enum Instruction {
     LoadLazy(u16, u64),
     Return(u16),
     ReturnLazy(u16),
+    Rescue(u16, u64),
 
     MarkClassTrue(u64),
     MarkClassFalse(u64),
@@ -87,6 +90,8 @@ const LOAD_GLOBAL: u8 = 0x90;
 const LOAD_LAZY: u8 = 0x91;
 const RETURN: u8 = 0x92;
 const RETURN_LAZY: u8 = 0x93;
+const TAIL_CALL_METHOD: u8 = 0x94;
+const RESCUE: u8 = 0x95;
 
 const MARK_CLASS_TRUE: u8 = 0xae;
 const MARK_CLASS_FALSE: u8 = 0xaf;
@@ -129,264 +134,351 @@ const LOAD_CONST_F32: u8 = 0xd1;
 const LOAD_CONST_F64: u8 = 0xd2;
 const LOAD_CONST_FBIG: u8 = 0xd3;
 
-impl BytecodeEncoding for Instruction {
-    fn serialize<W: Write>(&self, mut w: W) -> io::Result<usize> {
+impl Instruction {
+    /// Encodes the instruction, adding the strings it holds to the pool and
+    /// referring to them by their index in it.
+    pub fn encode<W: Write>(&self, pool: &mut ConstantPool, w: W) -> io::Result<usize> {
+        let mut e = Encoder {
+            w,
+            pool,
+            written: 0,
+        };
         match self {
-            Instruction::Noop => NOOP.serialize(w),
-            Instruction::Halt => HALT.serialize(w),
-            Instruction::Panic => PANIC.serialize(w),
-            Instruction::DumpStack => DUMP_STACK.serialize(w),
+            Instruction::Noop => e.opcode(NOOP)?,
+            Instruction::Halt => e.opcode(HALT)?,
+            Instruction::Panic => e.opcode(PANIC)?,
+            Instruction::DumpStack => e.opcode(DUMP_STACK)?,
             Instruction::DeclareClass(ref name) => {
-                Ok(DECLARE_CLASS.serialize(&mut w)? + name.serialize(w)?)
+                e.opcode(DECLARE_CLASS)?;
+                e.string(name)?;
+            }
+            Instruction::DeclareVariable(ref name, vl, gl, sl) => {
+                e.opcode(DECLARE_VARIABLE)?;
+                e.string(name)?;
+                e.varint(*vl)?;
+                e.varint(*gl)?;
+                e.varint(*sl)?;
+            }
+            Instruction::UseVariable(id) => {
+                e.opcode(USE_VARIABLE)?;
+                e.varint(*id)?;
+            }
+            Instruction::DeclareMethod(ref name, id) => {
+                e.opcode(DECLARE_METHOD)?;
+                e.string(name)?;
+                e.varint(*id)?;
+            }
+            Instruction::UseMethod(id) => {
+                e.opcode(USE_METHOD)?;
+                e.varint(*id)?;
             }
-            Instruction::DeclareVariable(ref name, vl, gl, sl) => Ok(DECLARE_VARIABLE
-                .serialize(&mut w)?
-                + name.serialize(&mut w)?
-                + vl.serialize(&mut w)?
-                + gl.serialize(&mut w)?
-                + sl.serialize(w)?),
-            Instruction::UseVariable(id) => Ok(USE_VARIABLE.serialize(&mut w)? + id.serialize(w)?),
-            Instruction::DeclareMethod(ref name, id) => Ok(DECLARE_METHOD.serialize(&mut w)?
-                + name.serialize(&mut w)?
-                + id.serialize(w)?),
-            Instruction::UseMethod(id) => Ok(USE_METHOD.serialize(&mut w)? + id.serialize(w)?),
             Instruction::OverrideMethod(s, t) => {
-                Ok(OVERRIDE_METHOD.serialize(&mut w)? + s.serialize(&mut w)? + t.serialize(w)?)
+                e.opcode(OVERRIDE_METHOD)?;
+                e.varint(*s)?;
+                e.varint(*t)?;
+            }
+            Instruction::LoadObject(id) => {
+                e.opcode(LOAD_OBJECT)?;
+                e.varint(*id)?;
+            }
+            Instruction::CallMethod(id, ref uri, line, character) => {
+                e.opcode(CALL_METHOD)?;
+                e.varint(*id)?;
+                e.string(uri)?;
+                e.varint(*line)?;
+                e.varint(*character)?;
+            }
+            Instruction::TailCallMethod(id, ref uri, line, character) => {
+                e.opcode(TAIL_CALL_METHOD)?;
+                e.varint(*id)?;
+                e.string(uri)?;
+                e.varint(*line)?;
+                e.varint(*character)?;
             }
-            Instruction::LoadObject(id) => Ok(LOAD_OBJECT.serialize(&mut w)? + id.serialize(w)?),
-            Instruction::CallMethod(id, ref uri, line, character) => Ok(CALL_METHOD
-                .serialize(&mut w)?
-                + id.serialize(&mut w)?
-                + uri.serialize(&mut w)?
-                + line.serialize(&mut w)?
-                + character.serialize(w)?),
             Instruction::CallNative(ref method) => {
-                Ok(CALL_NATIVE.serialize(&mut w)? + method.serialize(w)?)
+                e.opcode(CALL_NATIVE)?;
+                e.string(method.name())?;
             }
             Instruction::LoadLocal(index) => {
-                Ok(LOAD_LOCAL.serialize(&mut w)? + index.serialize(w)?)
+                e.opcode(LOAD_LOCAL)?;
+                e.varint(*index as u64)?;
             }
             Instruction::DropLocal(index) => {
-                Ok(DROP_LOCAL.serialize(&mut w)? + index.serialize(w)?)
+                e.opcode(DROP_LOCAL)?;
+                e.varint(*index as u64)?;
             }
             Instruction::StoreGlobal(label) => {
-                Ok(STORE_GLOBAL.serialize(&mut w)? + label.serialize(w)?)
+                e.opcode(STORE_GLOBAL)?;
+                e.varint(*label)?;
             }
             Instruction::LoadGlobal(label) => {
-                Ok(LOAD_GLOBAL.serialize(&mut w)? + label.serialize(w)?)
+                e.opcode(LOAD_GLOBAL)?;
+                e.varint(*label)?;
             }
             Instruction::LoadLazy(arity, label) => {
-                Ok(LOAD_LAZY.serialize(&mut w)? + arity.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::Return(index) => Ok(RETURN.serialize(&mut w)? + index.serialize(w)?),
-            Instruction::ReturnLazy(index) => {
-                Ok(RETURN_LAZY.serialize(&mut w)? + index.serialize(w)?)
-            }
-
-            Instruction::MarkClassTrue(label) => {
-                Ok(MARK_CLASS_TRUE.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassFalse(label) => {
-                Ok(MARK_CLASS_FALSE.serialize(&mut w)? + label.serialize(w)?)
-            }
-
-            Instruction::MarkClassString(label) => {
-                Ok(MARK_CLASS_STRING.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassCharacter(label) => {
-                Ok(MARK_CLASS_CHARACTER.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassSymbol(label) => {
-                Ok(MARK_CLASS_SYMBOL.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassU8(label) => {
-                Ok(MARK_CLASS_U8.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassU16(label) => {
-                Ok(MARK_CLASS_U16.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassU32(label) => {
-                Ok(MARK_CLASS_U32.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassU64(label) => {
-                Ok(MARK_CLASS_U64.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassU128(label) => {
-                Ok(MARK_CLASS_U128.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassUBig(label) => {
-                Ok(MARK_CLASS_UBIG.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassI8(label) => {
-                Ok(MARK_CLASS_I8.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassI16(label) => {
-                Ok(MARK_CLASS_I16.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassI32(label) => {
-                Ok(MARK_CLASS_I32.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassI64(label) => {
-                Ok(MARK_CLASS_I64.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassI128(label) => {
-                Ok(MARK_CLASS_I128.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassIBig(label) => {
-                Ok(MARK_CLASS_IBIG.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassF32(label) => {
-                Ok(MARK_CLASS_F32.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassF64(label) => {
-                Ok(MARK_CLASS_F64.serialize(&mut w)? + label.serialize(w)?)
-            }
-            Instruction::MarkClassFBig(label) => {
-                Ok(MARK_CLASS_FBIG.serialize(&mut w)? + label.serialize(w)?)
-            }
-
-            Instruction::LoadConstString(value) => {
-                Ok(LOAD_CONST_STRING.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstCharacter(value) => {
-                Ok(LOAD_CONST_CHARACTER.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstSymbol(value) => {
-                Ok(LOAD_CONST_SYMBOL.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstU8(value) => {
-                Ok(LOAD_CONST_U8.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstU16(value) => {
-                Ok(LOAD_CONST_U16.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstU32(value) => {
-                Ok(LOAD_CONST_U32.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstU64(value) => {
-                Ok(LOAD_CONST_U64.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstU128(value) => {
-                Ok(LOAD_CONST_U128.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstUBig(value) => {
-                Ok(LOAD_CONST_UBIG.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstI8(value) => {
-                Ok(LOAD_CONST_I8.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstI16(value) => {
-                Ok(LOAD_CONST_I16.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstI32(value) => {
-                Ok(LOAD_CONST_I32.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstI64(value) => {
-                Ok(LOAD_CONST_I64.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstI128(value) => {
-                Ok(LOAD_CONST_I128.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstIBig(value) => {
-                Ok(LOAD_CONST_IBIG.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstF32(value) => {
-                Ok(LOAD_CONST_F32.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstF64(value) => {
-                Ok(LOAD_CONST_F64.serialize(&mut w)? + value.serialize(w)?)
-            }
-            Instruction::LoadConstFBig(value) => {
-                Ok(LOAD_CONST_FBIG.serialize(&mut w)? + value.serialize(w)?)
-            }
+                e.opcode(LOAD_LAZY)?;
+                e.varint(*arity as u64)?;
+                e.varint(*label)?;
+            }
+            Instruction::Return(arity) => {
+                e.opcode(RETURN)?;
+                e.varint(*arity as u64)?;
+            }
+            Instruction::ReturnLazy(arity) => {
+                e.opcode(RETURN_LAZY)?;
+                e.varint(*arity as u64)?;
+            }
+            Instruction::Rescue(arity, label) => {
+                e.opcode(RESCUE)?;
+                e.varint(*arity as u64)?;
+                e.varint(*label)?;
+            }
+
+            Instruction::MarkClassTrue(label) => e.mark_class(MARK_CLASS_TRUE, *label)?,
+            Instruction::MarkClassFalse(label) => e.mark_class(MARK_CLASS_FALSE, *label)?,
+
+            Instruction::MarkClassString(label) => e.mark_class(MARK_CLASS_STRING, *label)?,
+            Instruction::MarkClassCharacter(label) => e.mark_class(MARK_CLASS_CHARACTER, *label)?,
+            Instruction::MarkClassSymbol(label) => e.mark_class(MARK_CLASS_SYMBOL, *label)?,
+            Instruction::MarkClassU8(label) => e.mark_class(MARK_CLASS_U8, *label)?,
+            Instruction::MarkClassU16(label) => e.mark_class(MARK_CLASS_U16, *label)?,
+            Instruction::MarkClassU32(label) => e.mark_class(MARK_CLASS_U32, *label)?,
+            Instruction::MarkClassU64(label) => e.mark_class(MARK_CLASS_U64, *label)?,
+            Instruction::MarkClassU128(label) => e.mark_class(MARK_CLASS_U128, *label)?,
+            Instruction::MarkClassUBig(label) => e.mark_class(MARK_CLASS_UBIG, *label)?,
+            Instruction::MarkClassI8(label) => e.mark_class(MARK_CLASS_I8, *label)?,
+            Instruction::MarkClassI16(label) => e.mark_class(MARK_CLASS_I16, *label)?,
+            Instruction::MarkClassI32(label) => e.mark_class(MARK_CLASS_I32, *label)?,
+            Instruction::MarkClassI64(label) => e.mark_class(MARK_CLASS_I64, *label)?,
+            Instruction::MarkClassI128(label) => e.mark_class(MARK_CLASS_I128, *label)?,
+            Instruction::MarkClassIBig(label) => e.mark_class(MARK_CLASS_IBIG, *label)?,
+            Instruction::MarkClassF32(label) => e.mark_class(MARK_CLASS_F32, *label)?,
+            Instruction::MarkClassF64(label) => e.mark_class(MARK_CLASS_F64, *label)?,
+            Instruction::MarkClassFBig(label) => e.mark_class(MARK_CLASS_FBIG, *label)?,
+
+            Instruction::LoadConstString(ref value) => {
+                e.opcode(LOAD_CONST_STRING)?;
+                e.string(value)?;
+            }
+            Instruction::LoadConstCharacter(value) => e.constant(LOAD_CONST_CHARACTER, value)?,
+            Instruction::LoadConstSymbol(ref value) => {
+                e.opcode(LOAD_CONST_SYMBOL)?;
+                e.string(value)?;
+            }
+            Instruction::LoadConstU8(value) => e.constant(LOAD_CONST_U8, value)?,
+            Instruction::LoadConstU16(value) => e.constant(LOAD_CONST_U16, value)?,
+            Instruction::LoadConstU32(value) => e.constant(LOAD_CONST_U32, value)?,
+            Instruction::LoadConstU64(value) => e.constant(LOAD_CONST_U64, value)?,
+            Instruction::LoadConstU128(value) => e.constant(LOAD_CONST_U128, value)?,
+            Instruction::LoadConstUBig(value) => e.constant(LOAD_CONST_UBIG, value)?,
+            Instruction::LoadConstI8(value) => e.constant(LOAD_CONST_I8, value)?,
+            Instruction::LoadConstI16(value) => e.constant(LOAD_CONST_I16, value)?,
+            Instruction::LoadConstI32(value) => e.constant(LOAD_CONST_I32, value)?,
+            Instruction::LoadConstI64(value) => e.constant(LOAD_CONST_I64, value)?,
+            Instruction::LoadConstI128(value) => e.constant(LOAD_CONST_I128, value)?,
+            Instruction::LoadConstIBig(value) => e.constant(LOAD_CONST_IBIG, value)?,
+            Instruction::LoadConstF32(value) => e.constant(LOAD_CONST_F32, value)?,
+            Instruction::LoadConstF64(value) => e.constant(LOAD_CONST_F64, value)?,
+            Instruction::LoadConstFBig(value) => e.constant(LOAD_CONST_FBIG, value)?,
         }
+        Ok(e.written)
     }
 
-    fn deserialize<R: Read>(mut r: R) -> io::Result<Instruction> {
+    /// Decodes an instruction encoded with `encode`, looking its strings up
+    /// in the pool. Returns `None` at the end of the input, but fails if it
+    /// ends in the middle of an instruction.
+    pub fn decode<R: Read>(r: R, pool: &ConstantPool) -> io::Result<Option<Instruction>> {
+        let mut d = Decoder { r, pool };
         let mut opcode = [0u8];
-        if let 0 = r.read(&mut opcode)? {
-            return Err(io::ErrorKind::Interrupted.into());
+        if let 0 = d.r.read(&mut opcode)? {
+            return Ok(None);
         }
-        match opcode {
+        let instruction = match opcode {
             [NOOP] => Ok(Instruction::Noop),
             [HALT] => Ok(Instruction::Halt),
             [PANIC] => Ok(Instruction::Panic),
             [DUMP_STACK] => Ok(Instruction::DumpStack),
-            [DECLARE_CLASS] => Ok(Instruction::DeclareClass(r.deserialize()?)),
+            [DECLARE_CLASS] => Ok(Instruction::DeclareClass(d.string()?)),
             [DECLARE_VARIABLE] => Ok(Instruction::DeclareVariable(
-                r.deserialize()?,
-                r.deserialize()?,
-                r.deserialize()?,
-                r.deserialize()?,
-            )),
-            [USE_VARIABLE] => Ok(Instruction::UseVariable(r.deserialize()?)),
-            [DECLARE_METHOD] => Ok(Instruction::DeclareMethod(
-                r.deserialize()?,
-                r.deserialize()?,
-            )),
-            [USE_METHOD] => Ok(Instruction::UseMethod(r.deserialize()?)),
-            [OVERRIDE_METHOD] => Ok(Instruction::OverrideMethod(
-                r.deserialize()?,
-                r.deserialize()?,
+                d.string()?,
+                d.varint()?,
+                d.varint()?,
+                d.varint()?,
             )),
-            [LOAD_OBJECT] => Ok(Instruction::LoadObject(r.deserialize()?)),
+            [USE_VARIABLE] => Ok(Instruction::UseVariable(d.varint()?)),
+            [DECLARE_METHOD] => Ok(Instruction::DeclareMethod(d.string()?, d.varint()?)),
+            [USE_METHOD] => Ok(Instruction::UseMethod(d.varint()?)),
+            [OVERRIDE_METHOD] => Ok(Instruction::OverrideMethod(d.varint()?, d.varint()?)),
+            [LOAD_OBJECT] => Ok(Instruction::LoadObject(d.varint()?)),
             [CALL_METHOD] => Ok(Instruction::CallMethod(
-                r.deserialize()?,
-                r.deserialize()?,
-                r.deserialize()?,
-                r.deserialize()?,
+                d.varint()?,
+                d.string()?,
+                d.varint()?,
+                d.varint()?,
+            )),
+            [TAIL_CALL_METHOD] => Ok(Instruction::TailCallMethod(
+                d.varint()?,
+                d.string()?,
+                d.varint()?,
+                d.varint()?,
             )),
-            [CALL_NATIVE] => Ok(Instruction::CallNative(r.deserialize()?)),
-            [LOAD_LOCAL] => Ok(Instruction::LoadLocal(r.deserialize()?)),
-            [DROP_LOCAL] => Ok(Instruction::DropLocal(r.deserialize()?)),
-            [STORE_GLOBAL] => Ok(Instruction::StoreGlobal(r.deserialize()?)),
-            [LOAD_GLOBAL] => Ok(Instruction::LoadGlobal(r.deserialize()?)),
-            [LOAD_LAZY] => Ok(Instruction::LoadLazy(r.deserialize()?, r.deserialize()?)),
-            [RETURN] => Ok(Instruction::Return(r.deserialize()?)),
-            [RETURN_LAZY] => Ok(Instruction::ReturnLazy(r.deserialize()?)),
-
-            [MARK_CLASS_TRUE] => Ok(Instruction::MarkClassTrue(r.deserialize()?)),
-            [MARK_CLASS_FALSE] => Ok(Instruction::MarkClassFalse(r.deserialize()?)),
-
-            [MARK_CLASS_STRING] => Ok(Instruction::MarkClassString(r.deserialize()?)),
-            [MARK_CLASS_CHARACTER] => Ok(Instruction::MarkClassCharacter(r.deserialize()?)),
-            [MARK_CLASS_SYMBOL] => Ok(Instruction::MarkClassSymbol(r.deserialize()?)),
-            [MARK_CLASS_U8] => Ok(Instruction::MarkClassU8(r.deserialize()?)),
-            [MARK_CLASS_U16] => Ok(Instruction::MarkClassU16(r.deserialize()?)),
-            [MARK_CLASS_U32] => Ok(Instruction::MarkClassU32(r.deserialize()?)),
-            [MARK_CLASS_U64] => Ok(Instruction::MarkClassU64(r.deserialize()?)),
-            [MARK_CLASS_U128] => Ok(Instruction::MarkClassU128(r.deserialize()?)),
-            [MARK_CLASS_UBIG] => Ok(Instruction::MarkClassUBig(r.deserialize()?)),
-            [MARK_CLASS_I8] => Ok(Instruction::MarkClassI8(r.deserialize()?)),
-            [MARK_CLASS_I16] => Ok(Instruction::MarkClassI16(r.deserialize()?)),
-            [MARK_CLASS_I32] => Ok(Instruction::MarkClassI32(r.deserialize()?)),
-            [MARK_CLASS_I64] => Ok(Instruction::MarkClassI64(r.deserialize()?)),
-            [MARK_CLASS_I128] => Ok(Instruction::MarkClassI128(r.deserialize()?)),
-            [MARK_CLASS_IBIG] => Ok(Instruction::MarkClassIBig(r.deserialize()?)),
-            [MARK_CLASS_F32] => Ok(Instruction::MarkClassF32(r.deserialize()?)),
-            [MARK_CLASS_F64] => Ok(Instruction::MarkClassF64(r.deserialize()?)),
-            [MARK_CLASS_FBIG] => Ok(Instruction::MarkClassFBig(r.deserialize()?)),
-
-            [LOAD_CONST_STRING] => Ok(Instruction::LoadConstString(r.deserialize()?)),
-            [LOAD_CONST_CHARACTER] => Ok(Instruction::LoadConstCharacter(r.deserialize()?)),
-            [LOAD_CONST_SYMBOL] => Ok(Instruction::LoadConstSymbol(r.deserialize()?)),
-            [LOAD_CONST_U8] => Ok(Instruction::LoadConstU8(r.deserialize()?)),
-            [LOAD_CONST_U16] => Ok(Instruction::LoadConstU16(r.deserialize()?)),
-            [LOAD_CONST_U32] => Ok(Instruction::LoadConstU32(r.deserialize()?)),
-            [LOAD_CONST_U64] => Ok(Instruction::LoadConstU64(r.deserialize()?)),
-            [LOAD_CONST_U128] => Ok(Instruction::LoadConstU128(r.deserialize()?)),
-            [LOAD_CONST_UBIG] => Ok(Instruction::LoadConstUBig(r.deserialize()?)),
-            [LOAD_CONST_I8] => Ok(Instruction::LoadConstI8(r.deserialize()?)),
-            [LOAD_CONST_I16] => Ok(Instruction::LoadConstI16(r.deserialize()?)),
-            [LOAD_CONST_I32] => Ok(Instruction::LoadConstI32(r.deserialize()?)),
-            [LOAD_CONST_I64] => Ok(Instruction::LoadConstI64(r.deserialize()?)),
-            [LOAD_CONST_I128] => Ok(Instruction::LoadConstI128(r.deserialize()?)),
-            [LOAD_CONST_IBIG] => Ok(Instruction::LoadConstIBig(r.deserialize()?)),
-            [LOAD_CONST_F32] => Ok(Instruction::LoadConstF32(r.deserialize()?)),
-            [LOAD_CONST_F64] => Ok(Instruction::LoadConstF64(r.deserialize()?)),
-            [LOAD_CONST_FBIG] => Ok(Instruction::LoadConstFBig(r.deserialize()?)),
+            [CALL_NATIVE] => Ok(Instruction::CallNative(d.string()?.into())),
+            [LOAD_LOCAL] => Ok(Instruction::LoadLocal(d.index()?)),
+            [DROP_LOCAL] => Ok(Instruction::DropLocal(d.index()?)),
+            [STORE_GLOBAL] => Ok(Instruction::StoreGlobal(d.varint()?)),
+            [LOAD_GLOBAL] => Ok(Instruction::LoadGlobal(d.varint()?)),
+            [LOAD_LAZY] => Ok(Instruction::LoadLazy(d.index()?, d.varint()?)),
+            [RETURN] => Ok(Instruction::Return(d.index()?)),
+            [RETURN_LAZY] => Ok(Instruction::ReturnLazy(d.index()?)),
+            [RESCUE] => Ok(Instruction::Rescue(d.index()?, d.varint()?)),
+
+            [MARK_CLASS_TRUE] => Ok(Instruction::MarkClassTrue(d.varint()?)),
+            [MARK_CLASS_FALSE] => Ok(Instruction::MarkClassFalse(d.varint()?)),
+
+            [MARK_CLASS_STRING] => Ok(Instruction::MarkClassString(d.varint()?)),
+            [MARK_CLASS_CHARACTER] => Ok(Instruction::MarkClassCharacter(d.varint()?)),
+            [MARK_CLASS_SYMBOL] => Ok(Instruction::MarkClassSymbol(d.varint()?)),
+            [MARK_CLASS_U8] => Ok(Instruction::MarkClassU8(d.varint()?)),
+            [MARK_CLASS_U16] => Ok(Instruction::MarkClassU16(d.varint()?)),
+            [MARK_CLASS_U32] => Ok(Instruction::MarkClassU32(d.varint()?)),
+            [MARK_CLASS_U64] => Ok(Instruction::MarkClassU64(d.varint()?)),
+            [MARK_CLASS_U128] => Ok(Instruction::MarkClassU128(d.varint()?)),
+            [MARK_CLASS_UBIG] => Ok(Instruction::MarkClassUBig(d.varint()?)),
+            [MARK_CLASS_I8] => Ok(Instruction::MarkClassI8(d.varint()?)),
+            [MARK_CLASS_I16] => Ok(Instruction::MarkClassI16(d.varint()?)),
+            [MARK_CLASS_I32] => Ok(Instruction::MarkClassI32(d.varint()?)),
+            [MARK_CLASS_I64] => Ok(Instruction::MarkClassI64(d.varint()?)),
+            [MARK_CLASS_I128] => Ok(Instruction::MarkClassI128(d.varint()?)),
+            [MARK_CLASS_IBIG] => Ok(Instruction::MarkClassIBig(d.varint()?)),
+            [MARK_CLASS_F32] => Ok(Instruction::MarkClassF32(d.varint()?)),
+            [MARK_CLASS_F64] => Ok(Instruction::MarkClassF64(d.varint()?)),
+            [MARK_CLASS_FBIG] => Ok(Instruction::MarkClassFBig(d.varint()?)),
+
+            [LOAD_CONST_STRING] => Ok(Instruction::LoadConstString(d.string()?)),
+            [LOAD_CONST_CHARACTER] => Ok(Instruction::LoadConstCharacter(d.r.deserialize()?)),
+            [LOAD_CONST_SYMBOL] => Ok(Instruction::LoadConstSymbol(d.string()?)),
+            [LOAD_CONST_U8] => Ok(Instruction::LoadConstU8(d.r.deserialize()?)),
+            [LOAD_CONST_U16] => Ok(Instruction::LoadConstU16(d.r.deserialize()?)),
+            [LOAD_CONST_U32] => Ok(Instruction::LoadConstU32(d.r.deserialize()?)),
+            [LOAD_CONST_U64] => Ok(Instruction::LoadConstU64(d.r.deserialize()?)),
+            [LOAD_CONST_U128] => Ok(Instruction::LoadConstU128(d.r.deserialize()?)),
+            [LOAD_CONST_UBIG] => Ok(Instruction::LoadConstUBig(d.r.deserialize()?)),
+            [LOAD_CONST_I8] => Ok(Instruction::LoadConstI8(d.r.deserialize()?)),
+            [LOAD_CONST_I16] => Ok(Instruction::LoadConstI16(d.r.deserialize()?)),
+            [LOAD_CONST_I32] => Ok(Instruction::LoadConstI32(d.r.deserialize()?)),
+            [LOAD_CONST_I64] => Ok(Instruction::LoadConstI64(d.r.deserialize()?)),
+            [LOAD_CONST_I128] => Ok(Instruction::LoadConstI128(d.r.deserialize()?)),
+            [LOAD_CONST_IBIG] => Ok(Instruction::LoadConstIBig(d.r.deserialize()?)),
+            [LOAD_CONST_F32] => Ok(Instruction::LoadConstF32(d.r.deserialize()?)),
+            [LOAD_CONST_F64] => Ok(Instruction::LoadConstF64(d.r.deserialize()?)),
+            [LOAD_CONST_FBIG] => Ok(Instruction::LoadConstFBig(d.r.deserialize()?)),
 
             _ => Err(io::ErrorKind::InvalidInput.into()),
+        };
+        instruction.map(Some)
+    }
+}
+
+struct Encoder<'a, W> {
+    w: W,
+    pool: &'a mut ConstantPool,
+    written: usize,
+}
+
+impl<'a, W: Write> Encoder<'a, W> {
+    fn opcode(&mut self, opcode: u8) -> io::Result<()> {
+        self.written += opcode.serialize(&mut self.w)?;
+        Ok(())
+    }
+
+    fn varint(&mut self, value: u64) -> io::Result<()> {
+        self.written += VarInt(value).serialize(&mut self.w)?;
+        Ok(())
+    }
+
+    fn string(&mut self, value: &str) -> io::Result<()> {
+        let index = self.pool.intern(value);
+        self.varint(index)
+    }
+
+    fn mark_class(&mut self, opcode: u8, label: u64) -> io::Result<()> {
+        self.opcode(opcode)?;
+        self.varint(label)
+    }
+
+    fn constant<T: BytecodeEncoding>(&mut self, opcode: u8, value: &T) -> io::Result<()> {
+        self.opcode(opcode)?;
+        self.written += value.serialize(&mut self.w)?;
+        Ok(())
+    }
+}
+
+struct Decoder<'a, R> {
+    r: R,
+    pool: &'a ConstantPool,
+}
+
+impl<'a, R: Read> Decoder<'a, R> {
+    fn varint(&mut self) -> io::Result<u64> {
+        let VarInt(value) = self.r.deserialize()?;
+        Ok(value)
+    }
+
+    /// A local index or an arity, which must fit in 16 bits.
+    fn index(&mut self) -> io::Result<u16> {
+        let value = self.varint()?;
+        if value > u16::MAX as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "index out of range",
+            ));
+        }
+        Ok(value as u16)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let index = self.varint()?;
+        self.pool.get(index).map(Clone::clone)
+    }
+}
+
+/// An unsigned integer, encoded in as few bytes as it needs: seven bits per
+/// byte, least significant first, with the high bit set on every byte but
+/// the last. Addresses, indices and lengths are mostly small, so they're
+/// encoded this way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VarInt(pub u64);
+
+impl BytecodeEncoding for VarInt {
+    fn serialize<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        let mut value = self.0;
+        let mut written = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                return Ok(written + byte.serialize(&mut w)?);
+            }
+            written += (byte | 0x80).serialize(&mut w)?;
+        }
+    }
+
+    fn deserialize<R: Read>(mut r: R) -> io::Result<Self> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        loop {
+            let byte: u8 = r.deserialize()?;
+            if shift == 63 && byte > 1 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "variable-length integer overflows 64 bits",
+                ));
+            }
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(VarInt(value));
+            }
+            shift += 7;
         }
     }
 }
@@ -630,8 +722,8 @@ const SIGN_MINUS_TAG: u8 = 0xf2;
 impl BytecodeEncoding for fraction::Sign {
     fn serialize<W: Write>(&self, w: W) -> io::Result<usize> {
         match self {
-            fraction::Sign::Plus => SIGN_MINUS_TAG.serialize(w),
-            fraction::Sign::Minus => SIGN_PLUS_TAG.serialize(w),
+            fraction::Sign::Plus => SIGN_PLUS_TAG.serialize(w),
+            fraction::Sign::Minus => SIGN_MINUS_TAG.serialize(w),
         }
     }
 
@@ -661,53 +753,32 @@ where
     }
 }
 
+/// A list of instructions is encoded as the constant pool holding their
+/// strings, followed by the instructions themselves.
 impl BytecodeEncoding for Vec<Instruction> {
     fn serialize<W: Write>(&self, mut w: W) -> io::Result<usize> {
-        let mut written = 0;
-        for instruction in self.iter() {
-            written += instruction.serialize(&mut w)?;
-        }
-        Ok(written)
+        let mut pool = ConstantPool::new();
+        let mut instructions = vec![];
+        pool.encode(self, &mut instructions)?;
+        let written = pool.serialize(&mut w)?;
+        w.write_all(&instructions)?;
+        Ok(written + instructions.len())
     }
 
     fn deserialize<R: Read>(mut r: R) -> io::Result<Vec<Instruction>> {
-        let mut instructions = vec![];
-        loop {
-            match Instruction::deserialize(&mut r) {
-                Err(e) if e.kind() == io::ErrorKind::Interrupted => break,
-                Ok(instruction) => instructions.push(instruction),
-                Err(e) => return Err(e),
-            }
-        }
-        Ok(instructions)
+        let pool = ConstantPool::deserialize(&mut r)?;
+        pool.decode(r)
     }
 }
 
-const NATIVE_NUMBER_PLUS: u8 = 0x1a;
-const NATIVE_NUMBER_MINUS: u8 = 0x1b;
-const NATIVE_OBJECT_EQ: u8 = 0x1c;
-const NATIVE_OBJECT_AS_STRING: u8 = 0x1d;
-
 impl BytecodeEncoding for NativeMethod {
     fn serialize<W: Write>(&self, w: W) -> io::Result<usize> {
-        match self {
-            NativeMethod::Number_plus => NATIVE_NUMBER_PLUS,
-            NativeMethod::Number_minus => NATIVE_NUMBER_MINUS,
-            NativeMethod::Object_eq => NATIVE_OBJECT_EQ,
-            NativeMethod::Object_asString => NATIVE_OBJECT_AS_STRING,
-        }
-        .serialize(w)
+        self.name().to_string().serialize(w)
     }
 
     fn deserialize<R: Read>(mut r: R) -> io::Result<Self> {
-        let code: u8 = r.deserialize()?;
-        match code {
-            NATIVE_NUMBER_PLUS => Ok(NativeMethod::Number_plus),
-            NATIVE_NUMBER_MINUS => Ok(NativeMethod::Number_minus),
-            NATIVE_OBJECT_EQ => Ok(NativeMethod::Object_eq),
-            NATIVE_OBJECT_AS_STRING => Ok(NativeMethod::Object_asString),
-            _ => Err(io::ErrorKind::InvalidInput.into()),
-        }
+        let name: String = r.deserialize()?;
+        Ok(name.into())
     }
 }
 
@@ -0,0 +1,327 @@
+use crate::bytecode::*;
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// The strings that a list of instructions refers to, each stored once.
+///
+/// Names, string constants and call site URIs are interned into the pool as
+/// the instructions are encoded, so that a URI repeated by every call in a
+/// file only takes up space once, and instructions refer to it by index.
+#[derive(Debug, Clone, Default)]
+pub struct ConstantPool {
+    strings: Vec<String>,
+    indices: HashMap<String, u64>,
+}
+
+impl ConstantPool {
+    pub fn new() -> ConstantPool {
+        ConstantPool::default()
+    }
+
+    /// The index of the string in the pool, adding it if it isn't already.
+    pub fn intern(&mut self, string: &str) -> u64 {
+        if let Some(index) = self.indices.get(string) {
+            return *index;
+        }
+        let index = self.strings.len() as u64;
+        self.strings.push(string.into());
+        self.indices.insert(string.into(), index);
+        index
+    }
+
+    pub fn get(&self, index: u64) -> io::Result<&String> {
+        self.strings.get(index as usize).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("constant {} is not in the pool", index),
+            )
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+
+    /// Encodes the instructions, interning their strings into the pool.
+    pub fn encode<W: Write>(
+        &mut self,
+        instructions: &[Instruction],
+        mut w: W,
+    ) -> io::Result<usize> {
+        let mut written = 0;
+        for instruction in instructions {
+            written += instruction.encode(self, &mut w)?;
+        }
+        Ok(written)
+    }
+
+    /// Decodes instructions that were encoded into this pool, until the end
+    /// of the input.
+    pub fn decode<R: Read>(&self, mut r: R) -> io::Result<Vec<Instruction>> {
+        let mut instructions = vec![];
+        while let Some(instruction) = Instruction::decode(&mut r, self)? {
+            instructions.push(instruction);
+        }
+        Ok(instructions)
+    }
+}
+
+impl BytecodeEncoding for ConstantPool {
+    fn serialize<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        let mut written = VarInt(self.strings.len() as u64).serialize(&mut w)?;
+        for string in self.strings.iter() {
+            written += VarInt(string.len() as u64).serialize(&mut w)?;
+            w.write_all(string.as_bytes())?;
+            written += string.len();
+        }
+        Ok(written)
+    }
+
+    fn deserialize<R: Read>(mut r: R) -> io::Result<ConstantPool> {
+        let VarInt(count) = r.deserialize()?;
+        let mut pool = ConstantPool::new();
+        for _ in 0..count {
+            let VarInt(length) = r.deserialize()?;
+            let mut bytes = vec![];
+            r.by_ref().take(length).read_to_end(&mut bytes)?;
+            if (bytes.len() as u64) < length {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            let string = String::from_utf8(bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            // Strings are only stored once, but a pool that repeats one still
+            // decodes to the same instructions.
+            let index = pool.strings.len() as u64;
+            pool.indices.entry(string.clone()).or_insert(index);
+            pool.strings.push(string);
+        }
+        Ok(pool)
+    }
+}
+
+/// Encodes the contents of a file that keeps its strings in a constant pool,
+/// like an object or an image. The contents are kept until `finish`, because
+/// the pool comes first in the file, but is only complete once everything
+/// referring to it has been encoded.
+#[derive(Default)]
+pub struct PooledEncoder {
+    pub w: Vec<u8>,
+    pub pool: ConstantPool,
+}
+
+impl PooledEncoder {
+    pub fn new() -> PooledEncoder {
+        PooledEncoder::default()
+    }
+
+    pub fn varint(&mut self, value: u64) -> io::Result<()> {
+        VarInt(value).serialize(&mut self.w)?;
+        Ok(())
+    }
+
+    pub fn string(&mut self, value: &str) -> io::Result<()> {
+        let index = self.pool.intern(value);
+        self.varint(index)
+    }
+
+    /// Indices that may be missing are stored one up, so that zero means
+    /// none.
+    pub fn optional_index(&mut self, value: Option<u64>) -> io::Result<()> {
+        self.varint(value.map(|v| v + 1).unwrap_or(0))
+    }
+
+    /// Absent strings are encoded as zero, and others as their index in the
+    /// pool plus one.
+    pub fn optional_string(&mut self, value: &Option<String>) -> io::Result<()> {
+        let index = value.as_ref().map(|value| self.pool.intern(value));
+        self.optional_index(index)
+    }
+
+    pub fn instruction(&mut self, instruction: &Instruction) -> io::Result<()> {
+        instruction.encode(&mut self.pool, &mut self.w)?;
+        Ok(())
+    }
+
+    /// Encodes the number of instructions, followed by the instructions.
+    pub fn instructions(&mut self, instructions: &[Instruction]) -> io::Result<()> {
+        self.varint(instructions.len() as u64)?;
+        self.pool.encode(instructions, &mut self.w)?;
+        Ok(())
+    }
+
+    /// Writes the pool, followed by the contents.
+    pub fn finish<W: Write>(self, mut w: W) -> io::Result<usize> {
+        let written = self.pool.serialize(&mut w)?;
+        w.write_all(&self.w)?;
+        Ok(written + self.w.len())
+    }
+}
+
+/// Decodes what a `PooledEncoder` encoded, after the pool has been read.
+pub struct PooledDecoder<'a, R: Read> {
+    pub r: R,
+    pub pool: &'a ConstantPool,
+}
+
+impl<'a, R: Read> PooledDecoder<'a, R> {
+    pub fn new(r: R, pool: &'a ConstantPool) -> PooledDecoder<'a, R> {
+        PooledDecoder { r, pool }
+    }
+
+    pub fn varint(&mut self) -> io::Result<u64> {
+        let VarInt(value) = self.r.deserialize()?;
+        Ok(value)
+    }
+
+    pub fn string(&mut self) -> io::Result<String> {
+        let index = self.varint()?;
+        Ok(self.pool.get(index)?.clone())
+    }
+
+    pub fn optional_index(&mut self) -> io::Result<Option<u64>> {
+        Ok(match self.varint()? {
+            0 => None,
+            value => Some(value - 1),
+        })
+    }
+
+    pub fn optional_string(&mut self) -> io::Result<Option<String>> {
+        match self.optional_index()? {
+            None => Ok(None),
+            Some(index) => self.pool.get(index).map(|s| Some(s.clone())),
+        }
+    }
+
+    /// Decodes an instruction, which unlike at the end of a binary has to be
+    /// there.
+    pub fn instruction(&mut self) -> io::Result<Instruction> {
+        Instruction::decode(&mut self.r, self.pool)?
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))
+    }
+
+    pub fn instructions(&mut self) -> io::Result<Vec<Instruction>> {
+        let mut instructions = vec![];
+        for _ in 0..self.varint()? {
+            instructions.push(self.instruction()?);
+        }
+        Ok(instructions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interns_strings_once() {
+        let mut pool = ConstantPool::new();
+        assert_eq!(pool.intern("Main.loa"), 0);
+        assert_eq!(pool.intern("Loa/String"), 1);
+        assert_eq!(pool.intern("Main.loa"), 0);
+        assert_eq!(pool.len(), 2);
+
+        let pool = pool.rotate().unwrap();
+        assert_eq!(pool.get(0).unwrap(), "Main.loa");
+        assert_eq!(pool.get(1).unwrap(), "Loa/String");
+        assert!(pool.get(2).is_err());
+    }
+
+    #[test]
+    fn repeated_strings_shrink_instructions() {
+        let call = Instruction::CallMethod(12, "/home/loa/project/src/Main.loa".into(), 3, 14);
+        let size = |count| {
+            let mut bytes = vec![];
+            vec![call.clone(); count].serialize(&mut bytes).unwrap();
+            bytes.len()
+        };
+
+        // The opcode and three single byte integers, with the URI interned.
+        assert_eq!(size(2) - size(1), 5);
+        assert_eq!(vec![call; 3].rotate().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn encoded_sizes() {
+        let mut pool = ConstantPool::new();
+        let mut size = |instruction| pool.encode(&[instruction], std::io::sink()).unwrap();
+
+        // The opcode, then the method, the URI's index, the line and the
+        // character, each a single byte.
+        assert_eq!(
+            size(Instruction::CallMethod(12, "Main.loa".into(), 3, 14)),
+            5
+        );
+        assert_eq!(
+            size(Instruction::TailCallMethod(300, "Main.loa".into(), 3, 14)),
+            6
+        );
+        assert_eq!(size(Instruction::DeclareClass("Loa/String".into())), 2);
+        assert_eq!(size(Instruction::LoadConstString("Main.loa".into())), 2);
+        assert_eq!(size(Instruction::LoadLocal(200)), 3);
+        assert_eq!(size(Instruction::Halt), 1);
+
+        // The number of strings, then each of them after its length.
+        assert_eq!(pool.serialize(std::io::sink()).unwrap(), 1 + 9 + 11);
+    }
+
+    #[test]
+    fn truncated_instructions() {
+        let mut pool = ConstantPool::new();
+        let mut bytes = vec![];
+        pool.encode(
+            &[Instruction::CallMethod(12, "Main.loa".into(), 3, 14)],
+            &mut bytes,
+        )
+        .unwrap();
+        assert!(pool.decode(&bytes[..0]).unwrap().is_empty());
+
+        let error = pool.decode(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn pooled_contents() {
+        let mut e = PooledEncoder::new();
+        e.string("Main.loa").unwrap();
+        e.optional_string(&None).unwrap();
+        e.optional_string(&Some("Main.loa".into())).unwrap();
+        e.instructions(&[Instruction::LoadConstString("Main.loa".into())])
+            .unwrap();
+        let mut bytes = vec![];
+        let written = e.finish(&mut bytes).unwrap();
+        assert_eq!(written, bytes.len());
+
+        let mut r = bytes.as_slice();
+        let pool = ConstantPool::deserialize(&mut r).unwrap();
+        assert_eq!(pool.len(), 1);
+        let mut d = PooledDecoder::new(r, &pool);
+        assert_eq!(d.string().unwrap(), "Main.loa");
+        assert_eq!(d.optional_string().unwrap(), None);
+        assert_eq!(d.optional_string().unwrap(), Some("Main.loa".into()));
+        assert_matches!(
+            d.instructions().unwrap().as_slice(),
+            [Instruction::LoadConstString(s)] if s == "Main.loa"
+        );
+        assert_eq!(
+            d.instruction().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn variable_length_integers() {
+        for (value, length) in vec![(0, 1), (127, 1), (128, 2), (16_383, 2), (u64::MAX, 10)] {
+            let mut bytes = vec![];
+            assert_eq!(VarInt(value).serialize(&mut bytes).unwrap(), length);
+            assert_eq!(
+                VarInt::deserialize(bytes.as_slice()).unwrap(),
+                VarInt(value)
+            );
+        }
+        assert!(VarInt::deserialize([0xff; 11].as_ref()).is_err());
+    }
+}
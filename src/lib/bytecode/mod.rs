@@ -1,2 +1,14 @@
 mod bytecode;
 pub use self::bytecode::*;
+
+mod constant_pool;
+pub use self::constant_pool::*;
+
+mod binary;
+pub use self::binary::*;
+
+mod source_map;
+pub use self::source_map::*;
+
+mod verifier;
+pub use self::verifier::*;
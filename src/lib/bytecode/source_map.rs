@@ -0,0 +1,280 @@
+use crate::bytecode::*;
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// Maps instruction offsets in a program back to the Loa source they were
+/// generated from.
+///
+/// Consecutive instructions that stem from the same span share a single
+/// entry. Files are referred to by URI, and their code is only embedded when
+/// asked for, so that excerpts can be shown without access to the original
+/// sources.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+    entries: Vec<SourceMapEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceFile {
+    pub uri: String,
+    pub code: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct SourceMapEntry {
+    offset: u64,
+    length: u64,
+    file: usize,
+    start: (u64, u64),
+    end: (u64, u64),
+}
+
+/// Where in the source an instruction came from. Lines and characters are
+/// 1-based, like in diagnostics.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceLocation {
+    pub uri: String,
+    pub line: u64,
+    pub character: u64,
+    pub end_line: u64,
+    pub end_character: u64,
+
+    /// The full source line on which the span starts.
+    pub code: Option<String>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn files(&self) -> &Vec<SourceFile> {
+        &self.files
+    }
+
+    /// Adds a file to the map, along with its code if it should be embedded,
+    /// unless one with the same URI is already in it, and returns its index.
+    pub fn add_file<U: Into<String>>(&mut self, uri: U, code: Option<String>) -> usize {
+        let uri = uri.into();
+        if let Some(index) = self.files.iter().position(|f| f.uri == uri) {
+            return index;
+        }
+        self.files.push(SourceFile { uri, code });
+        self.files.len() - 1
+    }
+
+    /// Maps the instruction at `offset` to a span in a file previously added
+    /// with `add_file`. Instructions must be mapped in increasing order.
+    pub fn map(&mut self, offset: u64, file: usize, start: (u64, u64), end: (u64, u64)) {
+        if let Some(last) = self.entries.last_mut() {
+            if last.offset + last.length == offset
+                && last.file == file
+                && last.start == start
+                && last.end == end
+            {
+                last.length += 1;
+                return;
+            }
+        }
+        self.entries.push(SourceMapEntry {
+            offset,
+            length: 1,
+            file,
+            start,
+            end,
+        });
+    }
+
+    pub fn lookup(&self, pc: usize) -> Option<SourceLocation> {
+        let pc = pc as u64;
+        let index = match self.entries.binary_search_by_key(&pc, |e| e.offset) {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let entry = &self.entries[index];
+        if pc >= entry.offset + entry.length {
+            return None;
+        }
+        let file = self.files.get(entry.file)?;
+
+        Some(SourceLocation {
+            uri: file.uri.clone(),
+            line: entry.start.0,
+            character: entry.start.1,
+            end_line: entry.end.0,
+            end_character: entry.end.1,
+            code: file.code.as_ref().and_then(|code| {
+                code.lines()
+                    .nth((entry.start.0 as usize).saturating_sub(1))
+                    .map(String::from)
+            }),
+        })
+    }
+}
+
+impl SourceLocation {
+    /// The source line with the span underlined, like:
+    ///
+    /// ```text
+    /// 12 | x foo: y bar.
+    ///    | ^^^^^^^^^^^^
+    /// ```
+    pub fn excerpt(&self) -> Option<String> {
+        let code = self.code.as_ref()?;
+        let gutter = self.line.to_string();
+        let start = (self.character as usize).saturating_sub(1);
+        let end = if self.end_line == self.line {
+            (self.end_character as usize).saturating_sub(1)
+        } else {
+            code.chars().count()
+        };
+        Some(format!(
+            "{} | {}\n{} | {}{}",
+            gutter,
+            code,
+            " ".repeat(gutter.len()),
+            " ".repeat(start),
+            "^".repeat(end.saturating_sub(start).max(1))
+        ))
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.uri, self.line, self.character)
+    }
+}
+
+impl BytecodeEncoding for SourceMap {
+    fn serialize<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        let mut written = (self.files.len() as u32).serialize(&mut w)?;
+        for file in self.files.iter() {
+            written += file.uri.serialize(&mut w)?;
+            written += match file.code {
+                None => 0u8.serialize(&mut w)?,
+                Some(ref code) => {
+                    // Source files easily outgrow the u16 length of regular
+                    // strings.
+                    let written = 1u8.serialize(&mut w)? + (code.len() as u32).serialize(&mut w)?;
+                    w.write_all(code.as_bytes())?;
+                    written + code.len()
+                }
+            };
+        }
+
+        written += VarInt(self.entries.len() as u64).serialize(&mut w)?;
+        for entry in self.entries.iter() {
+            written += VarInt(entry.offset).serialize(&mut w)?;
+            written += VarInt(entry.length).serialize(&mut w)?;
+            written += VarInt(entry.file as u64).serialize(&mut w)?;
+            written += VarInt(entry.start.0).serialize(&mut w)?;
+            written += VarInt(entry.start.1).serialize(&mut w)?;
+            written += VarInt(entry.end.0).serialize(&mut w)?;
+            written += VarInt(entry.end.1).serialize(&mut w)?;
+        }
+        Ok(written)
+    }
+
+    fn deserialize<R: Read>(mut r: R) -> io::Result<SourceMap> {
+        let mut source_map = SourceMap::new();
+
+        let files: u32 = r.deserialize()?;
+        for _ in 0..files {
+            let uri: String = r.deserialize()?;
+            let embedded: u8 = r.deserialize()?;
+            let code = if embedded == 0 {
+                None
+            } else {
+                let length: u32 = r.deserialize()?;
+                let mut code = vec![0u8; length as usize];
+                r.read_exact(&mut code)?;
+                Some(
+                    String::from_utf8(code)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+                )
+            };
+            source_map.files.push(SourceFile { uri, code });
+        }
+
+        let VarInt(entries) = r.deserialize()?;
+        for _ in 0..entries {
+            let VarInt(offset) = r.deserialize()?;
+            let VarInt(length) = r.deserialize()?;
+            let VarInt(file) = r.deserialize()?;
+            let (VarInt(start_line), VarInt(start_character)) =
+                (r.deserialize()?, r.deserialize()?);
+            let (VarInt(end_line), VarInt(end_character)) = (r.deserialize()?, r.deserialize()?);
+            source_map.entries.push(SourceMapEntry {
+                offset,
+                length,
+                file: file as usize,
+                start: (start_line, start_character),
+                end: (end_line, end_character),
+            });
+        }
+        Ok(source_map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_map() -> SourceMap {
+        let mut map = SourceMap::new();
+        let main = map.add_file("Main.loa", Some("namespace N.\n\nx foo: y bar.\n".into()));
+        let other = map.add_file("Other.loa", None);
+        map.map(2, main, (3, 8), (3, 13));
+        map.map(3, main, (3, 8), (3, 13));
+        map.map(4, main, (3, 1), (3, 13));
+        map.map(6, other, (1, 1), (1, 1));
+        map
+    }
+
+    #[test]
+    fn consecutive_instructions_share_entries() {
+        let mut map = source_map();
+        assert_eq!(map.entries.len(), 3);
+        assert_eq!(map.add_file("Main.loa", None), 0);
+    }
+
+    #[test]
+    fn lookup() {
+        let map = source_map();
+
+        assert_eq!(map.lookup(0), None);
+        assert_eq!(map.lookup(3).unwrap().to_string(), "Main.loa:3:8");
+        assert_eq!(map.lookup(4).unwrap().to_string(), "Main.loa:3:1");
+        assert_eq!(map.lookup(5), None);
+        assert_eq!(map.lookup(6).unwrap().to_string(), "Other.loa:1:1");
+        assert_eq!(map.lookup(7), None);
+    }
+
+    #[test]
+    fn excerpt() {
+        assert_eq!(
+            source_map().lookup(2).unwrap().excerpt().unwrap(),
+            "3 | x foo: y bar.\n  |        ^^^^^"
+        );
+    }
+
+    #[test]
+    fn no_excerpt_without_code() {
+        let location = source_map().lookup(6).unwrap();
+
+        assert_eq!(location.code, None);
+        assert_eq!(location.excerpt(), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let map = source_map();
+        assert_eq!(map.rotate().unwrap(), map);
+    }
+}
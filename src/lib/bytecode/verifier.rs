@@ -0,0 +1,633 @@
+use crate::bytecode::Instruction;
+use crate::vm::selector_arity;
+use crate::*;
+
+/// Why a program was rejected by `verify`. Every error carries the offset
+/// of the instruction at fault.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationError {
+    /// The instruction at the offset refers to an address past the end of
+    /// the program.
+    OutOfBounds { offset: usize, address: u64 },
+    /// The instruction at the offset refers to a class that isn't declared
+    /// at the address.
+    UnknownClass { offset: usize, class: u64 },
+    /// The instruction at the offset refers to a method that isn't declared
+    /// at the address.
+    UnknownMethod { offset: usize, method: u64 },
+    /// The instruction at the offset refers to a variable that isn't
+    /// declared.
+    UnknownVariable { offset: usize, variable: u64 },
+    /// The instruction at the offset loads a global that is never stored.
+    UnknownGlobal { offset: usize, global: u64 },
+    /// The instruction at the offset declares a member before any class has
+    /// been declared.
+    OutsideClass { offset: usize },
+    /// The instruction at the offset pops more objects than the stack holds.
+    StackUnderflow {
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    /// The instruction at the offset refers to a local past the bottom of
+    /// the frame.
+    LocalOutOfFrame {
+        offset: usize,
+        index: u16,
+        available: usize,
+    },
+    /// The instruction at the offset returns, leaving objects behind on the
+    /// stack, or popping some that belong to the caller.
+    UnbalancedReturn {
+        offset: usize,
+        expected: usize,
+        actual: usize,
+    },
+    /// The instruction at the offset ends a kind of section it doesn't
+    /// belong in, like a `Return` outside of a method.
+    MisplacedInstruction { offset: usize },
+    /// The section that starts at the offset runs past the end of the
+    /// program.
+    RunsPastEnd { offset: usize },
+}
+
+pub type VerificationResult<T> = Result<T, VerificationError>;
+
+impl VerificationError {
+    pub fn offset(&self) -> usize {
+        match *self {
+            VerificationError::OutOfBounds { offset, .. }
+            | VerificationError::UnknownClass { offset, .. }
+            | VerificationError::UnknownMethod { offset, .. }
+            | VerificationError::UnknownVariable { offset, .. }
+            | VerificationError::UnknownGlobal { offset, .. }
+            | VerificationError::OutsideClass { offset }
+            | VerificationError::StackUnderflow { offset, .. }
+            | VerificationError::LocalOutOfFrame { offset, .. }
+            | VerificationError::UnbalancedReturn { offset, .. }
+            | VerificationError::MisplacedInstruction { offset }
+            | VerificationError::RunsPastEnd { offset } => offset,
+        }
+    }
+}
+
+impl fmt::Display for VerificationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VerificationError::OutOfBounds { offset, address } => write!(
+                f,
+                "address {} is past the end of the program at {}",
+                address, offset
+            ),
+            VerificationError::UnknownClass { offset, class } => {
+                write!(f, "unknown class {} used at {}", class, offset)
+            }
+            VerificationError::UnknownMethod { offset, method } => {
+                write!(f, "unknown method {} used at {}", method, offset)
+            }
+            VerificationError::UnknownVariable { offset, variable } => {
+                write!(f, "unknown variable {} used at {}", variable, offset)
+            }
+            VerificationError::UnknownGlobal { offset, global } => {
+                write!(
+                    f,
+                    "global {} is loaded at {}, but never stored",
+                    global, offset
+                )
+            }
+            VerificationError::OutsideClass { offset } => {
+                write!(f, "member declared outside of a class at {}", offset)
+            }
+            VerificationError::StackUnderflow {
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "stack underflow at {}: {} objects needed, but only {} available",
+                offset, needed, available
+            ),
+            VerificationError::LocalOutOfFrame {
+                offset,
+                index,
+                available,
+            } => write!(
+                f,
+                "local {} is out of the frame at {}, which holds {} objects",
+                index, offset, available
+            ),
+            VerificationError::UnbalancedReturn {
+                offset,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "unbalanced return at {}: it pops {} objects, but the stack holds {}",
+                offset, expected, actual
+            ),
+            VerificationError::MisplacedInstruction { offset } => write!(
+                f,
+                "the instruction at {} doesn't belong in its section",
+                offset
+            ),
+            VerificationError::RunsPastEnd { offset } => write!(
+                f,
+                "the section starting at {} runs past the end of the program",
+                offset
+            ),
+        }
+    }
+}
+
+impl Error for VerificationError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Section {
+    Main,
+    Method,
+    Lazy,
+}
+
+/// Checks a program before it runs, so that a malformed program is rejected
+/// up front, instead of panicking somewhere deep into its execution.
+///
+/// Every address that an instruction refers to must be that of a
+/// declaration of the right kind. The program is then checked in sections:
+/// the entry point at offset 0, up to `Halt`; every method from its declared
+/// offset, up to `Return`; and every lazy object and rescue clause from the
+/// offset it's loaded with, up to `ReturnLazy`. There are no jumps within a
+/// section, so the depth of the stack is known at every instruction, and
+/// checked against what the instruction pops, or the local it refers to.
+pub fn verify(program: &[Instruction]) -> VerificationResult<()> {
+    let declarations = Declarations::collect(program)?;
+
+    let mut sections = vec![];
+    if !program.is_empty() {
+        sections.push((0, Section::Main, 0));
+    }
+    for instruction in program.iter() {
+        match instruction {
+            Instruction::DeclareMethod(name, method) => {
+                sections.push((*method as usize, Section::Method, selector_arity(name) + 1))
+            }
+            Instruction::LoadLazy(arity, lazy) => {
+                sections.push((*lazy as usize, Section::Lazy, *arity as usize))
+            }
+            // A rescue clause gets the panic message on top of the objects
+            // it captured.
+            Instruction::Rescue(arity, clause) => {
+                sections.push((*clause as usize, Section::Lazy, *arity as usize + 1))
+            }
+            _ => {}
+        }
+    }
+
+    let mut verified = HashSet::new();
+    for section in sections {
+        if verified.insert(section) {
+            let (start, kind, depth) = section;
+            verify_section(program, &declarations, start, kind, depth)?;
+        }
+    }
+    Ok(())
+}
+
+/// Every declaration in the program, wherever it is. Declarations are made
+/// as they're executed, but since they're only ever made once, a reference
+/// to a declaration elsewhere in the program is assumed to be valid.
+struct Declarations {
+    classes: HashSet<u64>,
+    methods: HashMap<u64, String>,
+    variables: HashSet<u64>,
+    getters: HashSet<u64>,
+    setters: HashSet<u64>,
+    globals: HashSet<u64>,
+}
+
+impl Declarations {
+    fn collect(program: &[Instruction]) -> VerificationResult<Declarations> {
+        let mut declarations = Declarations {
+            classes: HashSet::new(),
+            methods: HashMap::new(),
+            variables: HashSet::new(),
+            getters: HashSet::new(),
+            setters: HashSet::new(),
+            globals: HashSet::new(),
+        };
+        for (offset, instruction) in program.iter().enumerate() {
+            match instruction {
+                Instruction::DeclareClass(_) => {
+                    declarations.classes.insert(offset as u64);
+                }
+                Instruction::DeclareMethod(name, method) => {
+                    if *method as usize >= program.len() {
+                        return Err(VerificationError::OutOfBounds {
+                            offset,
+                            address: *method,
+                        });
+                    }
+                    declarations.methods.insert(*method, name.clone());
+                }
+                Instruction::DeclareVariable(_, id, getter_id, setter_id) => {
+                    declarations.variables.insert(*id);
+                    declarations.getters.insert(*getter_id);
+                    declarations.setters.insert(*setter_id);
+                }
+                Instruction::StoreGlobal(global) => {
+                    declarations.globals.insert(*global);
+                }
+                _ => {}
+            }
+        }
+
+        let mut declaring = false;
+        for (offset, instruction) in program.iter().enumerate() {
+            match instruction {
+                Instruction::DeclareClass(_) => declaring = true,
+                Instruction::UseVariable(variable) => {
+                    if !declaring {
+                        return Err(VerificationError::OutsideClass { offset });
+                    }
+                    if !declarations.variables.contains(variable) {
+                        return Err(VerificationError::UnknownVariable {
+                            offset,
+                            variable: *variable,
+                        });
+                    }
+                }
+                Instruction::UseMethod(method) => {
+                    if !declaring {
+                        return Err(VerificationError::OutsideClass { offset });
+                    }
+                    declarations.method(offset, *method)?;
+                }
+                Instruction::OverrideMethod(source, target) => {
+                    if !declaring {
+                        return Err(VerificationError::OutsideClass { offset });
+                    }
+                    declarations.method(offset, *source)?;
+                    declarations.method(offset, *target)?;
+                }
+                Instruction::LoadObject(class)
+                | Instruction::MarkClassTrue(class)
+                | Instruction::MarkClassFalse(class)
+                | Instruction::MarkClassString(class)
+                | Instruction::MarkClassCharacter(class)
+                | Instruction::MarkClassSymbol(class)
+                | Instruction::MarkClassU8(class)
+                | Instruction::MarkClassU16(class)
+                | Instruction::MarkClassU32(class)
+                | Instruction::MarkClassU64(class)
+                | Instruction::MarkClassU128(class)
+                | Instruction::MarkClassUBig(class)
+                | Instruction::MarkClassI8(class)
+                | Instruction::MarkClassI16(class)
+                | Instruction::MarkClassI32(class)
+                | Instruction::MarkClassI64(class)
+                | Instruction::MarkClassI128(class)
+                | Instruction::MarkClassIBig(class)
+                | Instruction::MarkClassF32(class)
+                | Instruction::MarkClassF64(class)
+                | Instruction::MarkClassFBig(class) => {
+                    if !declarations.classes.contains(class) {
+                        return Err(VerificationError::UnknownClass {
+                            offset,
+                            class: *class,
+                        });
+                    }
+                }
+                Instruction::CallMethod(method, _, _, _)
+                | Instruction::TailCallMethod(method, _, _, _) => {
+                    declarations.selector_arity(offset, *method)?;
+                }
+                Instruction::LoadGlobal(global) => {
+                    if !declarations.globals.contains(global) {
+                        return Err(VerificationError::UnknownGlobal {
+                            offset,
+                            global: *global,
+                        });
+                    }
+                }
+                Instruction::LoadLazy(_, lazy) | Instruction::Rescue(_, lazy)
+                    if *lazy as usize >= program.len() =>
+                {
+                    return Err(VerificationError::OutOfBounds {
+                        offset,
+                        address: *lazy,
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(declarations)
+    }
+
+    fn method(&self, offset: usize, method: u64) -> VerificationResult<&String> {
+        self.methods
+            .get(&method)
+            .ok_or(VerificationError::UnknownMethod { offset, method })
+    }
+
+    /// How many arguments a message sent with the selector takes, which is
+    /// either a method, or the getter or setter of a variable.
+    fn selector_arity(&self, offset: usize, selector: u64) -> VerificationResult<usize> {
+        if self.getters.contains(&selector) {
+            Ok(0)
+        } else if self.setters.contains(&selector) {
+            Ok(1)
+        } else {
+            self.method(offset, selector)
+                .map(|name| selector_arity(name))
+        }
+    }
+}
+
+fn verify_section(
+    program: &[Instruction],
+    declarations: &Declarations,
+    start: usize,
+    section: Section,
+    mut depth: usize,
+) -> VerificationResult<()> {
+    for (offset, instruction) in program.iter().enumerate().skip(start) {
+        let pop = |depth: usize, needed: usize| {
+            if depth < needed {
+                Err(VerificationError::StackUnderflow {
+                    offset,
+                    needed,
+                    available: depth,
+                })
+            } else {
+                Ok(depth - needed)
+            }
+        };
+        let local = |depth: usize, index: u16| {
+            if index as usize >= depth {
+                Err(VerificationError::LocalOutOfFrame {
+                    offset,
+                    index,
+                    available: depth,
+                })
+            } else {
+                Ok(())
+            }
+        };
+        let ret = |depth: usize, arity: u16, expected: Section| {
+            if section != expected {
+                Err(VerificationError::MisplacedInstruction { offset })
+            } else if depth != arity as usize + 1 {
+                Err(VerificationError::UnbalancedReturn {
+                    offset,
+                    expected: arity as usize + 1,
+                    actual: depth,
+                })
+            } else {
+                Ok(())
+            }
+        };
+
+        match instruction {
+            Instruction::Halt => {
+                return match section {
+                    Section::Main => Ok(()),
+                    _ => Err(VerificationError::MisplacedInstruction { offset }),
+                };
+            }
+            Instruction::Panic => return Ok(()),
+            Instruction::Return(arity) => return ret(depth, *arity, Section::Method),
+            Instruction::ReturnLazy(arity) => return ret(depth, *arity, Section::Lazy),
+
+            Instruction::Noop
+            | Instruction::DumpStack
+            | Instruction::DeclareClass(_)
+            | Instruction::DeclareVariable(_, _, _, _)
+            | Instruction::UseVariable(_)
+            | Instruction::DeclareMethod(_, _)
+            | Instruction::UseMethod(_)
+            | Instruction::OverrideMethod(_, _)
+            | Instruction::MarkClassTrue(_)
+            | Instruction::MarkClassFalse(_)
+            | Instruction::MarkClassString(_)
+            | Instruction::MarkClassCharacter(_)
+            | Instruction::MarkClassSymbol(_)
+            | Instruction::MarkClassU8(_)
+            | Instruction::MarkClassU16(_)
+            | Instruction::MarkClassU32(_)
+            | Instruction::MarkClassU64(_)
+            | Instruction::MarkClassU128(_)
+            | Instruction::MarkClassUBig(_)
+            | Instruction::MarkClassI8(_)
+            | Instruction::MarkClassI16(_)
+            | Instruction::MarkClassI32(_)
+            | Instruction::MarkClassI64(_)
+            | Instruction::MarkClassI128(_)
+            | Instruction::MarkClassIBig(_)
+            | Instruction::MarkClassF32(_)
+            | Instruction::MarkClassF64(_)
+            | Instruction::MarkClassFBig(_) => {}
+
+            Instruction::LoadObject(_)
+            | Instruction::LoadGlobal(_)
+            | Instruction::LoadConstString(_)
+            | Instruction::LoadConstCharacter(_)
+            | Instruction::LoadConstSymbol(_)
+            | Instruction::LoadConstU8(_)
+            | Instruction::LoadConstU16(_)
+            | Instruction::LoadConstU32(_)
+            | Instruction::LoadConstU64(_)
+            | Instruction::LoadConstU128(_)
+            | Instruction::LoadConstUBig(_)
+            | Instruction::LoadConstI8(_)
+            | Instruction::LoadConstI16(_)
+            | Instruction::LoadConstI32(_)
+            | Instruction::LoadConstI64(_)
+            | Instruction::LoadConstI128(_)
+            | Instruction::LoadConstIBig(_)
+            | Instruction::LoadConstF32(_)
+            | Instruction::LoadConstF64(_)
+            | Instruction::LoadConstFBig(_) => depth += 1,
+
+            // A tail call to a method never returns here, but a tail call to
+            // a variable accessor does, so it's followed by a `Return`.
+            Instruction::TailCallMethod(_, _, _, _) if section != Section::Method => {
+                return Err(VerificationError::MisplacedInstruction { offset });
+            }
+            Instruction::CallMethod(method, _, _, _)
+            | Instruction::TailCallMethod(method, _, _, _) => {
+                depth = pop(depth, declarations.selector_arity(offset, *method)? + 1)? + 1;
+            }
+            Instruction::CallNative(method) => {
+                let selector = method.name().rsplit('#').next().unwrap_or("");
+                depth = pop(depth, selector_arity(selector) + 1)? + 1;
+            }
+            Instruction::LoadLocal(index) => {
+                local(depth, *index)?;
+                depth += 1;
+            }
+            Instruction::DropLocal(index) => {
+                local(depth, *index)?;
+                depth -= 1;
+            }
+            Instruction::StoreGlobal(_) => depth = pop(depth, 1)?,
+            Instruction::LoadLazy(arity, _) => depth = pop(depth, *arity as usize)? + 1,
+            Instruction::Rescue(arity, _) => depth = pop(depth, *arity as usize + 1)? + 1,
+        }
+    }
+    Err(VerificationError::RunsPastEnd { offset: start })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::Parser;
+
+    fn verify_assembly(code: &str) -> VerificationResult<()> {
+        let instructions: Vec<Instruction> = Parser::new().parse(code).unwrap().into();
+        verify(&instructions)
+    }
+
+    fn program(main: &str, methods: &str) -> String {
+        format!(
+            r#"
+                DeclareMethod "add:" @A#add:
+            @A
+                DeclareClass "A"
+                DeclareVariable "value" @value @value_get @value_set
+                UseVariable @value
+                UseMethod @A#add:
+            {}
+                Halt
+            {}
+            @lazy
+                LoadLocal 0
+                ReturnLazy 1
+            @value
+                Noop
+            @value_get
+                Noop
+            @value_set
+                Noop
+            "#,
+            main, methods
+        )
+    }
+
+    const ADD: &str = r#"
+        @A#add:
+            LoadLocal 1
+            CallMethod @value_get "test.loa" 1 1
+            Return 2
+    "#;
+
+    #[test]
+    fn valid_program() {
+        assert_eq!(
+            verify_assembly(&program(
+                r#"
+                LoadObject @A
+                LoadObject @A
+                CallMethod @A#add: "test.loa" 1 1
+                LoadLazy 1 @lazy
+                "#,
+                ADD
+            )),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn unknown_addresses() {
+        assert_eq!(
+            verify_assembly("LoadObject @a\n@a\nHalt"),
+            Err(VerificationError::UnknownClass {
+                offset: 0,
+                class: 1
+            })
+        );
+        assert_eq!(
+            verify_assembly("DeclareMethod \"a\" @a\nHalt\n@a"),
+            Err(VerificationError::OutOfBounds {
+                offset: 0,
+                address: 2
+            })
+        );
+        assert_eq!(
+            verify_assembly(&program(
+                "LoadObject @A\nCallMethod @lazy \"test.loa\" 1 1",
+                ADD
+            )),
+            Err(VerificationError::UnknownMethod {
+                offset: 6,
+                method: 11
+            })
+        );
+        assert_eq!(
+            verify_assembly(&program("OverrideMethod @lazy @A#add:", ADD)),
+            Err(VerificationError::UnknownMethod {
+                offset: 5,
+                method: 10
+            })
+        );
+    }
+
+    #[test]
+    fn rescue_clauses() {
+        let rescue = |clause: &str| {
+            verify_assembly(&program(
+                r#"
+                LoadObject @A
+                LoadLazy 1 @lazy
+                LoadObject @A
+                Rescue 1 @clause
+                "#,
+                &format!("{}\n@clause\n{}\nReturnLazy 2", ADD, clause),
+            ))
+        };
+
+        // The clause gets the panic message on top of what it captured.
+        assert_eq!(rescue("LoadLocal 1"), Ok(()));
+        assert_eq!(
+            rescue("LoadLocal 2"),
+            Err(VerificationError::LocalOutOfFrame {
+                offset: 13,
+                index: 2,
+                available: 2
+            })
+        );
+    }
+
+    #[test]
+    fn stack_effects() {
+        assert_eq!(
+            verify_assembly(&program(
+                "LoadObject @A\nCallMethod @A#add: \"test.loa\" 1 1",
+                ADD
+            )),
+            Err(VerificationError::StackUnderflow {
+                offset: 6,
+                needed: 2,
+                available: 1
+            })
+        );
+        assert_eq!(
+            verify_assembly(&program("LoadLocal 0", ADD)),
+            Err(VerificationError::LocalOutOfFrame {
+                offset: 5,
+                index: 0,
+                available: 0
+            })
+        );
+        assert_eq!(
+            verify_assembly(&program("", &ADD.replace("Return 2", "ReturnLazy 2"))),
+            Err(VerificationError::MisplacedInstruction { offset: 8 })
+        );
+        assert_eq!(
+            verify_assembly(&program("", &ADD.replace("Return 2", "Return 3"))),
+            Err(VerificationError::UnbalancedReturn {
+                offset: 8,
+                expected: 4,
+                actual: 3
+            })
+        );
+    }
+}
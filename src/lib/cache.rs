@@ -1,9 +1,30 @@
 use crate::*;
+use std::collections::HashSet;
 use std::hash::Hash;
 
 #[derive(Clone)]
 pub struct Cache<K, T> {
     mutex: Arc<Mutex<HashMap<K, T>>>,
+    cycles: Arc<Mutex<Cycles<K>>>,
+}
+
+/// Bookkeeping for `Cache::gate`. Values that are computed while a cycle is
+/// broken depend on the default placeholder of a value that's still being
+/// computed, so they're only kept until the outermost gate is done.
+struct Cycles<K> {
+    pending: HashSet<K>,
+    provisional: Vec<K>,
+    hits: usize,
+}
+
+impl<K: Hash + Eq> Default for Cycles<K> {
+    fn default() -> Self {
+        Cycles {
+            pending: HashSet::new(),
+            provisional: vec![],
+            hits: 0,
+        }
+    }
 }
 
 impl<K, T> Cache<K, T>
@@ -13,6 +34,7 @@ where
     pub fn new() -> Cache<K, T> {
         Cache {
             mutex: Arc::new(Mutex::new(HashMap::new())),
+            cycles: Arc::new(Mutex::new(Cycles::default())),
         }
     }
 }
@@ -23,21 +45,40 @@ where
     K: Hash + Eq + Clone,
 {
     pub fn gate<F: FnOnce() -> T>(&self, key: &K, f: F) -> T {
+        let hits_before;
         {
-            if let Ok(mut cache) = self.mutex.lock() {
-                if let Some(type_) = cache.get(key) {
-                    return type_.clone();
-                }
+            let (mut cache, mut cycles) = match (self.mutex.lock(), self.cycles.lock()) {
+                (Ok(cache), Ok(cycles)) => (cache, cycles),
+                _ => return f(),
+            };
 
-                cache.insert(key.clone(), Default::default());
+            if let Some(type_) = cache.get(key) {
+                if cycles.pending.contains(key) {
+                    cycles.hits += 1;
+                }
+                return type_.clone();
             }
+
+            cache.insert(key.clone(), Default::default());
+            cycles.pending.insert(key.clone());
+            hits_before = cycles.hits;
         }
 
         let result = f();
 
         {
-            if let Ok(mut cache) = self.mutex.lock() {
+            if let (Ok(mut cache), Ok(mut cycles)) = (self.mutex.lock(), self.cycles.lock()) {
                 cache.insert(key.clone(), result.clone());
+                cycles.pending.remove(key);
+
+                if cycles.pending.is_empty() {
+                    for provisional in std::mem::replace(&mut cycles.provisional, vec![]) {
+                        cache.remove(&provisional);
+                    }
+                    cycles.hits = 0;
+                } else if cycles.hits != hits_before {
+                    cycles.provisional.push(key.clone());
+                }
             }
         }
 
@@ -0,0 +1,404 @@
+// Every pointer passed to these functions must either be null, or have been
+// returned by this library and not yet released.
+#![allow(clippy::missing_safety_doc)]
+
+use crate::bytecode::Binary;
+use crate::vm::*;
+use crate::*;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr::{null, null_mut};
+use std::slice;
+
+/// A VM, and the program loaded into it.
+///
+/// Functions that can fail return null or false, and leave a message to be
+/// read with `loa_vm_last_error`.
+pub struct LoaVM {
+    program: Option<Program<CRuntime>>,
+    error: Option<CString>,
+}
+
+/// An object passed to, or returned from, a program. Every value returned
+/// by this library must be released with `loa_value_free`.
+pub struct LoaValue {
+    object: Arc<Object>,
+
+    /// The `True` and `False` objects of the VM that the value came from.
+    /// Every VM has booleans of its own, and they have to outlive the VM if
+    /// the value does.
+    booleans: (Option<Arc<Object>>, Option<Arc<Object>>),
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoaValueKind {
+    Object,
+    Boolean,
+    String,
+    Character,
+    Symbol,
+    Integer,
+    Float,
+}
+
+/// Grants programs no capabilities, since the host decides what they may
+/// do through the messages it sends them.
+struct CRuntime;
+
+impl Runtime for CRuntime {
+    fn print_panic(message: String, call_stack: CallStack) {
+        eprintln!("{}\n{:?}", message, call_stack);
+    }
+}
+
+type FFIResult<T> = Result<T, Box<dyn Error>>;
+
+impl LoaVM {
+    fn program(&mut self) -> FFIResult<&mut Program<CRuntime>> {
+        match self.program {
+            Some(ref mut program) => Ok(program),
+            None => Err("no program has been loaded".into()),
+        }
+    }
+
+    /// Calls `f`, and keeps its error, or the Rust panic it caused, as the
+    /// last error.
+    fn guard<T, F>(&mut self, default: T, f: F) -> T
+    where
+        F: FnOnce(&mut LoaVM) -> FFIResult<T>,
+    {
+        self.error = None;
+        let result = match catch_unwind(AssertUnwindSafe(|| f(self))) {
+            Ok(result) => result,
+            Err(_) => Err("the VM crashed".into()),
+        };
+        match result {
+            Ok(value) => value,
+            Err(e) => {
+                let message = e.to_string().replace('\0', "\\0");
+                self.error = CString::new(message).ok();
+                default
+            }
+        }
+    }
+}
+
+impl LoaValue {
+    fn as_bool(&self) -> Option<bool> {
+        let (ref t, ref f) = self.booleans;
+        if t.as_ref() == Some(&self.object) {
+            Some(true)
+        } else if f.as_ref() == Some(&self.object) {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+fn into_raw(vm: &VM, value: Option<Arc<Object>>) -> *mut LoaValue {
+    match value {
+        Some(object) => Box::into_raw(Box::new(LoaValue {
+            object,
+            booleans: (vm.box_bool(true), vm.box_bool(false)),
+        })),
+        None => null_mut(),
+    }
+}
+
+unsafe fn string<'a>(s: *const c_char) -> FFIResult<&'a str> {
+    if s.is_null() {
+        return Err("expected a string, but got null".into());
+    }
+    Ok(CStr::from_ptr(s).to_str()?)
+}
+
+/// Creates a VM without a program. Release it with `loa_vm_free`.
+#[no_mangle]
+pub extern "C" fn loa_vm_new() -> *mut LoaVM {
+    Box::into_raw(Box::new(LoaVM {
+        program: None,
+        error: None,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn loa_vm_free(vm: *mut LoaVM) {
+    if !vm.is_null() {
+        drop(Box::from_raw(vm));
+    }
+}
+
+/// The message of the last error, or null if the last call succeeded. The
+/// message is owned by the VM, and lives until the next call.
+#[no_mangle]
+pub unsafe extern "C" fn loa_vm_last_error(vm: *const LoaVM) -> *const c_char {
+    match vm.as_ref().and_then(|vm| vm.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => null(),
+    }
+}
+
+/// Loads a program from the contents of a `.loabin` file, and runs its
+/// entry point. Returns the result of the entry point, or null if the
+/// program doesn't have one, or failed.
+#[no_mangle]
+pub unsafe extern "C" fn loa_vm_load(
+    vm: *mut LoaVM,
+    bytecode: *const u8,
+    length: usize,
+) -> *mut LoaValue {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return null_mut(),
+    };
+    vm.guard(null_mut(), |vm| {
+        if bytecode.is_null() {
+            return Err("expected bytecode, but got null".into());
+        }
+        let binary = Binary::read(slice::from_raw_parts(bytecode, length))?;
+        let mut program = Program::load(binary)?;
+        let result = program.take_result()?;
+        let value = into_raw(program.vm(), result);
+        vm.program = Some(program);
+        Ok(value)
+    })
+}
+
+/// An instance of the class with the qualified name, like
+/// `"Example/Calculator"`, to send messages to.
+#[no_mangle]
+pub unsafe extern "C" fn loa_vm_class(
+    vm: *mut LoaVM,
+    qualified_name: *const c_char,
+) -> *mut LoaValue {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return null_mut(),
+    };
+    vm.guard(null_mut(), |vm| {
+        let name = string(qualified_name)?;
+        let program = vm.program()?;
+        let class = program.class(name)?;
+        Ok(into_raw(program.vm(), Some(class)))
+    })
+}
+
+/// Sends a message, like `"add:to:"`, to the receiver, and returns the
+/// result. The arguments are not released.
+#[no_mangle]
+pub unsafe extern "C" fn loa_vm_send(
+    vm: *mut LoaVM,
+    receiver: *const LoaValue,
+    selector: *const c_char,
+    arguments: *const *const LoaValue,
+    argument_count: usize,
+) -> *mut LoaValue {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return null_mut(),
+    };
+    vm.guard(null_mut(), |vm| {
+        let receiver = receiver
+            .as_ref()
+            .ok_or("expected a receiver, but got null")?;
+        let selector = string(selector)?;
+        let arguments = if argument_count == 0 {
+            vec![]
+        } else if arguments.is_null() {
+            return Err("expected arguments, but got null".into());
+        } else {
+            let mut objects = vec![];
+            for argument in slice::from_raw_parts(arguments, argument_count) {
+                let argument = argument
+                    .as_ref()
+                    .ok_or("expected an argument, but got null")?;
+                objects.push(argument.object.clone());
+            }
+            objects
+        };
+        let program = vm.program()?;
+        let result: Arc<Object> = program.send(receiver.object.clone(), selector, arguments)?;
+        Ok(into_raw(program.vm(), Some(result)))
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_free(value: *mut LoaValue) {
+    if !value.is_null() {
+        drop(Box::from_raw(value));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_kind(value: *const LoaValue) -> LoaValueKind {
+    let value = match value.as_ref() {
+        Some(value) => value,
+        None => return LoaValueKind::Object,
+    };
+    if value.as_bool().is_some() {
+        return LoaValueKind::Boolean;
+    }
+    match value.object.const_value {
+        ConstValue::String(_) => LoaValueKind::String,
+        ConstValue::Character(_) => LoaValueKind::Character,
+        ConstValue::Symbol(_) => LoaValueKind::Symbol,
+        ref n => match NumberKind::of(n) {
+            Some(kind) if kind.is_float() => LoaValueKind::Float,
+            Some(_) => LoaValueKind::Integer,
+            None => LoaValueKind::Object,
+        },
+    }
+}
+
+/// Reads a boolean into `out`. Returns false if the value isn't a boolean.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_as_bool(value: *const LoaValue, out: *mut bool) -> bool {
+    match (value.as_ref(), out.as_mut()) {
+        (Some(value), Some(out)) => match value.as_bool() {
+            Some(b) => {
+                *out = b;
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Reads an integer of any kind into `out`. Returns false if the value isn't
+/// an integer, or doesn't fit.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_as_i64(value: *const LoaValue, out: *mut i64) -> bool {
+    match (value.as_ref(), out.as_mut()) {
+        (Some(value), Some(out)) => {
+            match integer_value(&value.object.const_value).and_then(|n| i64::try_from(n).ok()) {
+                Some(n) => {
+                    *out = n;
+                    true
+                }
+                None => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Like `loa_value_as_i64`, for integers that fit in a `uint64_t`.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_as_u64(value: *const LoaValue, out: *mut u64) -> bool {
+    match (value.as_ref(), out.as_mut()) {
+        (Some(value), Some(out)) => {
+            match integer_value(&value.object.const_value).and_then(|n| u64::try_from(n).ok()) {
+                Some(n) => {
+                    *out = n;
+                    true
+                }
+                None => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Reads a number of any kind into `out`, rounded to the nearest double.
+/// Returns false if the value isn't a number.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_as_f64(value: *const LoaValue, out: *mut f64) -> bool {
+    match (value.as_ref(), out.as_mut()) {
+        (Some(value), Some(out)) => match float_value(&value.object.const_value) {
+            Some(n) => {
+                *out = n;
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+/// Formats the value like the VM prints results. Release the string with
+/// `loa_string_free`.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_to_string(value: *const LoaValue) -> *mut c_char {
+    match value.as_ref() {
+        Some(value) => match CString::new(value.object.to_string().replace('\0', "\\0")) {
+            Ok(s) => s.into_raw(),
+            Err(_) => null_mut(),
+        },
+        None => null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn loa_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Creates a boolean. Like the other values, it can only be created once the
+/// loaded program has declared its class.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_bool(vm: *mut LoaVM, value: bool) -> *mut LoaValue {
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return null_mut(),
+    };
+    vm.guard(null_mut(), |vm| {
+        let vm = vm.program()?.vm();
+        let object = vm.box_bool(value).ok_or_else(|| {
+            EmbeddingError::ClassNotLoaded(if value { "Loa/True" } else { "Loa/False" })
+        })?;
+        Ok(into_raw(vm, Some(object)))
+    })
+}
+
+/// Creates a `Loa/Int64`.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_i64(vm: *mut LoaVM, value: i64) -> *mut LoaValue {
+    box_value(vm, "Loa/Int64", || Ok(ConstValue::I64(value)))
+}
+
+/// Creates a `Loa/UInt64`.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_u64(vm: *mut LoaVM, value: u64) -> *mut LoaValue {
+    box_value(vm, "Loa/UInt64", || Ok(ConstValue::U64(value)))
+}
+
+/// Creates a `Loa/Float64`.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_f64(vm: *mut LoaVM, value: f64) -> *mut LoaValue {
+    box_value(vm, "Loa/Float64", || Ok(ConstValue::F64(value)))
+}
+
+/// Creates a `Loa/String` from a UTF-8 string, which is copied.
+#[no_mangle]
+pub unsafe extern "C" fn loa_value_string(vm: *mut LoaVM, value: *const c_char) -> *mut LoaValue {
+    box_value(vm, "Loa/String", || {
+        Ok(ConstValue::String(string(value)?.into()))
+    })
+}
+
+/// Boxes a constant with the class that the program loaded into this VM
+/// declared for it.
+unsafe fn box_value<F>(vm: *mut LoaVM, class_name: &'static str, value: F) -> *mut LoaValue
+where
+    F: FnOnce() -> FFIResult<ConstValue>,
+{
+    let vm = match vm.as_mut() {
+        Some(vm) => vm,
+        None => return null_mut(),
+    };
+    vm.guard(null_mut(), |vm| {
+        let value = value()?;
+        let vm = vm.program()?.vm();
+        let object = vm
+            .box_const(value)
+            .ok_or(EmbeddingError::ClassNotLoaded(class_name))?;
+        Ok(into_raw(vm, Some(object)))
+    })
+}
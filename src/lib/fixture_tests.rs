@@ -8,6 +8,11 @@ extern crate simple_logging;
 #[derive(Deserialize)]
 struct FixtureConfig {
     main_class: Option<String>,
+    /// Whether the fixture runs on a runtime that grants every capability.
+    #[serde(default)]
+    effects: bool,
+    /// The limits of the VM that runs the fixture, like `call-depth=1000`.
+    limits: Option<String>,
     expected: FixtureExpectations,
 }
 
@@ -17,6 +22,36 @@ struct FixtureExpectations {
     stdout: Vec<String>,
 }
 
+/// The fixtures that use constructs the WebAssembly backend doesn't lower
+/// yet, and the reason why. Any other fixture must run the same as in the VM,
+/// and these ones must keep reporting that they're unsupported, so that the
+/// list is updated when the backend catches up.
+const UNSUPPORTED_IN_WASM: &[(&str, &str)] = &[
+    ("DeniedCapabilities", "rescuing panics"),
+    ("NumberComparison", "big floats"),
+    ("NumberConversions", "big floats"),
+    ("NumberDivision", "big floats"),
+    ("NumberMultiplication", "integers that don't fit in 64 bits"),
+    ("NumberNegation", "big floats"),
+    ("NumberRemainder", "integers that don't fit in 64 bits"),
+    ("NumberSubtraction", "big floats"),
+    ("ProcessAndFiles", "effects"),
+    ("RescuedPanics", "rescuing panics"),
+];
+
+/// The runtime of fixtures that have effects, like reading files.
+struct EffectfulRuntime;
+
+impl vm::Runtime for EffectfulRuntime {
+    fn print_panic(message: String, call_stack: vm::CallStack) {
+        <() as vm::Runtime>::print_panic(message, call_stack)
+    }
+
+    fn capabilities() -> vm::Capabilities {
+        vm::Capabilities::all()
+    }
+}
+
 #[test]
 fn fixtures() {
     simple_logging::log_to_stderr(LevelFilter::Debug);
@@ -105,11 +140,55 @@ fn fixtures() {
             eprintln!("Optimizing.");
             assembly.optimize();
 
+            eprintln!("Encoding.");
+            let mut binary = vec![];
+            bytecode::Binary::new(assembly.clone().into())
+                .write(&mut binary)
+                .unwrap();
+            eprintln!("{} bytes.", binary.len());
+            let instructions = bytecode::Binary::read(binary.as_slice())
+                .unwrap()
+                .instructions;
+
+            eprintln!("Verifying.");
+            if let Err(error) = bytecode::verify(&instructions) {
+                failures.push(format!("{}: {}", fixture_name, error));
+            }
+
             eprintln!("Running.");
             eprintln!("{:?}", assembly);
 
             let mut vm = vm::VM::new();
-            let result = vm.eval_pop::<()>(assembly.clone().into()).unwrap();
+            if let Some(ref limits) = fixture_config.limits {
+                vm.set_limits(limits.parse().unwrap());
+            }
+            let result = if fixture_config.effects {
+                vm.eval_pop::<EffectfulRuntime>(instructions)
+            } else {
+                vm.eval_pop::<()>(instructions)
+            }
+            .unwrap();
+
+            eprintln!("Running as WebAssembly.");
+            let module = wasm::Generator::new().generate(assembly).unwrap();
+            let unsupported = UNSUPPORTED_IN_WASM
+                .iter()
+                .find(|(name, _)| *name == fixture_name)
+                .map(|(_, feature)| format!("{}: {}", wasm::UNSUPPORTED, feature));
+            match (wasm::run(&module), unsupported) {
+                (Ok(ref wasm_result), None) if *wasm_result == Some(result.to_string()) => {}
+                (Err(ref message), Some(ref expected)) if message.starts_with(expected) => {
+                    eprintln!("Skipping: {}", message);
+                }
+                (wasm_result, Some(expected)) => failures.push(format!(
+                    "{}:\n  Expected WebAssembly to fail with: {}\n  WebAssembly result: {:?}",
+                    fixture_name, expected, wasm_result
+                )),
+                (wasm_result, None) => failures.push(format!(
+                    "{}:\n       VM result: {}\n  WebAssembly result: {:?}",
+                    fixture_name, result, wasm_result
+                )),
+            }
 
             let actual_stdout = format!("{}\n", result);
             let expected_stdout: String = fixture_config
@@ -507,6 +507,31 @@ impl<'a> Formatter<'a> {
                 self.space(f)?;
                 self.write_child(f, expression)
             }
+            TryExpression {
+                try_keyword,
+                expression,
+                rescue_clause,
+            } => {
+                self.write_token(f, try_keyword)?;
+                self.space(f)?;
+                self.write_child(f, expression)?;
+                self.space(f)?;
+                self.write_child(f, rescue_clause)
+            }
+            RescueClause {
+                rescue_keyword,
+                symbol,
+                fat_arrow,
+                expression,
+            } => {
+                self.write_token_or(f, rescue_keyword, "rescue")?;
+                self.space(f)?;
+                self.write_child(f, symbol)?;
+                self.space(f)?;
+                self.write_token_or(f, fat_arrow, "=>")?;
+                self.space(f)?;
+                self.write_child(f, expression)
+            }
             UnaryMessage { symbol } => self.write_child(f, symbol),
             BinaryMessage {
                 operator,
@@ -628,3 +653,29 @@ impl Display for Tree {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(code: &str) -> String {
+        let (tree, _) = Parser::new(Source::test_repl(code)).parse();
+        Formatter::format(&tree, "  ")
+    }
+
+    #[test]
+    fn try_expression() {
+        assert_eq!(
+            format("try   x\n  rescue  message=>message"),
+            "try x rescue message => message."
+        );
+    }
+
+    #[test]
+    fn incomplete_rescue_clause() {
+        assert_eq!(
+            format("try x rescue message y"),
+            "try x rescue message => y."
+        );
+    }
+}
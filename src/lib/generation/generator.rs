@@ -124,21 +124,40 @@ impl<'a> Generator<'a> {
     }
 
     pub fn generate_all(&mut self) -> GenerationResult<Assembly> {
+        let mut assembly = self.generate_package(|_| true)?;
+        assembly
+            .last_main_section_mut()
+            .add_instruction(InstructionKind::Halt);
+
+        Ok(assembly)
+    }
+
+    /// Generates the sources for which `includes` returns true, without
+    /// halting at the end of the main sections. The references they make to
+    /// declarations in other sources are left for the linker to resolve.
+    pub fn generate_package<F: Fn(&Source) -> bool>(
+        &mut self,
+        includes: F,
+    ) -> GenerationResult<Assembly> {
+        let sources: Vec<_> = self
+            .analysis
+            .navigator
+            .sources()
+            .into_iter()
+            .filter(|source| includes(source))
+            .collect();
+
         let mut assembly = Assembly::new();
-        for source in self.analysis.navigator.sources() {
+        for source in sources.iter() {
             if let SourceKind::Module = source.kind {
                 self.generate_source((), &mut assembly, &source.uri)?;
             }
         }
-        for source in self.analysis.navigator.sources() {
+        for source in sources.iter() {
             if let SourceKind::REPLLine = source.kind {
                 self.generate_source((), &mut assembly, &source.uri)?;
             }
         }
-        assembly
-            .last_main_section_mut()
-            .add_instruction(InstructionKind::Halt);
-
         Ok(assembly)
     }
 
@@ -414,6 +433,24 @@ impl<'a> Generator<'a> {
                 match behaviour_node.kind {
                     Method { .. } => {
                         let label = format!("{}#{}", owning_class_qn, behaviour.selector());
+
+                        // Sends to the methods that the inherited method
+                        // overrides must reach it through this class as well.
+                        for overridden_method in self
+                            .analysis
+                            .navigator
+                            .methods_overridden_by(&behaviour_node)
+                        {
+                            let overridden_label = self
+                                .analysis
+                                .navigator
+                                .qualified_name_of_method(&overridden_method)?;
+                            section.add_instruction(InstructionKind::OverrideMethod(
+                                overridden_label,
+                                label.clone(),
+                            ));
+                        }
+
                         section.add_instruction(InstructionKind::UseMethod(label));
                     }
                     Variable { .. } => {
@@ -492,6 +529,16 @@ impl<'a> Generator<'a> {
 
             self.generate_expression(assembly, &mut method_section, &body)?;
 
+            // A message send that is the last thing the method does can reuse
+            // the method's frame, since there's nothing left to return to.
+            if let Some(instruction) = method_section.instructions.last_mut() {
+                if let InstructionKind::CallMethod(label, uri, line, character) =
+                    instruction.kind.clone()
+                {
+                    instruction.kind = InstructionKind::TailCallMethod(label, uri, line, character);
+                }
+            }
+
             method_section.add_instruction(InstructionKind::Return(
                 self.analysis.navigator.method_arity(method)? as u16,
             ));
@@ -541,6 +588,9 @@ impl<'a> Generator<'a> {
                     self.analysis.navigator.symbol_of(declaration)?.0,
                 )),
             },
+            RescueClause { .. } => section.add_instruction(InstructionKind::LoadLocal(
+                self.simulated_stack.index_of(declaration.id)?,
+            )),
 
             _ => return Err(invalid_node(declaration, "Expected value declaration.")),
         }
@@ -574,9 +624,10 @@ impl<'a> Generator<'a> {
         expression: &Node,
     ) -> GenerationResult<()> {
         match expression.kind {
-            TupleExpression { .. } | MessageSendExpression { .. } | PanicExpression { .. } => {
-                self.generate_lazy(assembly, section, expression)
-            }
+            TupleExpression { .. }
+            | MessageSendExpression { .. }
+            | PanicExpression { .. }
+            | TryExpression { .. } => self.generate_lazy(assembly, section, expression),
             _ => self.generate_expression(assembly, section, expression),
         }
     }
@@ -586,6 +637,25 @@ impl<'a> Generator<'a> {
         assembly: &mut Assembly,
         section: &mut Section,
         expression: &Node,
+    ) -> GenerationResult<()> {
+        let first = section.instructions.len();
+        self.generate_expression_instructions(assembly, section, expression)?;
+
+        // Instructions of subexpressions have already been attributed to
+        // them, so whatever is left was generated for this expression.
+        for instruction in section.instructions[first..].iter_mut() {
+            if instruction.source.is_none() {
+                instruction.source = Some(expression.span.clone());
+            }
+        }
+        Ok(())
+    }
+
+    fn generate_expression_instructions(
+        &mut self,
+        assembly: &mut Assembly,
+        section: &mut Section,
+        expression: &Node,
     ) -> GenerationResult<()> {
         match expression.kind {
             SelfExpression(_) => {
@@ -600,6 +670,25 @@ impl<'a> Generator<'a> {
                 section.add_instruction(InstructionKind::Panic);
                 self.simulated_stack.pop();
             }
+            TryExpression {
+                expression: e,
+                rescue_clause,
+                ..
+            } => {
+                let e = self.analysis.navigator.find_child(expression, e)?;
+                let rescue_clause = self
+                    .analysis
+                    .navigator
+                    .find_child(expression, rescue_clause)?;
+
+                // Both the expression and the rescue clause are lazy, so that
+                // the VM can evaluate the expression where it can recover from
+                // a panic.
+                self.generate_lazy(assembly, section, &e)?;
+                self.generate_rescue_clause(assembly, section, &rescue_clause)?;
+                self.simulated_stack.pop();
+                self.simulated_stack.push_expression(expression.id);
+            }
             CascadeExpression { expression: e, .. } => {
                 let e = self.analysis.navigator.find_child(expression, e)?;
                 self.generate_expression(assembly, section, &e)?;
@@ -694,12 +783,57 @@ impl<'a> Generator<'a> {
         Ok(())
     }
 
+    fn generate_rescue_clause(
+        &mut self,
+        assembly: &mut Assembly,
+        section: &mut Section,
+        rescue_clause: &Node,
+    ) -> GenerationResult<()> {
+        match rescue_clause.kind {
+            RescueClause { expression, .. } => {
+                let expression = self
+                    .analysis
+                    .navigator
+                    .find_child(rescue_clause, expression)?;
+                let (arity, label) = self.generate_lazy_section(
+                    assembly,
+                    section,
+                    &expression,
+                    Some(rescue_clause),
+                )?;
+                section.add_instruction(InstructionKind::Rescue(arity, label));
+                Ok(())
+            }
+            _ => Err(invalid_node(rescue_clause, "Expected rescue clause.")),
+        }
+    }
+
     fn generate_lazy(
         &mut self,
         assembly: &mut Assembly,
         section: &mut Section,
         expression: &Node,
     ) -> GenerationResult<()> {
+        let (arity, label) = self.generate_lazy_section(assembly, section, expression, None)?;
+        section.add_instruction(InstructionKind::LoadLazy(arity, label));
+        self.simulated_stack.push_expression(expression.id);
+        Ok(())
+    }
+
+    /// Generates the section that evaluates the expression lazily, and loads
+    /// the locals it captures. Returns how many locals were captured, and the
+    /// label of the section.
+    ///
+    /// The binding, if any, isn't captured. Instead, it's expected on top of
+    /// the captured locals when the section is evaluated, like the panic
+    /// message of a rescue clause.
+    fn generate_lazy_section(
+        &mut self,
+        assembly: &mut Assembly,
+        section: &mut Section,
+        expression: &Node,
+        binding: Option<&Node>,
+    ) -> GenerationResult<(u16, Label)> {
         self.lazies += 1;
         let label = format!(
             "{}$lazy{}",
@@ -707,7 +841,13 @@ impl<'a> Generator<'a> {
             self.lazies
         );
 
-        let arguments = self.analysis.navigator.locals_crossing_into(expression);
+        let arguments: Vec<_> = self
+            .analysis
+            .navigator
+            .locals_crossing_into(expression)
+            .into_iter()
+            .filter(|local| binding.map(|b| b.id != local.id).unwrap_or(true))
+            .collect();
         let mut arity = arguments.len() as u16;
         let mut lazy_stack = SimulatedStack::new();
         for argument in arguments.iter() {
@@ -722,22 +862,25 @@ impl<'a> Generator<'a> {
             ));
             lazy_stack.push_self();
         }
-        section.add_instruction(InstructionKind::LoadLazy(arity, label.clone()));
+        if let Some(binding) = binding {
+            lazy_stack.push_declaration(binding.id);
+        }
         for argument in arguments {
             self.simulated_stack.drop(argument.id)?;
         }
-        self.simulated_stack.push_expression(expression.id);
 
         let mut sub_generator = self.sub();
         sub_generator.simulated_stack = lazy_stack;
-        let mut lazy_section = Section::named(label);
+        let mut lazy_section = Section::named(label.clone());
         sub_generator.generate_expression(assembly, &mut lazy_section, expression)?;
-        lazy_section.add_instruction(InstructionKind::ReturnLazy(arity));
+        lazy_section.add_instruction(InstructionKind::ReturnLazy(
+            arity + binding.is_some() as u16,
+        ));
 
         assembly.add_section(lazy_section);
         self.lazies = sub_generator.lazies;
 
-        Ok(())
+        Ok((arity, label))
     }
 
     fn generate_repl_line<D: REPLDirectives>(
@@ -924,20 +1067,24 @@ fn invalid_node(node: &Node, message: &str) -> GenerationError {
 
 #[cfg(test)]
 mod tests {
-    use crate::assembly::Parser as AssemblyParser;
+    use crate::assembly::{Assembly, Parser as AssemblyParser};
     use crate::generation::*;
     use crate::semantics::Analysis;
     use crate::syntax::Parser;
     use crate::*;
 
-    fn assert_generates(source: Arc<Source>, expected: &str) {
+    fn generate(source: Arc<Source>) -> Assembly {
         let mut analysis = Analysis::new(Arc::new(
             vec![(source.uri.clone(), Parser::new(source).parse().0)]
                 .into_iter()
                 .collect(),
         ));
         let mut generator = Generator::new(&mut analysis);
-        let assembly = generator.generate_all().unwrap();
+        generator.generate_all().unwrap()
+    }
+
+    fn assert_generates(source: Arc<Source>, expected: &str) {
+        let assembly = generate(source);
 
         let expected = AssemblyParser::new().parse(expected).unwrap();
 
@@ -1101,7 +1248,7 @@ mod tests {
                 Halt
                 @N/A#x
                     LoadLocal 0
-                    CallMethod @N/A#y "test:" 5 37
+                    TailCallMethod @N/A#y "test:" 5 37
                     Return 1
 
                 @N/A#y
@@ -1454,7 +1601,7 @@ mod tests {
                 @N/A#main
                     LoadLazy 0 @N/A#main$lazy1
                     LoadLocal 1
-                    CallMethod @N/A#return: "test:" 10 29
+                    TailCallMethod @N/A#return: "test:" 10 29
                     Return 1
             "#,
         );
@@ -1651,7 +1798,7 @@ mod tests {
 				  LoadLazy 1 @N/A#a$lazy1
 				  LoadLocal 1
 				  CallMethod @N/A#p "test:" 7 37
-				  CallMethod @N/P#+ "test:" 7 37
+				  TailCallMethod @N/P#+ "test:" 7 37
 				  Return 1
 
 				@N/A#p
@@ -1711,9 +1858,88 @@ mod tests {
               LoadLocal 5
               LoadLazy 3 @N/A#a:b:c:$lazy1
               LoadObject @N/P
-              CallMethod @N/P#+ "test:" 8 25
+              TailCallMethod @N/P#+ "test:" 8 25
               Return 4
             "#,
         );
     }
+
+    #[test]
+    fn try_expression() {
+        assert_generates(
+            Source::test(
+                r#"
+                namespace N.
+                class A {
+                    public a: A a => try a b rescue message => a c: message.
+                    public b => self.
+                    public c: String s => self.
+                }
+                "#,
+            ),
+            r#"
+            @N/A$methods
+              DeclareMethod "a:" @N/A#a:
+              DeclareMethod "b" @N/A#b
+              DeclareMethod "c:" @N/A#c:
+
+            @N/A
+              DeclareClass "N/A"
+              UseMethod @N/A#a:
+              UseMethod @N/A#b
+              UseMethod @N/A#c:
+
+            Halt
+
+            @N/A#a:$lazy1
+              LoadLocal 0
+              CallMethod @N/A#b "test:" 4 42
+              ReturnLazy 1
+
+            @N/A#a:$lazy2
+              LoadLocal 0
+              LoadLocal 2
+              CallMethod @N/A#c: "test:" 4 64
+              ReturnLazy 2
+
+            @N/A#a:
+              LoadLocal 1
+              LoadLazy 1 @N/A#a:$lazy1
+              LoadLocal 2
+              Rescue 1 @N/A#a:$lazy2
+              Return 2
+
+            @N/A#b
+              LoadLocal 0
+              Return 1
+
+            @N/A#c:
+              LoadLocal 0
+              Return 2
+            "#,
+        );
+    }
+
+    #[test]
+    fn source_map() {
+        let source = Source::test_repl(
+            r#"
+                    class A {
+                        public x => self.
+                    }
+
+                    A x.
+                "#,
+        );
+        let source_map = generate(source.clone()).source_map(&[source], true);
+
+        // LoadObject @A
+        assert_eq!(source_map.lookup(3).unwrap().to_string(), "test::6:21");
+        // CallMethod @A#x
+        assert_eq!(source_map.lookup(4).unwrap().to_string(), "test::6:21");
+        // Halt
+        assert_eq!(source_map.lookup(5), None);
+        // LoadLocal 0
+        assert_eq!(source_map.lookup(6).unwrap().to_string(), "test::3:37");
+    }
 }
@@ -74,6 +74,10 @@ pub mod bytecode;
 
 pub mod optimization;
 
+pub mod ffi;
+
+pub mod wasm;
+
 #[cfg(test)]
 mod fixture_tests;
 
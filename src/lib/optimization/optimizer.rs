@@ -1,9 +1,10 @@
 use crate::assembly::*;
-use crate::vm::NativeMethod;
+use crate::vm::{ConstClass, NativeRegistry, NumberKind, Runtime};
 
 pub struct Optimizer {
     sections: Vec<(String, Section)>,
     marked_sections: Vec<String>,
+    natives: NativeRegistry,
 
     true_class_label: Option<String>,
     false_class_label: Option<String>,
@@ -44,6 +45,7 @@ impl Optimizer {
                 })
                 .collect(),
             marked_sections: vec![],
+            natives: NativeRegistry::standard(),
 
             true_class_label: None,
             false_class_label: None,
@@ -68,6 +70,13 @@ impl Optimizer {
         }
     }
 
+    /// Keeps the classes that these natives box, instead of the ones of the
+    /// standard natives.
+    pub fn with_natives(mut self, natives: NativeRegistry) -> Optimizer {
+        self.natives = natives;
+        self
+    }
+
     pub fn optimize(mut self) -> Assembly {
         self.collect_const_class_marks();
         self.mark_from_beginning();
@@ -140,6 +149,40 @@ impl Optimizer {
         }
     }
 
+    /// The labels of the sections that mark the classes.
+    fn class_labels(&self, classes: &[ConstClass]) -> Vec<String> {
+        let mut labels = vec![];
+        for class in classes {
+            match class {
+                ConstClass::Boolean => {
+                    labels.push(&self.true_class_label);
+                    labels.push(&self.false_class_label);
+                }
+                ConstClass::String => labels.push(&self.string_class_label),
+                ConstClass::Character => labels.push(&self.character_class_label),
+                ConstClass::Symbol => labels.push(&self.symbol_class_label),
+                ConstClass::Number(kind) => labels.push(match kind {
+                    NumberKind::U8 => &self.u8_class_label,
+                    NumberKind::U16 => &self.u16_class_label,
+                    NumberKind::U32 => &self.u32_class_label,
+                    NumberKind::U64 => &self.u64_class_label,
+                    NumberKind::U128 => &self.u128_class_label,
+                    NumberKind::UBig => &self.ubig_class_label,
+                    NumberKind::I8 => &self.i8_class_label,
+                    NumberKind::I16 => &self.i16_class_label,
+                    NumberKind::I32 => &self.i32_class_label,
+                    NumberKind::I64 => &self.i64_class_label,
+                    NumberKind::I128 => &self.i128_class_label,
+                    NumberKind::IBig => &self.ibig_class_label,
+                    NumberKind::F32 => &self.f32_class_label,
+                    NumberKind::F64 => &self.f64_class_label,
+                    NumberKind::FBig => &self.fbig_class_label,
+                }),
+            }
+        }
+        labels.into_iter().filter_map(Clone::clone).collect()
+    }
+
     fn optimize_section(section: &mut Section, marks: &Vec<String>) {
         section.instructions.retain(|i| match i.kind {
             InstructionKind::Noop => false,
@@ -232,12 +275,18 @@ impl Optimizer {
                         | InstructionKind::LoadLazy(_, ref label) => {
                             mark!(label);
                         }
+                        InstructionKind::Rescue(_, ref label) => {
+                            mark!(label);
+                            // Rescued panics are passed on as strings.
+                            mark!(?self.string_class_label);
+                        }
                         InstructionKind::DeclareVariable(_, ref vl, ref gl, ref sl) => {
                             mark!(vl);
                             mark!(gl);
                             mark!(sl);
                         }
-                        InstructionKind::CallMethod(ref label, _, _, _) => {
+                        InstructionKind::CallMethod(ref label, _, _, _)
+                        | InstructionKind::TailCallMethod(ref label, _, _, _) => {
                             // Mark method implementation as used
                             mark!(label);
 
@@ -260,13 +309,13 @@ impl Optimizer {
                             }
                         }
 
-                        InstructionKind::CallNative(NativeMethod::Object_eq) => {
-                            mark!(?self.true_class_label);
-                            mark!(?self.false_class_label);
-                        }
-
-                        InstructionKind::CallNative(NativeMethod::Object_asString) => {
-                            mark!(?self.string_class_label);
+                        InstructionKind::CallNative(ref method) => {
+                            // Natives that aren't registered may box anything.
+                            let all = ConstClass::all();
+                            let classes = self.natives.boxes(method.name()).unwrap_or(&all);
+                            for label in self.class_labels(classes) {
+                                mark!(&label);
+                            }
                         }
 
                         InstructionKind::LoadConstString(_) => mark!(?self.string_class_label),
@@ -298,13 +347,22 @@ impl Optimizer {
 }
 
 pub trait Optimizable {
-    fn optimize(&mut self);
+    fn optimize(&mut self) {
+        self.optimize_for::<()>()
+    }
+
+    /// Optimizes a program that runs on the runtime, which may register
+    /// natives of its own.
+    fn optimize_for<M: Runtime>(&mut self);
 }
 
 impl Optimizable for Assembly {
-    fn optimize(&mut self) {
+    fn optimize_for<M: Runtime>(&mut self) {
+        let mut natives = NativeRegistry::new();
+        M::register_natives(&mut natives);
+
         let this = std::mem::replace(self, Assembly::new());
-        let optimized = Optimizer::new(this).optimize();
+        let optimized = Optimizer::new(this).with_natives(natives).optimize();
         std::mem::replace(self, optimized);
     }
 }
@@ -376,4 +434,63 @@ mod tests {
             "#,
         );
     }
+
+    #[test]
+    fn classes_boxed_by_natives_retained() {
+        assert_optimizes(
+            r#"
+                @String
+                    DeclareClass "String"
+                    MarkClassString @String
+
+                @UInt64
+                    DeclareClass "UInt64"
+                    MarkClassU64 @UInt64
+
+                CallNative Loa/Process#argumentCount
+                Halt
+            "#,
+            r#"
+                @UInt64
+                    DeclareClass "UInt64"
+                    MarkClassU64 @UInt64
+
+                CallNative Loa/Process#argumentCount
+                Halt
+            "#,
+        );
+    }
+
+    #[test]
+    fn unknown_natives_retain_every_class() {
+        let input = r#"
+            @String
+                DeclareClass "String"
+                MarkClassString @String
+
+            @UInt64
+                DeclareClass "UInt64"
+                MarkClassU64 @UInt64
+
+            CallNative Host/Answer#get
+            Halt
+        "#;
+        assert_optimizes(input, input);
+    }
+
+    #[test]
+    fn string_class_retained_for_rescued_panics() {
+        let input = r#"
+            @String
+                DeclareClass "String"
+                MarkClassString @String
+
+            @rescued
+                Return 1
+
+            Rescue 0 @rescued
+            Halt
+        "#;
+        assert_optimizes(input, input);
+    }
 }
@@ -168,6 +168,42 @@ impl TypeAssignment {
         }
         None
     }
+
+    fn check_try_expression(
+        &self,
+        try_expression: &Node,
+        analysis: &mut Analysis,
+        diagnostics: &mut Vec<Diagnostic>,
+    ) -> Option<()> {
+        if let TryExpression {
+            expression,
+            rescue_clause,
+            ..
+        } = try_expression.kind
+        {
+            let expression = analysis.navigator.find_child(try_expression, expression)?;
+            let rescue_clause = analysis
+                .navigator
+                .find_child(try_expression, rescue_clause)?;
+            if let RescueClause { expression: e, .. } = rescue_clause.kind {
+                let rescue_expression = analysis.navigator.find_child(&rescue_clause, e)?;
+
+                // Whatever the expression evaluates to, the rescue clause has
+                // to be able to stand in for it.
+                let assignee = analysis.types.get_type_of_expression(&expression);
+                let assigned = analysis.types.get_type_of_expression(&rescue_expression);
+
+                self.diagnose_assignment(
+                    rescue_expression.span,
+                    assignee,
+                    assigned,
+                    analysis,
+                    diagnostics,
+                );
+            }
+        }
+        None
+    }
 }
 
 impl Checker for TypeAssignment {
@@ -184,7 +220,55 @@ impl Checker for TypeAssignment {
                 self.check_let_binding(n, analysis, diagnostics)
                     .unwrap_or(());
             }
+            if n.is_try_expression() {
+                self.check_try_expression(n, analysis, diagnostics)
+                    .unwrap_or(());
+            }
             true
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODE: &str = r#"namespace Test.
+
+class A {
+  public a -> A => self.
+}
+
+class B {
+  public b -> B => self.
+}
+
+class Main {
+  public a -> A => self a.
+  public b -> B => self b.
+
+  public same -> A => try self a rescue message => self a.
+  public different -> A => try self a rescue message => self b.
+}
+"#;
+
+    #[test]
+    fn rescue_clause_stands_in_for_the_expression() {
+        let (tree, parse_diagnostics) = Parser::new(Source::test(CODE)).parse();
+        assert!(parse_diagnostics.is_empty(), "{:?}", parse_diagnostics);
+
+        let mut analysis: Analysis = vec![(URI::Test, tree)].into_iter().into();
+        let mut diagnostics = vec![];
+        TypeAssignment.check(&mut analysis, &mut diagnostics);
+
+        // Message sends start where the node they're nested in does, so the
+        // span of the rescue expression covers the whole clause.
+        assert_eq!(diagnostics.len(), 1, "{:?}", diagnostics);
+        assert_matches!(
+            diagnostics[0],
+            Diagnostic::UnassignableType { ref span, .. }
+                if &CODE[span.start.offset..span.end.offset] == "rescue message => self b"
+                    && span.start.offset > CODE.find("different").unwrap()
+        );
+    }
+}
@@ -41,14 +41,16 @@ impl InvalidInherit {
     ) -> Option<()> {
         let mut violations = vec![];
 
+        let sub_behaviours = analysis.types.get_behaviours(&sub_type);
+
         'super_behaviours: for super_behaviour in analysis.types.get_behaviours(&super_type) {
             let super_selector = super_behaviour.selector();
-            for sub_behaviour in analysis.types.get_behaviours(&sub_type) {
+            for sub_behaviour in sub_behaviours.iter() {
                 let sub_selector = sub_behaviour.selector();
                 if super_selector == sub_selector {
                     let super_behaviour_type =
                         analysis.types.get_type_of_behaviour(&super_behaviour);
-                    let sub_behaviour_type = analysis.types.get_type_of_behaviour(&sub_behaviour);
+                    let sub_behaviour_type = analysis.types.get_type_of_behaviour(sub_behaviour);
 
                     let assignment = check_assignment(
                         super_behaviour_type.clone(),
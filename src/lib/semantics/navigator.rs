@@ -188,6 +188,7 @@ impl Navigator {
             }
             Class { symbol, .. }
             | LetBinding { symbol, .. }
+            | RescueClause { symbol, .. }
             | ReferenceTypeExpression { symbol, .. }
             | ReferenceExpression { symbol, .. }
             | TypeParameter { symbol, .. }
@@ -1551,22 +1552,47 @@ impl Navigator {
         let class = self.closest_class_upwards(method)?;
 
         let mut methods = vec![];
+        self.collect_methods_overridden_in_super_classes(&class, &selector, &mut methods);
+        Some(methods)
+    }
 
-        for super_type in self.super_type_expressions(&class) {
-            if let Some(super_class) = self.find_declaration(&super_type, DeclarationKind::Type) {
-                for super_method in self.methods_of_class(&super_class) {
-                    if let Some(super_method_selector) = self.method_selector(&super_method) {
-                        if super_method_selector == selector {
-                            methods.extend(self.methods_overridden_by(&super_method));
-
-                            methods.push(super_method);
-                        }
-                    }
+    fn collect_methods_overridden_in_super_classes(
+        &self,
+        class: &Node,
+        selector: &str,
+        methods: &mut Vec<Node>,
+    ) {
+        let mut super_classes = self
+            .super_type_expressions(class)
+            .iter()
+            .filter_map(|t| self.find_declaration(t, DeclarationKind::Type))
+            .collect::<Vec<_>>();
+
+        // Classes without super types implicitly inherit from Loa/Object.
+        if super_classes.is_empty() {
+            if let Some(object_class) = self.find_stdlib_class("Loa/Object") {
+                if object_class.id != class.id {
+                    super_classes.push(object_class);
                 }
             }
         }
 
-        Some(methods)
+        for super_class in super_classes {
+            let mut declared = false;
+            for super_method in self.methods_of_class(&super_class) {
+                if self.method_selector(&super_method).as_deref() == Some(selector) {
+                    declared = true;
+                    methods.extend(self.methods_overridden_by(&super_method));
+                    methods.push(super_method);
+                }
+            }
+
+            // A super class that doesn't declare the method might still
+            // inherit it from further up.
+            if !declared {
+                self.collect_methods_overridden_in_super_classes(&super_class, selector, methods);
+            }
+        }
     }
 
     pub fn locals_crossing_into(&self, expression: &Node) -> Vec<Node> {
@@ -1574,7 +1600,7 @@ impl Navigator {
             .all_references_downwards(expression, DeclarationKind::Value)
             .into_iter()
             .filter_map(|r| self.find_declaration(&r, DeclarationKind::Value))
-            .filter(|d| !matches!(d.kind, Class{..}))
+            .filter(|d| !matches!(d.kind, Class { .. }))
             .filter(|d| !self.is_within(&d, expression))
             .map(|d| (d.id, d))
             .collect();
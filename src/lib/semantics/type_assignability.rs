@@ -344,7 +344,9 @@ pub fn check_assignment(
 
             if assignee_class.id != assigned_class.id {
                 if !invariant {
-                    for super_type in types.get_super_types(&assigned_class) {
+                    for super_type in
+                        types.get_super_types_with_args(&assigned_class, assigned_args)
+                    {
                         let assigned_super_type_assignability =
                             check_assignment(assignee.clone(), super_type, navigator, types, false);
 
@@ -441,6 +443,17 @@ pub fn check_assignment(
         // TODO: Implement constraints on type parameters
         // which would be checked here
         (Type::Parameter(_, _, _), _) => TypeAssignability::Valid,
+
+        // Whatever a type parameter is bound to, it's an object.
+        (Type::Class(_, class, _), Type::Parameter(_, _, _))
+            if !invariant
+                && navigator
+                    .find_stdlib_class("Loa/Object")
+                    .map(|object| object.id == *class)
+                    .unwrap_or(false) =>
+        {
+            TypeAssignability::Valid
+        }
         (Type::Class(_, _, _), Type::Parameter(_, _, _)) => TypeAssignability::Invalid {
             assigned,
             assignee,
@@ -83,6 +83,11 @@ impl Types {
                     self.get_type_of_expression(&expression)
                 }
 
+                TryExpression { expression, .. } => {
+                    let expression = self.navigator.find_node(expression)?;
+                    self.get_type_of_expression(&expression)
+                }
+
                 MessageSendExpression {
                     expression,
                     message,
@@ -134,6 +139,12 @@ impl Types {
             if self.navigator.has_class_object(declaration) {
                 return Type::ClassObject(Box::new(self.get_type_of_declaration(declaration)));
             }
+
+            // A generic class without initializers, like `None`, is a single
+            // value that can act as any application of the class.
+            if let Type::Class(name, id, args) = self.get_type_of_declaration(declaration) {
+                return Type::Class(name, id, args.into_iter().map(|_| Type::Unknown).collect());
+            }
         }
         self.get_type_of_declaration(declaration)
     }
@@ -176,6 +187,9 @@ impl Types {
                         self.get_type_of_type_expression(&type_expression)
                     }
                 }
+                RescueClause { .. } => {
+                    self.get_type_of_declaration(&self.navigator.find_stdlib_class("Loa/String")?)
+                }
                 _ => Type::Unknown,
             })
     }
@@ -323,7 +337,7 @@ impl Types {
             Type::UnresolvedInteger(_, _) => self.get_behaviours_from_stdlib_class("Loa/Integer"),
             Type::UnresolvedFloat(_, _) => self.get_behaviours_from_stdlib_class("Loa/Float"),
             Type::Symbol(_) => self.get_behaviours_from_stdlib_class("Loa/Symbol"),
-            Type::Parameter(_, _, _) => vec![],
+            Type::Parameter(_, _, _) => self.get_behaviours_from_stdlib_class("Loa/Object"),
             Type::Class(_, class_id, args) => self
                 .navigator
                 .find_node(*class_id)
@@ -340,34 +354,8 @@ impl Types {
         args: &Vec<Type>,
     ) -> Option<Vec<Behaviour>> {
         let receiver_type = self.get_type_of_declaration(class);
-        if let Class {
-            class_body,
-            type_parameter_list,
-            ..
-        } = class.kind
-        {
-            let type_parameters =
-                if let Some(type_parameter_list) = self.navigator.find_node(type_parameter_list) {
-                    if let TypeParameterList {
-                        type_parameters, ..
-                    } = type_parameter_list.kind
-                    {
-                        type_parameters
-                    } else {
-                        vec![]
-                    }
-                } else {
-                    vec![]
-                };
-
-            let mut type_arg_map = HashMap::new();
-            for (i, param_id) in type_parameters.iter().enumerate() {
-                if i < args.len() {
-                    type_arg_map.insert(*param_id, args[i].clone());
-                } else {
-                    type_arg_map.insert(*param_id, Type::Unknown);
-                }
-            }
+        if let Class { class_body, .. } = class.kind {
+            let type_arg_map = self.get_type_argument_map(class, args);
 
             let mut behaviours = HashMap::new();
 
@@ -397,7 +385,7 @@ impl Types {
                 }
             }
 
-            for super_type in self.get_super_types(class) {
+            for super_type in self.get_super_types_with_args(class, args) {
                 for super_behaviour in self.get_behaviours(&super_type) {
                     let selector = super_behaviour.selector();
                     if !behaviours.contains_key(&selector) {
@@ -591,9 +579,9 @@ impl Types {
             | Type::UnresolvedFloat(_, _)
             | Type::Symbol(_)
             | Type::ClassObject(_) => {}
-            Type::Class(_, id, _) => {
+            Type::Class(_, id, args) => {
                 if let Some(class) = self.navigator.find_node(*id) {
-                    for super_type in self.get_super_types(&class) {
+                    for super_type in self.get_super_types_with_args(&class, args) {
                         self.insert_distanced_types(distance + 1, super_type, types);
                     }
                 }
@@ -656,6 +644,41 @@ impl Types {
         Type::Unknown
     }
 
+    /// Maps the type parameters of a class to the arguments it's applied
+    /// with. Parameters without an argument map to `Type::Unknown`.
+    pub fn get_type_argument_map(&self, class: &Node, args: &[Type]) -> HashMap<Id, Type> {
+        let mut type_arg_map = HashMap::new();
+        if let Class {
+            type_parameter_list,
+            ..
+        } = class.kind
+        {
+            if let Some(TypeParameterList {
+                type_parameters, ..
+            }) = self
+                .navigator
+                .find_node(type_parameter_list)
+                .map(|l| l.kind)
+            {
+                for (i, param_id) in type_parameters.iter().enumerate() {
+                    type_arg_map.insert(*param_id, args.get(i).cloned().unwrap_or(Type::Unknown));
+                }
+            }
+        }
+        type_arg_map
+    }
+
+    /// The super types of a class, in terms of the arguments the class is
+    /// applied with. `Some<UInt8>` is an `Optional<UInt8>` rather than an
+    /// `Optional<a>`.
+    pub fn get_super_types_with_args(&self, class: &Node, args: &[Type]) -> Vec<Type> {
+        let type_arg_map = self.get_type_argument_map(class, args);
+        self.get_super_types(class)
+            .into_iter()
+            .map(|t| t.with_applied_type_arguments(&type_arg_map))
+            .collect()
+    }
+
     pub fn get_super_types(&self, class: &Node) -> Vec<Type> {
         let mut super_types = vec![];
         for super_type in self.navigator.super_type_expressions(&class) {
@@ -895,19 +918,16 @@ impl Behaviour {
         match (&self.message, &message.kind) {
             (BehaviourMessage::Unary(_), UnaryMessage { .. }) => {}
             (BehaviourMessage::Binary(_, ref param_type), BinaryMessage { expression, .. }) => {
-                if let Type::Parameter(_, id, _) = param_type {
-                    if let Some(expression) = navigator.find_child(message, *expression) {
-                        let arg_type = types.get_type_of_expression(&expression);
-
-                        if !type_parameter_assignment_candidates.contains_key(id) {
-                            type_parameter_assignment_candidates.insert(*id, vec![]);
-                        }
-
-                        type_parameter_assignment_candidates
-                            .get_mut(id)
-                            .unwrap()
-                            .push(arg_type);
-                    }
+                if let Some(expression) = navigator.find_child(message, *expression) {
+                    let arg_type = types.get_type_of_expression(&expression);
+
+                    collect_type_argument_candidates(
+                        param_type,
+                        &arg_type,
+                        navigator,
+                        types,
+                        &mut type_parameter_assignment_candidates,
+                    );
                 }
             }
             (
@@ -917,21 +937,18 @@ impl Behaviour {
                 },
             ) => {
                 for ((_, param_type), pair) in params.iter().zip(keyword_pairs.iter()) {
-                    if let Type::Parameter(_, id, _) = param_type {
-                        if let Some(pair) = navigator.find_child(message, *pair) {
-                            if let KeywordPair { value, .. } = pair.kind {
-                                if let Some(expression) = navigator.find_child(&pair, value) {
-                                    let arg_type = types.get_type_of_expression(&expression);
-
-                                    if !type_parameter_assignment_candidates.contains_key(id) {
-                                        type_parameter_assignment_candidates.insert(*id, vec![]);
-                                    }
-
-                                    type_parameter_assignment_candidates
-                                        .get_mut(id)
-                                        .unwrap()
-                                        .push(arg_type);
-                                }
+                    if let Some(pair) = navigator.find_child(message, *pair) {
+                        if let KeywordPair { value, .. } = pair.kind {
+                            if let Some(expression) = navigator.find_child(&pair, value) {
+                                let arg_type = types.get_type_of_expression(&expression);
+
+                                collect_type_argument_candidates(
+                                    param_type,
+                                    &arg_type,
+                                    navigator,
+                                    types,
+                                    &mut type_parameter_assignment_candidates,
+                                );
                             }
                         }
                     }
@@ -1000,6 +1017,43 @@ impl Behaviour {
     }
 }
 
+/// Finds the types that the type parameters in a parameter type would have
+/// to be bound to for an argument to fit. When passing a `ListNode<UInt8>` as
+/// a `List<a>`, `a` is a candidate for `UInt8`.
+fn collect_type_argument_candidates(
+    param_type: &Type,
+    arg_type: &Type,
+    navigator: &Navigator,
+    types: &Types,
+    candidates: &mut HashMap<Id, Vec<Type>>,
+) {
+    match (param_type, arg_type) {
+        (Type::Parameter(_, id, _), _) => {
+            candidates.entry(*id).or_default().push(arg_type.clone());
+        }
+        (Type::Class(_, param_class, param_args), Type::Class(_, arg_class, arg_args)) => {
+            if param_class == arg_class {
+                for (param_arg, arg_arg) in param_args.iter().zip(arg_args.iter()) {
+                    collect_type_argument_candidates(
+                        param_arg, arg_arg, navigator, types, candidates,
+                    );
+                }
+            } else if let Some(arg_class) = navigator.find_node(*arg_class) {
+                for super_type in types.get_super_types_with_args(&arg_class, arg_args) {
+                    collect_type_argument_candidates(
+                        param_type,
+                        &super_type,
+                        navigator,
+                        types,
+                        candidates,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
 impl fmt::Display for BehaviourMessage {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
@@ -1,3 +1,4 @@
+use std::env::current_dir;
 use std::fmt;
 use std::path::PathBuf;
 
@@ -56,6 +57,21 @@ impl URI {
             _ => false,
         }
     }
+
+    /// A name for the URI that doesn't depend on where the project was
+    /// built. Files are made relative to the current directory, and files in
+    /// the standard library relative to the SDK.
+    pub fn to_relative_string(&self) -> String {
+        let (path, base) = match self {
+            URI::File(path) => (path, current_dir().ok()),
+            URI::Stdlib(path) => (path, Some(crate::sdk_dir())),
+            _ => return self.to_string(),
+        };
+        base.and_then(|base| path.strip_prefix(base).ok())
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    }
 }
 
 impl PartialEq for URI {
@@ -25,8 +25,15 @@ impl<T> Stack<T> {
         self.vec.remove(self.vec.len() - 1 - index);
     }
 
+    /// Removes the items from the absolute position `from` (counted from the
+    /// bottom of the stack) up to, but not including, `to`.
+    pub fn remove(&mut self, from: usize, to: usize) {
+        self.vec.drain(from..to);
+    }
+
     pub fn at(&self, index: usize) -> Option<&T> {
-        self.vec.get(self.vec.len() - 1 - index)
+        let position = self.vec.len().checked_sub(index + 1)?;
+        self.vec.get(position)
     }
 
     pub fn top(&self) -> Option<&T> {
@@ -37,6 +44,10 @@ impl<T> Stack<T> {
         self.vec.len()
     }
 
+    pub fn as_slice(&self) -> &[T] {
+        self.vec.as_slice()
+    }
+
     pub fn iter(&self) -> std::iter::Rev<std::slice::Iter<T>> {
         self.vec.iter().rev()
     }
@@ -333,6 +333,8 @@ fn next_token(
                 "let" => kind = TokenKind::LetKeyword,
                 "native" => kind = TokenKind::NativeKeyword,
                 "panic" => kind = TokenKind::PanicKeyword,
+                "try" => kind = TokenKind::TryKeyword,
+                "rescue" => kind = TokenKind::RescueKeyword,
                 "init" => kind = TokenKind::InitKeyword,
                 "var" => kind = TokenKind::VarKeyword,
 
@@ -94,9 +94,12 @@ impl Node {
 
     pub fn is_scope_root(&self) -> bool {
         match self.kind {
-            REPLLine { .. } | Module { .. } | Class { .. } | Method { .. } | LetBinding { .. } => {
-                true
-            }
+            REPLLine { .. }
+            | Module { .. }
+            | Class { .. }
+            | Method { .. }
+            | LetBinding { .. }
+            | RescueClause { .. } => true,
             _ => false,
         }
     }
@@ -143,6 +146,13 @@ impl Node {
         }
     }
 
+    pub fn is_try_expression(&self) -> bool {
+        match self.kind {
+            TryExpression { .. } => true,
+            _ => false,
+        }
+    }
+
     pub fn is_type_parameter(&self) -> bool {
         match self.kind {
             TypeParameter { .. } => true,
@@ -182,9 +192,10 @@ impl Node {
         match self.kind {
             Class { .. } => DeclarationKind::Any,
             TypeParameter { .. } | ReferenceTypeExpression { .. } => DeclarationKind::Type,
-            ParameterPattern { .. } | ReferenceExpression { .. } | LetBinding { .. } => {
-                DeclarationKind::Value
-            }
+            ParameterPattern { .. }
+            | ReferenceExpression { .. }
+            | LetBinding { .. }
+            | RescueClause { .. } => DeclarationKind::Value,
             _ => DeclarationKind::None,
         }
     }
@@ -193,7 +204,9 @@ impl Node {
         match self.kind {
             Class { .. } => true,
             TypeParameter { .. } => declaration_kind.is_type(),
-            ParameterPattern { .. } | LetBinding { .. } => declaration_kind.is_value(),
+            ParameterPattern { .. } | LetBinding { .. } | RescueClause { .. } => {
+                declaration_kind.is_value()
+            }
             _ => false,
         }
     }
@@ -219,7 +232,8 @@ impl Node {
             | CharacterExpression(_, _)
             | IntegerExpression(_, _)
             | FloatExpression(_, _)
-            | PanicExpression { .. } => true,
+            | PanicExpression { .. }
+            | TryExpression { .. } => true,
             _ => false,
         }
     }
@@ -662,7 +676,8 @@ pub enum NodeKind {
     ///   SymbolExpression |
     ///   CascadeExpression |
     ///   TupleExpression |
-    ///   PanicExpression
+    ///   PanicExpression |
+    ///   TryExpression
     /// ```
 
     /// ```bnf
@@ -746,6 +761,32 @@ pub enum NodeKind {
         expression: Id,
     },
 
+    /// ```bnf
+    /// TryExpression ::=
+    ///   TRY_KEYWORD
+    ///   Expression
+    ///   RescueClause
+    /// ```
+    TryExpression {
+        try_keyword: Token,
+        expression: Id,
+        rescue_clause: Id,
+    },
+
+    /// ```bnf
+    /// RescueClause ::=
+    ///   RESCUE_KEYWORD
+    ///   Symbol
+    ///   FAT_ARROW
+    ///   Expression
+    /// ```
+    RescueClause {
+        rescue_keyword: Option<Token>,
+        symbol: Id,
+        fat_arrow: Option<Token>,
+        expression: Id,
+    },
+
     /// ```bnf
     /// Message ::=
     ///   UnaryMessage |
@@ -978,6 +1019,16 @@ impl NodeKind {
                 ref panic_keyword, ..
             } => vec![Some(panic_keyword)],
 
+            TryExpression {
+                ref try_keyword, ..
+            } => vec![Some(try_keyword)],
+
+            RescueClause {
+                ref rescue_keyword,
+                ref fat_arrow,
+                ..
+            } => vec![rescue_keyword.as_ref(), fat_arrow.as_ref()],
+
             IsDirective {
                 ref is_keyword,
                 ref period,
@@ -1248,6 +1299,20 @@ impl NodeKind {
             PanicExpression { expression, .. } => {
                 children.push(expression);
             }
+            TryExpression {
+                expression,
+                rescue_clause,
+                ..
+            } => {
+                children.push(expression);
+                children.push(rescue_clause);
+            }
+            RescueClause {
+                symbol, expression, ..
+            } => {
+                children.push(symbol);
+                children.push(expression);
+            }
             StringExpression(_, _) => {}
             CharacterExpression(_, _) => {}
             IntegerExpression(_, _) => {}
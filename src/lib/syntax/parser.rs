@@ -675,6 +675,31 @@ impl Parser {
         )
     }
 
+    // A signature starting with an angle bracket can either have a type
+    // parameter list (`<a, b out>`) or be a binary method like `< Number n`.
+    fn sees_type_parameter_list(&self) -> bool {
+        let mut significants = self.tokens.iter().filter(|t| match t.kind {
+            Whitespace(_) | LineComment(_) => false,
+            _ => true,
+        });
+
+        match significants.next().map(|t| &t.kind) {
+            Some(OpenAngle) => {}
+            _ => return false,
+        }
+
+        match significants.next().map(|t| &t.kind) {
+            Some(SimpleSymbol(_)) => {}
+            _ => return false,
+        }
+
+        match significants.next().map(|t| &t.kind) {
+            Some(Comma) | Some(CloseAngle) | Some(InKeyword) | Some(OutKeyword)
+            | Some(InoutKeyword) => true,
+            _ => false,
+        }
+    }
+
     fn parse_type_parameter(&mut self, mut builder: NodeBuilder) -> Id {
         let mut symbol = Id::NULL;
         let mut variance_keyword = None;
@@ -939,7 +964,7 @@ impl Parser {
         let message_pattern;
         let mut return_type = Id::NULL;
 
-        if sees!(self, OpenAngle) {
+        if self.sees_type_parameter_list() {
             type_parameter_list = self.parse_type_parameter_list(self.child(&mut builder));
         }
 
@@ -1316,6 +1341,9 @@ impl Parser {
         if sees!(self, PanicKeyword) {
             return self.parse_panic_expression(builder);
         }
+        if sees!(self, TryKeyword) {
+            return self.parse_try_expression(builder);
+        }
         if sees!(self, SimpleString(_)) {
             return self.parse_string_expression(builder);
         }
@@ -1509,6 +1537,57 @@ impl Parser {
         )
     }
 
+    fn parse_try_expression(&mut self, mut builder: NodeBuilder) -> Id {
+        let try_keyword = self.next();
+        let expression = self.parse_expression(self.child(&mut builder));
+        let rescue_clause = self.parse_rescue_clause(self.child(&mut builder));
+
+        self.finalize(
+            builder,
+            TryExpression {
+                try_keyword,
+                expression,
+                rescue_clause,
+            },
+        )
+    }
+
+    fn parse_rescue_clause(&mut self, mut builder: NodeBuilder) -> Id {
+        let mut rescue_keyword = None;
+        let mut symbol = Id::NULL;
+        let mut fat_arrow = None;
+
+        if sees!(self, RescueKeyword) {
+            rescue_keyword = Some(self.next());
+        } else {
+            self.syntax_error_end("Expected rescue clause.");
+        }
+
+        if sees!(self, SimpleSymbol(_)) {
+            symbol = self.parse_symbol(self.child(&mut builder));
+        } else {
+            self.syntax_error_end("Rescue clause must name the panic message.");
+        }
+
+        if sees!(self, FatArrow) {
+            fat_arrow = Some(self.next());
+        } else {
+            self.syntax_error_end("Expected fat arrow.");
+        }
+
+        let expression = self.parse_expression(self.child(&mut builder));
+
+        self.finalize(
+            builder,
+            RescueClause {
+                rescue_keyword,
+                symbol,
+                fat_arrow,
+                expression,
+            },
+        )
+    }
+
     fn parse_unary_message_send(&mut self, mut builder: NodeBuilder, receiver: Id) -> Id {
         if sees!(self, SimpleSymbol(_)) {
             if self.peek_next_significant().kind != Colon {
@@ -1703,3 +1782,79 @@ impl Parser {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_expression(code: &str) -> (Arc<Tree>, Node, Vec<Diagnostic>) {
+        let (tree, diagnostics) = Parser::new(Source::test_repl(code)).parse();
+        let statement = tree.get(tree.root().unwrap().children()[0]).unwrap();
+        let expression = match statement.kind {
+            REPLExpression { expression, .. } => tree.get(expression).unwrap(),
+            _ => panic!("expected an expression, got {:?}", statement),
+        };
+        (tree, expression, diagnostics)
+    }
+
+    #[test]
+    fn try_expression() {
+        let (tree, expression, diagnostics) =
+            parse_expression("try self check: 0 rescue message => message.");
+
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        let (expression, rescue_clause) = match expression.kind {
+            TryExpression {
+                expression,
+                rescue_clause,
+                ..
+            } => (expression, rescue_clause),
+            _ => panic!("expected a try expression, got {:?}", expression),
+        };
+        assert_matches!(
+            tree.get(expression).unwrap().kind,
+            MessageSendExpression { .. }
+        );
+        match tree.get(rescue_clause).unwrap().kind {
+            RescueClause {
+                rescue_keyword: Some(_),
+                symbol,
+                fat_arrow: Some(_),
+                expression,
+            } => {
+                assert_matches!(
+                    tree.get(symbol).unwrap().kind,
+                    Symbol(ref t) if t.lexeme() == "message"
+                );
+                assert_matches!(
+                    tree.get(expression).unwrap().kind,
+                    ReferenceExpression { .. }
+                );
+            }
+            ref kind => panic!("expected a rescue clause, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn try_expression_without_rescue_clause() {
+        let (_, expression, diagnostics) = parse_expression("try self check: 0.");
+
+        assert_matches!(expression.kind, TryExpression { .. });
+        assert_matches!(
+            diagnostics[0],
+            Diagnostic::SyntaxError(_, ref message) if message == "Expected rescue clause."
+        );
+    }
+
+    #[test]
+    fn rescue_clause_without_message() {
+        let (_, _, diagnostics) = parse_expression("try self check: 0 rescue => 1.");
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_matches!(
+            diagnostics[0],
+            Diagnostic::SyntaxError(_, ref message)
+                if message == "Rescue clause must name the panic message."
+        );
+    }
+}
@@ -25,6 +25,8 @@ pub enum TokenKind {
     LetKeyword,
     NativeKeyword,
     PanicKeyword,
+    TryKeyword,
+    RescueKeyword,
     InitKeyword,
     VarKeyword,
 
@@ -96,6 +98,8 @@ impl Token {
             LetKeyword => "let".into(),
             NativeKeyword => "native".into(),
             PanicKeyword => "panic".into(),
+            TryKeyword => "try".into(),
+            RescueKeyword => "rescue".into(),
             InitKeyword => "init".into(),
             VarKeyword => "var".into(),
 
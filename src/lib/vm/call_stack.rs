@@ -12,11 +12,50 @@ pub struct StackFrame {
 
     pub return_address: usize,
     pub callsite: SourceCodeLocation,
+    pub stack_base: usize,
+
+    /// The number of frames in the call stack, including this one.
+    pub depth: usize,
+}
+
+impl Drop for StackFrame {
+    // Long chains of frames are released one at a time, rather than
+    // recursively, so that deep call stacks don't overflow the native stack.
+    fn drop(&mut self) {
+        let mut parent = self.parent.take();
+        while let Some(frame) = parent {
+            match Arc::try_unwrap(frame) {
+                Ok(mut frame) => parent = frame.parent.take(),
+                Err(_) => break,
+            }
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct SourceCodeLocation(pub String, pub u64, pub u64);
 
+impl fmt::Display for SourceCodeLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.0, self.1, self.2)
+    }
+}
+
+/// Names a frame by the class of its receiver and the selector of its
+/// method, like `Loa/List#map:`.
+pub fn frame_name(frame: &StackFrame) -> String {
+    format!(
+        "{}#{}",
+        frame
+            .receiver
+            .class
+            .as_ref()
+            .map(|c| c.name.as_ref())
+            .unwrap_or("?"),
+        frame.method.name
+    )
+}
+
 impl CallStack {
     pub fn new() -> CallStack {
         CallStack(None)
@@ -28,8 +67,10 @@ impl CallStack {
         method: Arc<Method>,
         return_address: usize,
         callsite: SourceCodeLocation,
+        stack_base: usize,
     ) {
         let parent = self.0.clone();
+        let depth = self.depth() + 1;
         std::mem::replace(
             self,
             CallStack(Some(Arc::new(StackFrame {
@@ -38,6 +79,8 @@ impl CallStack {
                 method,
                 return_address,
                 callsite,
+                stack_base,
+                depth,
             }))),
         );
     }
@@ -53,6 +96,18 @@ impl CallStack {
     pub fn detach(&mut self) -> CallStack {
         std::mem::replace(self, CallStack::new())
     }
+
+    pub fn top(&self) -> Option<&Arc<StackFrame>> {
+        self.0.as_ref()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.0.as_ref().map(|f| f.depth).unwrap_or(0)
+    }
+
+    pub fn frames(&self) -> Vec<Arc<StackFrame>> {
+        self.clone().into()
+    }
 }
 
 impl Into<Vec<Arc<StackFrame>>> for CallStack {
@@ -67,6 +122,12 @@ impl Into<Vec<Arc<StackFrame>>> for CallStack {
     }
 }
 
+impl From<Option<Arc<StackFrame>>> for CallStack {
+    fn from(top: Option<Arc<StackFrame>>) -> CallStack {
+        CallStack(top)
+    }
+}
+
 impl fmt::Debug for CallStack {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         for frame in self.0.iter() {
@@ -0,0 +1,51 @@
+/// The effects a runtime lets programs have outside of the VM, through the
+/// native methods of `Loa/Process` and `Loa/File`. Those methods panic when
+/// the capability they need isn't granted.
+///
+/// Nothing is granted by default, so that embedding a VM doesn't hand the
+/// programs running on it access to the host.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Capabilities {
+    /// Writing to the standard output.
+    pub stdout: bool,
+
+    /// Writing to the standard error.
+    pub stderr: bool,
+
+    /// Reading lines from the standard input.
+    pub stdin: bool,
+
+    /// Reading files.
+    pub read_files: bool,
+
+    /// Creating and overwriting files.
+    pub write_files: bool,
+
+    /// Reading the arguments passed to the program.
+    pub args: bool,
+
+    /// Reading environment variables.
+    pub env: bool,
+
+    /// Ending the program with an exit code.
+    pub exit: bool,
+}
+
+impl Capabilities {
+    pub fn none() -> Capabilities {
+        Capabilities::default()
+    }
+
+    pub fn all() -> Capabilities {
+        Capabilities {
+            stdout: true,
+            stderr: true,
+            stdin: true,
+            read_files: true,
+            write_files: true,
+            args: true,
+            env: true,
+            exit: true,
+        }
+    }
+}
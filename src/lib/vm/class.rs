@@ -21,6 +21,27 @@ impl Class {
             offset,
         })
     }
+
+    /// The id that messages with the selector are sent by, to instances of
+    /// this class.
+    pub fn selector(&self, selector: &str) -> Option<u64> {
+        self.methods
+            .iter()
+            .find(|(_, method)| method.name == selector)
+            .map(|(id, _)| *id)
+            .or_else(|| {
+                self.variable_getters
+                    .iter()
+                    .find(|(_, variable)| variable.name == selector)
+                    .map(|(id, _)| *id)
+            })
+            .or_else(|| {
+                self.variable_setters
+                    .iter()
+                    .find(|(_, variable)| format!("{}:", variable.name) == selector)
+                    .map(|(id, _)| *id)
+            })
+    }
 }
 
 #[derive(Debug)]
@@ -29,6 +50,23 @@ pub struct Method {
     pub offset: usize,
 }
 
+impl Method {
+    /// The number of arguments the method takes, not counting the receiver,
+    /// as implied by the shape of its selector.
+    pub fn arity(&self) -> usize {
+        selector_arity(self.name.as_str())
+    }
+}
+
+/// Like `Method::arity`, for any selector.
+pub fn selector_arity(selector: &str) -> usize {
+    match selector.chars().next() {
+        Some(c) if c.is_alphabetic() || c == '_' => selector.chars().filter(|c| *c == ':').count(),
+        Some(_) => 1,
+        None => 0,
+    }
+}
+
 #[derive(Debug)]
 pub struct Variable {
     pub name: String,
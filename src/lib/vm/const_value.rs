@@ -8,7 +8,7 @@ pub enum ConstValue {
     Nothing,
     InstanceVariables(HashMap<u64, Arc<Object>>),
     String(String),
-    Lazy(u64, CallStack, Vec<Arc<Object>>),
+    Lazy(u64, Thunk),
     Character(u16),
     Symbol(String),
     U8(u8),
@@ -28,6 +28,77 @@ pub enum ConstValue {
     FBig(BigFraction),
 }
 
+/// What a lazy object needs to be evaluated, or its value once it has been.
+/// Values are immutable, so a lazy object never has to be evaluated more than
+/// once, and the call stack and dependencies it captured can be let go of.
+#[derive(Debug, Clone)]
+pub struct Thunk(Arc<Mutex<ThunkState>>);
+
+#[derive(Debug)]
+pub enum ThunkState {
+    Unevaluated(CallStack, Vec<Arc<Object>>),
+    Evaluated(Arc<Object>),
+}
+
+impl Thunk {
+    pub fn new(call_stack: CallStack, dependencies: Vec<Arc<Object>>) -> Thunk {
+        Thunk(Arc::new(Mutex::new(ThunkState::Unevaluated(
+            call_stack,
+            dependencies,
+        ))))
+    }
+
+    pub fn value(&self) -> Option<Arc<Object>> {
+        match *self.0.lock().ok()? {
+            ThunkState::Evaluated(ref value) => Some(value.clone()),
+            ThunkState::Unevaluated(_, _) => None,
+        }
+    }
+
+    /// The call stack and dependencies captured by the lazy object, unless
+    /// it has already been evaluated.
+    pub fn captured(&self) -> Option<(CallStack, Vec<Arc<Object>>)> {
+        match *self.0.lock().ok()? {
+            ThunkState::Unevaluated(ref call_stack, ref dependencies) => {
+                Some((call_stack.clone(), dependencies.clone()))
+            }
+            ThunkState::Evaluated(_) => None,
+        }
+    }
+
+    pub fn resolve(&self, value: Arc<Object>) {
+        self.set(ThunkState::Evaluated(value));
+    }
+
+    /// Replaces the state of the thunk, like when the cycle collector
+    /// releases what garbage captured, or when an image is restored.
+    pub(super) fn set(&self, state: ThunkState) {
+        let previous = match self.0.lock() {
+            Ok(mut current) => std::mem::replace(&mut *current, state),
+            Err(_) => return,
+        };
+        // The captured values are dropped after the lock is released, since
+        // dropping them might release other thunks.
+        drop(previous);
+    }
+}
+
+impl PartialEq for Thunk {
+    fn eq(&self, other: &Thunk) -> bool {
+        if Arc::ptr_eq(&self.0, &other.0) {
+            return true;
+        }
+        match (self.value(), other.value()) {
+            (Some(l), Some(r)) => l == r,
+            (None, None) => match (self.captured(), other.captured()) {
+                (Some((_, l)), Some((_, r))) => l == r,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
+}
+
 impl PartialEq for ConstValue {
     fn eq(&self, other: &ConstValue) -> bool {
         use ConstValue::*;
@@ -35,7 +106,7 @@ impl PartialEq for ConstValue {
             (Nothing, Nothing) => true,
             (InstanceVariables(l), InstanceVariables(r)) => l == r,
             (String(l), String(r)) => l == r,
-            (Lazy(l, _, la), Lazy(r, _, ra)) => l == r && la == ra,
+            (Lazy(l, lt), Lazy(r, rt)) => l == r && lt == rt,
             (Character(l), Character(r)) => l == r,
             (Symbol(l), Symbol(r)) => l == r,
             (U8(l), U8(r)) => l == r,
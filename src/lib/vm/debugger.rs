@@ -0,0 +1,77 @@
+use crate::vm::*;
+use crate::*;
+
+/// Drives a paused VM. Whenever the VM stops at a breakpoint or after a step,
+/// it hands control over to the attached debugger, which can inspect the VM
+/// and decide how execution should resume.
+pub trait Debugger {
+    fn paused(&mut self, vm: &mut VM, reason: PauseReason) -> DebugCommand;
+}
+
+#[derive(Debug, Clone)]
+pub enum PauseReason {
+    Pause,
+    Step,
+    Breakpoint(Breakpoint),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DebugCommand {
+    Continue,
+    StepInstruction,
+    StepOver,
+    StepOut,
+    Terminate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StepMode {
+    Run,
+    Pause,
+    Instruction,
+    Over(usize),
+    Out(usize),
+}
+
+/// A breakpoint on a source line, matched against the callsites of
+/// `CallMethod` instructions. The URI only needs to be a suffix of the
+/// callsite's URI, so that `Main.loa:12` matches regardless of where the
+/// program was built.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Breakpoint {
+    pub uri: String,
+    pub line: u64,
+}
+
+impl Breakpoint {
+    pub fn new<S: Into<String>>(uri: S, line: u64) -> Breakpoint {
+        Breakpoint {
+            uri: uri.into(),
+            line,
+        }
+    }
+
+    pub fn matches(&self, uri: &str, line: u64) -> bool {
+        self.line == line && uri.ends_with(self.uri.as_str())
+    }
+}
+
+impl std::str::FromStr for Breakpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Breakpoint, String> {
+        let colon = s
+            .rfind(':')
+            .ok_or_else(|| format!("expected FILE:LINE, got {}", s))?;
+        let line = s[colon + 1..]
+            .parse()
+            .map_err(|_| format!("invalid line number in {}", s))?;
+        Ok(Breakpoint::new(&s[..colon], line))
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.uri, self.line)
+    }
+}
@@ -0,0 +1,440 @@
+use crate::bytecode::{Binary, Instruction};
+use crate::vm::*;
+use crate::*;
+use std::marker::PhantomData;
+
+/// A compiled program loaded into a VM of its own, that the host sends
+/// messages to.
+///
+/// ```ignore
+/// let mut program = Program::<MyRuntime>::load(binary)?;
+/// let calculator = program.class("Example/Calculator")?;
+/// let sum: u64 = program.send(calculator, "add:to:", (1u64, 2u64))?;
+/// ```
+///
+/// Arguments and results are converted through `IntoLoa` and `FromLoa`, and
+/// panics come back as `EmbeddingError::Panic`. The VM is left in a usable
+/// state after a panic, so more messages can be sent.
+pub struct Program<M: Runtime> {
+    vm: VM,
+    runtime: PhantomData<M>,
+}
+
+#[derive(Debug)]
+pub enum EmbeddingError {
+    Panic(String, CallStack),
+    UnknownClass(String),
+    UnknownSelector {
+        class: String,
+        selector: String,
+    },
+    WrongNumberOfArguments {
+        selector: String,
+        expected: usize,
+        actual: usize,
+    },
+    /// A value can't be converted into a Loa object, because the program
+    /// doesn't load the class that it would be an instance of.
+    ClassNotLoaded(&'static str),
+    /// A Loa object can't be converted into the requested type.
+    UnexpectedObject {
+        expected: &'static str,
+        actual: String,
+    },
+}
+
+pub type EmbeddingResult<T> = Result<T, EmbeddingError>;
+
+impl<M: Runtime> Program<M> {
+    /// Evaluates the declarations, and any top level code, of a program.
+    pub fn load(binary: Binary) -> EmbeddingResult<Program<M>> {
+        let mut vm = VM::new();
+        vm.set_source_map(binary.source_map);
+        Program::load_into(vm, binary.instructions)
+    }
+
+    /// Like `load`, on a VM that has already been configured, with limits,
+    /// parallel workers and so on.
+    pub fn load_into(mut vm: VM, instructions: Vec<Instruction>) -> EmbeddingResult<Program<M>> {
+        vm.run::<M>(instructions).into_result()?;
+        Ok(Program {
+            vm,
+            runtime: PhantomData,
+        })
+    }
+
+    /// The value that the top level code of the program left behind, which
+    /// is the result of its entry point, if it has one.
+    pub fn take_result<T: FromLoa>(&mut self) -> EmbeddingResult<Option<T>> {
+        if self.vm.stack().size() == 0 {
+            return Ok(None);
+        }
+        let result = self.vm.pop_eval::<M>().into_result()?;
+        T::from_loa(result, &self.vm).map(Some)
+    }
+
+    /// An instance of the class with the qualified name, to send messages
+    /// to.
+    pub fn class(&self, qualified_name: &str) -> EmbeddingResult<Arc<Object>> {
+        self.vm
+            .class(qualified_name)
+            .map(Object::new)
+            .ok_or_else(|| EmbeddingError::UnknownClass(qualified_name.into()))
+    }
+
+    /// Sends a message to the receiver, and converts the result.
+    pub fn send<R, A, T>(&mut self, receiver: R, selector: &str, arguments: A) -> EmbeddingResult<T>
+    where
+        R: IntoLoa,
+        A: IntoArguments,
+        T: FromLoa,
+    {
+        let receiver = receiver.into_loa(&self.vm)?;
+        self.vm.push(receiver);
+        let receiver = self.vm.pop_eval::<M>().into_result()?;
+        let arguments = arguments.into_arguments(&self.vm)?;
+        let expected = selector_arity(selector);
+        if arguments.len() != expected {
+            return Err(EmbeddingError::WrongNumberOfArguments {
+                selector: selector.into(),
+                expected,
+                actual: arguments.len(),
+            });
+        }
+        let class = receiver
+            .class
+            .as_ref()
+            .ok_or_else(|| EmbeddingError::UnknownSelector {
+                class: receiver.to_string(),
+                selector: selector.into(),
+            })?;
+        let id = class
+            .selector(selector)
+            .ok_or_else(|| EmbeddingError::UnknownSelector {
+                class: class.name.clone(),
+                selector: selector.into(),
+            })?;
+
+        let result = self.vm.send::<M>(receiver, id, arguments).into_result()?;
+        T::from_loa(result, &self.vm)
+    }
+
+    pub fn vm(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+}
+
+impl<T> VMResult<T> {
+    pub fn into_result(self) -> EmbeddingResult<T> {
+        match self {
+            VMResult::Ok(t) => Ok(t),
+            VMResult::Panic(message, call_stack) => Err(EmbeddingError::Panic(message, call_stack)),
+        }
+    }
+}
+
+impl fmt::Display for EmbeddingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EmbeddingError::Panic(message, call_stack) => {
+                write!(f, "{}\n{:?}", message, call_stack)
+            }
+            EmbeddingError::UnknownClass(name) => write!(f, "unknown class {}", name),
+            EmbeddingError::UnknownSelector { class, selector } => {
+                write!(f, "message #{} not understood by {}", selector, class)
+            }
+            EmbeddingError::WrongNumberOfArguments {
+                selector,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "message #{} takes {} arguments, but {} were given",
+                selector, expected, actual
+            ),
+            EmbeddingError::ClassNotLoaded(class) => {
+                write!(f, "the program doesn't load {}", class)
+            }
+            EmbeddingError::UnexpectedObject { expected, actual } => {
+                write!(f, "expected {}, but got {}", expected, actual)
+            }
+        }
+    }
+}
+
+impl Error for EmbeddingError {}
+
+/// Converts a Rust value into a Loa object, an instance of one of the
+/// classes that the VM's program loaded.
+pub trait IntoLoa {
+    fn into_loa(self, vm: &VM) -> EmbeddingResult<Arc<Object>>;
+}
+
+/// Converts a Loa object, that came from the VM, into a Rust value.
+pub trait FromLoa: Sized {
+    fn from_loa(object: Arc<Object>, vm: &VM) -> EmbeddingResult<Self>;
+}
+
+/// The arguments of a message, as a tuple of values that are `IntoLoa`.
+pub trait IntoArguments {
+    fn into_arguments(self, vm: &VM) -> EmbeddingResult<Vec<Arc<Object>>>;
+}
+
+/// A Loa symbol, like `#hello:world:`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Symbol(pub String);
+
+/// A Loa character, which is a UTF-16 code unit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Character(pub u16);
+
+impl IntoLoa for Arc<Object> {
+    fn into_loa(self, _vm: &VM) -> EmbeddingResult<Arc<Object>> {
+        Ok(self)
+    }
+}
+
+impl FromLoa for Arc<Object> {
+    fn from_loa(object: Arc<Object>, _vm: &VM) -> EmbeddingResult<Self> {
+        Ok(object)
+    }
+}
+
+impl FromLoa for () {
+    fn from_loa(_object: Arc<Object>, _vm: &VM) -> EmbeddingResult<Self> {
+        Ok(())
+    }
+}
+
+impl IntoLoa for bool {
+    fn into_loa(self, vm: &VM) -> EmbeddingResult<Arc<Object>> {
+        vm.box_bool(self).ok_or_else(|| {
+            EmbeddingError::ClassNotLoaded(if self { "Loa/True" } else { "Loa/False" })
+        })
+    }
+}
+
+impl FromLoa for bool {
+    fn from_loa(object: Arc<Object>, vm: &VM) -> EmbeddingResult<Self> {
+        if vm.box_bool(true).as_ref() == Some(&object) {
+            Ok(true)
+        } else if vm.box_bool(false).as_ref() == Some(&object) {
+            Ok(false)
+        } else {
+            Err(unexpected("Boolean", &object))
+        }
+    }
+}
+
+fn unexpected(expected: &'static str, object: &Object) -> EmbeddingError {
+    EmbeddingError::UnexpectedObject {
+        expected,
+        actual: object.to_string(),
+    }
+}
+
+macro_rules! convert_const {
+    ($type:ty, $kind:ident, $class_name:expr, $value:ident => $into:expr, $from:pat => $out:expr) => {
+        impl IntoLoa for $type {
+            fn into_loa(self, vm: &VM) -> EmbeddingResult<Arc<Object>> {
+                let $value = self;
+                vm.box_const($into)
+                    .ok_or(EmbeddingError::ClassNotLoaded($class_name))
+            }
+        }
+
+        impl FromLoa for $type {
+            fn from_loa(object: Arc<Object>, _vm: &VM) -> EmbeddingResult<Self> {
+                match object.const_value {
+                    ConstValue::$kind($from) => Ok($out),
+                    _ => Err(unexpected($class_name, &object)),
+                }
+            }
+        }
+    };
+    ($type:ty, $kind:ident, $class_name:expr) => {
+        convert_const!($type, $kind, $class_name, v => ConstValue::$kind(v), ref v => v.clone());
+    };
+}
+
+convert_const!(String, String, "Loa/String");
+convert_const!(Symbol, Symbol, "Loa/Symbol", v => ConstValue::Symbol(v.0), ref v => Symbol(v.clone()));
+convert_const!(Character, Character, "Loa/Character", v => ConstValue::Character(v.0), v => Character(v));
+convert_const!(u8, U8, "Loa/UInt8");
+convert_const!(u16, U16, "Loa/UInt16");
+convert_const!(u32, U32, "Loa/UInt32");
+convert_const!(u64, U64, "Loa/UInt64");
+convert_const!(u128, U128, "Loa/UInt128");
+convert_const!(BigUint, UBig, "Loa/BigNatural");
+convert_const!(i8, I8, "Loa/Int8");
+convert_const!(i16, I16, "Loa/Int16");
+convert_const!(i32, I32, "Loa/Int32");
+convert_const!(i64, I64, "Loa/Int64");
+convert_const!(i128, I128, "Loa/Int128");
+convert_const!(BigInt, IBig, "Loa/BigInteger");
+convert_const!(f32, F32, "Loa/Float32");
+convert_const!(f64, F64, "Loa/Float64");
+convert_const!(BigFraction, FBig, "Loa/BigFloat");
+
+impl<'a> IntoLoa for &'a str {
+    fn into_loa(self, vm: &VM) -> EmbeddingResult<Arc<Object>> {
+        self.to_string().into_loa(vm)
+    }
+}
+
+macro_rules! into_arguments {
+    ($($argument:ident),*) => {
+        impl<$($argument: IntoLoa),*> IntoArguments for ($($argument,)*) {
+            #[allow(non_snake_case)]
+            fn into_arguments(self, _vm: &VM) -> EmbeddingResult<Vec<Arc<Object>>> {
+                let ($($argument,)*) = self;
+                Ok(vec![$($argument.into_loa(_vm)?),*])
+            }
+        }
+    };
+}
+
+into_arguments!();
+into_arguments!(A);
+into_arguments!(A, B);
+into_arguments!(A, B, C);
+into_arguments!(A, B, C, D);
+into_arguments!(A, B, C, D, E);
+into_arguments!(A, B, C, D, E, F);
+
+impl IntoArguments for Vec<Arc<Object>> {
+    fn into_arguments(self, _vm: &VM) -> EmbeddingResult<Vec<Arc<Object>>> {
+        Ok(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::Parser;
+
+    const PROGRAM: &str = r#"
+        @Loa/UInt8$methods
+            DeclareMethod "+" @Loa/UInt8#+
+            DeclareMethod "/" @Loa/UInt8#/
+
+        @Example/Calculator$methods
+            DeclareMethod "add:to:" @Example/Calculator#add:to:
+            DeclareMethod "divide:by:" @Example/Calculator#divide:by:
+
+        @Loa/UInt8
+            DeclareClass "Loa/UInt8"
+            MarkClassU8 @Loa/UInt8
+            UseMethod @Loa/UInt8#+
+            UseMethod @Loa/UInt8#/
+
+        @Example/Calculator
+            DeclareClass "Example/Calculator"
+            UseMethod @Example/Calculator#add:to:
+            UseMethod @Example/Calculator#divide:by:
+
+        Halt
+
+        @Loa/UInt8#+
+            CallNative Loa/Number#+
+            Return 0
+
+        @Loa/UInt8#/
+            CallNative Loa/Number#/
+            Return 0
+
+        @Example/Calculator#add:to:
+            LoadLocal 2
+            LoadLocal 2
+            CallMethod @Loa/UInt8#+ "Example/Calculator" 1 1
+            Return 3
+
+        @Example/Calculator#divide:by:
+            LoadLocal 2
+            LoadLocal 2
+            CallMethod @Loa/UInt8#/ "Example/Calculator" 2 1
+            Return 3
+    "#;
+
+    fn program() -> Program<()> {
+        let assembly = Parser::new().parse(PROGRAM).unwrap();
+        Program::load(Binary::new(assembly.into())).unwrap()
+    }
+
+    #[test]
+    fn send_message() {
+        let mut program = program();
+        let calculator = program.class("Example/Calculator").unwrap();
+
+        let sum: u8 = program
+            .send(calculator.clone(), "add:to:", (12u8, 30u8))
+            .unwrap();
+        assert_eq!(sum, 42);
+
+        let sum: u8 = program.send(20u8, "+", (3u8,)).unwrap();
+        assert_eq!(sum, 23);
+    }
+
+    #[test]
+    fn panics_are_errors() {
+        let mut program = program();
+        let calculator = program.class("Example/Calculator").unwrap();
+
+        assert_matches!(
+            program.send::<_, _, u8>(calculator.clone(), "divide:by:", (1u8, 0u8)),
+            Err(EmbeddingError::Panic(ref message, ref call_stack))
+                if message.contains("division by zero") && call_stack.depth() == 2
+        );
+
+        let quotient: u8 = program.send(calculator, "divide:by:", (12u8, 4u8)).unwrap();
+        assert_eq!(quotient, 3);
+        assert_eq!(program.vm().stack().size(), 0);
+    }
+
+    #[test]
+    fn lookup_errors() {
+        let mut program = program();
+        assert_matches!(
+            program.class("Example/Missing"),
+            Err(EmbeddingError::UnknownClass(_))
+        );
+
+        let calculator = program.class("Example/Calculator").unwrap();
+        assert_matches!(
+            program.send::<_, _, u8>(calculator.clone(), "subtract:from:", (1u8, 2u8)),
+            Err(EmbeddingError::UnknownSelector { .. })
+        );
+        assert_matches!(
+            program.send::<_, _, u8>(calculator.clone(), "add:to:", (1u8,)),
+            Err(EmbeddingError::WrongNumberOfArguments {
+                expected: 2,
+                actual: 1,
+                ..
+            })
+        );
+        assert_matches!(
+            program.send::<_, _, String>(calculator, "add:to:", (1u8, 2u8)),
+            Err(EmbeddingError::UnexpectedObject { .. })
+        );
+    }
+
+    #[test]
+    fn converts_with_the_classes_of_the_program() {
+        let mut first = program();
+        let mut second = program();
+
+        let seven = 7u8.into_loa(second.vm()).unwrap();
+        let class = seven.class.as_ref().unwrap();
+        assert!(Arc::ptr_eq(class, second.vm().class("Loa/UInt8").unwrap()));
+        assert!(!Arc::ptr_eq(class, first.vm().class("Loa/UInt8").unwrap()));
+
+        assert_matches!(
+            7u16.into_loa(first.vm()),
+            Err(EmbeddingError::ClassNotLoaded("Loa/UInt16"))
+        );
+        assert_matches!(
+            true.into_loa(first.vm()),
+            Err(EmbeddingError::ClassNotLoaded("Loa/True"))
+        );
+    }
+}
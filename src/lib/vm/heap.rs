@@ -0,0 +1,407 @@
+use crate::vm::*;
+use crate::*;
+use std::collections::hash_map::Entry;
+use std::collections::HashSet;
+use std::sync::Weak;
+
+const INITIAL_THRESHOLD: usize = 1024;
+
+/// Statistics about the objects managed by a VM's heap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Objects that can reference other objects, which are currently alive.
+    pub live_objects: usize,
+
+    /// Objects that can reference other objects, allocated since the VM was
+    /// created.
+    pub allocated_objects: u64,
+
+    /// The number of times the cycle collector has run.
+    pub collections: u64,
+
+    /// Objects that were only kept alive by reference cycles, and that were
+    /// reclaimed by the cycle collector.
+    pub collected_objects: u64,
+}
+
+/// Keeps track of the objects that the VM allocates, and reclaims the ones
+/// that are only kept alive by cycles of references among themselves.
+///
+/// Objects are reference counted, so anything that isn't part of a cycle is
+/// freed as soon as its last reference is dropped. Only objects that can
+/// reference other objects (instances with variables and lazy objects) are
+/// tracked, since only they can form cycles.
+///
+/// The cycle collector uses trial deletion: the references between the
+/// tracked objects, and the call frames they capture, are subtracted from
+/// their reference counts. Whatever still has references left is referenced
+/// from outside of the heap (the stack, globals, an embedder), and is alive
+/// along with everything it references. The rest is garbage.
+///
+/// Objects are immutable once allocated, so every cycle goes through a lazy
+/// object, whose thunk refers to the rest of the cycle. Garbage is freed by
+/// releasing what its thunks hold.
+///
+/// Counting references is only sound while nothing else changes them, so a
+/// VM pauses automatic collections while parallel workers are evaluating.
+pub struct Heap {
+    objects: Vec<Weak<Object>>,
+    threshold: usize,
+    paused: bool,
+    allocated_objects: u64,
+    collections: u64,
+    collected_objects: u64,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap {
+            objects: vec![],
+            threshold: INITIAL_THRESHOLD,
+            paused: false,
+            allocated_objects: 0,
+            collections: 0,
+            collected_objects: 0,
+        }
+    }
+
+    /// Starts tracking an object, running the cycle collector if the number
+    /// of tracked objects has grown past the threshold, and collections
+    /// aren't paused.
+    pub fn track(&mut self, object: &Arc<Object>) {
+        self.objects.push(Arc::downgrade(object));
+        self.allocated_objects += 1;
+
+        if !self.paused && self.objects.len() >= self.threshold {
+            self.collect();
+        }
+    }
+
+    /// Whether tracking another object would run the cycle collector, if
+    /// collections aren't paused.
+    pub fn is_due(&self) -> bool {
+        self.objects.len() + 1 >= self.threshold
+    }
+
+    /// Pauses or resumes automatic collections. Explicit calls to `collect`
+    /// still run.
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// Stops tracking every object, for another heap to adopt them.
+    pub fn take_objects(&mut self) -> Vec<Weak<Object>> {
+        std::mem::replace(&mut self.objects, vec![])
+    }
+
+    /// Tracks objects that another heap stopped tracking.
+    pub fn adopt(&mut self, objects: Vec<Weak<Object>>) {
+        self.objects.extend(objects);
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            live_objects: self.objects.iter().filter(|o| o.strong_count() > 0).count(),
+            allocated_objects: self.allocated_objects,
+            collections: self.collections,
+            collected_objects: self.collected_objects,
+        }
+    }
+
+    /// Runs the cycle collector, returning the number of objects reclaimed.
+    pub fn collect(&mut self) -> usize {
+        let mut graph = Graph::new();
+        for object in self.objects.iter().filter_map(Weak::upgrade) {
+            graph.add(Node::Object(object));
+        }
+        graph.expand();
+
+        let garbage = graph.garbage();
+        let collected = garbage.len();
+
+        // The thunks of garbage are only referenced by other garbage, so
+        // nothing that runs can see them being released. If anything else
+        // took a reference since the graph was counted, something changed
+        // reference counts during the collection.
+        debug_assert!(
+            garbage.iter().all(|object| graph.is_unreachable(object)),
+            "garbage was referenced during a collection"
+        );
+
+        // Breaking the cycles frees all of the garbage when the graph is
+        // dropped.
+        for object in garbage {
+            if let ConstValue::Lazy(_, ref thunk) = object.const_value {
+                thunk.set(ThunkState::Unevaluated(CallStack::new(), vec![]));
+            }
+        }
+        drop(graph);
+
+        self.objects.retain(|o| o.strong_count() > 0);
+        self.threshold = INITIAL_THRESHOLD.max(self.objects.len() * 2);
+        self.collections += 1;
+        self.collected_objects += collected as u64;
+
+        collected
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap::new()
+    }
+}
+
+enum Node {
+    Object(Arc<Object>),
+    Frame(Arc<StackFrame>),
+}
+
+impl Node {
+    fn key(&self) -> usize {
+        match self {
+            Node::Object(o) => &**o as *const Object as usize,
+            Node::Frame(f) => &**f as *const StackFrame as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Object(o) => Arc::strong_count(o),
+            Node::Frame(f) => Arc::strong_count(f),
+        }
+    }
+
+    fn references(&self) -> Vec<Node> {
+        let mut references = vec![];
+        match self {
+            Node::Object(o) => match o.const_value {
+                ConstValue::InstanceVariables(ref variables) => {
+                    for value in variables.values() {
+                        references.push(Node::Object(value.clone()));
+                    }
+                }
+                ConstValue::Lazy(_, ref thunk) => {
+                    if let Some(value) = thunk.value() {
+                        references.push(Node::Object(value));
+                    } else if let Some((call_stack, dependencies)) = thunk.captured() {
+                        if let Some(frame) = call_stack.top() {
+                            references.push(Node::Frame(frame.clone()));
+                        }
+                        for dependency in dependencies {
+                            references.push(Node::Object(dependency));
+                        }
+                    }
+                }
+                _ => {}
+            },
+            Node::Frame(f) => {
+                references.push(Node::Object(f.receiver.clone()));
+                if let Some(ref parent) = f.parent {
+                    references.push(Node::Frame(parent.clone()));
+                }
+            }
+        }
+        references
+    }
+}
+
+/// The tracked objects, and everything reachable from them. The graph holds
+/// one reference to each node, which is accounted for when looking for
+/// outside references.
+struct Graph {
+    nodes: HashMap<usize, Node>,
+    references: HashMap<usize, Vec<usize>>,
+    internal: HashMap<usize, usize>,
+    queue: Vec<usize>,
+}
+
+impl Graph {
+    fn new() -> Graph {
+        Graph {
+            nodes: HashMap::new(),
+            references: HashMap::new(),
+            internal: HashMap::new(),
+            queue: vec![],
+        }
+    }
+
+    fn add(&mut self, node: Node) -> usize {
+        let key = node.key();
+        if let Entry::Vacant(entry) = self.nodes.entry(key) {
+            entry.insert(node);
+            self.queue.push(key);
+        }
+        key
+    }
+
+    fn expand(&mut self) {
+        while let Some(key) = self.queue.pop() {
+            let references = self.nodes[&key].references();
+            let keys = references.into_iter().map(|r| self.add(r)).collect();
+            self.references.insert(key, keys);
+        }
+    }
+
+    /// The references to each node from other nodes.
+    fn count_internal_references(&mut self) {
+        self.internal.clear();
+        for keys in self.references.values() {
+            for key in keys.iter() {
+                *self.internal.entry(*key).or_insert(0) += 1;
+            }
+        }
+    }
+
+    fn garbage(&mut self) -> Vec<Arc<Object>> {
+        self.count_internal_references();
+
+        let mut alive = HashSet::new();
+        let mut queue = vec![];
+        for (key, node) in self.nodes.iter() {
+            let internal = self.internal.get(key).cloned().unwrap_or(0);
+            if node.strong_count() > internal + 1 {
+                alive.insert(*key);
+                queue.push(*key);
+            }
+        }
+        while let Some(key) = queue.pop() {
+            for reference in self.references[&key].iter() {
+                if alive.insert(*reference) {
+                    queue.push(*reference);
+                }
+            }
+        }
+
+        self.nodes
+            .iter()
+            .filter(|(key, _)| !alive.contains(key))
+            .filter_map(|(_, node)| match node {
+                Node::Object(o) => Some(o.clone()),
+                Node::Frame(_) => None,
+            })
+            .collect()
+    }
+
+    /// Whether garbage is still only referenced from other nodes, the graph
+    /// itself, and the list of garbage.
+    fn is_unreachable(&self, object: &Arc<Object>) -> bool {
+        let key = &**object as *const Object as usize;
+        let internal = self.internal.get(&key).cloned().unwrap_or(0);
+        Arc::strong_count(object) == internal + 2
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object_referencing(values: Vec<Arc<Object>>) -> Arc<Object> {
+        Arc::new(Object {
+            class: None,
+            const_value: ConstValue::InstanceVariables(
+                values
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, v)| (i as u64, v))
+                    .collect(),
+            ),
+        })
+    }
+
+    /// Makes two tracked objects reference each other, through a lazy
+    /// object that evaluates to an object referencing it.
+    fn cycle(heap: &mut Heap) -> (Weak<Object>, Weak<Object>, Arc<Object>) {
+        let a = Object::lazy(0, CallStack::new(), vec![]);
+        let b = object_referencing(vec![a.clone()]);
+        if let ConstValue::Lazy(_, ref thunk) = a.const_value {
+            thunk.resolve(b.clone());
+        }
+        heap.track(&a);
+        heap.track(&b);
+        (Arc::downgrade(&a), Arc::downgrade(&b), b)
+    }
+
+    #[test]
+    fn collects_unreachable_cycles() {
+        let mut heap = Heap::new();
+        let (a, b, handle) = cycle(&mut heap);
+        drop(handle);
+
+        assert!(a.upgrade().is_some());
+        assert_eq!(heap.collect(), 2);
+        assert!(a.upgrade().is_none());
+        assert!(b.upgrade().is_none());
+        assert_eq!(
+            heap.stats(),
+            HeapStats {
+                live_objects: 0,
+                allocated_objects: 2,
+                collections: 1,
+                collected_objects: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_cycles_referenced_from_outside() {
+        let mut heap = Heap::new();
+        let (a, b, handle) = cycle(&mut heap);
+        let holder = object_referencing(vec![handle]);
+
+        assert_eq!(heap.collect(), 0);
+        assert!(a.upgrade().is_some());
+        assert!(b.upgrade().is_some());
+        assert_eq!(heap.stats().live_objects, 2);
+
+        drop(holder);
+        assert_eq!(heap.collect(), 2);
+    }
+
+    #[test]
+    fn collects_when_threshold_is_reached() {
+        let mut heap = Heap::new();
+        for _ in 0..INITIAL_THRESHOLD / 2 {
+            let (_, _, handle) = cycle(&mut heap);
+            drop(handle);
+        }
+
+        // The last cycle is still referenced from the outside while it's
+        // being tracked, so it survives the collection.
+        let stats = heap.stats();
+        assert_eq!(stats.collections, 1);
+        assert_eq!(stats.collected_objects, INITIAL_THRESHOLD as u64 - 2);
+        assert_eq!(stats.live_objects, 2);
+    }
+
+    #[test]
+    fn paused_heaps_defer_collections() {
+        let mut heap = Heap::new();
+        heap.set_paused(true);
+        for _ in 0..INITIAL_THRESHOLD / 2 {
+            let (_, _, handle) = cycle(&mut heap);
+            drop(handle);
+        }
+        assert!(heap.is_due());
+        assert_eq!(heap.stats().collections, 0);
+
+        heap.set_paused(false);
+        let (_, _, handle) = cycle(&mut heap);
+        assert_eq!(heap.stats().collections, 1);
+        assert_eq!(heap.stats().collected_objects, INITIAL_THRESHOLD as u64);
+        drop(handle);
+    }
+
+    #[test]
+    fn adopted_objects_are_collected() {
+        let mut worker = Heap::new();
+        let (a, _, handle) = cycle(&mut worker);
+        drop(handle);
+
+        let mut heap = Heap::new();
+        heap.adopt(worker.take_objects());
+        assert_eq!(worker.collect(), 0);
+        assert_eq!(heap.collect(), 2);
+        assert!(a.upgrade().is_none());
+    }
+}
@@ -0,0 +1,1081 @@
+use super::recording::invalid_data;
+use crate::bytecode::{
+    BytecodeEncoding, BytecodeEncodingRead, ConstantPool, Instruction, PooledDecoder,
+    PooledEncoder, SourceMap,
+};
+use crate::vm::*;
+use crate::*;
+use std::io::{self, Read, Write};
+use std::ptr::null;
+
+/// Identifies a file as an image of a VM.
+pub const IMAGE_MAGIC: [u8; 4] = *b"\x7fLIM";
+
+/// The version of the image format. Images with any other version are
+/// rejected.
+pub const IMAGE_VERSION: u16 = 4;
+
+/// The state of a VM between evaluations, as captured by `VM::image`, from
+/// which a VM can be resumed with `VM::from_image`.
+///
+/// An image holds the program along with the classes, methods and variables
+/// it has declared, and the globals it has stored. Objects reachable from
+/// the globals are kept as a table, in which objects refer to each other by
+/// index, so that shared objects stay shared and cycles survive the trip.
+/// Lazy objects that haven't been evaluated yet keep the call frames they
+/// captured, in a table of their own.
+///
+/// The file starts with the magic number and the format version, followed by
+/// the constant pool, the program and the instructions that mark the classes
+/// constants are boxed with, and then the source map and the tables.
+pub struct Image {
+    program: Vec<Instruction>,
+    source_map: SourceMap,
+    declaring_class: u64,
+    methods: Vec<(u64, String)>,
+    variables: Vec<Variable>,
+    classes: Vec<ClassImage>,
+    marks: Vec<Instruction>,
+    objects: Vec<ObjectImage>,
+    frames: Vec<FrameImage>,
+    globals: Vec<(u64, u64)>,
+}
+
+struct ClassImage {
+    offset: u64,
+    name: String,
+
+    /// The offsets of the methods, by the ids they are called by.
+    methods: Vec<(u64, u64)>,
+
+    /// The ids of the variables.
+    variables: Vec<u64>,
+}
+
+struct ObjectImage {
+    class: Option<u64>,
+    value: ValueImage,
+}
+
+#[derive(Debug)]
+enum ValueImage {
+    Nothing,
+    Const(ConstValue),
+    InstanceVariables(Vec<(u64, u64)>),
+    Evaluated(u64, u64),
+    Unevaluated(u64, Option<u64>, Vec<u64>),
+}
+
+struct FrameImage {
+    parent: Option<u64>,
+    receiver: u64,
+    method: (String, u64),
+    return_address: u64,
+    callsite: (String, u64, u64),
+    stack_base: u64,
+}
+
+/// What a VM is resumed with.
+pub(super) struct Restored {
+    pub program: Vec<Instruction>,
+    pub source_map: Option<SourceMap>,
+    pub classes: HashMap<u64, Arc<Class>>,
+    pub methods: HashMap<u64, Arc<Method>>,
+    pub variables: HashMap<u64, Arc<Variable>>,
+    pub globals: HashMap<u64, Arc<Object>>,
+    pub declaring_class: u64,
+    pub marks: Vec<Instruction>,
+
+    /// The objects that can reference other objects, for the heap to track.
+    pub references: Vec<Arc<Object>>,
+}
+
+impl Image {
+    pub(super) fn capture(
+        program: &[Instruction],
+        source_map: Option<&SourceMap>,
+        classes: &HashMap<u64, Arc<Class>>,
+        methods: &HashMap<u64, Arc<Method>>,
+        variables: &HashMap<u64, Arc<Variable>>,
+        globals: &HashMap<u64, Arc<Object>>,
+        declaring_class: u64,
+    ) -> Image {
+        let marks = marks(classes);
+        let mut graph = Graph::default();
+        let mut globals: Vec<_> = globals
+            .iter()
+            .map(|(offset, object)| (*offset, graph.object(object)))
+            .collect();
+        globals.sort();
+        graph.expand();
+
+        let mut methods: Vec<_> = methods
+            .iter()
+            .map(|(offset, method)| (*offset, method.name.clone()))
+            .collect();
+        methods.sort();
+
+        let mut variables: Vec<_> = variables
+            .values()
+            .map(|variable| Variable {
+                name: variable.name.clone(),
+                id: variable.id,
+                getter_id: variable.getter_id,
+                setter_id: variable.setter_id,
+            })
+            .collect();
+        variables.sort_by_key(|variable| variable.id);
+
+        let mut classes: Vec<_> = classes
+            .iter()
+            .map(|(offset, class)| {
+                let mut methods: Vec<_> = class
+                    .methods
+                    .iter()
+                    .map(|(id, method)| (*id, method.offset as u64))
+                    .collect();
+                methods.sort();
+                let mut variables: Vec<_> = class.variables.keys().cloned().collect();
+                variables.sort();
+                ClassImage {
+                    offset: *offset,
+                    name: class.name.clone(),
+                    methods,
+                    variables,
+                }
+            })
+            .collect();
+        classes.sort_by_key(|class| class.offset);
+
+        Image {
+            program: program.to_vec(),
+            source_map: source_map.cloned().unwrap_or_default(),
+            declaring_class,
+            methods,
+            variables,
+            classes,
+            marks,
+            objects: graph.objects,
+            frames: graph.frames,
+            globals,
+        }
+    }
+
+    pub(super) fn restore(self) -> io::Result<Restored> {
+        if let Some(mark) = self.marks.iter().find(|i| !is_mark(i)) {
+            return Err(invalid_data(format!("{:?} does not mark a class", mark)));
+        }
+
+        let methods: HashMap<_, _> = self
+            .methods
+            .into_iter()
+            .map(|(offset, name)| {
+                let method = Method {
+                    name,
+                    offset: offset as usize,
+                };
+                (offset, Arc::new(method))
+            })
+            .collect();
+        let variables: HashMap<_, _> = self
+            .variables
+            .into_iter()
+            .map(|variable| (variable.id, Arc::new(variable)))
+            .collect();
+
+        let mut classes = HashMap::new();
+        for image in self.classes {
+            let mut class = Class::new(image.name, image.offset as usize);
+            let c = Arc::get_mut(&mut class).unwrap();
+            for (id, offset) in image.methods {
+                let method = methods
+                    .get(&offset)
+                    .ok_or_else(|| invalid_data(format!("unknown method {:X}", offset)))?;
+                c.methods.insert(id, method.clone());
+            }
+            for id in image.variables {
+                let variable = variables
+                    .get(&id)
+                    .ok_or_else(|| invalid_data(format!("unknown variable {:X}", id)))?;
+                c.variables.insert(id, variable.clone());
+                c.variable_getters
+                    .insert(variable.getter_id, variable.clone());
+                c.variable_setters
+                    .insert(variable.setter_id, variable.clone());
+            }
+            classes.insert(image.offset, class);
+        }
+
+        let class = |image: &ObjectImage| match image.class {
+            Some(offset) => classes
+                .get(&offset)
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| invalid_data(format!("unknown class {:X}", offset))),
+            None => Ok(None),
+        };
+
+        // Objects only refer to each other in cycles through lazy objects,
+        // whose thunks can be filled in after they are allocated. So lazy
+        // objects are allocated first, and every other object after the
+        // objects it refers to.
+        let mut objects: Vec<Option<Arc<Object>>> = vec![None; self.objects.len()];
+        for (index, image) in self.objects.iter().enumerate() {
+            if let ValueImage::Evaluated(offset, _) | ValueImage::Unevaluated(offset, _, _) =
+                image.value
+            {
+                objects[index] = Some(Arc::new(Object {
+                    class: class(image)?,
+                    const_value: ConstValue::Lazy(offset, Thunk::new(CallStack::new(), vec![])),
+                }));
+            }
+        }
+        let mut visiting = vec![false; self.objects.len()];
+        for index in 0..self.objects.len() {
+            let mut pending = vec![index];
+            while let Some(&index) = pending.last() {
+                if objects[index].is_some() {
+                    pending.pop();
+                    continue;
+                }
+                let image = &self.objects[index];
+                let value = match image.value {
+                    ValueImage::Nothing => ConstValue::Nothing,
+                    ValueImage::Const(ref value) => value.clone(),
+                    ValueImage::InstanceVariables(ref variables) => {
+                        let missing = variables
+                            .iter()
+                            .map(|(_, v)| *v as usize)
+                            .find(|v| objects.get(*v).map(Option::is_none).unwrap_or(false));
+                        if let Some(missing) = missing {
+                            if visiting[missing] {
+                                return Err(invalid_data("the objects form a cycle"));
+                            }
+                            visiting[index] = true;
+                            pending.push(missing);
+                            continue;
+                        }
+                        ConstValue::InstanceVariables(
+                            variables
+                                .iter()
+                                .map(|(id, value)| Ok((*id, restored(&objects, *value)?)))
+                                .collect::<io::Result<_>>()?,
+                        )
+                    }
+                    ValueImage::Evaluated(..) | ValueImage::Unevaluated(..) => unreachable!(),
+                };
+                objects[index] = Some(Arc::new(Object {
+                    class: class(image)?,
+                    const_value: value,
+                }));
+                visiting[index] = false;
+                pending.pop();
+            }
+        }
+        let objects: Vec<_> = objects.into_iter().map(Option::unwrap).collect();
+        let object = |index: u64| {
+            objects
+                .get(index as usize)
+                .cloned()
+                .ok_or_else(|| invalid_data(format!("unknown object {}", index)))
+        };
+
+        let frames = restore_frames(&self.frames, &methods, &object)?;
+        let frame = |index: u64| {
+            frames
+                .get(index as usize)
+                .cloned()
+                .ok_or_else(|| invalid_data(format!("unknown call frame {}", index)))
+        };
+
+        let mut references = vec![];
+        for (image, target) in self.objects.into_iter().zip(objects.iter()) {
+            let thunk = match target.const_value {
+                ConstValue::Lazy(_, ref thunk) => thunk,
+                ConstValue::InstanceVariables(_) => {
+                    references.push(target.clone());
+                    continue;
+                }
+                _ => continue,
+            };
+            match image.value {
+                ValueImage::Evaluated(_, value) => thunk.set(ThunkState::Evaluated(object(value)?)),
+                ValueImage::Unevaluated(_, top, dependencies) => {
+                    let call_stack = match top {
+                        Some(index) => CallStack::from(Some(frame(index)?)),
+                        None => CallStack::new(),
+                    };
+                    let dependencies = dependencies
+                        .into_iter()
+                        .map(object)
+                        .collect::<io::Result<_>>()?;
+                    thunk.set(ThunkState::Unevaluated(call_stack, dependencies));
+                }
+                _ => {}
+            }
+            references.push(target.clone());
+        }
+
+        let globals = self
+            .globals
+            .into_iter()
+            .map(|(offset, index)| Ok((offset, object(index)?)))
+            .collect::<io::Result<_>>()?;
+
+        Ok(Restored {
+            program: self.program,
+            source_map: if self.source_map.is_empty() {
+                None
+            } else {
+                Some(self.source_map)
+            },
+            classes,
+            methods,
+            variables,
+            globals,
+            declaring_class: self.declaring_class,
+            marks: self.marks,
+            references,
+        })
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        let mut e = PooledEncoder::new();
+        e.instructions(&self.program)?;
+        e.instructions(&self.marks)?;
+        self.source_map.serialize(&mut e.w)?;
+        e.varint(self.declaring_class)?;
+
+        e.varint(self.methods.len() as u64)?;
+        for (offset, name) in self.methods.iter() {
+            e.varint(*offset)?;
+            e.string(name)?;
+        }
+
+        e.varint(self.variables.len() as u64)?;
+        for variable in self.variables.iter() {
+            e.string(&variable.name)?;
+            e.varint(variable.id)?;
+            e.varint(variable.getter_id)?;
+            e.varint(variable.setter_id)?;
+        }
+
+        e.varint(self.classes.len() as u64)?;
+        for class in self.classes.iter() {
+            e.varint(class.offset)?;
+            e.string(&class.name)?;
+            e.varint(class.methods.len() as u64)?;
+            for (id, offset) in class.methods.iter() {
+                e.varint(*id)?;
+                e.varint(*offset)?;
+            }
+            e.varint(class.variables.len() as u64)?;
+            for id in class.variables.iter() {
+                e.varint(*id)?;
+            }
+        }
+
+        e.varint(self.objects.len() as u64)?;
+        for object in self.objects.iter() {
+            e.optional_index(object.class)?;
+            encode_value(&mut e, &object.value)?;
+        }
+
+        e.varint(self.frames.len() as u64)?;
+        for frame in self.frames.iter() {
+            e.optional_index(frame.parent)?;
+            e.varint(frame.receiver)?;
+            e.string(&frame.method.0)?;
+            e.varint(frame.method.1)?;
+            e.varint(frame.return_address)?;
+            e.string(&frame.callsite.0)?;
+            e.varint(frame.callsite.1)?;
+            e.varint(frame.callsite.2)?;
+            e.varint(frame.stack_base)?;
+        }
+
+        e.varint(self.globals.len() as u64)?;
+        for (offset, object) in self.globals.iter() {
+            e.varint(*offset)?;
+            e.varint(*object)?;
+        }
+
+        w.write_all(&IMAGE_MAGIC)?;
+        let written = IMAGE_MAGIC.len() + IMAGE_VERSION.serialize(&mut w)?;
+        Ok(written + e.finish(w)?)
+    }
+
+    pub fn read<R: Read>(mut r: R) -> io::Result<Image> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != IMAGE_MAGIC {
+            return Err(invalid_data("not an image of a Loa VM"));
+        }
+        let version: u16 = r.deserialize()?;
+        if version != IMAGE_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported image format version {}",
+                version
+            )));
+        }
+
+        let pool = ConstantPool::deserialize(&mut r)?;
+        let mut d = PooledDecoder::new(r, &pool);
+        let program = d.instructions()?;
+        let marks = d.instructions()?;
+        let source_map = SourceMap::deserialize(&mut d.r)?;
+        let declaring_class = d.varint()?;
+
+        let mut methods = vec![];
+        for _ in 0..d.varint()? {
+            methods.push((d.varint()?, d.string()?));
+        }
+
+        let mut variables = vec![];
+        for _ in 0..d.varint()? {
+            variables.push(Variable {
+                name: d.string()?,
+                id: d.varint()?,
+                getter_id: d.varint()?,
+                setter_id: d.varint()?,
+            });
+        }
+
+        let mut classes = vec![];
+        for _ in 0..d.varint()? {
+            let offset = d.varint()?;
+            let name = d.string()?;
+            let mut methods = vec![];
+            for _ in 0..d.varint()? {
+                methods.push((d.varint()?, d.varint()?));
+            }
+            let mut variables = vec![];
+            for _ in 0..d.varint()? {
+                variables.push(d.varint()?);
+            }
+            classes.push(ClassImage {
+                offset,
+                name,
+                methods,
+                variables,
+            });
+        }
+
+        let mut objects = vec![];
+        for _ in 0..d.varint()? {
+            objects.push(ObjectImage {
+                class: d.optional_index()?,
+                value: decode_value(&mut d)?,
+            });
+        }
+
+        let mut frames = vec![];
+        for _ in 0..d.varint()? {
+            frames.push(FrameImage {
+                parent: d.optional_index()?,
+                receiver: d.varint()?,
+                method: (d.string()?, d.varint()?),
+                return_address: d.varint()?,
+                callsite: (d.string()?, d.varint()?, d.varint()?),
+                stack_base: d.varint()?,
+            });
+        }
+
+        let mut globals = vec![];
+        for _ in 0..d.varint()? {
+            globals.push((d.varint()?, d.varint()?));
+        }
+
+        Ok(Image {
+            program,
+            source_map,
+            declaring_class,
+            methods,
+            variables,
+            classes,
+            marks,
+            objects,
+            frames,
+            globals,
+        })
+    }
+}
+
+/// Numbers the objects and call frames reachable from the globals.
+#[derive(Default)]
+struct Graph {
+    objects: Vec<ObjectImage>,
+    frames: Vec<FrameImage>,
+    object_indices: HashMap<*const Object, u64>,
+    frame_indices: HashMap<*const StackFrame, u64>,
+
+    /// Objects that have been numbered, but whose values have yet to be.
+    unvisited: Vec<(u64, Arc<Object>)>,
+}
+
+impl Graph {
+    fn object(&mut self, object: &Arc<Object>) -> u64 {
+        let key = &**object as *const Object;
+        if let Some(index) = self.object_indices.get(&key) {
+            return *index;
+        }
+        let index = self.objects.len() as u64;
+        self.object_indices.insert(key, index);
+        self.objects.push(ObjectImage {
+            class: object.class.as_ref().map(|class| class.offset as u64),
+            value: ValueImage::Nothing,
+        });
+        self.unvisited.push((index, object.clone()));
+        index
+    }
+
+    /// Numbers the frame along with its parents, which are numbered after
+    /// it unless they already have been.
+    fn frame(&mut self, frame: &Arc<StackFrame>) -> u64 {
+        let key = |frame: &Arc<StackFrame>| &**frame as *const StackFrame;
+
+        let mut chain = vec![];
+        let mut next = Some(frame);
+        while let Some(frame) = next {
+            if self.frame_indices.contains_key(&key(frame)) {
+                break;
+            }
+            let index = (self.frames.len() + chain.len()) as u64;
+            self.frame_indices.insert(key(frame), index);
+            chain.push(frame.clone());
+            next = frame.parent.as_ref();
+        }
+
+        for frame in chain {
+            let SourceCodeLocation(ref uri, line, character) = frame.callsite;
+            let image = FrameImage {
+                parent: frame.parent.as_ref().map(|p| self.frame_indices[&key(p)]),
+                receiver: self.object(&frame.receiver),
+                method: (frame.method.name.clone(), frame.method.offset as u64),
+                return_address: frame.return_address as u64,
+                callsite: (uri.clone(), line, character),
+                stack_base: frame.stack_base as u64,
+            };
+            self.frames.push(image);
+        }
+        self.frame_indices[&key(frame)]
+    }
+
+    /// Captures the values of the objects numbered so far, and of everything
+    /// they reference in turn.
+    fn expand(&mut self) {
+        while let Some((index, object)) = self.unvisited.pop() {
+            let value = match object.const_value {
+                ConstValue::Nothing => ValueImage::Nothing,
+                ConstValue::InstanceVariables(ref variables) => {
+                    let mut variables: Vec<_> = variables
+                        .iter()
+                        .map(|(id, value)| (*id, self.object(value)))
+                        .collect();
+                    variables.sort();
+                    ValueImage::InstanceVariables(variables)
+                }
+                ConstValue::Lazy(offset, ref thunk) => match (thunk.value(), thunk.captured()) {
+                    (Some(value), _) => ValueImage::Evaluated(offset, self.object(&value)),
+                    (None, Some((call_stack, dependencies))) => ValueImage::Unevaluated(
+                        offset,
+                        call_stack.top().map(|frame| self.frame(frame)),
+                        dependencies.iter().map(|d| self.object(d)).collect(),
+                    ),
+                    (None, None) => ValueImage::Unevaluated(offset, None, vec![]),
+                },
+                ref value => ValueImage::Const(value.clone()),
+            };
+            self.objects[index as usize].value = value;
+        }
+    }
+}
+
+/// The instructions that mark the classes constants are boxed with, as far
+/// as they are classes of the VM.
+fn marks(classes: &HashMap<u64, Arc<Class>>) -> Vec<Instruction> {
+    let constant_class = |constant: *const Arc<Object>| unsafe {
+        constant
+            .as_ref()
+            .and_then(|object| object.class.as_ref())
+            .map(|class| &**class as *const Class)
+            .unwrap_or(null())
+    };
+
+    let marked: [(*const Class, fn(u64) -> Instruction); 20] = unsafe {
+        [
+            (constant_class(TRUE), Instruction::MarkClassTrue),
+            (constant_class(FALSE), Instruction::MarkClassFalse),
+            (STRING_CLASS, Instruction::MarkClassString),
+            (CHARACTER_CLASS, Instruction::MarkClassCharacter),
+            (SYMBOL_CLASS, Instruction::MarkClassSymbol),
+            (U8_CLASS, Instruction::MarkClassU8),
+            (U16_CLASS, Instruction::MarkClassU16),
+            (U32_CLASS, Instruction::MarkClassU32),
+            (U64_CLASS, Instruction::MarkClassU64),
+            (U128_CLASS, Instruction::MarkClassU128),
+            (UBIG_CLASS, Instruction::MarkClassUBig),
+            (I8_CLASS, Instruction::MarkClassI8),
+            (I16_CLASS, Instruction::MarkClassI16),
+            (I32_CLASS, Instruction::MarkClassI32),
+            (I64_CLASS, Instruction::MarkClassI64),
+            (I128_CLASS, Instruction::MarkClassI128),
+            (IBIG_CLASS, Instruction::MarkClassIBig),
+            (F32_CLASS, Instruction::MarkClassF32),
+            (F64_CLASS, Instruction::MarkClassF64),
+            (FBIG_CLASS, Instruction::MarkClassFBig),
+        ]
+    };
+
+    // The marks are global, so they may point at the classes of another VM,
+    // in which case there is nothing to mark.
+    marked
+        .iter()
+        .filter_map(|(marked, mark)| {
+            classes
+                .iter()
+                .find(|(_, class)| &***class as *const Class == *marked)
+                .map(|(offset, _)| mark(*offset))
+        })
+        .collect()
+}
+
+fn is_mark(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::MarkClassTrue(_)
+        | Instruction::MarkClassFalse(_)
+        | Instruction::MarkClassString(_)
+        | Instruction::MarkClassCharacter(_)
+        | Instruction::MarkClassSymbol(_)
+        | Instruction::MarkClassU8(_)
+        | Instruction::MarkClassU16(_)
+        | Instruction::MarkClassU32(_)
+        | Instruction::MarkClassU64(_)
+        | Instruction::MarkClassU128(_)
+        | Instruction::MarkClassUBig(_)
+        | Instruction::MarkClassI8(_)
+        | Instruction::MarkClassI16(_)
+        | Instruction::MarkClassI32(_)
+        | Instruction::MarkClassI64(_)
+        | Instruction::MarkClassI128(_)
+        | Instruction::MarkClassIBig(_)
+        | Instruction::MarkClassF32(_)
+        | Instruction::MarkClassF64(_)
+        | Instruction::MarkClassFBig(_) => true,
+        _ => false,
+    }
+}
+
+/// Recreates the call frames, each after its parent.
+fn restored(objects: &[Option<Arc<Object>>], index: u64) -> io::Result<Arc<Object>> {
+    objects
+        .get(index as usize)
+        .cloned()
+        .and_then(|object| object)
+        .ok_or_else(|| invalid_data(format!("unknown object {}", index)))
+}
+
+fn restore_frames<F: Fn(u64) -> io::Result<Arc<Object>>>(
+    images: &[FrameImage],
+    methods: &HashMap<u64, Arc<Method>>,
+    object: F,
+) -> io::Result<Vec<Arc<StackFrame>>> {
+    let mut frames: Vec<Option<Arc<StackFrame>>> = vec![None; images.len()];
+    for index in 0..images.len() {
+        let mut chain = vec![];
+        let mut next = Some(index as u64);
+        while let Some(index) = next {
+            let image = images
+                .get(index as usize)
+                .ok_or_else(|| invalid_data(format!("unknown call frame {}", index)))?;
+            if frames[index as usize].is_some() {
+                break;
+            }
+            if chain.len() > images.len() {
+                return Err(invalid_data("the call frames form a cycle"));
+            }
+            chain.push(index);
+            next = image.parent;
+        }
+
+        for index in chain.into_iter().rev() {
+            let image = &images[index as usize];
+            let parent = image.parent.and_then(|p| frames[p as usize].clone());
+            let (ref name, offset) = image.method;
+            let method = match methods.get(&offset) {
+                Some(method) if method.name == *name => method.clone(),
+                _ => Arc::new(Method {
+                    name: name.clone(),
+                    offset: offset as usize,
+                }),
+            };
+            let (ref uri, line, character) = image.callsite;
+            frames[index as usize] = Some(Arc::new(StackFrame {
+                depth: parent.as_ref().map(|p| p.depth).unwrap_or(0) + 1,
+                parent,
+                receiver: object(image.receiver)?,
+                method,
+                return_address: image.return_address as usize,
+                callsite: SourceCodeLocation(uri.clone(), line, character),
+                stack_base: image.stack_base as usize,
+            }));
+        }
+    }
+    Ok(frames.into_iter().map(Option::unwrap).collect())
+}
+
+fn encode_value(e: &mut PooledEncoder, value: &ValueImage) -> io::Result<()> {
+    match value {
+        ValueImage::Nothing => e.varint(0),
+        ValueImage::Const(value) => {
+            e.varint(1)?;
+            let instruction = load_const(value)
+                .ok_or_else(|| invalid_data("cannot keep an object without a value"))?;
+            e.instruction(&instruction)
+        }
+        ValueImage::InstanceVariables(variables) => {
+            e.varint(2)?;
+            e.varint(variables.len() as u64)?;
+            for (id, value) in variables.iter() {
+                e.varint(*id)?;
+                e.varint(*value)?;
+            }
+            Ok(())
+        }
+        ValueImage::Evaluated(offset, value) => {
+            e.varint(3)?;
+            e.varint(*offset)?;
+            e.varint(*value)
+        }
+        ValueImage::Unevaluated(offset, frame, dependencies) => {
+            e.varint(4)?;
+            e.varint(*offset)?;
+            e.optional_index(*frame)?;
+            e.varint(dependencies.len() as u64)?;
+            for dependency in dependencies.iter() {
+                e.varint(*dependency)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+fn decode_value<R: Read>(d: &mut PooledDecoder<R>) -> io::Result<ValueImage> {
+    Ok(match d.varint()? {
+        0 => ValueImage::Nothing,
+        1 => match loaded_const(d.instruction()?) {
+            Some(value) => ValueImage::Const(value),
+            None => return Err(invalid_data("expected a constant value")),
+        },
+        2 => {
+            let mut variables = vec![];
+            for _ in 0..d.varint()? {
+                variables.push((d.varint()?, d.varint()?));
+            }
+            ValueImage::InstanceVariables(variables)
+        }
+        3 => ValueImage::Evaluated(d.varint()?, d.varint()?),
+        4 => {
+            let offset = d.varint()?;
+            let frame = d.optional_index()?;
+            let mut dependencies = vec![];
+            for _ in 0..d.varint()? {
+                dependencies.push(d.varint()?);
+            }
+            ValueImage::Unevaluated(offset, frame, dependencies)
+        }
+        tag => return Err(invalid_data(format!("unknown value tag {}", tag))),
+    })
+}
+
+/// Constants are kept as the instructions that load them, so that their
+/// strings end up in the constant pool along with those of the program.
+fn load_const(value: &ConstValue) -> Option<Instruction> {
+    use ConstValue::*;
+
+    Some(match value.clone() {
+        String(s) => Instruction::LoadConstString(s),
+        Character(c) => Instruction::LoadConstCharacter(c),
+        Symbol(s) => Instruction::LoadConstSymbol(s),
+        U8(n) => Instruction::LoadConstU8(n),
+        U16(n) => Instruction::LoadConstU16(n),
+        U32(n) => Instruction::LoadConstU32(n),
+        U64(n) => Instruction::LoadConstU64(n),
+        U128(n) => Instruction::LoadConstU128(n),
+        UBig(n) => Instruction::LoadConstUBig(n),
+        I8(n) => Instruction::LoadConstI8(n),
+        I16(n) => Instruction::LoadConstI16(n),
+        I32(n) => Instruction::LoadConstI32(n),
+        I64(n) => Instruction::LoadConstI64(n),
+        I128(n) => Instruction::LoadConstI128(n),
+        IBig(n) => Instruction::LoadConstIBig(n),
+        F32(n) => Instruction::LoadConstF32(n),
+        F64(n) => Instruction::LoadConstF64(n),
+        FBig(n) => Instruction::LoadConstFBig(n),
+        Nothing | InstanceVariables(_) | Lazy(_, _) => return None,
+    })
+}
+
+fn loaded_const(instruction: Instruction) -> Option<ConstValue> {
+    Some(match instruction {
+        Instruction::LoadConstString(s) => ConstValue::String(s),
+        Instruction::LoadConstCharacter(c) => ConstValue::Character(c),
+        Instruction::LoadConstSymbol(s) => ConstValue::Symbol(s),
+        Instruction::LoadConstU8(n) => ConstValue::U8(n),
+        Instruction::LoadConstU16(n) => ConstValue::U16(n),
+        Instruction::LoadConstU32(n) => ConstValue::U32(n),
+        Instruction::LoadConstU64(n) => ConstValue::U64(n),
+        Instruction::LoadConstU128(n) => ConstValue::U128(n),
+        Instruction::LoadConstUBig(n) => ConstValue::UBig(n),
+        Instruction::LoadConstI8(n) => ConstValue::I8(n),
+        Instruction::LoadConstI16(n) => ConstValue::I16(n),
+        Instruction::LoadConstI32(n) => ConstValue::I32(n),
+        Instruction::LoadConstI64(n) => ConstValue::I64(n),
+        Instruction::LoadConstI128(n) => ConstValue::I128(n),
+        Instruction::LoadConstIBig(n) => ConstValue::IBig(n),
+        Instruction::LoadConstF32(n) => ConstValue::F32(n),
+        Instruction::LoadConstF64(n) => ConstValue::F64(n),
+        Instruction::LoadConstFBig(n) => ConstValue::FBig(n),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::Parser;
+
+    fn program() -> Vec<Instruction> {
+        Parser::new()
+            .parse(
+                r#"
+                @N/String
+                  DeclareClass "N/String"
+                  MarkClassString @N/String
+
+                @N/A$class$methods
+                  DeclareMethod "new:" @N/A$class#new:
+
+                @N/A$methods
+                  DeclareVariable "b" @N/A(b) @N/A#b @N/A#b:
+
+                @N/A$class
+                  DeclareClass "N/A class"
+                  UseMethod @N/A$class#new:
+
+                @N/A
+                  DeclareClass "N/A"
+                  UseVariable @N/A(b)
+
+                @N/B
+                  DeclareClass "N/B"
+
+                LoadObject @N/B
+                LoadObject @N/A$class
+                CallMethod @N/A$class#new: "test:" 0 0
+                StoreGlobal @N/a
+                LoadConstString "lazy"
+                LoadLazy 1 @N$lazy1
+                StoreGlobal @N/b
+                Halt
+
+                @N/A$class#new:
+                  LoadLocal 1
+                  LoadObject @N/A
+                  CallMethod @N/A#b: "test:" 6 30
+                  Return 2
+
+                @N$lazy1
+                  LoadLocal 0
+                  ReturnLazy 1
+
+                @N/A(b)
+                  Noop
+
+                @N/A#b
+                  Noop
+
+                @N/A#b:
+                  Noop
+
+                @N/a
+                  Noop
+
+                @N/b
+                  Noop
+                "#,
+            )
+            .unwrap()
+            .into()
+    }
+
+    /// The offsets of the globals the program stores, in order.
+    fn globals(program: &[Instruction]) -> Vec<u64> {
+        program
+            .iter()
+            .filter_map(|instruction| match instruction {
+                Instruction::StoreGlobal(offset) => Some(*offset),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn resume(vm: &VM) -> VM {
+        let mut bytes = vec![];
+        let written = vm.image().write(&mut bytes).unwrap();
+        assert_eq!(written, bytes.len());
+        VM::from_image(Image::read(bytes.as_slice()).unwrap()).unwrap()
+    }
+
+    fn load_global(vm: &mut VM, offset: u64) -> String {
+        vm.eval_pop::<()>(vec![Instruction::LoadGlobal(offset), Instruction::Halt])
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn round_trip() {
+        let program = program();
+        let globals = globals(&program);
+        let mut vm = VM::new();
+        vm.eval::<()>(program);
+
+        let mut resumed = resume(&vm);
+
+        assert_eq!(load_global(&mut resumed, globals[0]), "a N/A(b=N/B)");
+        assert_eq!(load_global(&mut resumed, globals[1]), "lazy");
+        assert_eq!(
+            resumed
+                .eval_pop::<()>(vec![
+                    Instruction::LoadConstString("constant".into()),
+                    Instruction::Halt
+                ])
+                .unwrap()
+                .class
+                .as_ref()
+                .unwrap()
+                .name,
+            "N/String"
+        );
+    }
+
+    #[test]
+    fn evaluated_lazy_objects() {
+        let program = program();
+        let globals = globals(&program);
+        let mut vm = VM::new();
+        vm.eval::<()>(program);
+        assert_eq!(load_global(&mut vm, globals[1]), "lazy");
+
+        let resumed = resume(&vm);
+        let image = resumed.image();
+
+        assert_matches!(
+            image
+                .objects
+                .iter()
+                .find(|o| o.class.is_none())
+                .map(|o| &o.value),
+            Some(ValueImage::Evaluated(_, _))
+        );
+    }
+
+    fn image_of(objects: Vec<ObjectImage>) -> Image {
+        Image {
+            program: vec![],
+            source_map: SourceMap::default(),
+            declaring_class: 0,
+            methods: vec![],
+            variables: vec![],
+            classes: vec![],
+            marks: vec![],
+            objects,
+            frames: vec![],
+            globals: vec![(0, 0)],
+        }
+    }
+
+    #[test]
+    fn interns_strings() {
+        let size = |count| {
+            let strings = (0..count)
+                .map(|_| ObjectImage {
+                    class: None,
+                    value: ValueImage::Const(ConstValue::String("a long enough string".into())),
+                })
+                .collect();
+            let mut bytes = vec![];
+            image_of(strings).write(&mut bytes).unwrap();
+            bytes.len()
+        };
+
+        // The missing class, the tag of the value, and the opcode of the
+        // instruction along with the index of the string in the pool.
+        assert_eq!(size(2) - size(1), 4);
+
+        let mut bytes = vec![];
+        image_of(vec![ObjectImage {
+            class: None,
+            value: ValueImage::Const(ConstValue::Symbol("symbol".into())),
+        }])
+        .write(&mut bytes)
+        .unwrap();
+        assert_matches!(
+            Image::read(bytes.as_slice()).unwrap().objects[0].value,
+            ValueImage::Const(ConstValue::Symbol(ref s)) if s == "symbol"
+        );
+    }
+
+    #[test]
+    fn restores_cycles_through_lazy_objects() {
+        let restored = image_of(vec![
+            ObjectImage {
+                class: None,
+                value: ValueImage::Evaluated(0, 1),
+            },
+            ObjectImage {
+                class: None,
+                value: ValueImage::InstanceVariables(vec![(0, 0)]),
+            },
+        ])
+        .restore()
+        .unwrap();
+
+        let lazy = &restored.globals[&0];
+        let value = match lazy.const_value {
+            ConstValue::Lazy(_, ref thunk) => thunk.value().unwrap(),
+            _ => panic!("expected a lazy object"),
+        };
+        assert_matches!(
+            value.const_value,
+            ConstValue::InstanceVariables(ref variables) if Arc::ptr_eq(&variables[&0], lazy)
+        );
+        assert_eq!(restored.references.len(), 2);
+    }
+
+    #[test]
+    fn rejects_cycles_without_lazy_objects() {
+        let error = image_of(vec![
+            ObjectImage {
+                class: None,
+                value: ValueImage::InstanceVariables(vec![(0, 1)]),
+            },
+            ObjectImage {
+                class: None,
+                value: ValueImage::InstanceVariables(vec![(0, 0)]),
+            },
+        ])
+        .restore()
+        .err()
+        .unwrap();
+
+        assert_eq!(error.to_string(), "the objects form a cycle");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = Image::read(b"\x7fLOA\x00\x03".as_ref()).err().unwrap();
+
+        assert_eq!(error.to_string(), "not an image of a Loa VM");
+    }
+}
@@ -0,0 +1,221 @@
+use crate::vm::*;
+use crate::*;
+
+/// The most classes a single call site remembers before it gives up on
+/// caching, and falls back to looking up every call.
+pub const POLYMORPHIC_LIMIT: usize = 4;
+
+/// What a message sent to instances of a class resolves to.
+#[derive(Debug, Clone)]
+pub enum Dispatch {
+    Getter(Arc<Variable>),
+    Setter(Arc<Variable>),
+    Method(Arc<Method>),
+}
+
+/// Remembers how the messages sent from each call site were dispatched, for
+/// the classes of the receivers seen there.
+///
+/// Call sites are identified by the offset of their `CallMethod`
+/// instruction. Classes are identified by address, which is only sound as
+/// long as classes aren't replaced or changed, so every declaration must
+/// `invalidate` the caches.
+pub struct InlineCaches {
+    enabled: bool,
+    epoch: u64,
+    sites: Vec<Option<InlineCache>>,
+    stats: InlineCacheStats,
+}
+
+struct InlineCache {
+    epoch: u64,
+    entries: Vec<(usize, Dispatch)>,
+    megamorphic: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InlineCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CallSiteState {
+    Uninitialized,
+    Monomorphic,
+    Polymorphic(usize),
+    Megamorphic,
+}
+
+impl InlineCaches {
+    pub fn new() -> InlineCaches {
+        InlineCaches {
+            enabled: true,
+            epoch: 0,
+            sites: vec![],
+            stats: InlineCacheStats::default(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.invalidate();
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn stats(&self) -> InlineCacheStats {
+        self.stats
+    }
+
+    /// Forgets everything cached so far.
+    #[inline]
+    pub fn invalidate(&mut self) {
+        self.epoch += 1;
+    }
+
+    #[inline]
+    pub fn lookup(&mut self, pc: usize, class: &Arc<Class>) -> Option<Dispatch> {
+        if !self.enabled {
+            return None;
+        }
+        let address = class_address(class);
+        let epoch = self.epoch;
+        let dispatch = match self.sites.get(pc) {
+            Some(Some(cache)) if cache.epoch == epoch => cache
+                .entries
+                .iter()
+                .find(|(a, _)| *a == address)
+                .map(|(_, dispatch)| dispatch.clone()),
+            _ => None,
+        };
+        if dispatch.is_some() {
+            self.stats.hits += 1;
+        } else {
+            self.stats.misses += 1;
+        }
+        dispatch
+    }
+
+    /// Records how a message sent from a call site to an instance of a
+    /// class was dispatched.
+    pub fn insert(&mut self, pc: usize, class: &Arc<Class>, dispatch: Dispatch) {
+        if !self.enabled {
+            return;
+        }
+        if self.sites.len() <= pc {
+            self.sites.resize_with(pc + 1, || None);
+        }
+        let epoch = self.epoch;
+        let cache = self.sites[pc].get_or_insert_with(|| InlineCache {
+            epoch,
+            entries: vec![],
+            megamorphic: false,
+        });
+        if cache.epoch != epoch {
+            cache.epoch = epoch;
+            cache.entries.clear();
+            cache.megamorphic = false;
+        }
+        if cache.megamorphic {
+            return;
+        }
+        if cache.entries.len() == POLYMORPHIC_LIMIT {
+            cache.entries.clear();
+            cache.megamorphic = true;
+            return;
+        }
+        cache.entries.push((class_address(class), dispatch));
+    }
+
+    pub fn state(&self, pc: usize) -> CallSiteState {
+        match self.sites.get(pc) {
+            Some(Some(cache)) if cache.epoch == self.epoch => {
+                if cache.megamorphic {
+                    CallSiteState::Megamorphic
+                } else {
+                    match cache.entries.len() {
+                        0 => CallSiteState::Uninitialized,
+                        1 => CallSiteState::Monomorphic,
+                        n => CallSiteState::Polymorphic(n),
+                    }
+                }
+            }
+            _ => CallSiteState::Uninitialized,
+        }
+    }
+}
+
+#[inline]
+fn class_address(class: &Arc<Class>) -> usize {
+    class.as_ref() as *const Class as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn method(name: &str, offset: usize) -> Dispatch {
+        Dispatch::Method(Arc::new(Method {
+            name: name.into(),
+            offset,
+        }))
+    }
+
+    fn offset(dispatch: Option<Dispatch>) -> Option<usize> {
+        match dispatch {
+            Some(Dispatch::Method(method)) => Some(method.offset),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn monomorphic_and_polymorphic() {
+        let a = Class::new("A".into(), 0);
+        let b = Class::new("B".into(), 1);
+        let mut caches = InlineCaches::new();
+
+        assert_eq!(offset(caches.lookup(7, &a)), None);
+        caches.insert(7, &a, method("m", 10));
+        assert_eq!(caches.state(7), CallSiteState::Monomorphic);
+        assert_eq!(offset(caches.lookup(7, &a)), Some(10));
+
+        assert_eq!(offset(caches.lookup(7, &b)), None);
+        caches.insert(7, &b, method("m", 20));
+        assert_eq!(caches.state(7), CallSiteState::Polymorphic(2));
+        assert_eq!(offset(caches.lookup(7, &b)), Some(20));
+        assert_eq!(offset(caches.lookup(7, &a)), Some(10));
+
+        assert_eq!(caches.state(3), CallSiteState::Uninitialized);
+        assert_eq!(caches.stats(), InlineCacheStats { hits: 3, misses: 2 });
+    }
+
+    #[test]
+    fn megamorphic() {
+        let classes: Vec<_> = (0..=POLYMORPHIC_LIMIT)
+            .map(|i| Class::new(format!("C{}", i), i))
+            .collect();
+        let mut caches = InlineCaches::new();
+        for class in classes.iter() {
+            caches.insert(0, class, method("m", 1));
+        }
+        assert_eq!(caches.state(0), CallSiteState::Megamorphic);
+        assert_eq!(offset(caches.lookup(0, &classes[0])), None);
+    }
+
+    #[test]
+    fn invalidation() {
+        let a = Class::new("A".into(), 0);
+        let mut caches = InlineCaches::new();
+        caches.insert(0, &a, method("m", 1));
+        caches.invalidate();
+        assert_eq!(caches.state(0), CallSiteState::Uninitialized);
+        assert_eq!(offset(caches.lookup(0, &a)), None);
+
+        caches.set_enabled(false);
+        caches.insert(0, &a, method("m", 1));
+        assert_eq!(offset(caches.lookup(0, &a)), None);
+    }
+}
@@ -0,0 +1,108 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Resource limits for a VM. Exceeding any of them ends the evaluation in a
+/// panic. Limits that are `None` are not enforced, which is the default.
+///
+/// The instruction budget and the timeout apply to each call to `VM::eval`
+/// and `VM::eval_pop`, so a REPL gets a fresh budget for every line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VMLimits {
+    /// The number of instructions that may be executed.
+    pub max_instructions: Option<u64>,
+
+    /// How many frames the call stack may hold.
+    pub max_call_depth: Option<usize>,
+
+    /// How many objects the stack may hold.
+    pub max_stack_size: Option<usize>,
+
+    /// How many lazy objects may be evaluated within each other, as one lazy
+    /// object depends on another.
+    pub max_lazy_depth: Option<usize>,
+
+    /// How long the evaluation may take.
+    pub timeout: Option<Duration>,
+}
+
+impl VMLimits {
+    pub fn unlimited() -> VMLimits {
+        VMLimits::default()
+    }
+}
+
+/// Parses limits from a comma separated list of `name=value` pairs, like
+/// `instructions=1000000,call-depth=10000,timeout=2.5`. The names are
+/// `instructions`, `call-depth`, `stack-size`, `lazy-depth` and `timeout`,
+/// which is in seconds.
+impl FromStr for VMLimits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut limits = VMLimits::unlimited();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(format!("expected a value for limit `{}`", name)),
+            };
+            let invalid = || format!("invalid value for limit `{}`: {}", name, value);
+
+            match name {
+                "instructions" => {
+                    limits.max_instructions = Some(value.parse().map_err(|_| invalid())?)
+                }
+                "call-depth" => limits.max_call_depth = Some(value.parse().map_err(|_| invalid())?),
+                "stack-size" => limits.max_stack_size = Some(value.parse().map_err(|_| invalid())?),
+                "lazy-depth" => limits.max_lazy_depth = Some(value.parse().map_err(|_| invalid())?),
+                "timeout" => {
+                    let seconds = f64::from_str(value).map_err(|_| invalid())?;
+                    if !seconds.is_finite() || seconds < 0.0 {
+                        return Err(invalid());
+                    }
+                    limits.timeout = Some(Duration::from_secs_f64(seconds));
+                }
+                _ => return Err(format!("unknown limit: {}", name)),
+            }
+        }
+        Ok(limits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!(
+            "instructions=1000, call-depth=20,stack-size=300,lazy-depth=40,timeout=1.5"
+                .parse::<VMLimits>(),
+            Ok(VMLimits {
+                max_instructions: Some(1000),
+                max_call_depth: Some(20),
+                max_stack_size: Some(300),
+                max_lazy_depth: Some(40),
+                timeout: Some(Duration::from_millis(1500)),
+            })
+        );
+        assert_eq!("".parse::<VMLimits>(), Ok(VMLimits::unlimited()));
+    }
+
+    #[test]
+    fn parse_invalid_limits() {
+        assert_eq!(
+            "instructions".parse::<VMLimits>(),
+            Err("expected a value for limit `instructions`".into())
+        );
+        assert_eq!(
+            "timeout=-1".parse::<VMLimits>(),
+            Err("invalid value for limit `timeout`: -1".into())
+        );
+        assert_eq!(
+            "memory=1".parse::<VMLimits>(),
+            Err("unknown limit: memory".into())
+        );
+    }
+}
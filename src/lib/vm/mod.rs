@@ -17,5 +17,44 @@ pub use self::class::*;
 mod runtime;
 pub use self::runtime::*;
 
+mod natives;
+pub use self::natives::*;
+
+mod capabilities;
+pub use self::capabilities::*;
+
+mod numbers;
+pub use self::numbers::*;
+
+mod limits;
+pub use self::limits::*;
+
+mod heap;
+pub use self::heap::*;
+
 mod call_stack;
 pub use self::call_stack::*;
+
+mod debugger;
+pub use self::debugger::*;
+
+mod profiler;
+pub use self::profiler::*;
+
+mod recording;
+pub use self::recording::*;
+
+mod tracer;
+pub use self::tracer::*;
+
+mod image;
+pub use self::image::*;
+
+mod inline_cache;
+pub use self::inline_cache::*;
+
+mod parallel;
+pub use self::parallel::*;
+
+mod embedding;
+pub use self::embedding::*;
@@ -0,0 +1,164 @@
+use crate::vm::*;
+use crate::*;
+
+/// A reference to a native method by its qualified name, like
+/// `Loa/Number#+`. It's resolved against the `NativeRegistry` of the runtime
+/// when it's called.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NativeMethod(String);
+
+impl NativeMethod {
+    pub fn new<S: Into<String>>(name: S) -> NativeMethod {
+        NativeMethod(name.into())
+    }
+
+    pub fn name(&self) -> &str {
+        self.0.as_str()
+    }
+}
+
+impl<'a> From<&'a str> for NativeMethod {
+    fn from(name: &'a str) -> Self {
+        NativeMethod::new(name)
+    }
+}
+
+impl From<String> for NativeMethod {
+    fn from(name: String) -> Self {
+        NativeMethod::new(name)
+    }
+}
+
+impl fmt::Display for NativeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+pub type NativeFn = Arc<dyn Fn(&mut VM) -> VMResult<()> + Send + Sync>;
+
+/// A class that native methods box constants with. Programs mark these
+/// classes with `MarkClass*` instructions, so the optimizer has to keep the
+/// marks of every class that the natives a program calls may box.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConstClass {
+    /// Both `True` and `False`.
+    Boolean,
+    String,
+    Character,
+    Symbol,
+    Number(NumberKind),
+}
+
+impl ConstClass {
+    /// Every number class, for natives that promote their results.
+    pub fn numbers() -> Vec<ConstClass> {
+        vec![
+            NumberKind::U8,
+            NumberKind::U16,
+            NumberKind::U32,
+            NumberKind::U64,
+            NumberKind::U128,
+            NumberKind::UBig,
+            NumberKind::I8,
+            NumberKind::I16,
+            NumberKind::I32,
+            NumberKind::I64,
+            NumberKind::I128,
+            NumberKind::IBig,
+            NumberKind::F32,
+            NumberKind::F64,
+            NumberKind::FBig,
+        ]
+        .into_iter()
+        .map(ConstClass::Number)
+        .collect()
+    }
+
+    /// Every class, for natives that don't tell which ones they box.
+    pub fn all() -> Vec<ConstClass> {
+        let mut all = vec![
+            ConstClass::Boolean,
+            ConstClass::String,
+            ConstClass::Character,
+            ConstClass::Symbol,
+        ];
+        all.extend(ConstClass::numbers());
+        all
+    }
+}
+
+/// Maps qualified names of native methods to their implementations, along
+/// with the classes they box their results with. Every `Runtime` fills one
+/// in through `Runtime::register_natives`.
+#[derive(Clone, Default)]
+pub struct NativeRegistry {
+    methods: HashMap<String, NativeFn>,
+    boxes: HashMap<String, Vec<ConstClass>>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> NativeRegistry {
+        NativeRegistry::default()
+    }
+
+    /// The natives of the standard library, as every runtime registers
+    /// them.
+    pub fn standard() -> NativeRegistry {
+        let mut natives = NativeRegistry::new();
+        <() as Runtime>::register_standard_natives(&mut natives);
+        natives
+    }
+
+    /// Registers a native that may box its result with any of the classes.
+    /// Use `register_boxing` to tell which ones, so that optimized programs
+    /// can leave out the others.
+    pub fn register<S, F>(&mut self, name: S, method: F)
+    where
+        S: Into<String>,
+        F: Fn(&mut VM) -> VMResult<()> + Send + Sync + 'static,
+    {
+        self.register_boxing(name, &ConstClass::all(), method);
+    }
+
+    /// Registers a native that boxes constants with only these classes.
+    pub fn register_boxing<S, F>(&mut self, name: S, boxes: &[ConstClass], method: F)
+    where
+        S: Into<String>,
+        F: Fn(&mut VM) -> VMResult<()> + Send + Sync + 'static,
+    {
+        let name = name.into();
+        self.boxes.insert(name.clone(), boxes.to_vec());
+        self.methods.insert(name, Arc::new(method));
+    }
+
+    pub fn unregister(&mut self, name: &str) -> Option<NativeFn> {
+        self.boxes.remove(name);
+        self.methods.remove(name)
+    }
+
+    /// The classes the native boxes constants with, if it's registered.
+    pub fn boxes(&self, name: &str) -> Option<&[ConstClass]> {
+        self.boxes.get(name).map(Vec::as_slice)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&NativeFn> {
+        self.methods.get(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.methods.contains_key(name)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names: Vec<_> = self.methods.keys().map(String::as_str).collect();
+        names.sort();
+        names
+    }
+}
+
+impl fmt::Debug for NativeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.names()).finish()
+    }
+}
@@ -0,0 +1,718 @@
+use crate::vm::*;
+use fraction::{BigFraction, BigUint, Sign, ToPrimitive};
+use num_bigint::BigInt;
+use num_traits::{One, Zero};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+/// The kinds of numbers that can be boxed by the VM.
+///
+/// Integer results that overflow their kind are promoted to the next wider
+/// kind of the same signedness, and negative results of natural numbers are
+/// promoted to the smallest signed kind that can represent every value of
+/// the natural kind. Float results that overflow to infinity are promoted to
+/// the next wider float kind. This mirrors how literals are checked by the
+/// `OutOfBounds` and `TooPreciseFloat` diagnostics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum NumberKind {
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    UBig,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    IBig,
+    F32,
+    F64,
+    FBig,
+}
+
+impl NumberKind {
+    pub fn of(value: &ConstValue) -> Option<NumberKind> {
+        match value {
+            ConstValue::U8(_) => Some(NumberKind::U8),
+            ConstValue::U16(_) => Some(NumberKind::U16),
+            ConstValue::U32(_) => Some(NumberKind::U32),
+            ConstValue::U64(_) => Some(NumberKind::U64),
+            ConstValue::U128(_) => Some(NumberKind::U128),
+            ConstValue::UBig(_) => Some(NumberKind::UBig),
+            ConstValue::I8(_) => Some(NumberKind::I8),
+            ConstValue::I16(_) => Some(NumberKind::I16),
+            ConstValue::I32(_) => Some(NumberKind::I32),
+            ConstValue::I64(_) => Some(NumberKind::I64),
+            ConstValue::I128(_) => Some(NumberKind::I128),
+            ConstValue::IBig(_) => Some(NumberKind::IBig),
+            ConstValue::F32(_) => Some(NumberKind::F32),
+            ConstValue::F64(_) => Some(NumberKind::F64),
+            ConstValue::FBig(_) => Some(NumberKind::FBig),
+            _ => None,
+        }
+    }
+
+    pub fn class_name(self) -> &'static str {
+        match self {
+            NumberKind::U8 => "Loa/UInt8",
+            NumberKind::U16 => "Loa/UInt16",
+            NumberKind::U32 => "Loa/UInt32",
+            NumberKind::U64 => "Loa/UInt64",
+            NumberKind::U128 => "Loa/UInt128",
+            NumberKind::UBig => "Loa/BigNatural",
+            NumberKind::I8 => "Loa/Int8",
+            NumberKind::I16 => "Loa/Int16",
+            NumberKind::I32 => "Loa/Int32",
+            NumberKind::I64 => "Loa/Int64",
+            NumberKind::I128 => "Loa/Int128",
+            NumberKind::IBig => "Loa/BigInteger",
+            NumberKind::F32 => "Loa/Float32",
+            NumberKind::F64 => "Loa/Float64",
+            NumberKind::FBig => "Loa/BigFloat",
+        }
+    }
+
+    pub fn is_natural(self) -> bool {
+        self <= NumberKind::UBig
+    }
+
+    pub fn is_float(self) -> bool {
+        self >= NumberKind::F32
+    }
+
+    pub(crate) fn bits(self) -> Option<usize> {
+        match self {
+            NumberKind::U8 | NumberKind::I8 => Some(8),
+            NumberKind::U16 | NumberKind::I16 => Some(16),
+            NumberKind::U32 | NumberKind::I32 | NumberKind::F32 => Some(32),
+            NumberKind::U64 | NumberKind::I64 | NumberKind::F64 => Some(64),
+            NumberKind::U128 | NumberKind::I128 => Some(128),
+            NumberKind::UBig | NumberKind::IBig | NumberKind::FBig => None,
+        }
+    }
+
+    pub(crate) fn wider(self) -> NumberKind {
+        match self {
+            NumberKind::U8 => NumberKind::U16,
+            NumberKind::U16 => NumberKind::U32,
+            NumberKind::U32 => NumberKind::U64,
+            NumberKind::U64 => NumberKind::U128,
+            NumberKind::U128 | NumberKind::UBig => NumberKind::UBig,
+            NumberKind::I8 => NumberKind::I16,
+            NumberKind::I16 => NumberKind::I32,
+            NumberKind::I32 => NumberKind::I64,
+            NumberKind::I64 => NumberKind::I128,
+            NumberKind::I128 | NumberKind::IBig => NumberKind::IBig,
+            NumberKind::F32 => NumberKind::F64,
+            NumberKind::F64 | NumberKind::FBig => NumberKind::FBig,
+        }
+    }
+
+    pub(crate) fn signed(self) -> NumberKind {
+        match self {
+            NumberKind::U8 => NumberKind::I16,
+            NumberKind::U16 => NumberKind::I32,
+            NumberKind::U32 => NumberKind::I64,
+            NumberKind::U64 => NumberKind::I128,
+            NumberKind::U128 | NumberKind::UBig => NumberKind::IBig,
+            kind => kind,
+        }
+    }
+
+    /// The kind that both operands of a binary operation are promoted to
+    /// before the operation is performed.
+    pub fn join(self, other: NumberKind) -> NumberKind {
+        match (self.is_float(), other.is_float()) {
+            (true, true) => self.max(other),
+            (true, false) => self.join_float(other),
+            (false, true) => other.join_float(self),
+            (false, false) => match (self.is_natural(), other.is_natural()) {
+                (true, false) => self.signed().max(other),
+                (false, true) => other.signed().max(self),
+                _ => self.max(other),
+            },
+        }
+    }
+
+    fn join_float(self, integer: NumberKind) -> NumberKind {
+        match integer.bits() {
+            Some(8) | Some(16) => self,
+            Some(32) => self.max(NumberKind::F64),
+            _ => NumberKind::FBig,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArithmeticOperation {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitwiseOperation {
+    And,
+    Or,
+    Xor,
+}
+
+pub type NumberResult<T> = Result<T, String>;
+
+pub fn arithmetic(
+    operation: ArithmeticOperation,
+    lhs: &ConstValue,
+    rhs: &ConstValue,
+) -> NumberResult<Arc<Object>> {
+    let kind = number_kind(lhs)?.join(number_kind(rhs)?);
+
+    if kind.is_float() {
+        float_arithmetic(operation, kind, lhs, rhs)
+    } else {
+        integer_arithmetic(operation, kind, integer(lhs)?, integer(rhs)?)
+    }
+}
+
+pub fn compare(lhs: &ConstValue, rhs: &ConstValue) -> NumberResult<Option<Ordering>> {
+    match number_kind(lhs)?.join(number_kind(rhs)?) {
+        NumberKind::F32 | NumberKind::F64 => Ok(to_f64(lhs).partial_cmp(&to_f64(rhs))),
+        NumberKind::FBig => Ok(to_fraction(lhs).partial_cmp(&to_fraction(rhs))),
+        _ => Ok(Some(integer(lhs)?.cmp(&integer(rhs)?))),
+    }
+}
+
+pub fn negate(value: &ConstValue) -> NumberResult<Arc<Object>> {
+    match value {
+        ConstValue::F32(f) => Ok(Object::box_f32(-f)),
+        ConstValue::F64(f) => Ok(Object::box_f64(-f)),
+        ConstValue::FBig(f) => Ok(Object::box_fbig(-f.clone())),
+        _ => integer_arithmetic(
+            ArithmeticOperation::Subtract,
+            integer_kind(value)?,
+            Integer::Small(0),
+            integer(value)?,
+        ),
+    }
+}
+
+pub fn bitwise(
+    operation: BitwiseOperation,
+    lhs: &ConstValue,
+    rhs: &ConstValue,
+) -> NumberResult<Arc<Object>> {
+    let kind = integer_kind(lhs)?.join(integer_kind(rhs)?);
+
+    let result = match (integer(lhs)?, integer(rhs)?) {
+        (Integer::Small(a), Integer::Small(b)) => Integer::Small(match operation {
+            BitwiseOperation::And => a & b,
+            BitwiseOperation::Or => a | b,
+            BitwiseOperation::Xor => a ^ b,
+        }),
+        (a, b) => {
+            let (a, b) = (a.into_big(), b.into_big());
+            Integer::Big(match operation {
+                BitwiseOperation::And => a & b,
+                BitwiseOperation::Or => a | b,
+                BitwiseOperation::Xor => a ^ b,
+            })
+        }
+    };
+
+    Ok(box_integer(kind, result))
+}
+
+/// Inverts the bits of an integer. Fixed size naturals are inverted within
+/// their size, while all other integers are inverted as if they were
+/// represented in two's complement, so the result is `-n - 1`.
+pub fn invert(value: &ConstValue) -> NumberResult<Arc<Object>> {
+    let kind = integer_kind(value)?;
+
+    let result = match (kind.is_natural(), kind.bits(), integer(value)?) {
+        (true, Some(bits), n) => {
+            Integer::Big(((BigInt::one() << bits) - BigInt::one()) - n.into_big())
+        }
+        (_, _, Integer::Small(n)) => Integer::Small(!n),
+        (_, _, Integer::Big(n)) => Integer::Big(!n),
+    };
+
+    Ok(box_integer(kind, result))
+}
+
+/// The most bits a big integer can be shifted by, so that a single shift
+/// can't exhaust memory.
+pub const MAX_BIG_SHIFT: usize = 1 << 24;
+
+/// Shifts the bits of an integer. Fixed size integers can be shifted by at
+/// most their size, and big integers by at most `MAX_BIG_SHIFT` bits.
+pub fn shift(value: &ConstValue, amount: &ConstValue, left: bool) -> NumberResult<Arc<Object>> {
+    let kind = integer_kind(value)?;
+    let amount = integer(amount)?.into_big();
+    if amount < BigInt::zero() {
+        return Err("invalid shift amount".into());
+    }
+    let limit = kind.bits().unwrap_or(MAX_BIG_SHIFT);
+    let amount = match amount.to_usize() {
+        Some(amount) if amount <= limit => amount,
+        _ => {
+            return Err(format!(
+                "cannot shift {} by {} bits",
+                kind.class_name(),
+                amount
+            ))
+        }
+    };
+
+    let value = integer(value)?.into_big();
+    let result = if left {
+        value << amount
+    } else {
+        value >> amount
+    };
+
+    Ok(box_integer(kind, Integer::Big(result)))
+}
+
+pub fn convert(value: &ConstValue, target: NumberKind) -> NumberResult<Arc<Object>> {
+    let kind = number_kind(value)?;
+
+    match target {
+        NumberKind::F32 => {
+            let result = match value {
+                ConstValue::FBig(f) => f.to_f32().unwrap_or(std::f32::INFINITY),
+                _ => to_f64(value) as f32,
+            };
+            if result.is_infinite() && !is_infinite(value) {
+                return Err(out_of_bounds(value, target));
+            }
+            Ok(Object::box_f32(result))
+        }
+        NumberKind::F64 => {
+            let result = match value {
+                ConstValue::FBig(f) => f.to_f64().unwrap_or(std::f64::INFINITY),
+                _ => to_f64(value),
+            };
+            if result.is_infinite() && !is_infinite(value) {
+                return Err(out_of_bounds(value, target));
+            }
+            Ok(Object::box_f64(result))
+        }
+        NumberKind::FBig => Ok(Object::box_fbig(to_fraction(value))),
+        _ => {
+            let integer = if kind.is_float() {
+                let truncated = to_fraction(value).trunc();
+                let numer = truncated
+                    .numer()
+                    .ok_or_else(|| out_of_bounds(value, target))?;
+                let numer = BigInt::from(numer.clone());
+                match truncated.sign() {
+                    Some(Sign::Minus) => Integer::Big(-numer),
+                    _ => Integer::Big(numer),
+                }
+            } else {
+                integer(value)?
+            };
+
+            try_box_integer(target, &integer).ok_or_else(|| out_of_bounds(value, target))
+        }
+    }
+}
+
+/// The value of an integer of any kind, if it fits in an `i128`.
+pub fn integer_value(value: &ConstValue) -> Option<i128> {
+    match integer(value).ok()? {
+        Integer::Small(n) => Some(n),
+        Integer::Big(n) => n.to_i128(),
+    }
+}
+
+/// The value of a number of any kind, as the nearest `f64`.
+pub fn float_value(value: &ConstValue) -> Option<f64> {
+    NumberKind::of(value).map(|_| to_f64(value))
+}
+
+#[derive(Debug, Clone)]
+enum Integer {
+    Small(i128),
+    Big(BigInt),
+}
+
+impl Integer {
+    fn into_big(self) -> BigInt {
+        match self {
+            Integer::Small(n) => n.into(),
+            Integer::Big(n) => n,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        match self {
+            Integer::Small(n) => *n == 0,
+            Integer::Big(n) => n.is_zero(),
+        }
+    }
+
+    fn is_negative(&self) -> bool {
+        match self {
+            Integer::Small(n) => *n < 0,
+            Integer::Big(n) => *n < BigInt::zero(),
+        }
+    }
+
+    fn cmp(&self, other: &Integer) -> Ordering {
+        match (self, other) {
+            (Integer::Small(a), Integer::Small(b)) => a.cmp(b),
+            (a, b) => a.clone().into_big().cmp(&b.clone().into_big()),
+        }
+    }
+}
+
+fn number_kind(value: &ConstValue) -> NumberResult<NumberKind> {
+    NumberKind::of(value).ok_or_else(|| "not a number".to_string())
+}
+
+fn integer_kind(value: &ConstValue) -> NumberResult<NumberKind> {
+    match NumberKind::of(value) {
+        Some(kind) if !kind.is_float() => Ok(kind),
+        _ => Err("not an integer".into()),
+    }
+}
+
+fn integer(value: &ConstValue) -> NumberResult<Integer> {
+    match value {
+        ConstValue::U8(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::U16(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::U32(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::U64(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::U128(n) => Ok(match i128::try_from(*n) {
+            Ok(n) => Integer::Small(n),
+            Err(_) => Integer::Big((*n).into()),
+        }),
+        ConstValue::UBig(n) => Ok(Integer::Big(n.clone().into())),
+        ConstValue::I8(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::I16(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::I32(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::I64(n) => Ok(Integer::Small((*n).into())),
+        ConstValue::I128(n) => Ok(Integer::Small(*n)),
+        ConstValue::IBig(n) => Ok(Integer::Big(n.clone())),
+        _ => Err("not an integer".into()),
+    }
+}
+
+fn to_f64(value: &ConstValue) -> f64 {
+    match value {
+        ConstValue::U8(n) => (*n).into(),
+        ConstValue::U16(n) => (*n).into(),
+        ConstValue::U32(n) => (*n).into(),
+        ConstValue::U64(n) => *n as f64,
+        ConstValue::U128(n) => *n as f64,
+        ConstValue::UBig(n) => n.to_f64().unwrap_or(std::f64::INFINITY),
+        ConstValue::I8(n) => (*n).into(),
+        ConstValue::I16(n) => (*n).into(),
+        ConstValue::I32(n) => (*n).into(),
+        ConstValue::I64(n) => *n as f64,
+        ConstValue::I128(n) => *n as f64,
+        ConstValue::IBig(n) => n.to_f64().unwrap_or(std::f64::NAN),
+        ConstValue::F32(n) => (*n).into(),
+        ConstValue::F64(n) => *n,
+        ConstValue::FBig(n) => n.to_f64().unwrap_or(std::f64::NAN),
+        _ => std::f64::NAN,
+    }
+}
+
+fn to_fraction(value: &ConstValue) -> BigFraction {
+    match value {
+        ConstValue::U8(n) => (*n).into(),
+        ConstValue::U16(n) => (*n).into(),
+        ConstValue::U32(n) => (*n).into(),
+        ConstValue::U64(n) => (*n).into(),
+        ConstValue::U128(n) => (*n).into(),
+        ConstValue::UBig(n) => n.clone().into(),
+        ConstValue::I8(n) => (*n).into(),
+        ConstValue::I16(n) => (*n).into(),
+        ConstValue::I32(n) => (*n).into(),
+        ConstValue::I64(n) => (*n).into(),
+        ConstValue::I128(n) => (*n).into(),
+        ConstValue::IBig(n) => n.clone().into(),
+        ConstValue::F32(n) => (*n).into(),
+        ConstValue::F64(n) => (*n).into(),
+        ConstValue::FBig(n) => n.clone(),
+        _ => BigFraction::nan(),
+    }
+}
+
+fn is_infinite(value: &ConstValue) -> bool {
+    match value {
+        ConstValue::F32(n) => n.is_infinite(),
+        ConstValue::F64(n) => n.is_infinite(),
+        ConstValue::FBig(n) => n.is_infinite(),
+        _ => false,
+    }
+}
+
+fn out_of_bounds(value: &ConstValue, target: NumberKind) -> String {
+    let value = match value {
+        ConstValue::FBig(n) => format!("{:.1$}", n, 310),
+        ConstValue::F32(n) => n.to_string(),
+        ConstValue::F64(n) => n.to_string(),
+        _ => integer(value)
+            .map(|n| n.into_big().to_string())
+            .unwrap_or_default(),
+    };
+    format!("{} is out of bounds for {}", value, target.class_name())
+}
+
+fn integer_arithmetic(
+    operation: ArithmeticOperation,
+    kind: NumberKind,
+    lhs: Integer,
+    rhs: Integer,
+) -> NumberResult<Arc<Object>> {
+    if rhs.is_zero()
+        && (operation == ArithmeticOperation::Divide || operation == ArithmeticOperation::Remainder)
+    {
+        return Err("division by zero".into());
+    }
+
+    if let (Integer::Small(a), Integer::Small(b)) = (&lhs, &rhs) {
+        let result = match operation {
+            ArithmeticOperation::Add => a.checked_add(*b),
+            ArithmeticOperation::Subtract => a.checked_sub(*b),
+            ArithmeticOperation::Multiply => a.checked_mul(*b),
+            ArithmeticOperation::Divide => a.checked_div(*b),
+            ArithmeticOperation::Remainder => a.checked_rem(*b),
+        };
+        if let Some(result) = result {
+            return Ok(box_integer(kind, Integer::Small(result)));
+        }
+    }
+
+    let (a, b) = (lhs.into_big(), rhs.into_big());
+    let result = match operation {
+        ArithmeticOperation::Add => a + b,
+        ArithmeticOperation::Subtract => a - b,
+        ArithmeticOperation::Multiply => a * b,
+        ArithmeticOperation::Divide => a / b,
+        ArithmeticOperation::Remainder => a % b,
+    };
+    Ok(box_integer(kind, Integer::Big(result)))
+}
+
+fn float_arithmetic(
+    operation: ArithmeticOperation,
+    kind: NumberKind,
+    lhs: &ConstValue,
+    rhs: &ConstValue,
+) -> NumberResult<Arc<Object>> {
+    if kind == NumberKind::FBig {
+        let (a, b) = (to_fraction(lhs), to_fraction(rhs));
+        if b.is_zero()
+            && (operation == ArithmeticOperation::Divide
+                || operation == ArithmeticOperation::Remainder)
+        {
+            return Err("division by zero".into());
+        }
+        return Ok(Object::box_fbig(match operation {
+            ArithmeticOperation::Add => a + b,
+            ArithmeticOperation::Subtract => a - b,
+            ArithmeticOperation::Multiply => a * b,
+            ArithmeticOperation::Divide => a / b,
+            ArithmeticOperation::Remainder => a % b,
+        }));
+    }
+
+    let (a, b) = (to_f64(lhs), to_f64(rhs));
+    if b == 0.0
+        && (operation == ArithmeticOperation::Divide || operation == ArithmeticOperation::Remainder)
+    {
+        return Err("division by zero".into());
+    }
+
+    let result = if kind == NumberKind::F32 {
+        let (a, b) = (a as f32, b as f32);
+        f64::from(match operation {
+            ArithmeticOperation::Add => a + b,
+            ArithmeticOperation::Subtract => a - b,
+            ArithmeticOperation::Multiply => a * b,
+            ArithmeticOperation::Divide => a / b,
+            ArithmeticOperation::Remainder => a % b,
+        })
+    } else {
+        match operation {
+            ArithmeticOperation::Add => a + b,
+            ArithmeticOperation::Subtract => a - b,
+            ArithmeticOperation::Multiply => a * b,
+            ArithmeticOperation::Divide => a / b,
+            ArithmeticOperation::Remainder => a % b,
+        }
+    };
+
+    if result.is_infinite() && a.is_finite() && b.is_finite() {
+        return float_arithmetic(operation, kind.wider(), lhs, rhs);
+    }
+
+    if kind == NumberKind::F32 {
+        Ok(Object::box_f32(result as f32))
+    } else {
+        Ok(Object::box_f64(result))
+    }
+}
+
+fn box_integer(kind: NumberKind, value: Integer) -> Arc<Object> {
+    let mut kind = kind;
+    if value.is_negative() {
+        kind = kind.signed();
+    }
+
+    loop {
+        if let Some(object) = try_box_integer(kind, &value) {
+            return object;
+        }
+        kind = kind.wider();
+    }
+}
+
+fn try_box_integer(kind: NumberKind, value: &Integer) -> Option<Arc<Object>> {
+    match value {
+        Integer::Small(n) => {
+            let n = *n;
+            match kind {
+                NumberKind::U8 => u8::try_from(n).ok().map(Object::box_u8),
+                NumberKind::U16 => u16::try_from(n).ok().map(Object::box_u16),
+                NumberKind::U32 => u32::try_from(n).ok().map(Object::box_u32),
+                NumberKind::U64 => u64::try_from(n).ok().map(Object::box_u64),
+                NumberKind::U128 => u128::try_from(n).ok().map(Object::box_u128),
+                NumberKind::UBig => u128::try_from(n)
+                    .ok()
+                    .map(|n| Object::box_ubig(BigUint::from(n))),
+                NumberKind::I8 => i8::try_from(n).ok().map(Object::box_i8),
+                NumberKind::I16 => i16::try_from(n).ok().map(Object::box_i16),
+                NumberKind::I32 => i32::try_from(n).ok().map(Object::box_i32),
+                NumberKind::I64 => i64::try_from(n).ok().map(Object::box_i64),
+                NumberKind::I128 => Some(Object::box_i128(n)),
+                NumberKind::IBig => Some(Object::box_ibig(n.into())),
+                NumberKind::F32 | NumberKind::F64 | NumberKind::FBig => None,
+            }
+        }
+        Integer::Big(n) => match kind {
+            NumberKind::U8 => n.to_u8().map(Object::box_u8),
+            NumberKind::U16 => n.to_u16().map(Object::box_u16),
+            NumberKind::U32 => n.to_u32().map(Object::box_u32),
+            NumberKind::U64 => n.to_u64().map(Object::box_u64),
+            NumberKind::U128 => n.to_u128().map(Object::box_u128),
+            NumberKind::UBig => n.to_biguint().map(Object::box_ubig),
+            NumberKind::I8 => n.to_i8().map(Object::box_i8),
+            NumberKind::I16 => n.to_i16().map(Object::box_i16),
+            NumberKind::I32 => n.to_i32().map(Object::box_i32),
+            NumberKind::I64 => n.to_i64().map(Object::box_i64),
+            NumberKind::I128 => n.to_i128().map(Object::box_i128),
+            NumberKind::IBig => Some(Object::box_ibig(n.clone())),
+            NumberKind::F32 | NumberKind::F64 | NumberKind::FBig => None,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_integers() {
+        assert_eq!(NumberKind::U8.join(NumberKind::U32), NumberKind::U32);
+        assert_eq!(NumberKind::I64.join(NumberKind::I8), NumberKind::I64);
+        assert_eq!(NumberKind::U8.join(NumberKind::I8), NumberKind::I16);
+        assert_eq!(NumberKind::I128.join(NumberKind::U32), NumberKind::I128);
+        assert_eq!(NumberKind::U128.join(NumberKind::I8), NumberKind::IBig);
+        assert_eq!(NumberKind::UBig.join(NumberKind::U8), NumberKind::UBig);
+    }
+
+    #[test]
+    fn join_floats() {
+        assert_eq!(NumberKind::F32.join(NumberKind::U16), NumberKind::F32);
+        assert_eq!(NumberKind::F32.join(NumberKind::I32), NumberKind::F64);
+        assert_eq!(NumberKind::F64.join(NumberKind::U32), NumberKind::F64);
+        assert_eq!(NumberKind::F64.join(NumberKind::I64), NumberKind::FBig);
+        assert_eq!(NumberKind::U8.join(NumberKind::FBig), NumberKind::FBig);
+        assert_eq!(NumberKind::F32.join(NumberKind::F64), NumberKind::F64);
+    }
+
+    #[test]
+    fn compare_across_kinds() {
+        assert_eq!(
+            compare(&ConstValue::U8(3), &ConstValue::F64(3.0)),
+            Ok(Some(Ordering::Equal))
+        );
+        assert_eq!(
+            compare(&ConstValue::U128(std::u128::MAX), &ConstValue::I8(-1)),
+            Ok(Some(Ordering::Greater))
+        );
+        assert_eq!(
+            compare(&ConstValue::F32(std::f32::NAN), &ConstValue::U8(1)),
+            Ok(None)
+        );
+        assert!(compare(&ConstValue::String("1".into()), &ConstValue::U8(1)).is_err());
+    }
+
+    #[test]
+    fn shift_within_bounds() {
+        // The results are boxed, so the VM marking their classes has to
+        // outlive them.
+        let assembly = crate::assembly::Parser::new()
+            .parse(
+                r#"
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+                @Int64
+                    DeclareClass "Int64"
+                    MarkClassI64 @Int64
+                @BigInteger
+                    DeclareClass "BigInteger"
+                    MarkClassIBig @BigInteger
+                Halt
+                "#,
+            )
+            .unwrap();
+        let mut vm = VM::new();
+        vm.eval::<()>(assembly.into());
+
+        assert_eq!(
+            shift(&ConstValue::U8(1), &ConstValue::U8(7), true)
+                .unwrap()
+                .to_string(),
+            "128"
+        );
+        assert_eq!(
+            shift(&ConstValue::I64(-8), &ConstValue::U8(2), false)
+                .unwrap()
+                .to_string(),
+            "-2"
+        );
+        assert!(shift(&ConstValue::IBig(1.into()), &ConstValue::U32(1000), true).is_ok());
+    }
+
+    #[test]
+    fn shift_out_of_bounds() {
+        assert_eq!(
+            shift(&ConstValue::U8(1), &ConstValue::U8(9), true).err(),
+            Some("cannot shift Loa/UInt8 by 9 bits".into())
+        );
+        assert_eq!(
+            shift(
+                &ConstValue::IBig(1.into()),
+                &ConstValue::U64(1_000_000_000_000),
+                true
+            )
+            .err(),
+            Some("cannot shift Loa/BigInteger by 1000000000000 bits".into())
+        );
+        assert_eq!(
+            shift(&ConstValue::I32(1), &ConstValue::I8(-1), false).err(),
+            Some("invalid shift amount".into())
+        );
+    }
+}
@@ -58,6 +58,21 @@ impl Object {
         }
     }
 
+    pub fn instance_variables(&self) -> Vec<(String, Arc<Object>)> {
+        let mut variables = vec![];
+        if let (Some(class), ConstValue::InstanceVariables(ref v)) =
+            (self.class.as_ref(), &self.const_value)
+        {
+            for (id, value) in v.iter() {
+                if let Some(variable) = class.variables.get(id) {
+                    variables.push((variable.name.clone(), value.clone()));
+                }
+            }
+        }
+        variables.sort_by(|(a, _), (b, _)| a.cmp(b));
+        variables
+    }
+
     pub fn set_variable(&self, variable: &Variable, value: Arc<Object>) -> Arc<Object> {
         Arc::new(Object {
             class: self.class.clone(),
@@ -100,7 +115,7 @@ impl Object {
     pub fn lazy(offset: u64, call_stack: CallStack, dependencies: Vec<Arc<Object>>) -> Arc<Object> {
         Arc::new(Object {
             class: None,
-            const_value: ConstValue::Lazy(offset, call_stack, dependencies),
+            const_value: ConstValue::Lazy(offset, Thunk::new(call_stack, dependencies)),
         })
     }
 
@@ -206,7 +221,7 @@ impl fmt::Display for Object {
                 }
             ),
             ConstValue::String(s) => write!(f, "{}", s),
-            ConstValue::Lazy(_, _, _) => write!(f, "$lazy"),
+            ConstValue::Lazy(_, _) => write!(f, "$lazy"),
             ConstValue::Character(c) => write!(f, "{}", characters_to_string([*c].iter().cloned())),
             ConstValue::Symbol(s) => write!(f, "#{}", s),
             ConstValue::U8(n) => write!(f, "{}", n),
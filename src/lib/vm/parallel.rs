@@ -0,0 +1,287 @@
+use crate::bytecode::Instruction;
+use crate::vm::*;
+use crate::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Condvar, Weak};
+use std::thread::{self, JoinHandle};
+
+/// How many tasks may wait for each worker. Beyond that, lazy values are
+/// left to the thread that needs them, so that deep recursion doesn't flood
+/// the queue with tasks that are stolen back anyway.
+const QUEUED_TASKS_PER_WORKER: usize = 4;
+
+/// Everything a worker needs to evaluate lazy values on a VM of its own.
+#[derive(Clone)]
+pub(super) struct Snapshot {
+    pub program: Vec<Instruction>,
+    pub classes: HashMap<u64, Arc<Class>>,
+    pub methods: HashMap<u64, Arc<Method>>,
+    pub variables: HashMap<u64, Arc<Variable>>,
+    pub globals: HashMap<u64, Arc<Object>>,
+    pub natives: NativeRegistry,
+    pub limits: VMLimits,
+}
+
+/// Threads that evaluate lazy values ahead of time, while the thread that
+/// submitted them carries on with something else.
+///
+/// Values are immutable, so evaluating a lazy value on a worker yields the
+/// same result as evaluating it where it is needed. Results are shared
+/// through the lazy object itself, and a lazy value that fails to evaluate
+/// on a worker is simply left to be evaluated, and fail, where it's needed.
+/// That keeps the output of a program the same with or without workers.
+///
+/// Dropping the pool cancels whatever the workers are doing, and waits for
+/// them to stop.
+pub struct WorkerPool {
+    queue: Arc<TaskQueue>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+pub struct TaskQueue {
+    tasks: Mutex<VecDeque<Arc<Task>>>,
+    available: Condvar,
+    cancelled: AtomicBool,
+    capacity: usize,
+
+    /// The number of tasks that workers have taken from the queue, and
+    /// haven't finished with yet.
+    busy: AtomicUsize,
+
+    /// The objects that workers allocated, for the heap of the VM that
+    /// started them to collect.
+    allocated: Mutex<Vec<Weak<Object>>>,
+}
+
+/// A lazy object submitted for evaluation on a worker.
+pub struct Task {
+    pub object: Arc<Object>,
+    state: Mutex<TaskState>,
+    done: Condvar,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TaskState {
+    Queued,
+    Running,
+    Done,
+}
+
+impl WorkerPool {
+    pub(super) fn start(workers: usize, snapshot: Snapshot) -> WorkerPool {
+        let queue = Arc::new(TaskQueue {
+            tasks: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            cancelled: AtomicBool::new(false),
+            capacity: workers * QUEUED_TASKS_PER_WORKER,
+            busy: AtomicUsize::new(0),
+            allocated: Mutex::new(vec![]),
+        });
+        let threads = (0..workers)
+            .map(|i| {
+                let snapshot = snapshot.clone();
+                let queue = queue.clone();
+                thread::Builder::new()
+                    .name(format!("loa-worker-{}", i))
+                    .spawn(move || VM::run_worker(snapshot, queue))
+                    .expect("failed to spawn worker thread")
+            })
+            .collect();
+        WorkerPool { queue, threads }
+    }
+
+    pub fn queue(&self) -> &Arc<TaskQueue> {
+        &self.queue
+    }
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        self.queue.cancel();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl TaskQueue {
+    /// Queues a lazy object for evaluation, unless the queue is full.
+    pub fn submit(&self, object: Arc<Object>) -> Option<Arc<Task>> {
+        let mut tasks = self.tasks.lock().ok()?;
+        if tasks.len() >= self.capacity || self.is_cancelled() {
+            return None;
+        }
+        let task = Arc::new(Task {
+            object,
+            state: Mutex::new(TaskState::Queued),
+            done: Condvar::new(),
+        });
+        tasks.push_back(task.clone());
+        self.available.notify_one();
+        Some(task)
+    }
+
+    /// Waits for the next task, or returns `None` once the queue has been
+    /// cancelled.
+    pub fn next(&self) -> Option<Arc<Task>> {
+        let mut tasks = self.tasks.lock().ok()?;
+        loop {
+            if self.is_cancelled() {
+                return None;
+            }
+            if let Some(task) = tasks.pop_front() {
+                self.busy.fetch_add(1, Ordering::SeqCst);
+                return Some(task);
+            }
+            tasks = self.available.wait(tasks).ok()?;
+        }
+    }
+
+    /// Tells that a worker is done with the task it took last, and won't
+    /// touch any objects until it takes the next one.
+    pub fn done(&self, allocated: Vec<Weak<Object>>) {
+        if let Ok(mut objects) = self.allocated.lock() {
+            objects.extend(allocated);
+        }
+        self.busy.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Whether no task is queued or being evaluated, so that no worker is
+    /// changing the reference counts of objects.
+    pub fn is_idle(&self) -> bool {
+        match self.tasks.lock() {
+            Ok(tasks) => tasks.is_empty() && self.busy.load(Ordering::SeqCst) == 0,
+            Err(_) => false,
+        }
+    }
+
+    /// The objects that workers allocated since the last call.
+    pub fn take_allocated(&self) -> Vec<Weak<Object>> {
+        match self.allocated.lock() {
+            Ok(mut objects) => std::mem::replace(&mut *objects, vec![]),
+            Err(_) => vec![],
+        }
+    }
+
+    pub fn cancel(&self) {
+        let tasks = self.tasks.lock();
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.available.notify_all();
+        drop(tasks);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+impl Task {
+    /// Claims the task for a worker, unless it has already been taken back
+    /// by the thread that submitted it.
+    pub fn claim(&self) -> bool {
+        match self.state.lock() {
+            Ok(mut state) if *state == TaskState::Queued => {
+                *state = TaskState::Running;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn finish(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            *state = TaskState::Done;
+        }
+        self.done.notify_all();
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self.state.lock() {
+            Ok(state) => *state == TaskState::Done,
+            Err(_) => true,
+        }
+    }
+
+    /// Waits for a worker to finish the task. A task that no worker has
+    /// started yet is taken back, to be evaluated by the caller instead.
+    pub fn join(&self) {
+        let mut state = match self.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        loop {
+            match *state {
+                TaskState::Queued | TaskState::Done => {
+                    *state = TaskState::Done;
+                    return;
+                }
+                TaskState::Running => {
+                    state = match self.done.wait(state) {
+                        Ok(state) => state,
+                        Err(_) => return,
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(capacity: usize) -> TaskQueue {
+        TaskQueue {
+            tasks: Mutex::new(VecDeque::new()),
+            available: Condvar::new(),
+            cancelled: AtomicBool::new(false),
+            capacity,
+            busy: AtomicUsize::new(0),
+            allocated: Mutex::new(vec![]),
+        }
+    }
+
+    fn object() -> Arc<Object> {
+        Object::lazy(0, CallStack::new(), vec![])
+    }
+
+    #[test]
+    fn stolen_tasks_are_not_claimed() {
+        let queue = queue(2);
+        let task = queue.submit(object()).unwrap();
+        task.join();
+        assert!(task.is_done());
+        assert!(!queue.next().unwrap().claim());
+    }
+
+    #[test]
+    fn full_and_cancelled_queues() {
+        let queue = queue(1);
+        assert!(queue.submit(object()).is_some());
+        assert!(queue.submit(object()).is_none());
+
+        queue.cancel();
+        assert!(queue.next().is_none());
+        assert!(queue.submit(object()).is_none());
+    }
+
+    #[test]
+    fn idle_once_tasks_are_done() {
+        let queue = queue(2);
+        assert!(queue.is_idle());
+        queue.submit(object()).unwrap();
+        assert!(!queue.is_idle());
+
+        let object = object();
+        let task = queue.next().unwrap();
+        assert!(task.claim());
+        assert!(!queue.is_idle());
+        task.finish();
+        queue.done(vec![Arc::downgrade(&object)]);
+        assert!(queue.is_idle());
+        assert_eq!(queue.take_allocated().len(), 1);
+        assert!(queue.take_allocated().is_empty());
+    }
+}
@@ -0,0 +1,311 @@
+use crate::vm::*;
+use crate::*;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+/// Attributes executed instructions and wall time to the call stacks they
+/// were executed in.
+///
+/// The call stack is only inspected when its top frame changes, so that
+/// instructions within the same frame are cheap to count, and time is only
+/// measured at those changes. The stacks form a tree, in which each frame
+/// is found by the stack below it and its method and class, so that only
+/// the frames that changed are looked at.
+pub struct Profiler {
+    nodes: Vec<Node>,
+    children: HashMap<(usize, usize, usize), usize>,
+
+    /// The frames of the current call stack, outermost first, along with the
+    /// nodes they were counted in.
+    frames: Vec<(Arc<StackFrame>, usize)>,
+    current: Option<usize>,
+    since: Instant,
+}
+
+struct Node {
+    parent: Option<usize>,
+    name: String,
+    instructions: u64,
+    time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfiledStack {
+    /// The frames of the stack, outermost first.
+    pub frames: Vec<String>,
+    pub instructions: u64,
+    pub time: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MethodProfile {
+    pub name: String,
+
+    /// Instructions executed by the method itself.
+    pub self_instructions: u64,
+
+    /// Instructions executed by the method, or anything it called.
+    pub total_instructions: u64,
+
+    pub self_time: Duration,
+    pub total_time: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    pub stacks: Vec<ProfiledStack>,
+}
+
+/// The name of the frame at the bottom of every profiled stack, for
+/// instructions executed outside of any method.
+pub const MAIN_FRAME: &str = "<main>";
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            nodes: vec![Node {
+                parent: None,
+                name: MAIN_FRAME.into(),
+                instructions: 0,
+                time: Duration::default(),
+            }],
+            children: HashMap::new(),
+            frames: vec![],
+            current: None,
+            since: Instant::now(),
+        }
+    }
+
+    /// Counts an instruction about to be executed in the given call stack.
+    #[inline]
+    pub fn sample(&mut self, call_stack: &CallStack) {
+        let same_frame = match (call_stack.top(), self.frames.last()) {
+            (None, None) => self.current.is_some(),
+            (Some(a), Some((b, _))) => Arc::ptr_eq(a, b),
+            _ => false,
+        };
+        let current = if same_frame {
+            self.current.unwrap()
+        } else {
+            self.switch(call_stack)
+        };
+        self.nodes[current].instructions += 1;
+    }
+
+    fn switch(&mut self, call_stack: &CallStack) -> usize {
+        self.stop_clock();
+
+        // Frames below the top are usually the ones of the last switch, so
+        // only the frames above those are new.
+        let mut kept = 0;
+        let mut new = vec![];
+        let mut frame = call_stack.top();
+        while let Some(f) = frame {
+            if let Some((known, _)) = self.frames.get(f.depth - 1) {
+                if Arc::ptr_eq(known, f) {
+                    kept = f.depth;
+                    break;
+                }
+            }
+            new.push(f.clone());
+            frame = f.parent.as_ref();
+        }
+        self.frames.truncate(kept);
+
+        for frame in new.into_iter().rev() {
+            let parent = self.frames.last().map(|(_, node)| *node).unwrap_or(0);
+            let class = frame
+                .receiver
+                .class
+                .as_ref()
+                .map(|c| &**c as *const Class as usize)
+                .unwrap_or(0);
+            let key = (parent, frame.method.offset, class);
+            let node = match self.children.get(&key) {
+                Some(node) => *node,
+                None => {
+                    self.nodes.push(Node {
+                        parent: Some(parent),
+                        name: frame_name(&frame),
+                        instructions: 0,
+                        time: Duration::default(),
+                    });
+                    self.children.insert(key, self.nodes.len() - 1);
+                    self.nodes.len() - 1
+                }
+            };
+            self.frames.push((frame, node));
+        }
+
+        let current = self.frames.last().map(|(_, node)| *node).unwrap_or(0);
+        self.current = Some(current);
+        current
+    }
+
+    /// Attributes the time since the last change of stacks to the current
+    /// stack.
+    fn stop_clock(&mut self) {
+        let now = Instant::now();
+        if let Some(current) = self.current {
+            self.nodes[current].time += now - self.since;
+        }
+        self.since = now;
+    }
+
+    pub fn finish(mut self) -> Profile {
+        self.stop_clock();
+        let stacks = self
+            .nodes
+            .iter()
+            .filter(|node| node.instructions > 0)
+            .map(|node| {
+                let mut frames = vec![node.name.clone()];
+                let mut parent = node.parent;
+                while let Some(index) = parent {
+                    frames.push(self.nodes[index].name.clone());
+                    parent = self.nodes[index].parent;
+                }
+                frames.reverse();
+                ProfiledStack {
+                    frames,
+                    instructions: node.instructions,
+                    time: node.time,
+                }
+            })
+            .collect();
+        Profile { stacks }
+    }
+}
+
+impl Profile {
+    /// Writes the stacks in the collapsed format read by flamegraph tools,
+    /// with one line per stack, weighted by the number of instructions
+    /// executed in it.
+    pub fn write_collapsed<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let mut lines: Vec<_> = self
+            .stacks
+            .iter()
+            .filter(|stack| stack.instructions > 0)
+            .map(|stack| format!("{} {}", stack.frames.join(";"), stack.instructions))
+            .collect();
+        lines.sort();
+        for line in lines {
+            writeln!(w, "{}", line)?;
+        }
+        Ok(())
+    }
+
+    /// The methods in the profile, with the ones that executed the most
+    /// instructions themselves first.
+    pub fn methods(&self) -> Vec<MethodProfile> {
+        let mut methods: HashMap<&str, MethodProfile> = HashMap::new();
+        for stack in self.stacks.iter() {
+            let mut seen = HashSet::new();
+            for (depth, name) in stack.frames.iter().enumerate() {
+                let method = methods
+                    .entry(name.as_str())
+                    .or_insert_with(|| MethodProfile {
+                        name: name.clone(),
+                        self_instructions: 0,
+                        total_instructions: 0,
+                        self_time: Duration::default(),
+                        total_time: Duration::default(),
+                    });
+                // Recursive methods appear multiple times in a stack, but
+                // only count once towards their total.
+                if seen.insert(name) {
+                    method.total_instructions += stack.instructions;
+                    method.total_time += stack.time;
+                }
+                if depth == stack.frames.len() - 1 {
+                    method.self_instructions += stack.instructions;
+                    method.self_time += stack.time;
+                }
+            }
+        }
+
+        let mut methods: Vec<_> = methods.into_iter().map(|(_, m)| m).collect();
+        methods.sort_by(|a, b| {
+            b.self_instructions
+                .cmp(&a.self_instructions)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        methods
+    }
+
+    pub fn total_instructions(&self) -> u64 {
+        self.stacks.iter().map(|s| s.instructions).sum()
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let total = self.total_instructions().max(1) as f64;
+        writeln!(
+            f,
+            "{:>12} {:>7} {:>12} {:>12} {:>12}  {}",
+            "self instr", "self %", "total instr", "self time", "total time", "method"
+        )?;
+        for method in self.methods() {
+            writeln!(
+                f,
+                "{:>12} {:>6.2}% {:>12} {:>12} {:>12}  {}",
+                method.self_instructions,
+                method.self_instructions as f64 / total * 100.0,
+                method.total_instructions,
+                format!("{:.3?}", method.self_time),
+                format!("{:.3?}", method.total_time),
+                method.name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stack(frames: &[&str], instructions: u64) -> ProfiledStack {
+        ProfiledStack {
+            frames: frames.iter().map(|f| f.to_string()).collect(),
+            instructions,
+            time: Duration::from_millis(instructions),
+        }
+    }
+
+    fn profile() -> Profile {
+        Profile {
+            stacks: vec![
+                stack(&["<main>"], 5),
+                stack(&["<main>", "A#f"], 10),
+                stack(&["<main>", "A#f", "A#f"], 20),
+                stack(&["<main>", "A#f", "B#g"], 10),
+            ],
+        }
+    }
+
+    #[test]
+    fn collapsed_stacks() {
+        let mut out = vec![];
+        profile().write_collapsed(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "<main> 5\n<main>;A#f 10\n<main>;A#f;A#f 20\n<main>;A#f;B#g 10\n"
+        );
+    }
+
+    #[test]
+    fn methods() {
+        let methods = profile().methods();
+        let summary: Vec<_> = methods
+            .iter()
+            .map(|m| (m.name.as_str(), m.self_instructions, m.total_instructions))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![("A#f", 30, 40), ("B#g", 10, 10), ("<main>", 5, 45),]
+        );
+        assert_eq!(methods[0].self_time, Duration::from_millis(30));
+    }
+}
@@ -0,0 +1,344 @@
+use crate::bytecode::{BytecodeEncoding, BytecodeEncodingRead};
+use crate::vm::*;
+use crate::*;
+use std::io::{self, Read, Write};
+
+/// Identifies a file as a recording of native calls.
+pub const RECORDING_MAGIC: [u8; 4] = *b"\x7fLRC";
+
+/// The version of the recording format. Recordings with any other version
+/// are rejected.
+pub const RECORDING_VERSION: u16 = 1;
+
+/// The native calls made by a program, in the order they were made, as
+/// recorded by `VM::start_recording`. Replaying a recording with
+/// `VM::start_replaying` makes the same program run the same way again,
+/// whatever the natives would do this time.
+///
+/// The file starts with the magic number and the format version, followed
+/// by the number of calls and the calls themselves.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Recording {
+    pub calls: Vec<NativeCall>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NativeCall {
+    /// The qualified name of the native method, like `Loa/Number#+`.
+    pub method: String,
+
+    /// The receiver, followed by the arguments.
+    pub inputs: Vec<RecordedValue>,
+
+    pub outcome: Outcome,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Returned(RecordedValue),
+    Panicked(String),
+    Exited(i32),
+}
+
+/// An object passed to or returned from a native method.
+#[derive(Debug, Clone)]
+pub enum RecordedValue {
+    /// An object of a class without any state, like `True`, by the
+    /// qualified name of its class.
+    Object(String),
+
+    /// A string, character, symbol or number.
+    Const(ConstValue),
+
+    /// Anything else, like an object with instance variables, by how it
+    /// formats. Such a value can be compared, but not replayed.
+    Opaque(String),
+}
+
+impl RecordedValue {
+    pub fn of(object: &Object) -> RecordedValue {
+        match (&object.const_value, &object.class) {
+            (ConstValue::Nothing, Some(class)) => RecordedValue::Object(class.name.clone()),
+            (ConstValue::Nothing, None)
+            | (ConstValue::InstanceVariables(_), _)
+            | (ConstValue::Lazy(_, _), _) => RecordedValue::Opaque(object.to_string()),
+            (value, _) => RecordedValue::Const(value.clone()),
+        }
+    }
+
+    /// Recreates the recorded object, unless it's opaque. The classes of
+    /// objects, and of constants, must have been declared in the VM.
+    pub fn revive(&self, vm: &VM) -> Option<Arc<Object>> {
+        use ConstValue::*;
+
+        match self {
+            RecordedValue::Object(name) => vm.class(name).map(Object::new),
+            RecordedValue::Opaque(_) => None,
+            RecordedValue::Const(value) => Some(match value.clone() {
+                String(s) => Object::box_string(s),
+                Character(c) => Object::box_character(c),
+                Symbol(s) => Object::box_symbol(s),
+                U8(n) => Object::box_u8(n),
+                U16(n) => Object::box_u16(n),
+                U32(n) => Object::box_u32(n),
+                U64(n) => Object::box_u64(n),
+                U128(n) => Object::box_u128(n),
+                UBig(n) => Object::box_ubig(n),
+                I8(n) => Object::box_i8(n),
+                I16(n) => Object::box_i16(n),
+                I32(n) => Object::box_i32(n),
+                I64(n) => Object::box_i64(n),
+                I128(n) => Object::box_i128(n),
+                IBig(n) => Object::box_ibig(n),
+                F32(n) => Object::box_f32(n),
+                F64(n) => Object::box_f64(n),
+                FBig(n) => Object::box_fbig(n),
+                Nothing | InstanceVariables(_) | Lazy(_, _) => return None,
+            }),
+        }
+    }
+}
+
+/// Values are compared by their encoding, so that a recorded NaN matches
+/// the same NaN when replayed.
+impl PartialEq for RecordedValue {
+    fn eq(&self, other: &RecordedValue) -> bool {
+        let mut l = vec![];
+        let mut r = vec![];
+        self.serialize(&mut l).is_ok() && other.serialize(&mut r).is_ok() && l == r
+    }
+}
+
+impl fmt::Display for RecordedValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RecordedValue::Object(name) => write!(f, "{}", name),
+            RecordedValue::Const(ConstValue::String(s)) => write!(f, "{:?}", s),
+            RecordedValue::Const(value) => write!(
+                f,
+                "{}",
+                Object {
+                    class: None,
+                    const_value: value.clone(),
+                }
+            ),
+            RecordedValue::Opaque(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl fmt::Display for NativeCall {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inputs: Vec<_> = self.inputs.iter().map(ToString::to_string).collect();
+        write!(f, "{}({})", self.method, inputs.join(", "))
+    }
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    pub fn write<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        w.write_all(&RECORDING_MAGIC)?;
+        let mut written = RECORDING_MAGIC.len()
+            + RECORDING_VERSION.serialize(&mut w)?
+            + (self.calls.len() as u64).serialize(&mut w)?;
+        for call in self.calls.iter() {
+            written += write_string(&mut w, &call.method)?;
+            written += (call.inputs.len() as u16).serialize(&mut w)?;
+            for input in call.inputs.iter() {
+                written += input.serialize(&mut w)?;
+            }
+            written += match call.outcome {
+                Outcome::Returned(ref value) => 0u8.serialize(&mut w)? + value.serialize(&mut w)?,
+                Outcome::Panicked(ref message) => {
+                    1u8.serialize(&mut w)? + write_string(&mut w, message)?
+                }
+                Outcome::Exited(code) => 2u8.serialize(&mut w)? + code.serialize(&mut w)?,
+            };
+        }
+        Ok(written)
+    }
+
+    pub fn read<R: Read>(mut r: R) -> io::Result<Recording> {
+        let mut magic = [0u8; 4];
+        r.read_exact(&mut magic)?;
+        if magic != RECORDING_MAGIC {
+            return Err(invalid_data("not a recording of native calls"));
+        }
+        let version: u16 = r.deserialize()?;
+        if version != RECORDING_VERSION {
+            return Err(invalid_data(format!(
+                "unsupported recording format version {}",
+                version
+            )));
+        }
+
+        let count: u64 = r.deserialize()?;
+        let mut calls = vec![];
+        for _ in 0..count {
+            let method = read_string(&mut r)?;
+            let arity: u16 = r.deserialize()?;
+            let inputs = (0..arity)
+                .map(|_| RecordedValue::deserialize(&mut r))
+                .collect::<io::Result<_>>()?;
+            let tag: u8 = r.deserialize()?;
+            let outcome = match tag {
+                0 => Outcome::Returned(r.deserialize()?),
+                1 => Outcome::Panicked(read_string(&mut r)?),
+                2 => Outcome::Exited(r.deserialize()?),
+                tag => return Err(invalid_data(format!("unknown outcome tag {}", tag))),
+            };
+            calls.push(NativeCall {
+                method,
+                inputs,
+                outcome,
+            });
+        }
+        Ok(Recording { calls })
+    }
+}
+
+impl BytecodeEncoding for RecordedValue {
+    fn serialize<W: Write>(&self, mut w: W) -> io::Result<usize> {
+        use ConstValue::*;
+
+        let value = match self {
+            RecordedValue::Object(name) => {
+                return Ok(0u8.serialize(&mut w)? + write_string(w, name)?)
+            }
+            RecordedValue::Opaque(s) => return Ok(1u8.serialize(&mut w)? + write_string(w, s)?),
+            RecordedValue::Const(value) => value,
+        };
+        Ok(match value {
+            String(s) => 2u8.serialize(&mut w)? + write_string(w, s)?,
+            Character(c) => 3u8.serialize(&mut w)? + c.serialize(w)?,
+            Symbol(s) => 4u8.serialize(&mut w)? + write_string(w, s)?,
+            U8(n) => 5u8.serialize(&mut w)? + n.serialize(w)?,
+            U16(n) => 6u8.serialize(&mut w)? + n.serialize(w)?,
+            U32(n) => 7u8.serialize(&mut w)? + n.serialize(w)?,
+            U64(n) => 8u8.serialize(&mut w)? + n.serialize(w)?,
+            U128(n) => 9u8.serialize(&mut w)? + n.serialize(w)?,
+            UBig(n) => 10u8.serialize(&mut w)? + n.serialize(w)?,
+            I8(n) => 11u8.serialize(&mut w)? + n.serialize(w)?,
+            I16(n) => 12u8.serialize(&mut w)? + n.serialize(w)?,
+            I32(n) => 13u8.serialize(&mut w)? + n.serialize(w)?,
+            I64(n) => 14u8.serialize(&mut w)? + n.serialize(w)?,
+            I128(n) => 15u8.serialize(&mut w)? + n.serialize(w)?,
+            IBig(n) => 16u8.serialize(&mut w)? + n.serialize(w)?,
+            F32(n) => 17u8.serialize(&mut w)? + n.serialize(w)?,
+            F64(n) => 18u8.serialize(&mut w)? + n.serialize(w)?,
+            FBig(n) => 19u8.serialize(&mut w)? + n.serialize(w)?,
+            Nothing | InstanceVariables(_) | Lazy(_, _) => {
+                return Err(invalid_data("cannot record an object without a value"))
+            }
+        })
+    }
+
+    fn deserialize<R: Read>(mut r: R) -> io::Result<Self> {
+        use ConstValue::*;
+
+        let tag: u8 = r.deserialize()?;
+        let value = match tag {
+            0 => return Ok(RecordedValue::Object(read_string(r)?)),
+            1 => return Ok(RecordedValue::Opaque(read_string(r)?)),
+            2 => String(read_string(r)?),
+            3 => Character(r.deserialize()?),
+            4 => Symbol(read_string(r)?),
+            5 => U8(r.deserialize()?),
+            6 => U16(r.deserialize()?),
+            7 => U32(r.deserialize()?),
+            8 => U64(r.deserialize()?),
+            9 => U128(r.deserialize()?),
+            10 => UBig(r.deserialize()?),
+            11 => I8(r.deserialize()?),
+            12 => I16(r.deserialize()?),
+            13 => I32(r.deserialize()?),
+            14 => I64(r.deserialize()?),
+            15 => I128(r.deserialize()?),
+            16 => IBig(r.deserialize()?),
+            17 => F32(r.deserialize()?),
+            18 => F64(r.deserialize()?),
+            19 => FBig(r.deserialize()?),
+            tag => return Err(invalid_data(format!("unknown value tag {}", tag))),
+        };
+        Ok(RecordedValue::Const(value))
+    }
+}
+
+/// Strings in recordings, like the contents of files read by a program,
+/// can be longer than the strings of a binary, so their lengths take up
+/// eight bytes.
+pub(super) fn write_string<W: Write>(mut w: W, s: &str) -> io::Result<usize> {
+    let length = (s.len() as u64).serialize(&mut w)?;
+    w.write_all(s.as_bytes())?;
+    Ok(length + s.len())
+}
+
+pub(super) fn read_string<R: Read>(mut r: R) -> io::Result<String> {
+    let length: u64 = r.deserialize()?;
+    let mut bytes = vec![];
+    r.take(length).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != length {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(invalid_data)
+}
+
+pub(super) fn invalid_data<E: Into<Box<dyn Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let recording = Recording {
+            calls: vec![
+                NativeCall {
+                    method: "Loa/Number#/".into(),
+                    inputs: vec![
+                        RecordedValue::Const(ConstValue::I32(1)),
+                        RecordedValue::Const(ConstValue::F64(std::f64::NAN)),
+                    ],
+                    outcome: Outcome::Returned(RecordedValue::Const(ConstValue::F64(
+                        std::f64::NAN,
+                    ))),
+                },
+                NativeCall {
+                    method: "Loa/Object#==".into(),
+                    inputs: vec![
+                        RecordedValue::Object("A".into()),
+                        RecordedValue::Opaque("a B(c=1)".into()),
+                    ],
+                    outcome: Outcome::Panicked("oops".into()),
+                },
+                NativeCall {
+                    method: "Loa/Process#exit:".into(),
+                    inputs: vec![
+                        RecordedValue::Object("Loa/Process".into()),
+                        RecordedValue::Const(ConstValue::String("x".repeat(70000))),
+                    ],
+                    outcome: Outcome::Exited(3),
+                },
+            ],
+        };
+
+        let mut bytes = vec![];
+        let written = recording.write(&mut bytes).unwrap();
+
+        assert_eq!(written, bytes.len());
+        assert_eq!(Recording::read(bytes.as_slice()).unwrap(), recording);
+        assert_eq!(recording.calls[1].to_string(), "Loa/Object#==(A, a B(c=1))");
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let error = Recording::read(b"\x7fLOA\x00\x02".as_ref()).unwrap_err();
+
+        assert_eq!(error.to_string(), "not a recording of native calls");
+    }
+}
@@ -1,22 +1,205 @@
+use crate::bytecode::SourceLocation;
 use crate::vm::*;
-use fraction::{BigFraction, BigUint};
-use num_bigint::BigInt;
-use std::sync::Arc;
+use std::char::decode_utf16;
+use std::cmp::Ordering;
+use std::io::{self, BufRead, Write};
+
+pub(crate) const CONVERSIONS: &[(&str, NumberKind)] = &[
+    ("asUInt8", NumberKind::U8),
+    ("asUInt16", NumberKind::U16),
+    ("asUInt32", NumberKind::U32),
+    ("asUInt64", NumberKind::U64),
+    ("asUInt128", NumberKind::U128),
+    ("asBigNatural", NumberKind::UBig),
+    ("asInt8", NumberKind::I8),
+    ("asInt16", NumberKind::I16),
+    ("asInt32", NumberKind::I32),
+    ("asInt64", NumberKind::I64),
+    ("asInt128", NumberKind::I128),
+    ("asBigInteger", NumberKind::IBig),
+    ("asFloat32", NumberKind::F32),
+    ("asFloat64", NumberKind::F64),
+    ("asBigFloat", NumberKind::FBig),
+];
 
 pub trait Runtime
 where
-    Self: Sized,
+    Self: Sized + 'static,
 {
     fn print_panic(message: String, call_stack: CallStack);
 
-    #[inline]
-    fn call(vm: &mut VM, method: NativeMethod) -> VMResult<()> {
-        match method {
-            NativeMethod::Number_plus => Self::number_plus(vm),
-            NativeMethod::Number_minus => Self::number_minus(vm),
-            NativeMethod::Object_eq => Self::object_eq(vm),
-            NativeMethod::Object_asString => Self::object_asString(vm),
+    /// Like `print_panic`, for programs with a source map, which tells
+    /// exactly where in the source the panic happened.
+    fn print_panic_at(message: String, call_stack: CallStack, _location: SourceLocation) {
+        Self::print_panic(message, call_stack)
+    }
+
+    /// The effects that programs running on this runtime may have, through
+    /// `Loa/Process` and `Loa/File`. Sandboxed runtimes keep the default,
+    /// which grants none.
+    fn capabilities() -> Capabilities {
+        Capabilities::none()
+    }
+
+    /// Registers the native methods that programs running on this runtime
+    /// can call. Embedders override this to expose host functions to `native`
+    /// methods, usually calling `register_standard_natives` as well.
+    fn register_natives(natives: &mut NativeRegistry) {
+        Self::register_standard_natives(natives);
+    }
+
+    fn register_standard_natives(natives: &mut NativeRegistry) {
+        const NOTHING: &[ConstClass] = &[];
+        const BOOLEAN: &[ConstClass] = &[ConstClass::Boolean];
+        const STRING: &[ConstClass] = &[ConstClass::String];
+        const CHARACTER: &[ConstClass] = &[ConstClass::Character];
+        const SYMBOL: &[ConstClass] = &[ConstClass::Symbol];
+        const U16: &[ConstClass] = &[ConstClass::Number(NumberKind::U16)];
+        const U64: &[ConstClass] = &[ConstClass::Number(NumberKind::U64)];
+        let numbers = ConstClass::numbers();
+
+        natives.register_boxing("Loa/Object#==", BOOLEAN, Self::object_eq);
+        natives.register_boxing("Loa/Object#asString", STRING, Self::object_asString);
+
+        natives.register_boxing("Loa/Number#+", &numbers, |vm| {
+            Self::number_arithmetic(vm, ArithmeticOperation::Add)
+        });
+        natives.register_boxing("Loa/Number#-", &numbers, |vm| {
+            Self::number_arithmetic(vm, ArithmeticOperation::Subtract)
+        });
+        natives.register_boxing("Loa/Number#*", &numbers, |vm| {
+            Self::number_arithmetic(vm, ArithmeticOperation::Multiply)
+        });
+        natives.register_boxing("Loa/Number#/", &numbers, |vm| {
+            Self::number_arithmetic(vm, ArithmeticOperation::Divide)
+        });
+        natives.register_boxing("Loa/Number#rem:", &numbers, |vm| {
+            Self::number_arithmetic(vm, ArithmeticOperation::Remainder)
+        });
+
+        natives.register_boxing("Loa/Number#==", BOOLEAN, |vm| {
+            Self::number_compare(vm, |o| o == Ordering::Equal)
+        });
+        natives.register_boxing("Loa/Number#<", BOOLEAN, |vm| {
+            Self::number_compare(vm, |o| o == Ordering::Less)
+        });
+        natives.register_boxing("Loa/Number#>", BOOLEAN, |vm| {
+            Self::number_compare(vm, |o| o == Ordering::Greater)
+        });
+        natives.register_boxing("Loa/Number#<=", BOOLEAN, |vm| {
+            Self::number_compare(vm, |o| o != Ordering::Greater)
+        });
+        natives.register_boxing("Loa/Number#>=", BOOLEAN, |vm| {
+            Self::number_compare(vm, |o| o != Ordering::Less)
+        });
+
+        natives.register_boxing("Loa/Number#negated", &numbers, Self::number_negated);
+
+        for &(selector, kind) in CONVERSIONS {
+            natives.register_boxing(format!("Loa/Number#{}", selector), &numbers, move |vm| {
+                Self::number_convert(vm, kind)
+            });
         }
+
+        natives.register_boxing("Loa/Integer#bitAnd:", &numbers, |vm| {
+            Self::integer_bitwise(vm, BitwiseOperation::And)
+        });
+        natives.register_boxing("Loa/Integer#bitOr:", &numbers, |vm| {
+            Self::integer_bitwise(vm, BitwiseOperation::Or)
+        });
+        natives.register_boxing("Loa/Integer#bitXor:", &numbers, |vm| {
+            Self::integer_bitwise(vm, BitwiseOperation::Xor)
+        });
+        natives.register_boxing("Loa/Integer#bitInvert", &numbers, Self::integer_bitInvert);
+        natives.register_boxing("Loa/Integer#bitShiftLeft:", &numbers, |vm| {
+            Self::integer_shift(vm, true)
+        });
+        natives.register_boxing("Loa/Integer#bitShiftRight:", &numbers, |vm| {
+            Self::integer_shift(vm, false)
+        });
+
+        natives.register_boxing("Loa/String#+", STRING, Self::string_concat);
+        natives.register_boxing("Loa/String#size", U64, Self::string_size);
+        natives.register_boxing("Loa/String#at:", CHARACTER, Self::string_at);
+        natives.register_boxing("Loa/String#asSymbol", SYMBOL, Self::string_asSymbol);
+        natives.register_boxing("Loa/String#asUppercase", STRING, |vm| {
+            Self::string_map(vm, str::to_uppercase)
+        });
+        natives.register_boxing("Loa/String#asLowercase", STRING, |vm| {
+            Self::string_map(vm, str::to_lowercase)
+        });
+
+        natives.register_boxing("Loa/Character#codePoint", U16, Self::character_codePoint);
+        natives.register_boxing("Loa/Character#isLetter", BOOLEAN, |vm| {
+            Self::character_test(vm, char::is_alphabetic)
+        });
+        natives.register_boxing("Loa/Character#isDigit", BOOLEAN, |vm| {
+            Self::character_test(vm, char::is_numeric)
+        });
+        natives.register_boxing("Loa/Character#isWhitespace", BOOLEAN, |vm| {
+            Self::character_test(vm, char::is_whitespace)
+        });
+        natives.register_boxing("Loa/Character#asUppercase", CHARACTER, |vm| {
+            Self::character_map(vm, char::to_uppercase)
+        });
+        natives.register_boxing("Loa/Character#asLowercase", CHARACTER, |vm| {
+            Self::character_map(vm, char::to_lowercase)
+        });
+
+        natives.register_boxing("Loa/Symbol#name", STRING, Self::symbol_name);
+
+        natives.register_boxing("Loa/Process#print:", NOTHING, |vm| {
+            Self::process_print(vm, false, "")
+        });
+        natives.register_boxing("Loa/Process#printLine:", NOTHING, |vm| {
+            Self::process_print(vm, false, "\n")
+        });
+        natives.register_boxing("Loa/Process#printError:", NOTHING, |vm| {
+            Self::process_print(vm, true, "")
+        });
+        natives.register_boxing("Loa/Process#printErrorLine:", NOTHING, |vm| {
+            Self::process_print(vm, true, "\n")
+        });
+        natives.register_boxing("Loa/Process#readLine", STRING, Self::process_readLine);
+        natives.register_boxing(
+            "Loa/Process#argumentCount",
+            U64,
+            Self::process_argumentCount,
+        );
+        natives.register_boxing("Loa/Process#argumentAt:", STRING, Self::process_argumentAt);
+        natives.register_boxing(
+            "Loa/Process#hasEnvironmentVariable:",
+            BOOLEAN,
+            Self::process_hasEnvironmentVariable,
+        );
+        natives.register_boxing(
+            "Loa/Process#environmentVariable:",
+            STRING,
+            Self::process_environmentVariable,
+        );
+        natives.register_boxing("Loa/Process#exit:", NOTHING, Self::process_exit);
+
+        natives.register_boxing("Loa/File#read:", STRING, Self::file_read);
+        natives.register_boxing("Loa/File#write:to:", NOTHING, Self::file_write);
+    }
+
+    /// Panics unless the runtime grants the capability, and the VM may have
+    /// effects at all.
+    fn require_capability(
+        vm: &mut VM,
+        granted: fn(&Capabilities) -> bool,
+        capability: &str,
+    ) -> VMResult<()> {
+        if vm.is_worker() {
+            return vm.panic(format!("workers have no access to {}", capability));
+        }
+        if !granted(&Self::capabilities()) {
+            return vm.panic(format!(
+                "the runtime doesn't grant access to {}",
+                capability
+            ));
+        }
+        VMResult::Ok(())
     }
 
     fn object_eq(vm: &mut VM) -> VMResult<()> {
@@ -37,969 +220,422 @@ where
         VMResult::Ok(())
     }
 
-    fn number_plus(vm: &mut VM) -> VMResult<()> {
+    fn number_arithmetic(vm: &mut VM, operation: ArithmeticOperation) -> VMResult<()> {
         let receiver = unwrap!(vm, vm.pop_eval::<Self>());
         let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-        match (&receiver.const_value, &operand.const_value) {
-            (ConstValue::Lazy(_, _, _), _) | (_, ConstValue::Lazy(_, _, _)) => {
-                return vm.panic("adding lazy".into())
-            }
+        match arithmetic(operation, &receiver.const_value, &operand.const_value) {
+            Ok(result) => vm.push(result),
+            Err(message) => return vm.panic(message),
+        }
 
-            (ConstValue::InstanceVariables(_), _)
-            | (_, ConstValue::InstanceVariables(_))
-            | (ConstValue::Nothing, _)
-            | (_, ConstValue::Nothing)
-            | (ConstValue::String(_), _)
-            | (_, ConstValue::String(_))
-            | (ConstValue::Character(_), _)
-            | (_, ConstValue::Character(_))
-            | (ConstValue::Symbol(_), _)
-            | (_, ConstValue::Symbol(_)) => return vm.panic("not a number".into()),
-
-            (ConstValue::U8(a), ConstValue::U8(b)) => vm.push(add_u8(*a, *b)),
-            (ConstValue::U8(a), ConstValue::U16(b)) => vm.push(add_u16(*a as u16, *b)),
-            (ConstValue::U8(a), ConstValue::U32(b)) => vm.push(add_u32(*a as u32, *b)),
-            (ConstValue::U8(a), ConstValue::U64(b)) => vm.push(add_u64(*a as u64, *b)),
-            (ConstValue::U8(a), ConstValue::U128(b)) => vm.push(add_u128(*a as u128, *b)),
-            (ConstValue::U8(a), ConstValue::UBig(b)) => vm.push(add_ubig(&BigUint::from(*a), b)),
-
-            (ConstValue::U16(a), ConstValue::U8(b)) => vm.push(add_u16(*a, *b as u16)),
-            (ConstValue::U16(a), ConstValue::U16(b)) => vm.push(add_u16(*a, *b)),
-            (ConstValue::U16(a), ConstValue::U32(b)) => vm.push(add_u32(*a as u32, *b)),
-            (ConstValue::U16(a), ConstValue::U64(b)) => vm.push(add_u64(*a as u64, *b)),
-            (ConstValue::U16(a), ConstValue::U128(b)) => vm.push(add_u128(*a as u128, *b)),
-            (ConstValue::U16(a), ConstValue::UBig(b)) => vm.push(add_ubig(&BigUint::from(*a), b)),
-
-            (ConstValue::U32(a), ConstValue::U8(b)) => vm.push(add_u32(*a, *b as u32)),
-            (ConstValue::U32(a), ConstValue::U16(b)) => vm.push(add_u32(*a, *b as u32)),
-            (ConstValue::U32(a), ConstValue::U32(b)) => vm.push(add_u32(*a, *b)),
-            (ConstValue::U32(a), ConstValue::U64(b)) => vm.push(add_u64(*a as u64, *b)),
-            (ConstValue::U32(a), ConstValue::U128(b)) => vm.push(add_u128(*a as u128, *b)),
-            (ConstValue::U32(a), ConstValue::UBig(b)) => vm.push(add_ubig(&BigUint::from(*a), b)),
-
-            (ConstValue::U64(a), ConstValue::U8(b)) => vm.push(add_u64(*a, *b as u64)),
-            (ConstValue::U64(a), ConstValue::U16(b)) => vm.push(add_u64(*a, *b as u64)),
-            (ConstValue::U64(a), ConstValue::U32(b)) => vm.push(add_u64(*a, *b as u64)),
-            (ConstValue::U64(a), ConstValue::U64(b)) => vm.push(add_u64(*a, *b)),
-            (ConstValue::U64(a), ConstValue::U128(b)) => vm.push(add_u128(*a as u128, *b)),
-            (ConstValue::U64(a), ConstValue::UBig(b)) => vm.push(add_ubig(&BigUint::from(*a), b)),
-
-            (ConstValue::U128(a), ConstValue::U8(b)) => vm.push(add_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U16(b)) => vm.push(add_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U32(b)) => vm.push(add_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U64(b)) => vm.push(add_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U128(b)) => vm.push(add_u128(*a, *b)),
-            (ConstValue::U128(a), ConstValue::UBig(b)) => vm.push(add_ubig(&BigUint::from(*a), b)),
-
-            (ConstValue::UBig(a), ConstValue::U8(b)) => vm.push(add_ubig(a, &BigUint::from(*b))),
-            (ConstValue::UBig(a), ConstValue::U16(b)) => vm.push(add_ubig(a, &BigUint::from(*b))),
-            (ConstValue::UBig(a), ConstValue::U32(b)) => vm.push(add_ubig(a, &BigUint::from(*b))),
-            (ConstValue::UBig(a), ConstValue::U64(b)) => vm.push(add_ubig(a, &BigUint::from(*b))),
-            (ConstValue::UBig(a), ConstValue::U128(b)) => vm.push(add_ubig(a, &BigUint::from(*b))),
-            (ConstValue::UBig(a), ConstValue::UBig(b)) => vm.push(add_ubig(a, b)),
-
-            (ConstValue::I8(a), ConstValue::I8(b)) => vm.push(add_i8(*a, *b)),
-            (ConstValue::I8(a), ConstValue::I16(b)) => vm.push(add_i16(*a as i16, *b)),
-            (ConstValue::I8(a), ConstValue::I32(b)) => vm.push(add_i32(*a as i32, *b)),
-            (ConstValue::I8(a), ConstValue::I64(b)) => vm.push(add_i64(*a as i64, *b)),
-            (ConstValue::I8(a), ConstValue::I128(b)) => vm.push(add_i128(*a as i128, *b)),
-            (ConstValue::I8(a), ConstValue::IBig(b)) => vm.push(add_ibig(&BigInt::from(*a), b)),
-
-            (ConstValue::I16(a), ConstValue::I8(b)) => vm.push(add_i16(*a, *b as i16)),
-            (ConstValue::I16(a), ConstValue::I16(b)) => vm.push(add_i16(*a, *b)),
-            (ConstValue::I16(a), ConstValue::I32(b)) => vm.push(add_i32(*a as i32, *b)),
-            (ConstValue::I16(a), ConstValue::I64(b)) => vm.push(add_i64(*a as i64, *b)),
-            (ConstValue::I16(a), ConstValue::I128(b)) => vm.push(add_i128(*a as i128, *b)),
-            (ConstValue::I16(a), ConstValue::IBig(b)) => vm.push(add_ibig(&BigInt::from(*a), b)),
-
-            (ConstValue::I32(a), ConstValue::I8(b)) => vm.push(add_i32(*a, *b as i32)),
-            (ConstValue::I32(a), ConstValue::I16(b)) => vm.push(add_i32(*a, *b as i32)),
-            (ConstValue::I32(a), ConstValue::I32(b)) => vm.push(add_i32(*a, *b)),
-            (ConstValue::I32(a), ConstValue::I64(b)) => vm.push(add_i64(*a as i64, *b)),
-            (ConstValue::I32(a), ConstValue::I128(b)) => vm.push(add_i128(*a as i128, *b)),
-            (ConstValue::I32(a), ConstValue::IBig(b)) => vm.push(add_ibig(&BigInt::from(*a), b)),
-
-            (ConstValue::I64(a), ConstValue::I8(b)) => vm.push(add_i64(*a, *b as i64)),
-            (ConstValue::I64(a), ConstValue::I16(b)) => vm.push(add_i64(*a, *b as i64)),
-            (ConstValue::I64(a), ConstValue::I32(b)) => vm.push(add_i64(*a, *b as i64)),
-            (ConstValue::I64(a), ConstValue::I64(b)) => vm.push(add_i64(*a, *b)),
-            (ConstValue::I64(a), ConstValue::I128(b)) => vm.push(add_i128(*a as i128, *b)),
-            (ConstValue::I64(a), ConstValue::IBig(b)) => vm.push(add_ibig(&BigInt::from(*a), b)),
-
-            (ConstValue::I128(a), ConstValue::I8(b)) => vm.push(add_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I16(b)) => vm.push(add_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I32(b)) => vm.push(add_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I64(b)) => vm.push(add_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I128(b)) => vm.push(add_i128(*a, *b)),
-            (ConstValue::I128(a), ConstValue::IBig(b)) => vm.push(add_ibig(&BigInt::from(*a), b)),
-
-            (ConstValue::IBig(a), ConstValue::I8(b)) => vm.push(add_ibig(a, &BigInt::from(*b))),
-            (ConstValue::IBig(a), ConstValue::I16(b)) => vm.push(add_ibig(a, &BigInt::from(*b))),
-            (ConstValue::IBig(a), ConstValue::I32(b)) => vm.push(add_ibig(a, &BigInt::from(*b))),
-            (ConstValue::IBig(a), ConstValue::I64(b)) => vm.push(add_ibig(a, &BigInt::from(*b))),
-            (ConstValue::IBig(a), ConstValue::I128(b)) => vm.push(add_ibig(a, &BigInt::from(*b))),
-            (ConstValue::IBig(a), ConstValue::IBig(b)) => vm.push(add_ibig(a, b)),
-
-            (ConstValue::U8(b), ConstValue::I8(a)) => vm.push(add_i16(*a as i16, *b as i16)),
-            (ConstValue::U16(b), ConstValue::I8(a)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::U32(b), ConstValue::I8(a)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I8(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U128(b), ConstValue::I8(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I8(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::U8(b), ConstValue::I16(a)) => vm.push(add_i16(*a as i16, *b as i16)),
-            (ConstValue::U16(b), ConstValue::I16(a)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::U32(b), ConstValue::I16(a)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I16(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U128(b), ConstValue::I16(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I16(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+    fn number_compare(vm: &mut VM, accept: fn(Ordering) -> bool) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::U8(b), ConstValue::I32(a)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::U16(b), ConstValue::I32(a)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::U32(b), ConstValue::I32(a)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I32(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U128(b), ConstValue::I32(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I32(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        // Comparing with something that isn't a number, or with NaN, is
+        // never true.
+        let result = match compare(&receiver.const_value, &operand.const_value) {
+            Ok(Some(ordering)) => accept(ordering),
+            _ => false,
+        };
+        vm.push(Object::box_bool(result));
 
-            (ConstValue::U8(b), ConstValue::I64(a)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::U16(b), ConstValue::I64(a)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::U32(b), ConstValue::I64(a)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I64(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U128(b), ConstValue::I64(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I64(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::U8(b), ConstValue::I128(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U16(b), ConstValue::I128(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U32(b), ConstValue::I128(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U64(b), ConstValue::I128(a)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::U128(b), ConstValue::I128(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I128(a)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+    fn number_negated(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::U8(b), ConstValue::IBig(a)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::U16(b), ConstValue::IBig(a)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::U32(b), ConstValue::IBig(a)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::U64(b), ConstValue::IBig(a)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::U128(b), ConstValue::IBig(a)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::UBig(b), ConstValue::IBig(a)) => vm.push(add_ibig(a, &(b.clone().into()))),
-
-            (ConstValue::I8(a), ConstValue::U8(b)) => vm.push(add_i16(*a as i16, *b as i16)),
-            (ConstValue::I8(a), ConstValue::U16(b)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::I8(a), ConstValue::U32(b)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::I8(a), ConstValue::U64(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I8(a), ConstValue::U128(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I8(a), ConstValue::UBig(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        match negate(&receiver.const_value) {
+            Ok(result) => vm.push(result),
+            Err(message) => return vm.panic(message),
+        }
 
-            (ConstValue::I16(a), ConstValue::U8(b)) => vm.push(add_i16(*a as i16, *b as i16)),
-            (ConstValue::I16(a), ConstValue::U16(b)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::I16(a), ConstValue::U32(b)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::I16(a), ConstValue::U64(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I16(a), ConstValue::U128(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I16(a), ConstValue::UBig(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::I32(a), ConstValue::U8(b)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::I32(a), ConstValue::U16(b)) => vm.push(add_i32(*a as i32, *b as i32)),
-            (ConstValue::I32(a), ConstValue::U32(b)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::I32(a), ConstValue::U64(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I32(a), ConstValue::U128(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I32(a), ConstValue::UBig(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+    fn number_convert(vm: &mut VM, kind: NumberKind) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::I64(a), ConstValue::U8(b)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::I64(a), ConstValue::U16(b)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::I64(a), ConstValue::U32(b)) => vm.push(add_i64(*a as i64, *b as i64)),
-            (ConstValue::I64(a), ConstValue::U64(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I64(a), ConstValue::U128(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I64(a), ConstValue::UBig(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        match convert(&receiver.const_value, kind) {
+            Ok(result) => vm.push(result),
+            Err(message) => return vm.panic(message),
+        }
 
-            (ConstValue::I128(a), ConstValue::U8(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I128(a), ConstValue::U16(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I128(a), ConstValue::U32(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I128(a), ConstValue::U64(b)) => vm.push(add_i128(*a as i128, *b as i128)),
-            (ConstValue::I128(a), ConstValue::U128(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I128(a), ConstValue::UBig(b)) => {
-                vm.push(add_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::IBig(a), ConstValue::U8(b)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U16(b)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U32(b)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U64(b)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U128(b)) => vm.push(add_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::UBig(b)) => vm.push(add_ibig(a, &(b.clone().into()))),
+    fn integer_bitwise(vm: &mut VM, operation: BitwiseOperation) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::F32(a), ConstValue::U8(b)) | (ConstValue::U8(b), ConstValue::F32(a)) => {
-                vm.push(add_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::I8(b)) | (ConstValue::I8(b), ConstValue::F32(a)) => {
-                vm.push(add_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::U16(b)) | (ConstValue::U16(b), ConstValue::F32(a)) => {
-                vm.push(add_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::I16(b)) | (ConstValue::I16(b), ConstValue::F32(a)) => {
-                vm.push(add_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::U32(b)) | (ConstValue::U32(b), ConstValue::F32(a)) => {
-                vm.push(add_f64(*a as f64, *b as f64))
-            }
-            (ConstValue::F32(a), ConstValue::I32(b)) | (ConstValue::I32(b), ConstValue::F32(a)) => {
-                vm.push(add_f64(*a as f64, *b as f64))
-            }
-            (ConstValue::F32(a), ConstValue::U64(b)) | (ConstValue::U64(b), ConstValue::F32(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::I64(b)) | (ConstValue::I64(b), ConstValue::F32(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::U128(b))
-            | (ConstValue::U128(b), ConstValue::F32(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::I128(b))
-            | (ConstValue::I128(b), ConstValue::F32(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::UBig(b))
-            | (ConstValue::UBig(b), ConstValue::F32(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(b.clone().into())))
-            }
-            (ConstValue::F32(a), ConstValue::IBig(b))
-            | (ConstValue::IBig(b), ConstValue::F32(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(b.clone().into())))
-            }
+        match bitwise(operation, &receiver.const_value, &operand.const_value) {
+            Ok(result) => vm.push(result),
+            Err(message) => return vm.panic(message),
+        }
 
-            (ConstValue::F64(a), ConstValue::U8(b)) | (ConstValue::U8(b), ConstValue::F64(a)) => {
-                vm.push(add_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::I8(b)) | (ConstValue::I8(b), ConstValue::F64(a)) => {
-                vm.push(add_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::U16(b)) | (ConstValue::U16(b), ConstValue::F64(a)) => {
-                vm.push(add_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::I16(b)) | (ConstValue::I16(b), ConstValue::F64(a)) => {
-                vm.push(add_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::U32(b)) | (ConstValue::U32(b), ConstValue::F64(a)) => {
-                vm.push(add_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::I32(b)) | (ConstValue::I32(b), ConstValue::F64(a)) => {
-                vm.push(add_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::U64(b)) | (ConstValue::U64(b), ConstValue::F64(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::I64(b)) | (ConstValue::I64(b), ConstValue::F64(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::U128(b))
-            | (ConstValue::U128(b), ConstValue::F64(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::I128(b))
-            | (ConstValue::I128(b), ConstValue::F64(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::UBig(b))
-            | (ConstValue::UBig(b), ConstValue::F64(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(b.clone().into())))
-            }
-            (ConstValue::F64(a), ConstValue::IBig(b))
-            | (ConstValue::IBig(b), ConstValue::F64(a)) => {
-                vm.push(add_fbig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::FBig(a), ConstValue::U8(b)) | (ConstValue::U8(b), ConstValue::FBig(a)) => {
-                vm.push(add_fbig(a, &(*b).into()))
-            }
-            (ConstValue::FBig(a), ConstValue::I8(b)) | (ConstValue::I8(b), ConstValue::FBig(a)) => {
-                vm.push(add_fbig(a, &(*b).into()))
-            }
-            (ConstValue::FBig(a), ConstValue::U16(b))
-            | (ConstValue::U16(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I16(b))
-            | (ConstValue::I16(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::U32(b))
-            | (ConstValue::U32(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I32(b))
-            | (ConstValue::I32(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::U64(b))
-            | (ConstValue::U64(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I64(b))
-            | (ConstValue::I64(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::U128(b))
-            | (ConstValue::U128(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I128(b))
-            | (ConstValue::I128(b), ConstValue::FBig(a)) => vm.push(add_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::UBig(b))
-            | (ConstValue::UBig(b), ConstValue::FBig(a)) => {
-                vm.push(add_fbig(a, &(b.clone().into())))
-            }
-            (ConstValue::FBig(a), ConstValue::IBig(b))
-            | (ConstValue::IBig(b), ConstValue::FBig(a)) => {
-                vm.push(add_fbig(a, &(b.clone().into())))
-            }
+    #[allow(non_snake_case)]
+    fn integer_bitInvert(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::F32(a), ConstValue::F32(b)) => vm.push(add_f32(*a, *b)),
-            (ConstValue::F32(a), ConstValue::F64(b)) => vm.push(add_f64(*a as f64, *b)),
-            (ConstValue::F64(a), ConstValue::F32(b)) => vm.push(add_f64(*a, *b as f64)),
-            (ConstValue::F64(a), ConstValue::F64(b)) => vm.push(add_f64(*a, *b)),
-            (ConstValue::F32(a), ConstValue::FBig(b)) => vm.push(add_fbig(&(*a).into(), b)),
-            (ConstValue::F64(a), ConstValue::FBig(b)) => vm.push(add_fbig(&(*a).into(), b)),
-            (ConstValue::FBig(b), ConstValue::F32(a)) => vm.push(add_fbig(&(*a).into(), b)),
-            (ConstValue::FBig(b), ConstValue::F64(a)) => vm.push(add_fbig(&(*a).into(), b)),
-            (ConstValue::FBig(a), ConstValue::FBig(b)) => vm.push(add_fbig(a, b)),
+        match invert(&receiver.const_value) {
+            Ok(result) => vm.push(result),
+            Err(message) => return vm.panic(message),
         }
+
         VMResult::Ok(())
     }
 
-    fn number_minus(vm: &mut VM) -> VMResult<()> {
+    fn integer_shift(vm: &mut VM, left: bool) -> VMResult<()> {
         let receiver = unwrap!(vm, vm.pop_eval::<Self>());
         let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-        match (&receiver.const_value, &operand.const_value) {
-            (ConstValue::Lazy(_, _, _), _) | (_, ConstValue::Lazy(_, _, _)) => {
-                return vm.panic("subtracting lazy".into())
-            }
+        match shift(&receiver.const_value, &operand.const_value, left) {
+            Ok(result) => vm.push(result),
+            Err(message) => return vm.panic(message),
+        }
 
-            (ConstValue::InstanceVariables(_), _)
-            | (_, ConstValue::InstanceVariables(_))
-            | (ConstValue::Nothing, _)
-            | (_, ConstValue::Nothing)
-            | (ConstValue::String(_), _)
-            | (_, ConstValue::String(_))
-            | (ConstValue::Character(_), _)
-            | (_, ConstValue::Character(_))
-            | (ConstValue::Symbol(_), _)
-            | (_, ConstValue::Symbol(_)) => return vm.panic("not a number".into()),
-
-            (ConstValue::U8(a), ConstValue::U8(b)) => vm.push(subtract_u8(*a, *b)),
-            (ConstValue::U8(a), ConstValue::U16(b)) => vm.push(subtract_u16(*a as u16, *b)),
-            (ConstValue::U8(a), ConstValue::U32(b)) => vm.push(subtract_u32(*a as u32, *b)),
-            (ConstValue::U8(a), ConstValue::U64(b)) => vm.push(subtract_u64(*a as u64, *b)),
-            (ConstValue::U8(a), ConstValue::U128(b)) => vm.push(subtract_u128(*a as u128, *b)),
-            (ConstValue::U8(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ubig(&BigUint::from(*a), b))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::U16(a), ConstValue::U8(b)) => vm.push(subtract_u16(*a, *b as u16)),
-            (ConstValue::U16(a), ConstValue::U16(b)) => vm.push(subtract_u16(*a, *b)),
-            (ConstValue::U16(a), ConstValue::U32(b)) => vm.push(subtract_u32(*a as u32, *b)),
-            (ConstValue::U16(a), ConstValue::U64(b)) => vm.push(subtract_u64(*a as u64, *b)),
-            (ConstValue::U16(a), ConstValue::U128(b)) => vm.push(subtract_u128(*a as u128, *b)),
-            (ConstValue::U16(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ubig(&BigUint::from(*a), b))
-            }
+    fn string_concat(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::U32(a), ConstValue::U8(b)) => vm.push(subtract_u32(*a, *b as u32)),
-            (ConstValue::U32(a), ConstValue::U16(b)) => vm.push(subtract_u32(*a, *b as u32)),
-            (ConstValue::U32(a), ConstValue::U32(b)) => vm.push(subtract_u32(*a, *b)),
-            (ConstValue::U32(a), ConstValue::U64(b)) => vm.push(subtract_u64(*a as u64, *b)),
-            (ConstValue::U32(a), ConstValue::U128(b)) => vm.push(subtract_u128(*a as u128, *b)),
-            (ConstValue::U32(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ubig(&BigUint::from(*a), b))
+        match (&receiver.const_value, &operand.const_value) {
+            (ConstValue::String(l), ConstValue::String(r)) => {
+                vm.push(Object::box_string(format!("{}{}", l, r)))
             }
+            _ => return vm.panic(format!("cannot concatenate {} and {}", receiver, operand)),
+        }
 
-            (ConstValue::U64(a), ConstValue::U8(b)) => vm.push(subtract_u64(*a, *b as u64)),
-            (ConstValue::U64(a), ConstValue::U16(b)) => vm.push(subtract_u64(*a, *b as u64)),
-            (ConstValue::U64(a), ConstValue::U32(b)) => vm.push(subtract_u64(*a, *b as u64)),
-            (ConstValue::U64(a), ConstValue::U64(b)) => vm.push(subtract_u64(*a, *b)),
-            (ConstValue::U64(a), ConstValue::U128(b)) => vm.push(subtract_u128(*a as u128, *b)),
-            (ConstValue::U64(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ubig(&BigUint::from(*a), b))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::U128(a), ConstValue::U8(b)) => vm.push(subtract_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U16(b)) => vm.push(subtract_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U32(b)) => vm.push(subtract_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U64(b)) => vm.push(subtract_u128(*a, *b as u128)),
-            (ConstValue::U128(a), ConstValue::U128(b)) => vm.push(subtract_u128(*a, *b)),
-            (ConstValue::U128(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ubig(&BigUint::from(*a), b))
-            }
+    fn string_size(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::UBig(a), ConstValue::U8(b)) => {
-                vm.push(subtract_ubig(a, &BigUint::from(*b)))
-            }
-            (ConstValue::UBig(a), ConstValue::U16(b)) => {
-                vm.push(subtract_ubig(a, &BigUint::from(*b)))
-            }
-            (ConstValue::UBig(a), ConstValue::U32(b)) => {
-                vm.push(subtract_ubig(a, &BigUint::from(*b)))
-            }
-            (ConstValue::UBig(a), ConstValue::U64(b)) => {
-                vm.push(subtract_ubig(a, &BigUint::from(*b)))
-            }
-            (ConstValue::UBig(a), ConstValue::U128(b)) => {
-                vm.push(subtract_ubig(a, &BigUint::from(*b)))
-            }
-            (ConstValue::UBig(a), ConstValue::UBig(b)) => vm.push(subtract_ubig(a, b)),
-
-            (ConstValue::I8(a), ConstValue::I8(b)) => vm.push(subtract_i8(*a, *b)),
-            (ConstValue::I8(a), ConstValue::I16(b)) => vm.push(subtract_i16(*a as i16, *b)),
-            (ConstValue::I8(a), ConstValue::I32(b)) => vm.push(subtract_i32(*a as i32, *b)),
-            (ConstValue::I8(a), ConstValue::I64(b)) => vm.push(subtract_i64(*a as i64, *b)),
-            (ConstValue::I8(a), ConstValue::I128(b)) => vm.push(subtract_i128(*a as i128, *b)),
-            (ConstValue::I8(a), ConstValue::IBig(b)) => {
-                vm.push(subtract_ibig(&BigInt::from(*a), b))
-            }
+        match receiver.const_value {
+            ConstValue::String(ref s) => vm.push(Object::box_u64(s.encode_utf16().count() as u64)),
+            _ => return vm.panic(format!("{} is not a string", receiver)),
+        }
 
-            (ConstValue::I16(a), ConstValue::I8(b)) => vm.push(subtract_i16(*a, *b as i16)),
-            (ConstValue::I16(a), ConstValue::I16(b)) => vm.push(subtract_i16(*a, *b)),
-            (ConstValue::I16(a), ConstValue::I32(b)) => vm.push(subtract_i32(*a as i32, *b)),
-            (ConstValue::I16(a), ConstValue::I64(b)) => vm.push(subtract_i64(*a as i64, *b)),
-            (ConstValue::I16(a), ConstValue::I128(b)) => vm.push(subtract_i128(*a as i128, *b)),
-            (ConstValue::I16(a), ConstValue::IBig(b)) => {
-                vm.push(subtract_ibig(&BigInt::from(*a), b))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::I32(a), ConstValue::I8(b)) => vm.push(subtract_i32(*a, *b as i32)),
-            (ConstValue::I32(a), ConstValue::I16(b)) => vm.push(subtract_i32(*a, *b as i32)),
-            (ConstValue::I32(a), ConstValue::I32(b)) => vm.push(subtract_i32(*a, *b)),
-            (ConstValue::I32(a), ConstValue::I64(b)) => vm.push(subtract_i64(*a as i64, *b)),
-            (ConstValue::I32(a), ConstValue::I128(b)) => vm.push(subtract_i128(*a as i128, *b)),
-            (ConstValue::I32(a), ConstValue::IBig(b)) => {
-                vm.push(subtract_ibig(&BigInt::from(*a), b))
-            }
+    fn string_at(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::I64(a), ConstValue::I8(b)) => vm.push(subtract_i64(*a, *b as i64)),
-            (ConstValue::I64(a), ConstValue::I16(b)) => vm.push(subtract_i64(*a, *b as i64)),
-            (ConstValue::I64(a), ConstValue::I32(b)) => vm.push(subtract_i64(*a, *b as i64)),
-            (ConstValue::I64(a), ConstValue::I64(b)) => vm.push(subtract_i64(*a, *b)),
-            (ConstValue::I64(a), ConstValue::I128(b)) => vm.push(subtract_i128(*a as i128, *b)),
-            (ConstValue::I64(a), ConstValue::IBig(b)) => {
-                vm.push(subtract_ibig(&BigInt::from(*a), b))
-            }
+        let s = match receiver.const_value {
+            ConstValue::String(ref s) => s,
+            _ => return vm.panic(format!("{} is not a string", receiver)),
+        };
+        let index = match convert(&operand.const_value, NumberKind::U64) {
+            Ok(index) => match index.const_value {
+                ConstValue::U64(index) => index,
+                _ => return vm.panic(format!("{} is not a valid index", operand)),
+            },
+            Err(message) => return vm.panic(message),
+        };
+
+        match s.encode_utf16().nth(index as usize) {
+            Some(c) => vm.push(Object::box_character(c)),
+            None => return vm.panic(format!("index {} is out of bounds", index)),
+        }
 
-            (ConstValue::I128(a), ConstValue::I8(b)) => vm.push(subtract_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I16(b)) => vm.push(subtract_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I32(b)) => vm.push(subtract_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I64(b)) => vm.push(subtract_i128(*a, *b as i128)),
-            (ConstValue::I128(a), ConstValue::I128(b)) => vm.push(subtract_i128(*a, *b)),
-            (ConstValue::I128(a), ConstValue::IBig(b)) => {
-                vm.push(subtract_ibig(&BigInt::from(*a), b))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::IBig(a), ConstValue::I8(b)) => {
-                vm.push(subtract_ibig(a, &BigInt::from(*b)))
-            }
-            (ConstValue::IBig(a), ConstValue::I16(b)) => {
-                vm.push(subtract_ibig(a, &BigInt::from(*b)))
-            }
-            (ConstValue::IBig(a), ConstValue::I32(b)) => {
-                vm.push(subtract_ibig(a, &BigInt::from(*b)))
-            }
-            (ConstValue::IBig(a), ConstValue::I64(b)) => {
-                vm.push(subtract_ibig(a, &BigInt::from(*b)))
-            }
-            (ConstValue::IBig(a), ConstValue::I128(b)) => {
-                vm.push(subtract_ibig(a, &BigInt::from(*b)))
-            }
-            (ConstValue::IBig(a), ConstValue::IBig(b)) => vm.push(subtract_ibig(a, b)),
+    #[allow(non_snake_case)]
+    fn string_asSymbol(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::U8(b), ConstValue::I8(a)) => vm.push(subtract_i16(*a as i16, *b as i16)),
-            (ConstValue::U16(b), ConstValue::I8(a)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::U32(b), ConstValue::I8(a)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I8(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U128(b), ConstValue::I8(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I8(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        match receiver.const_value {
+            ConstValue::String(ref s) => vm.push(Object::box_symbol(s.clone())),
+            _ => return vm.panic(format!("{} is not a string", receiver)),
+        }
 
-            (ConstValue::U8(b), ConstValue::I16(a)) => vm.push(subtract_i16(*a as i16, *b as i16)),
-            (ConstValue::U16(b), ConstValue::I16(a)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::U32(b), ConstValue::I16(a)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I16(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U128(b), ConstValue::I16(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I16(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::U8(b), ConstValue::I32(a)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::U16(b), ConstValue::I32(a)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::U32(b), ConstValue::I32(a)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I32(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U128(b), ConstValue::I32(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I32(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+    fn string_map(vm: &mut VM, f: fn(&str) -> String) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::U8(b), ConstValue::I64(a)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::U16(b), ConstValue::I64(a)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::U32(b), ConstValue::I64(a)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::U64(b), ConstValue::I64(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U128(b), ConstValue::I64(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I64(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        match receiver.const_value {
+            ConstValue::String(ref s) => vm.push(Object::box_string(f(s))),
+            _ => return vm.panic(format!("{} is not a string", receiver)),
+        }
 
-            (ConstValue::U8(b), ConstValue::I128(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U16(b), ConstValue::I128(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U32(b), ConstValue::I128(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U64(b), ConstValue::I128(a)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::U128(b), ConstValue::I128(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::UBig(b), ConstValue::I128(a)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::U8(b), ConstValue::IBig(a)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::U16(b), ConstValue::IBig(a)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::U32(b), ConstValue::IBig(a)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::U64(b), ConstValue::IBig(a)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::U128(b), ConstValue::IBig(a)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::UBig(b), ConstValue::IBig(a)) => {
-                vm.push(subtract_ibig(a, &(b.clone().into())))
-            }
+    #[allow(non_snake_case)]
+    fn character_codePoint(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::I8(a), ConstValue::U8(b)) => vm.push(subtract_i16(*a as i16, *b as i16)),
-            (ConstValue::I8(a), ConstValue::U16(b)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::I8(a), ConstValue::U32(b)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::I8(a), ConstValue::U64(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I8(a), ConstValue::U128(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I8(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        match receiver.const_value {
+            ConstValue::Character(c) => vm.push(Object::box_u16(c)),
+            _ => return vm.panic(format!("{} is not a character", receiver)),
+        }
 
-            (ConstValue::I16(a), ConstValue::U8(b)) => vm.push(subtract_i16(*a as i16, *b as i16)),
-            (ConstValue::I16(a), ConstValue::U16(b)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::I16(a), ConstValue::U32(b)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::I16(a), ConstValue::U64(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I16(a), ConstValue::U128(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I16(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::I32(a), ConstValue::U8(b)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::I32(a), ConstValue::U16(b)) => vm.push(subtract_i32(*a as i32, *b as i32)),
-            (ConstValue::I32(a), ConstValue::U32(b)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::I32(a), ConstValue::U64(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I32(a), ConstValue::U128(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I32(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+    fn character_test(vm: &mut VM, test: fn(char) -> bool) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::I64(a), ConstValue::U8(b)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::I64(a), ConstValue::U16(b)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::I64(a), ConstValue::U32(b)) => vm.push(subtract_i64(*a as i64, *b as i64)),
-            (ConstValue::I64(a), ConstValue::U64(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I64(a), ConstValue::U128(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I64(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        match receiver.const_value {
+            // Unpaired surrogates are neither letters, digits nor whitespace.
+            ConstValue::Character(c) => vm.push(Object::box_bool(
+                decode_utf16(Some(c)).all(|r| r.map(test).unwrap_or(false)),
+            )),
+            _ => return vm.panic(format!("{} is not a character", receiver)),
+        }
 
-            (ConstValue::I128(a), ConstValue::U8(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I128(a), ConstValue::U16(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I128(a), ConstValue::U32(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I128(a), ConstValue::U64(b)) => {
-                vm.push(subtract_i128(*a as i128, *b as i128))
-            }
-            (ConstValue::I128(a), ConstValue::U128(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::I128(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ibig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::IBig(a), ConstValue::U8(b)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U16(b)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U32(b)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U64(b)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::U128(b)) => vm.push(subtract_ibig(a, &(*b).into())),
-            (ConstValue::IBig(a), ConstValue::UBig(b)) => {
-                vm.push(subtract_ibig(a, &(b.clone().into())))
-            }
+    fn character_map<I: Iterator<Item = char>>(vm: &mut VM, f: fn(char) -> I) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::F32(a), ConstValue::U8(b)) | (ConstValue::U8(b), ConstValue::F32(a)) => {
-                vm.push(subtract_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::I8(b)) | (ConstValue::I8(b), ConstValue::F32(a)) => {
-                vm.push(subtract_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::U16(b)) | (ConstValue::U16(b), ConstValue::F32(a)) => {
-                vm.push(subtract_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::I16(b)) | (ConstValue::I16(b), ConstValue::F32(a)) => {
-                vm.push(subtract_f32(*a, *b as f32))
-            }
-            (ConstValue::F32(a), ConstValue::U32(b)) | (ConstValue::U32(b), ConstValue::F32(a)) => {
-                vm.push(subtract_f64(*a as f64, *b as f64))
-            }
-            (ConstValue::F32(a), ConstValue::I32(b)) | (ConstValue::I32(b), ConstValue::F32(a)) => {
-                vm.push(subtract_f64(*a as f64, *b as f64))
-            }
-            (ConstValue::F32(a), ConstValue::U64(b)) | (ConstValue::U64(b), ConstValue::F32(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::I64(b)) | (ConstValue::I64(b), ConstValue::F32(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::U128(b))
-            | (ConstValue::U128(b), ConstValue::F32(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::I128(b))
-            | (ConstValue::I128(b), ConstValue::F32(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F32(a), ConstValue::UBig(b))
-            | (ConstValue::UBig(b), ConstValue::F32(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(b.clone().into())))
-            }
-            (ConstValue::F32(a), ConstValue::IBig(b))
-            | (ConstValue::IBig(b), ConstValue::F32(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(b.clone().into())))
-            }
+        let c = match receiver.const_value {
+            ConstValue::Character(c) => c,
+            _ => return vm.panic(format!("{} is not a character", receiver)),
+        };
+
+        // Characters are UTF-16 code units, so a character that maps to more
+        // than one code unit (like `ß` to `SS`) is left as it is.
+        let mapped = match decode_utf16(Some(c)).next() {
+            Some(Ok(decoded)) => {
+                let mut units = f(decoded)
+                    .collect::<String>()
+                    .encode_utf16()
+                    .collect::<Vec<_>>();
+                if units.len() == 1 {
+                    units.remove(0)
+                } else {
+                    c
+                }
+            }
+            _ => c,
+        };
+        vm.push(Object::box_character(mapped));
 
-            (ConstValue::F64(a), ConstValue::U8(b)) | (ConstValue::U8(b), ConstValue::F64(a)) => {
-                vm.push(subtract_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::I8(b)) | (ConstValue::I8(b), ConstValue::F64(a)) => {
-                vm.push(subtract_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::U16(b)) | (ConstValue::U16(b), ConstValue::F64(a)) => {
-                vm.push(subtract_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::I16(b)) | (ConstValue::I16(b), ConstValue::F64(a)) => {
-                vm.push(subtract_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::U32(b)) | (ConstValue::U32(b), ConstValue::F64(a)) => {
-                vm.push(subtract_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::I32(b)) | (ConstValue::I32(b), ConstValue::F64(a)) => {
-                vm.push(subtract_f64(*a, *b as f64))
-            }
-            (ConstValue::F64(a), ConstValue::U64(b)) | (ConstValue::U64(b), ConstValue::F64(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::I64(b)) | (ConstValue::I64(b), ConstValue::F64(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::U128(b))
-            | (ConstValue::U128(b), ConstValue::F64(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::I128(b))
-            | (ConstValue::I128(b), ConstValue::F64(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(*b).into()))
-            }
-            (ConstValue::F64(a), ConstValue::UBig(b))
-            | (ConstValue::UBig(b), ConstValue::F64(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(b.clone().into())))
-            }
-            (ConstValue::F64(a), ConstValue::IBig(b))
-            | (ConstValue::IBig(b), ConstValue::F64(a)) => {
-                vm.push(subtract_fbig(&(*a).into(), &(b.clone().into())))
-            }
+        VMResult::Ok(())
+    }
 
-            (ConstValue::FBig(a), ConstValue::U8(b)) | (ConstValue::U8(b), ConstValue::FBig(a)) => {
-                vm.push(subtract_fbig(a, &(*b).into()))
-            }
-            (ConstValue::FBig(a), ConstValue::I8(b)) | (ConstValue::I8(b), ConstValue::FBig(a)) => {
-                vm.push(subtract_fbig(a, &(*b).into()))
-            }
-            (ConstValue::FBig(a), ConstValue::U16(b))
-            | (ConstValue::U16(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I16(b))
-            | (ConstValue::I16(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::U32(b))
-            | (ConstValue::U32(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I32(b))
-            | (ConstValue::I32(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::U64(b))
-            | (ConstValue::U64(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I64(b))
-            | (ConstValue::I64(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::U128(b))
-            | (ConstValue::U128(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::I128(b))
-            | (ConstValue::I128(b), ConstValue::FBig(a)) => vm.push(subtract_fbig(a, &(*b).into())),
-            (ConstValue::FBig(a), ConstValue::UBig(b))
-            | (ConstValue::UBig(b), ConstValue::FBig(a)) => {
-                vm.push(subtract_fbig(a, &(b.clone().into())))
-            }
-            (ConstValue::FBig(a), ConstValue::IBig(b))
-            | (ConstValue::IBig(b), ConstValue::FBig(a)) => {
-                vm.push(subtract_fbig(a, &(b.clone().into())))
-            }
+    fn symbol_name(vm: &mut VM) -> VMResult<()> {
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
 
-            (ConstValue::F32(a), ConstValue::F32(b)) => vm.push(subtract_f32(*a, *b)),
-            (ConstValue::F32(a), ConstValue::F64(b)) => vm.push(subtract_f64(*a as f64, *b)),
-            (ConstValue::F64(a), ConstValue::F32(b)) => vm.push(subtract_f64(*a, *b as f64)),
-            (ConstValue::F64(a), ConstValue::F64(b)) => vm.push(subtract_f64(*a, *b)),
-            (ConstValue::F32(a), ConstValue::FBig(b)) => vm.push(subtract_fbig(&(*a).into(), b)),
-            (ConstValue::F64(a), ConstValue::FBig(b)) => vm.push(subtract_fbig(&(*a).into(), b)),
-            (ConstValue::FBig(b), ConstValue::F32(a)) => vm.push(subtract_fbig(&(*a).into(), b)),
-            (ConstValue::FBig(b), ConstValue::F64(a)) => vm.push(subtract_fbig(&(*a).into(), b)),
-            (ConstValue::FBig(a), ConstValue::FBig(b)) => vm.push(subtract_fbig(a, b)),
+        match receiver.const_value {
+            ConstValue::Symbol(ref s) => vm.push(Object::box_string(s.clone())),
+            _ => return vm.panic(format!("{} is not a symbol", receiver)),
         }
+
         VMResult::Ok(())
     }
-}
 
-fn add_u8(lhs: u8, rhs: u8) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_u8(res),
-        None => add_u16(lhs as u16, rhs as u16),
-    }
-}
+    fn process_print(vm: &mut VM, error: bool, terminator: &str) -> VMResult<()> {
+        if error {
+            unwrap!(
+                vm,
+                Self::require_capability(vm, |c| c.stderr, "the standard error")
+            );
+        } else {
+            unwrap!(
+                vm,
+                Self::require_capability(vm, |c| c.stdout, "the standard output")
+            );
+        }
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn add_u16(lhs: u16, rhs: u16) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_u16(res),
-        None => add_u32(lhs as u32, rhs as u32),
-    }
-}
+        let text = match operand.const_value {
+            ConstValue::String(ref s) => s,
+            _ => return vm.panic(format!("{} is not a string", operand)),
+        };
+        let result = if error {
+            let mut stderr = io::stderr();
+            write!(stderr, "{}{}", text, terminator).and_then(|_| stderr.flush())
+        } else {
+            let mut stdout = io::stdout();
+            write!(stdout, "{}{}", text, terminator).and_then(|_| stdout.flush())
+        };
+        if let Err(e) = result {
+            return vm.panic(e.to_string());
+        }
+        vm.push(receiver);
 
-fn add_u32(lhs: u32, rhs: u32) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_u32(res),
-        None => add_u64(lhs as u64, rhs as u64),
+        VMResult::Ok(())
     }
-}
 
-fn add_u64(lhs: u64, rhs: u64) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_u64(res),
-        None => add_u128(lhs as u128, rhs as u128),
-    }
-}
+    #[allow(non_snake_case)]
+    fn process_readLine(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.stdin, "the standard input")
+        );
+        unwrap!(vm, vm.pop_eval::<Self>());
+
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => return vm.panic("end of input".into()),
+            Ok(_) => {
+                if line.ends_with('\n') {
+                    line.pop();
+                    if line.ends_with('\r') {
+                        line.pop();
+                    }
+                }
+                vm.push(Object::box_string(line))
+            }
+            Err(e) => return vm.panic(e.to_string()),
+        }
 
-fn add_u128(lhs: u128, rhs: u128) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_u128(res),
-        None => add_ubig(&BigUint::from(lhs), &BigUint::from(rhs)),
+        VMResult::Ok(())
     }
-}
 
-fn add_ubig(lhs: &BigUint, rhs: &BigUint) -> Arc<Object> {
-    Object::box_ubig(lhs + rhs)
-}
+    #[allow(non_snake_case)]
+    fn process_argumentCount(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.args, "the arguments")
+        );
+        unwrap!(vm, vm.pop_eval::<Self>());
 
-fn add_i8(lhs: i8, rhs: i8) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_i8(res),
-        None => add_i16(lhs as i16, rhs as i16),
-    }
-}
+        vm.push(Object::box_u64(vm.arguments().len() as u64));
 
-fn add_i16(lhs: i16, rhs: i16) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_i16(res),
-        None => add_i32(lhs as i32, rhs as i32),
+        VMResult::Ok(())
     }
-}
 
-fn add_i32(lhs: i32, rhs: i32) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_i32(res),
-        None => add_i64(lhs as i64, rhs as i64),
-    }
-}
+    #[allow(non_snake_case)]
+    fn process_argumentAt(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.args, "the arguments")
+        );
+        unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn add_i64(lhs: i64, rhs: i64) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_i64(res),
-        None => add_i128(lhs as i128, rhs as i128),
-    }
-}
+        let index = match convert(&operand.const_value, NumberKind::U64) {
+            Ok(index) => match index.const_value {
+                ConstValue::U64(index) => index,
+                _ => return vm.panic(format!("{} is not a valid index", operand)),
+            },
+            Err(message) => return vm.panic(message),
+        };
+
+        match vm.arguments().get(index as usize).cloned() {
+            Some(argument) => vm.push(Object::box_string(argument)),
+            None => return vm.panic(format!("index {} is out of bounds", index)),
+        }
 
-fn add_i128(lhs: i128, rhs: i128) -> Arc<Object> {
-    match lhs.checked_add(rhs) {
-        Some(res) => Object::box_i128(res),
-        None => add_ibig(&lhs.into(), &rhs.into()),
+        VMResult::Ok(())
     }
-}
-
-fn add_ibig(lhs: &BigInt, rhs: &BigInt) -> Arc<Object> {
-    Object::box_ibig(lhs + rhs)
-}
 
-fn add_f32(lhs: f32, rhs: f32) -> Arc<Object> {
-    Object::box_f32(lhs + rhs)
-}
-
-fn add_f64(lhs: f64, rhs: f64) -> Arc<Object> {
-    Object::box_f64(lhs + rhs)
-}
-
-fn add_fbig(lhs: &BigFraction, rhs: &BigFraction) -> Arc<Object> {
-    Object::box_fbig(lhs + rhs)
-}
+    #[allow(non_snake_case)]
+    fn process_hasEnvironmentVariable(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.env, "the environment")
+        );
+        unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn subtract_u8(lhs: u8, rhs: u8) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_u8(res),
-        None => subtract_u16(lhs as u16, rhs as u16),
-    }
-}
+        match operand.const_value {
+            ConstValue::String(ref name) => {
+                vm.push(Object::box_bool(std::env::var_os(name).is_some()))
+            }
+            _ => return vm.panic(format!("{} is not a string", operand)),
+        }
 
-fn subtract_u16(lhs: u16, rhs: u16) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_u16(res),
-        None => subtract_u32(lhs as u32, rhs as u32),
+        VMResult::Ok(())
     }
-}
 
-fn subtract_u32(lhs: u32, rhs: u32) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_u32(res),
-        None => subtract_u64(lhs as u64, rhs as u64),
-    }
-}
+    #[allow(non_snake_case)]
+    fn process_environmentVariable(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.env, "the environment")
+        );
+        unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn subtract_u64(lhs: u64, rhs: u64) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_u64(res),
-        None => subtract_u128(lhs as u128, rhs as u128),
-    }
-}
+        let name = match operand.const_value {
+            ConstValue::String(ref name) => name,
+            _ => return vm.panic(format!("{} is not a string", operand)),
+        };
+        match std::env::var(name) {
+            Ok(value) => vm.push(Object::box_string(value)),
+            Err(e) => return vm.panic(format!("{}: {}", name, e)),
+        }
 
-fn subtract_u128(lhs: u128, rhs: u128) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_u128(res),
-        None => subtract_ubig(&BigUint::from(lhs), &BigUint::from(rhs)),
+        VMResult::Ok(())
     }
-}
-
-fn subtract_ubig(lhs: &BigUint, rhs: &BigUint) -> Arc<Object> {
-    Object::box_ubig(lhs + rhs)
-}
 
-fn subtract_i8(lhs: i8, rhs: i8) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_i8(res),
-        None => subtract_i16(lhs as i16, rhs as i16),
-    }
-}
+    fn process_exit(vm: &mut VM) -> VMResult<()> {
+        unwrap!(vm, Self::require_capability(vm, |c| c.exit, "exit codes"));
+        unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn subtract_i16(lhs: i16, rhs: i16) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_i16(res),
-        None => subtract_i32(lhs as i32, rhs as i32),
+        match convert(&operand.const_value, NumberKind::I32) {
+            Ok(code) => match code.const_value {
+                ConstValue::I32(code) => vm.exit(code),
+                _ => vm.panic(format!("{} is not a valid exit code", operand)),
+            },
+            Err(message) => vm.panic(message),
+        }
     }
-}
 
-fn subtract_i32(lhs: i32, rhs: i32) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_i32(res),
-        None => subtract_i64(lhs as i64, rhs as i64),
-    }
-}
+    fn file_read(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.read_files, "file reads")
+        );
+        unwrap!(vm, vm.pop_eval::<Self>());
+        let operand = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn subtract_i64(lhs: i64, rhs: i64) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_i64(res),
-        None => subtract_i128(lhs as i128, rhs as i128),
-    }
-}
+        let path = match operand.const_value {
+            ConstValue::String(ref path) => path,
+            _ => return vm.panic(format!("{} is not a string", operand)),
+        };
+        match std::fs::read_to_string(path) {
+            Ok(contents) => vm.push(Object::box_string(contents)),
+            Err(e) => return vm.panic(format!("{}: {}", path, e)),
+        }
 
-fn subtract_i128(lhs: i128, rhs: i128) -> Arc<Object> {
-    match lhs.checked_sub(rhs) {
-        Some(res) => Object::box_i128(res),
-        None => subtract_ibig(&lhs.into(), &rhs.into()),
+        VMResult::Ok(())
     }
-}
 
-fn subtract_ibig(lhs: &BigInt, rhs: &BigInt) -> Arc<Object> {
-    Object::box_ibig(lhs - rhs)
-}
-
-fn subtract_f32(lhs: f32, rhs: f32) -> Arc<Object> {
-    Object::box_f32(lhs - rhs)
-}
+    fn file_write(vm: &mut VM) -> VMResult<()> {
+        unwrap!(
+            vm,
+            Self::require_capability(vm, |c| c.write_files, "file writes")
+        );
+        let receiver = unwrap!(vm, vm.pop_eval::<Self>());
+        let contents = unwrap!(vm, vm.pop_eval::<Self>());
+        let path = unwrap!(vm, vm.pop_eval::<Self>());
 
-fn subtract_f64(lhs: f64, rhs: f64) -> Arc<Object> {
-    Object::box_f64(lhs - rhs)
-}
+        match (&contents.const_value, &path.const_value) {
+            (ConstValue::String(contents), ConstValue::String(path)) => {
+                if let Err(e) = std::fs::write(path, contents) {
+                    return vm.panic(format!("{}: {}", path, e));
+                }
+            }
+            _ => return vm.panic(format!("cannot write {} to {}", contents, path)),
+        }
+        vm.push(receiver);
 
-fn subtract_fbig(lhs: &BigFraction, rhs: &BigFraction) -> Arc<Object> {
-    Object::box_fbig(lhs - rhs)
+        VMResult::Ok(())
+    }
 }
 
 impl Runtime for () {
@@ -1007,37 +643,3 @@ impl Runtime for () {
         panic!("{}\n{:#?}", message, call_stack)
     }
 }
-
-#[allow(non_camel_case_types)]
-#[derive(Debug, Clone, PartialEq)]
-pub enum NativeMethod {
-    Number_plus,
-    Number_minus,
-    Object_eq,
-    Object_asString,
-}
-
-impl<'a> From<&'a str> for NativeMethod {
-    fn from(name: &'a str) -> Self {
-        use NativeMethod::*;
-        match name {
-            "Loa/Number#+" => Number_plus,
-            "Loa/Number#-" => Number_minus,
-            "Loa/Object#==" => Object_eq,
-            "Loa/Object#asString" => Object_asString,
-            n => panic!("unknown native method: {}", n),
-        }
-    }
-}
-
-impl std::fmt::Display for NativeMethod {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        use NativeMethod::*;
-        match self {
-            Number_plus => write!(f, "Loa/Number#+"),
-            Number_minus => write!(f, "Loa/Number#-"),
-            Object_eq => write!(f, "Loa/Object#=="),
-            Object_asString => write!(f, "Loa/Object#asString"),
-        }
-    }
-}
@@ -0,0 +1,324 @@
+use crate::bytecode::Instruction;
+use crate::vm::*;
+use crate::*;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// The environment variable that turns on tracing in the `loa` and `loavm`
+/// binaries, holding the trace options.
+pub const TRACE_VAR: &str = "LOA_TRACE";
+
+/// The most characters of the object on top of the stack that are written
+/// to a trace.
+const SUMMARY_LENGTH: usize = 60;
+
+/// What to trace, and where to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceOptions {
+    /// The file the trace is written to.
+    pub file: PathBuf,
+
+    /// Only instructions executed in these methods are traced, either by
+    /// selector, like `map:`, or by class and selector, like
+    /// `Loa/List#map:`. All methods are traced if there are none.
+    pub methods: Vec<String>,
+
+    /// Only instructions within these ranges of the program, inclusive, are
+    /// traced. The whole program is traced if there are none.
+    pub pcs: Vec<(usize, usize)>,
+}
+
+/// Writes a line for every instruction the VM executes, with the program
+/// counter, the method it is executed in, the instruction itself and the
+/// object on top of the stack before it's executed. The columns are
+/// separated by tabs, so the trace can be cut up with the usual tools.
+pub struct Tracer {
+    options: TraceOptions,
+    out: Box<dyn Write>,
+    error: Option<io::Error>,
+}
+
+impl Default for TraceOptions {
+    fn default() -> TraceOptions {
+        TraceOptions {
+            file: "loa.trace".into(),
+            methods: vec![],
+            pcs: vec![],
+        }
+    }
+}
+
+/// Parses trace options from a comma separated list of `name=value` pairs,
+/// like `file=out.trace,method=Loa/List#map:,pc=120-180`. The names are
+/// `file`, which defaults to `loa.trace`, and `method` and `pc`, which may
+/// be given more than once. A `pc` is either a single offset, or a range
+/// like `120-180`.
+impl FromStr for TraceOptions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = TraceOptions::default();
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let mut parts = pair.splitn(2, '=');
+            let name = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return Err(format!("expected a value for trace option `{}`", name)),
+            };
+            let invalid = || format!("invalid value for trace option `{}`: {}", name, value);
+
+            match name {
+                "file" if !value.is_empty() => options.file = value.into(),
+                "method" if !value.is_empty() => options.methods.push(value.into()),
+                "pc" => {
+                    let mut bounds = value.splitn(2, '-');
+                    let start = bounds.next().unwrap_or("").trim();
+                    let start: usize = start.parse().map_err(|_| invalid())?;
+                    let end = match bounds.next() {
+                        Some(end) => end.trim().parse().map_err(|_| invalid())?,
+                        None => start,
+                    };
+                    if end < start {
+                        return Err(invalid());
+                    }
+                    options.pcs.push((start, end));
+                }
+                "file" | "method" => return Err(invalid()),
+                _ => return Err(format!("unknown trace option: {}", name)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl TraceOptions {
+    /// The options in the environment variable, if it's set.
+    pub fn from_env() -> Option<Result<TraceOptions, String>> {
+        let value = std::env::var(TRACE_VAR).ok()?;
+        Some(value.parse().map_err(|e| format!("{}: {}", TRACE_VAR, e)))
+    }
+
+    fn traces(&self, pc: usize, frame: Option<&Arc<StackFrame>>) -> bool {
+        if !self.pcs.is_empty() && !self.pcs.iter().any(|(s, e)| *s <= pc && pc <= *e) {
+            return false;
+        }
+        if self.methods.is_empty() {
+            return true;
+        }
+        match frame {
+            Some(frame) => {
+                let class = frame
+                    .receiver
+                    .class
+                    .as_ref()
+                    .map(|c| c.name.as_ref())
+                    .unwrap_or("?");
+                self.methods.iter().any(|m| {
+                    if *m == frame.method.name {
+                        return true;
+                    }
+                    let mut parts = m.splitn(2, '#');
+                    parts.next() == Some(class) && parts.next() == Some(frame.method.name.as_str())
+                })
+            }
+            None => self.methods.iter().any(|m| m == MAIN_FRAME),
+        }
+    }
+}
+
+impl Tracer {
+    pub fn new<W: Write + 'static>(options: TraceOptions, out: W) -> Tracer {
+        Tracer {
+            options,
+            out: Box::new(out),
+            error: None,
+        }
+    }
+
+    /// Traces to the file named by the options, replacing it if it exists.
+    pub fn create(options: TraceOptions) -> io::Result<Tracer> {
+        let file = File::create(&options.file)?;
+        Ok(Tracer::new(options, BufWriter::new(file)))
+    }
+
+    pub fn options(&self) -> &TraceOptions {
+        &self.options
+    }
+
+    /// Traces an instruction about to be executed.
+    #[inline]
+    pub fn trace(
+        &mut self,
+        pc: usize,
+        instruction: &Instruction,
+        top: Option<&Arc<Object>>,
+        frame: Option<&Arc<StackFrame>>,
+    ) {
+        if self.error.is_some() || !self.options.traces(pc, frame) {
+            return;
+        }
+        let method = match frame {
+            Some(frame) => frame_name(frame),
+            None => MAIN_FRAME.into(),
+        };
+        let top = match top {
+            Some(top) => summary(top),
+            None => "-".into(),
+        };
+        // A trace that can't be written is given up on, rather than failing
+        // the program it traces.
+        if let Err(e) = writeln!(self.out, "{}\t{}\t{:?}\t{}", pc, method, instruction, top) {
+            self.error = Some(e);
+        }
+    }
+
+    /// Flushes the trace, and returns the first error writing it, if any.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(e) => Err(e),
+            None => self.out.flush(),
+        }
+    }
+}
+
+/// Describes the object without the objects it refers to, and cut short, so
+/// that tracing a large object graph doesn't format all of it for every
+/// instruction. Strings are escaped, to keep them from breaking up the
+/// columns and lines of the trace.
+fn summary(object: &Object) -> String {
+    let summary: String = match object.const_value {
+        ConstValue::InstanceVariables(_) => format!(
+            "a {}",
+            object.class.as_ref().map(|c| c.name.as_ref()).unwrap_or("")
+        ),
+        ConstValue::String(ref s) => s
+            .chars()
+            .take(SUMMARY_LENGTH + 1)
+            .flat_map(char::escape_debug)
+            .collect(),
+        ConstValue::FBig(ref n) => format!("{:.1$}", n, SUMMARY_LENGTH),
+        _ => object.to_string(),
+    };
+    if summary.chars().count() <= SUMMARY_LENGTH {
+        return summary;
+    }
+    let mut summary: String = summary.chars().take(SUMMARY_LENGTH).collect();
+    summary.push_str("...");
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::Parser;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(options: &str) -> Vec<String> {
+        let instructions: Vec<Instruction> = Parser::new()
+            .parse(
+                r#"
+                @A$methods
+                  DeclareMethod "a" @A#a
+
+                @A
+                  DeclareClass "A"
+                  UseMethod @A#a
+
+                LoadObject @A
+                CallMethod @A#a "call site" 1 1
+                Halt
+
+                @A#a
+                  LoadLocal 0
+                  Return 1
+                "#,
+            )
+            .unwrap()
+            .into();
+
+        let buffer = SharedBuffer::default();
+        let mut vm = VM::new();
+        vm.start_tracing(Tracer::new(options.parse().unwrap(), buffer.clone()));
+        vm.eval::<()>(instructions);
+        vm.stop_tracing().unwrap().finish().unwrap();
+
+        let trace = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        trace.lines().map(String::from).collect()
+    }
+
+    #[test]
+    fn traces_every_instruction() {
+        assert_eq!(
+            trace(""),
+            vec![
+                "0\t<main>\tDeclareMethod(\"a\", 6)\t-",
+                "1\t<main>\tDeclareClass(\"A\")\t-",
+                "2\t<main>\tUseMethod(6)\t-",
+                "3\t<main>\tLoadObject(1)\t-",
+                "4\t<main>\tCallMethod(6, \"call site\", 1, 1)\tA",
+                "6\tA#a\tLoadLocal(0)\tA",
+                "7\tA#a\tReturn(1)\tA",
+                "5\t<main>\tHalt\tA",
+            ]
+        );
+    }
+
+    #[test]
+    fn filters_by_method_and_pc() {
+        assert_eq!(
+            trace("method=a"),
+            vec!["6\tA#a\tLoadLocal(0)\tA", "7\tA#a\tReturn(1)\tA"]
+        );
+        assert_eq!(trace("method=A#a,pc=7"), vec!["7\tA#a\tReturn(1)\tA"]);
+        assert!(trace("method=B#a").is_empty());
+        assert_eq!(
+            trace("pc=3-4"),
+            vec![
+                "3\t<main>\tLoadObject(1)\t-",
+                "4\t<main>\tCallMethod(6, \"call site\", 1, 1)\tA",
+            ]
+        );
+    }
+
+    #[test]
+    fn summarizes_the_top_of_the_stack() {
+        let string = |s: &str| Object {
+            class: None,
+            const_value: ConstValue::String(s.into()),
+        };
+        assert_eq!(summary(&string("tab\there")), "tab\\there");
+
+        let long = summary(&string(&"x".repeat(1000)));
+        assert_eq!(long, format!("{}...", "x".repeat(SUMMARY_LENGTH)));
+    }
+
+    #[test]
+    fn parse_invalid_options() {
+        assert_eq!(
+            "pc=9-3".parse::<TraceOptions>(),
+            Err("invalid value for trace option `pc`: 9-3".into())
+        );
+        assert_eq!(
+            "method".parse::<TraceOptions>(),
+            Err("expected a value for trace option `method`".into())
+        );
+        assert_eq!(
+            "depth=1".parse::<TraceOptions>(),
+            Err("unknown trace option: depth".into())
+        );
+    }
+}
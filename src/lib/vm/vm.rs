@@ -1,11 +1,19 @@
-use crate::bytecode::Instruction;
+use crate::bytecode::{Instruction, SourceLocation, SourceMap};
 use crate::vm::*;
 use crate::*;
+use num_traits::Zero;
+use std::any::TypeId;
+use std::collections::VecDeque;
+use std::io;
+use std::mem::{discriminant, Discriminant};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 pub struct VM {
     stack: Stack<Arc<Object>>,
     call_stack: CallStack,
     program: Vec<Instruction>,
+    source_map: Option<SourceMap>,
     pc: usize,
 
     classes: HashMap<u64, Arc<Class>>,
@@ -13,8 +21,50 @@ pub struct VM {
     variables: HashMap<u64, Arc<Variable>>,
     globals: HashMap<u64, Arc<Object>>,
     declaring_class: u64,
+    inline_caches: InlineCaches,
+    call_stubs: HashMap<u64, usize>,
+
+    // Declared before the constant holder, so that workers, which may use
+    // the constants, are stopped before they are dropped.
+    parallel_workers: usize,
+    workers: Option<WorkerPool>,
+    /// Whether declarations changed since the workers were started, so that
+    /// they are started again before any more work is handed to them.
+    workers_stale: bool,
+    task_queue: Option<Arc<TaskQueue>>,
+    tasks: Vec<Arc<Task>>,
+    worker: bool,
 
     constant_holder: Vec<Arc<Object>>,
+    /// The addresses of the classes this VM marked for boxing constants of
+    /// each kind, and the booleans it marked. The classes are looked up when
+    /// boxing, since holding on to them would keep methods from being added.
+    constant_classes: HashMap<Discriminant<ConstValue>, u64>,
+    booleans: (Option<Arc<Object>>, Option<Arc<Object>>),
+    /// The natives of the runtime the VM last evaluated with, which are
+    /// registered again when it's evaluated with another one.
+    natives: Option<(TypeId, NativeRegistry)>,
+    heap: Heap,
+
+    limits: VMLimits,
+    executed_instructions: u64,
+    deadline: Option<Instant>,
+    lazy_depth: usize,
+    panic_value: Option<String>,
+    panic_origin: PanicOrigin,
+
+    arguments: Vec<String>,
+    exit_code: Option<i32>,
+
+    profiler: Option<Profiler>,
+    recorder: Option<Recorder>,
+    diverged: bool,
+    tracer: Option<Tracer>,
+
+    debugger: Option<Box<dyn Debugger>>,
+    breakpoints: Vec<Breakpoint>,
+    step_mode: StepMode,
+    interrupt: Arc<AtomicBool>,
 }
 
 impl VM {
@@ -23,6 +73,7 @@ impl VM {
             stack: Stack::new(),
             call_stack: CallStack::new(),
             program: Vec::new(),
+            source_map: None,
             pc: 0,
 
             classes: HashMap::new(),
@@ -30,12 +81,55 @@ impl VM {
             variables: HashMap::new(),
             globals: HashMap::new(),
             declaring_class: 0,
+            inline_caches: InlineCaches::new(),
+            call_stubs: HashMap::new(),
+
+            parallel_workers: 0,
+            workers: None,
+            workers_stale: false,
+            task_queue: None,
+            tasks: vec![],
+            worker: false,
 
             constant_holder: vec![],
+            constant_classes: HashMap::new(),
+            booleans: (None, None),
+            natives: None,
+            heap: Heap::new(),
+
+            limits: VMLimits::unlimited(),
+            executed_instructions: 0,
+            deadline: None,
+            lazy_depth: 0,
+            panic_value: None,
+            panic_origin: PanicOrigin::VM,
+
+            arguments: vec![],
+            exit_code: None,
+
+            profiler: None,
+            recorder: None,
+            diverged: false,
+            tracer: None,
+
+            debugger: None,
+            breakpoints: vec![],
+            step_mode: StepMode::Run,
+            interrupt: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Raises a panic on behalf of the program, like natives do when they
+    /// fail, which the program may rescue.
     pub fn panic<T>(&mut self, message: String) -> VMResult<T> {
+        self.panic_origin = PanicOrigin::Program;
+        VMResult::Panic(message, self.call_stack.detach())
+    }
+
+    /// Raises a panic that the program can't rescue, because it's the VM
+    /// ending the evaluation rather than the program failing.
+    fn fatal<T>(&mut self, message: String) -> VMResult<T> {
+        self.panic_origin = PanicOrigin::VM;
         VMResult::Panic(message, self.call_stack.detach())
     }
 
@@ -53,17 +147,572 @@ impl VM {
         println!("{:?}", self.stack);
     }
 
+    pub fn call_stack(&self) -> &CallStack {
+        &self.call_stack
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn instruction_at(&self, pc: usize) -> Option<&Instruction> {
+        self.program.get(pc)
+    }
+
+    /// Sets the source map of the program about to be evaluated, which is
+    /// used to tell where in the source code a panic happened.
+    pub fn set_source_map(&mut self, source_map: SourceMap) {
+        self.source_map = Some(source_map);
+    }
+
+    pub fn source_location(&self, pc: usize) -> Option<SourceLocation> {
+        self.source_map.as_ref()?.lookup(pc)
+    }
+
+    /// The values on the stack belonging to a frame in the call stack, where
+    /// depth 0 is the innermost frame. They are ordered bottom to top, so the
+    /// arguments come first (last argument first), followed by the receiver
+    /// and any temporaries the method has pushed.
+    pub fn locals(&self, depth: usize) -> Vec<Arc<Object>> {
+        let frames = self.call_stack.frames();
+        let stack = self.stack.as_slice();
+        let start = match frames.get(depth) {
+            None => return vec![],
+            Some(frame) => frame.stack_base,
+        };
+        let end = if depth == 0 {
+            stack.len()
+        } else {
+            frames[depth - 1].stack_base.min(stack.len())
+        };
+        stack[start.min(end)..end].to_vec()
+    }
+
+    /// Like `locals`, but named after their role in the frame: `self` for
+    /// the receiver, `$1`, `$2`... for the arguments and `%0`, `%1`... for
+    /// temporaries.
+    pub fn named_locals(&self, depth: usize) -> Vec<(String, Arc<Object>)> {
+        let arity = match self.call_stack.frames().get(depth) {
+            None => return vec![],
+            Some(frame) => frame.method.arity(),
+        };
+        let locals = self.locals(depth);
+        let mut named = vec![];
+
+        if let Some(receiver) = locals.get(arity) {
+            named.push(("self".into(), receiver.clone()));
+        }
+        for (i, argument) in locals.iter().take(arity).enumerate().rev() {
+            named.push((format!("${}", arity - i), argument.clone()));
+        }
+        for (i, temporary) in locals.iter().skip(arity + 1).enumerate() {
+            named.push((format!("%{}", i), temporary.clone()));
+        }
+        named
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    /// Reclaims objects that are only kept alive by reference cycles. This
+    /// also happens automatically as the number of objects grows, but
+    /// embedders can call it when the VM is idle. Returns the number of
+    /// objects reclaimed.
+    ///
+    /// Parallel workers are stopped first, since they would change reference
+    /// counts while the collector counts them. They start again when they're
+    /// needed. Workers themselves leave collecting to the VM that started
+    /// them.
+    pub fn collect_garbage(&mut self) -> usize {
+        if self.worker {
+            return 0;
+        }
+        self.stop_workers();
+        self.heap.collect()
+    }
+
+    /// Tracks an object on the heap. Automatic collections wait until the
+    /// workers are idle, and then also collect what they allocated.
+    fn track(&mut self, object: &Arc<Object>) {
+        if !self.worker && self.heap.is_due() {
+            let idle = match self.task_queue {
+                Some(ref queue) if queue.is_idle() => {
+                    self.heap.adopt(queue.take_allocated());
+                    true
+                }
+                Some(_) => false,
+                None => true,
+            };
+            self.heap.set_paused(!idle);
+        }
+        self.heap.track(object);
+    }
+
+    pub fn set_limits(&mut self, limits: VMLimits) {
+        self.limits = limits;
+    }
+
+    pub fn limits(&self) -> &VMLimits {
+        &self.limits
+    }
+
+    fn check_limits(&mut self) -> VMResult<()> {
+        self.executed_instructions += 1;
+
+        if let Some(ref queue) = self.task_queue {
+            if queue.is_cancelled() {
+                return self.fatal("cancelled".into());
+            }
+        }
+
+        if let Some(max) = self.limits.max_instructions {
+            if self.executed_instructions > max {
+                return self.fatal(format!("instruction budget of {} exceeded", max));
+            }
+        }
+        if let Some(max) = self.limits.max_stack_size {
+            if self.stack.size() > max {
+                return self.fatal(format!("stack size limit of {} exceeded", max));
+            }
+        }
+        if let Some(max) = self.limits.max_call_depth {
+            if self.call_stack.depth() > max {
+                return self.fatal(format!("call depth limit of {} exceeded", max));
+            }
+        }
+        // Reading the clock is relatively slow, so it's only done every once
+        // in a while.
+        if let Some(deadline) = self.deadline {
+            if self.executed_instructions % 1024 == 0 && Instant::now() > deadline {
+                let timeout = self.limits.timeout.unwrap_or_default();
+                return self.fatal(format!("evaluation timed out after {:?}", timeout));
+            }
+        }
+        VMResult::Ok(())
+    }
+
+    /// Starts attributing the instructions executed from now on, and the
+    /// time they take, to the methods on the call stack.
+    pub fn start_profiling(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    /// Stops profiling, and returns what has been profiled since profiling
+    /// was started.
+    pub fn stop_profiling(&mut self) -> Option<Profile> {
+        self.profiler.take().map(Profiler::finish)
+    }
+
+    /// Starts recording every call to a native method, with the objects
+    /// passed to it and what it did. Parallel workers are not used while
+    /// recording, so that the calls are made in the same order every time.
+    pub fn start_recording(&mut self) {
+        self.stop_workers();
+        self.recorder = Some(Recorder::Recording(Recording::new()));
+    }
+
+    /// Stops recording, and returns the calls recorded since recording was
+    /// started.
+    pub fn stop_recording(&mut self) -> Option<Recording> {
+        match self.recorder.take() {
+            Some(Recorder::Recording(recording)) => Some(recording),
+            recorder => {
+                self.recorder = recorder;
+                None
+            }
+        }
+    }
+
+    /// Starts replaying a recording. Instead of being called, every native
+    /// method returns what it returned when it was recorded, so the program
+    /// has none of the effects it had then. The program panics, in a way
+    /// that can't be rescued, as soon as it calls a native method that
+    /// wasn't recorded, or calls it with different objects.
+    pub fn start_replaying(&mut self, recording: Recording) {
+        self.stop_workers();
+        self.recorder = Some(Recorder::Replaying(
+            recording.calls.into_iter().collect(),
+            0,
+        ));
+    }
+
+    /// Stops replaying, and returns how many of the recorded calls the
+    /// program didn't get to make, unless the replay diverged before.
+    pub fn stop_replaying(&mut self) -> Option<usize> {
+        match self.recorder.take() {
+            Some(Recorder::Replaying(calls, _)) => Some(calls.len()),
+            recorder => {
+                self.recorder = recorder;
+                None
+            }
+        }
+    }
+
+    /// Starts writing a line to the tracer for every instruction executed.
+    /// Like while recording, parallel workers are not used while tracing, so
+    /// that the trace shows every instruction in the order it was executed.
+    pub fn start_tracing(&mut self, tracer: Tracer) {
+        self.stop_workers();
+        self.tracer = Some(tracer);
+    }
+
+    /// Stops tracing, and returns the tracer to be finished.
+    pub fn stop_tracing(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    /// Captures the program, its declarations and its globals, along with
+    /// every object reachable from them, so that the VM can be resumed from
+    /// this point later on, without running the program again.
+    pub fn image(&self) -> Image {
+        Image::capture(
+            &self.program,
+            self.source_map.as_ref(),
+            &self.classes,
+            &self.methods,
+            &self.variables,
+            &self.globals,
+            self.declaring_class,
+        )
+    }
+
+    /// Resumes a VM from an image. The VM is set up as it was when the image
+    /// was captured, so instructions that refer to its declarations and
+    /// globals can be evaluated on it.
+    pub fn from_image(image: Image) -> io::Result<VM> {
+        let restored = image.restore()?;
+
+        let mut vm = VM::new();
+        vm.classes = restored.classes;
+        vm.methods = restored.methods;
+        vm.variables = restored.variables;
+        vm.globals = restored.globals;
+        vm.declaring_class = restored.declaring_class;
+        for object in restored.references.iter() {
+            vm.heap.track(object);
+        }
+
+        // The classes constants are boxed with are marked again by running
+        // the instructions that marked them.
+        vm.program = restored.marks;
+        vm.program.push(Instruction::Halt);
+        if let VMResult::Panic(message, _) = vm.do_eval::<()>() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+
+        vm.program = restored.program;
+        vm.source_map = restored.source_map;
+        vm.pc = 0;
+        Ok(vm)
+    }
+
+    /// Inline caches remember how each call site dispatched, so that
+    /// repeated calls to the same classes skip the method lookup. They are
+    /// enabled by default.
+    pub fn set_inline_caches(&mut self, enabled: bool) {
+        self.inline_caches.set_enabled(enabled);
+    }
+
+    pub fn inline_cache_stats(&self) -> InlineCacheStats {
+        self.inline_caches.stats()
+    }
+
+    /// Evaluates independent lazy values on a pool of worker threads, while
+    /// the VM carries on with something else. Only the arguments of native
+    /// methods, which need all of them, are handed to workers, and only
+    /// while the receiver or another argument remains to be evaluated.
+    ///
+    /// The output of a program is the same with or without workers. Zero
+    /// workers, the default, evaluates everything on the calling thread.
+    pub fn set_parallel_workers(&mut self, workers: usize) {
+        self.stop_workers();
+        self.parallel_workers = workers;
+    }
+
+    pub fn parallel_workers(&self) -> usize {
+        self.parallel_workers
+    }
+
+    /// Whether this VM evaluates lazy values ahead of time for another one.
+    /// Such a VM must not have effects, since the value it evaluates might
+    /// never be needed, so native methods that have them panic instead, and
+    /// leave the value to the VM that needs it.
+    pub fn is_worker(&self) -> bool {
+        self.worker
+    }
+
+    /// Sets the arguments that programs read through `Loa/Process`.
+    pub fn set_arguments(&mut self, arguments: Vec<String>) {
+        self.arguments = arguments;
+    }
+
+    pub fn arguments(&self) -> &[String] {
+        &self.arguments
+    }
+
+    /// Ends the evaluation, unwinding like a panic that can't be rescued
+    /// and isn't reported. The code is kept for the host to exit with.
+    pub fn exit<T>(&mut self, code: i32) -> VMResult<T> {
+        self.exit_code = Some(code);
+        self.fatal(format!("exited with code {}", code))
+    }
+
+    /// The code the program exited with, if it ended by exiting during the
+    /// last evaluation.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Classes, methods or globals changed, so whatever was cached about
+    /// them, or handed to workers, is out of date. Declarations usually come
+    /// in bursts, so the workers are only restarted when they are needed
+    /// again.
+    fn declarations_changed(&mut self) {
+        self.inline_caches.invalidate();
+        self.workers_stale = self.workers.is_some();
+    }
+
+    fn stop_workers(&mut self) {
+        self.workers_stale = false;
+        if let Some(workers) = self.workers.take() {
+            // Dropping the pool waits for the workers to stop.
+            drop(workers);
+            if let Some(queue) = self.task_queue.take() {
+                self.heap.adopt(queue.take_allocated());
+            }
+            self.tasks.clear();
+        }
+    }
+
+    fn task_queue(&mut self) -> Option<Arc<TaskQueue>> {
+        if self.workers_stale {
+            self.stop_workers();
+        }
+        if self.task_queue.is_none()
+            && self.parallel_workers > 0
+            && self.recorder.is_none()
+            && self.tracer.is_none()
+        {
+            let workers = WorkerPool::start(
+                self.parallel_workers,
+                Snapshot {
+                    program: self.program.clone(),
+                    classes: self.classes.clone(),
+                    methods: self.methods.clone(),
+                    variables: self.variables.clone(),
+                    globals: self.globals.clone(),
+                    natives: self
+                        .natives
+                        .as_ref()
+                        .map(|(_, natives)| natives.clone())
+                        .unwrap_or_default(),
+                    limits: self.limits.clone(),
+                },
+            );
+            self.task_queue = Some(workers.queue().clone());
+            self.workers = Some(workers);
+        }
+        self.task_queue.clone()
+    }
+
+    /// Hands the unevaluated arguments of a call to a native method over to
+    /// workers, before the receiver is evaluated.
+    fn submit_arguments(&mut self, method_offset: u64) {
+        let arity = match self.methods.get(&method_offset) {
+            Some(method) => match self.program.get(method.offset) {
+                Some(Instruction::CallNative(_)) => method.arity(),
+                _ => return,
+            },
+            None => return,
+        };
+        let receiver_is_lazy = self.stack.top().map(is_unevaluated).unwrap_or(false);
+        let mut arguments: Vec<_> = (1..=arity)
+            .take_while(|i| *i < self.stack.size())
+            .filter_map(|i| self.stack.at(i))
+            .filter(|o| is_unevaluated(o))
+            .cloned()
+            .collect();
+        // The first argument is the next thing to be evaluated after the
+        // receiver, so without a receiver to evaluate in the meantime, this
+        // thread might as well evaluate it itself.
+        if !receiver_is_lazy && !arguments.is_empty() {
+            arguments.remove(0);
+        }
+        if arguments.is_empty() {
+            return;
+        }
+
+        let queue = match self.task_queue() {
+            Some(queue) => queue,
+            None => return,
+        };
+        self.tasks.retain(|task| !task.is_done());
+        for argument in arguments {
+            match queue.submit(argument) {
+                Some(task) => self.tasks.push(task),
+                None => break,
+            }
+        }
+    }
+
+    /// Waits for a worker that is evaluating the object, if any.
+    fn join_task(&mut self, object: &Arc<Object>) {
+        if let Some(index) = self
+            .tasks
+            .iter()
+            .position(|task| Arc::ptr_eq(&task.object, object))
+        {
+            self.tasks.swap_remove(index).join();
+        }
+    }
+
+    /// Evaluates lazy values submitted to a worker pool, until the pool is
+    /// stopped.
+    pub(super) fn run_worker(snapshot: Snapshot, queue: Arc<TaskQueue>) {
+        let mut vm = VM::new();
+        vm.program = snapshot.program;
+        vm.classes = snapshot.classes;
+        vm.methods = snapshot.methods;
+        vm.variables = snapshot.variables;
+        vm.globals = snapshot.globals;
+        // Workers evaluate with the unit runtime, but with the natives of
+        // the runtime that started them.
+        vm.natives = Some((TypeId::of::<()>(), snapshot.natives));
+        vm.limits = snapshot.limits;
+        vm.task_queue = Some(queue.clone());
+        vm.worker = true;
+        // The objects a worker allocates are collected by the VM that
+        // started it, while every worker is idle.
+        vm.heap.set_paused(true);
+
+        while let Some(task) = queue.next() {
+            if task.claim() {
+                vm.executed_instructions = 0;
+                vm.deadline = vm.limits.timeout.map(|timeout| Instant::now() + timeout);
+                // A lazy value that fails to evaluate is left unevaluated,
+                // for the thread that needs it to fail on.
+                if let VMResult::Panic(_, _) = vm.eval_lazy::<()>(task.object.clone()) {
+                    vm.stack = Stack::new();
+                    vm.call_stack = CallStack::new();
+                    vm.lazy_depth = 0;
+                    vm.pc = 0;
+                }
+                task.finish();
+            }
+            drop(task);
+            queue.done(vm.heap.take_objects());
+        }
+    }
+
+    pub fn attach_debugger<D: Debugger + 'static>(&mut self, debugger: D) {
+        self.debugger = Some(Box::new(debugger));
+    }
+
+    pub fn detach_debugger(&mut self) -> Option<Box<dyn Debugger>> {
+        self.step_mode = StepMode::Run;
+        self.debugger.take()
+    }
+
+    /// Requests that an attached debugger is given control before the next
+    /// instruction is executed.
+    pub fn pause(&mut self) {
+        self.step_mode = StepMode::Pause;
+    }
+
+    /// A flag that can be set from another thread to pause the VM at the
+    /// next instruction, as if `pause` had been called. It only has an effect
+    /// while a debugger is attached.
+    pub fn interrupt_handle(&self) -> Arc<AtomicBool> {
+        self.interrupt.clone()
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) {
+        self.breakpoints.retain(|b| b != breakpoint);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> &Vec<Breakpoint> {
+        &self.breakpoints
+    }
+
+    fn pause_reason(&self) -> Option<PauseReason> {
+        if self.interrupt.swap(false, Ordering::SeqCst) {
+            return Some(PauseReason::Pause);
+        }
+
+        match self.step_mode {
+            StepMode::Pause => return Some(PauseReason::Pause),
+            StepMode::Instruction => return Some(PauseReason::Step),
+            StepMode::Over(depth) if self.call_stack.depth() <= depth => {
+                return Some(PauseReason::Step)
+            }
+            StepMode::Out(depth) if self.call_stack.depth() < depth => {
+                return Some(PauseReason::Step)
+            }
+            _ => {}
+        }
+
+        if let Instruction::CallMethod(_, ref uri, line, _)
+        | Instruction::TailCallMethod(_, ref uri, line, _) = self.program[self.pc]
+        {
+            for breakpoint in self.breakpoints.iter() {
+                if breakpoint.matches(uri, line) {
+                    return Some(PauseReason::Breakpoint(breakpoint.clone()));
+                }
+            }
+        }
+
+        None
+    }
+
+    fn debug(&mut self) -> VMResult<()> {
+        let reason = match self.pause_reason() {
+            None => return VMResult::Ok(()),
+            Some(reason) => reason,
+        };
+        let mut debugger = match self.debugger.take() {
+            None => return VMResult::Ok(()),
+            Some(d) => d,
+        };
+        let command = debugger.paused(self, reason);
+        self.debugger = Some(debugger);
+
+        let depth = self.call_stack.depth();
+        self.step_mode = match command {
+            DebugCommand::Continue => StepMode::Run,
+            DebugCommand::StepInstruction => StepMode::Instruction,
+            DebugCommand::StepOver => StepMode::Over(depth),
+            DebugCommand::StepOut => StepMode::Out(depth),
+            DebugCommand::Terminate => {
+                self.step_mode = StepMode::Run;
+                return self.fatal("terminated by debugger".into());
+            }
+        };
+        VMResult::Ok(())
+    }
+
     #[inline]
-    fn raw_class_ptr(&mut self, address: u64) -> VMResult<*const Class> {
-        VMResult::Ok(
-            expect!(
-                self,
-                self.classes.get(&address),
-                "no class found at {:X}",
-                address
-            )
-            .as_ref() as *const _,
+    /// Marks the class constants of the kind of `kind` are boxed with.
+    fn mark_class(&mut self, address: u64, kind: ConstValue) -> VMResult<*const Class> {
+        let class = expect!(
+            self,
+            self.classes.get(&address),
+            "no class found at {:X}",
+            address
         )
+        .as_ref() as *const _;
+        self.constant_classes.insert(discriminant(&kind), address);
+        VMResult::Ok(class)
     }
 
     #[inline]
@@ -72,8 +721,43 @@ impl VM {
         self.pc += 1;
     }
 
+    fn register_natives<M: Runtime>(&mut self) {
+        let runtime = TypeId::of::<M>();
+        if let Some((registered, _)) = self.natives {
+            if registered == runtime {
+                return;
+            }
+        }
+        let mut natives = NativeRegistry::new();
+        M::register_natives(&mut natives);
+        self.natives = Some((runtime, natives));
+        // Workers were started with the natives of the previous runtime.
+        self.stop_workers();
+    }
+
     fn do_eval<M: Runtime>(&mut self) -> VMResult<()> {
+        self.register_natives::<M>();
+
         loop {
+            unwrap!(self, self.check_limits());
+
+            if let Some(ref mut profiler) = self.profiler {
+                profiler.sample(&self.call_stack);
+            }
+
+            if let Some(ref mut tracer) = self.tracer {
+                tracer.trace(
+                    self.pc,
+                    &self.program[self.pc],
+                    self.stack.top(),
+                    self.call_stack.top(),
+                );
+            }
+
+            if self.debugger.is_some() {
+                unwrap!(self, self.debug());
+            }
+
             match self.program[self.pc] {
                 Instruction::Noop => {
                     self.pc += 1;
@@ -84,17 +768,15 @@ impl VM {
                 }
 
                 Instruction::Panic => {
-                    return VMResult::Panic(
-                        format!(
-                            "{:?}",
-                            self.stack
-                                .pop()
-                                .as_ref()
-                                .map(ToString::to_string)
-                                .unwrap_or(String::new())
-                        ),
-                        self.call_stack.detach(),
-                    )
+                    let value = self
+                        .stack
+                        .pop()
+                        .as_ref()
+                        .map(ToString::to_string)
+                        .unwrap_or(String::new());
+                    let message = format!("{:?}", value);
+                    self.panic_value = Some(value);
+                    return self.panic(message);
                 }
 
                 Instruction::DumpStack => {
@@ -106,6 +788,7 @@ impl VM {
                     let class = Class::new(name.clone(), self.pc);
                     let class_id = self.pc as u64;
                     self.classes.insert(class_id, class);
+                    self.declarations_changed();
                     self.declaring_class = class_id;
                     self.pc += 1;
                 }
@@ -119,9 +802,11 @@ impl VM {
                         setter_id,
                     });
                     self.variables.insert(id, variable);
+                    self.declarations_changed();
                 }
 
                 Instruction::UseVariable(id) => {
+                    self.declarations_changed();
                     let variable =
                         expect!(self, self.variables.get(&id), "cannot use unknown variable");
                     let class = expect!(
@@ -146,10 +831,12 @@ impl VM {
                         offset: offset as usize,
                     });
                     self.methods.insert(offset, method);
+                    self.declarations_changed();
                     self.pc += 1;
                 }
 
                 Instruction::UseMethod(offset) => {
+                    self.declarations_changed();
                     let method =
                         expect!(self, self.methods.get(&offset), "cannot use unknown method");
                     let class = expect!(
@@ -163,6 +850,7 @@ impl VM {
                 }
 
                 Instruction::OverrideMethod(source_offset, target_offset) => {
+                    self.declarations_changed();
                     let method = expect!(
                         self,
                         self.methods.get(&target_offset),
@@ -186,14 +874,22 @@ impl VM {
                 }
 
                 // TODO: Optimize this so Instruction doesn't have to be cloned
-                ref i @ Instruction::CallMethod(_, _, _, _) => {
-                    if let Instruction::CallMethod(ref offset, ref uri, line, character) = i.clone()
+                ref i @ Instruction::CallMethod(_, _, _, _)
+                | ref i @ Instruction::TailCallMethod(_, _, _, _) => {
+                    let tail = match i {
+                        Instruction::TailCallMethod(_, _, _, _) => true,
+                        _ => false,
+                    };
+                    if let Instruction::CallMethod(ref offset, ref uri, line, character)
+                    | Instruction::TailCallMethod(ref offset, ref uri, line, character) =
+                        i.clone()
                     {
+                        if self.task_queue.is_some() || self.parallel_workers > 0 {
+                            self.submit_arguments(*offset);
+                        }
+
                         let top = expect!(self, self.stack.top(), "empty stack").clone();
-                        let receiver = match self.eval_lazy::<M>(top) {
-                            None => continue,
-                            Some(r) => r,
-                        };
+                        let receiver = unwrap!(self, self.eval_lazy::<M>(top));
 
                         let class = expect!(
                             self,
@@ -201,53 +897,101 @@ impl VM {
                             "cannot call method on object without class"
                         );
 
-                        if let Some(var) = class.variable_getters.get(offset) {
-                            let receiver = unwrap!(self, self.pop_eval::<M>());
-                            let value = expect!(
-                                self,
-                                receiver.get_variable(var),
-                                "{} has no value in variable {}",
-                                receiver,
-                                var.name
-                            );
-                            self.push(value);
-                            self.pc += 1;
-                            continue;
-                        }
+                        let dispatch = match self.inline_caches.lookup(self.pc, class) {
+                            Some(dispatch) => dispatch,
+                            None => {
+                                let dispatch = if let Some(var) = class.variable_getters.get(offset)
+                                {
+                                    Dispatch::Getter(var.clone())
+                                } else if let Some(var) = class.variable_setters.get(offset) {
+                                    Dispatch::Setter(var.clone())
+                                } else {
+                                    Dispatch::Method(
+                                        expect!(
+                                            self,
+                                            class.methods.get(offset),
+                                            "message #{} not understood by {}",
+                                            self.methods
+                                                .get(offset)
+                                                .map(|m| &m.name)
+                                                .unwrap_or(&"?".into()),
+                                            receiver
+                                        )
+                                        .clone(),
+                                    )
+                                };
+                                self.inline_caches.insert(self.pc, class, dispatch.clone());
+                                dispatch
+                            }
+                        };
 
-                        if let Some(var) = class.variable_setters.get(offset) {
-                            let receiver = unwrap!(self, self.pop());
-                            let value = unwrap!(self, self.pop());
-                            self.push(receiver.set_variable(var, value));
-                            self.pc += 1;
-                            continue;
+                        let method = match dispatch {
+                            Dispatch::Getter(var) => {
+                                let receiver = unwrap!(self, self.pop_eval::<M>());
+                                let value = expect!(
+                                    self,
+                                    receiver.get_variable(&var),
+                                    "{} has no value in variable {}",
+                                    receiver,
+                                    var.name
+                                );
+                                self.push(value);
+                                self.pc += 1;
+                                continue;
+                            }
+                            Dispatch::Setter(var) => {
+                                let receiver = unwrap!(self, self.pop());
+                                let value = unwrap!(self, self.pop());
+                                let object = receiver.set_variable(&var, value);
+                                self.track(&object);
+                                self.push(object);
+                                self.pc += 1;
+                                continue;
+                            }
+                            Dispatch::Method(method) => method,
+                        };
+                        let mut return_address = self.pc + 1;
+                        let mut stack_base = self.stack.size().saturating_sub(method.arity() + 1);
+
+                        // A call in tail position replaces the current frame,
+                        // whose receiver and arguments are no longer needed,
+                        // and returns straight to its caller.
+                        if tail {
+                            let frame = expect!(self, self.call_stack.top(), "empty call stack");
+                            let base = frame.stack_base;
+                            self.stack.remove(base, stack_base);
+                            stack_base = base;
+                            return_address =
+                                expect!(self, self.call_stack.ret(), "empty call stack");
                         }
 
-                        let method = expect!(
-                            self,
-                            class.methods.get(offset),
-                            "message #{} not understood by {}",
-                            self.methods
-                                .get(offset)
-                                .map(|m| &m.name)
-                                .unwrap_or(&"?".into()),
-                            receiver
-                        )
-                        .clone();
-                        let return_address = self.pc + 1;
                         self.pc = method.offset;
                         self.call_stack.push(
                             receiver,
                             method,
                             return_address,
                             SourceCodeLocation(uri.clone(), line, character),
+                            stack_base,
                         );
                     }
                 }
 
                 Instruction::CallNative(ref method) => {
-                    let method = method.clone();
-                    unwrap!(self, M::call(self, method));
+                    let native = expect!(
+                        self,
+                        self.natives
+                            .as_ref()
+                            .and_then(|(_, natives)| natives.get(method.name()))
+                            .cloned(),
+                        "unknown native method: {}",
+                        method
+                    );
+                    if self.recorder.is_some() {
+                        let method = method.clone();
+                        unwrap!(self, self.call_recorded_native::<M>(method, native));
+                    } else {
+                        unwrap!(self, native(self));
+                    }
                     self.pc += 1;
                 }
 
@@ -272,6 +1016,7 @@ impl VM {
                         offset,
                         expect!(self, self.stack.pop(), "nothing on stack to store"),
                     );
+                    self.declarations_changed();
                     self.pc += 1;
                 }
 
@@ -285,7 +1030,9 @@ impl VM {
                     for _ in 0..arity {
                         dependencies.push(unwrap!(self, self.pop()));
                     }
-                    self.push(Object::lazy(offset, self.call_stack.clone(), dependencies));
+                    let object = Object::lazy(offset, self.call_stack.clone(), dependencies);
+                    self.track(&object);
+                    self.push(object);
                     self.pc += 1;
                 }
 
@@ -311,90 +1058,144 @@ impl VM {
                     break;
                 }
 
+                Instruction::Rescue(arity, clause) => {
+                    let mut captured = vec![];
+                    for _ in 0..arity {
+                        captured.push(unwrap!(self, self.pop()));
+                    }
+                    let expression = unwrap!(self, self.pop());
+
+                    let pc = self.pc;
+                    let stack_size = self.stack.size();
+                    let call_stack = self.call_stack.clone();
+                    let lazy_depth = self.lazy_depth;
+                    self.panic_value = None;
+
+                    let value = match self.eval_lazy::<M>(expression) {
+                        VMResult::Ok(value) => value,
+                        // Only panics of the program itself are recovered
+                        // from, not the VM ending the evaluation. Neither is
+                        // one on a worker, which might come from an effect
+                        // that only the thread needing the value may have.
+                        VMResult::Panic(message, call_stack)
+                            if self.panic_origin != PanicOrigin::Program || self.worker =>
+                        {
+                            return VMResult::Panic(message, call_stack)
+                        }
+                        VMResult::Panic(message, _) => {
+                            // Whatever the expression left behind when it
+                            // panicked is thrown away, as if it had never been
+                            // evaluated.
+                            let size = self.stack.size();
+                            self.stack.remove(stack_size.min(size), size);
+                            self.call_stack = call_stack;
+                            self.lazy_depth = lazy_depth;
+                            self.pc = pc;
+
+                            let message = self.panic_value.take().unwrap_or(message);
+                            captured.insert(0, Object::box_string(message));
+                            let clause = Object::lazy(clause, self.call_stack.clone(), captured);
+                            self.track(&clause);
+                            unwrap!(self, self.eval_lazy::<M>(clause))
+                        }
+                    };
+                    self.push(value);
+                    self.pc += 1;
+                }
+
                 Instruction::MarkClassTrue(id) => unsafe {
                     let true_class = expect!(self, self.classes.get(&id), "True not loaded");
                     self.constant_holder.push(Object::new(true_class));
+                    self.booleans.0 = self.constant_holder.last().cloned();
                     TRUE = self.constant_holder.last().unwrap() as *const _;
                     self.pc += 1;
                 },
                 Instruction::MarkClassFalse(id) => unsafe {
                     let false_class = expect!(self, self.classes.get(&id), "False not loaded");
                     self.constant_holder.push(Object::new(false_class));
+                    self.booleans.1 = self.constant_holder.last().cloned();
                     FALSE = self.constant_holder.last().unwrap() as *const _;
                     self.pc += 1;
                 },
 
                 Instruction::MarkClassString(id) => unsafe {
-                    STRING_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    STRING_CLASS =
+                        unwrap!(self, self.mark_class(id, ConstValue::String(String::new())));
                     self.pc += 1;
                 },
                 Instruction::MarkClassCharacter(id) => unsafe {
-                    CHARACTER_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    CHARACTER_CLASS = unwrap!(self, self.mark_class(id, ConstValue::Character(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassSymbol(id) => unsafe {
-                    SYMBOL_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    SYMBOL_CLASS =
+                        unwrap!(self, self.mark_class(id, ConstValue::Symbol(String::new())));
                     self.pc += 1;
                 },
 
                 Instruction::MarkClassU8(id) => unsafe {
-                    U8_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    U8_CLASS = unwrap!(self, self.mark_class(id, ConstValue::U8(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassU16(id) => unsafe {
-                    U16_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    U16_CLASS = unwrap!(self, self.mark_class(id, ConstValue::U16(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassU32(id) => unsafe {
-                    U32_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    U32_CLASS = unwrap!(self, self.mark_class(id, ConstValue::U32(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassU64(id) => unsafe {
-                    U64_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    U64_CLASS = unwrap!(self, self.mark_class(id, ConstValue::U64(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassU128(id) => unsafe {
-                    U128_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    U128_CLASS = unwrap!(self, self.mark_class(id, ConstValue::U128(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassUBig(id) => unsafe {
-                    UBIG_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    UBIG_CLASS =
+                        unwrap!(self, self.mark_class(id, ConstValue::UBig(BigUint::zero())));
                     self.pc += 1;
                 },
                 Instruction::MarkClassI8(id) => unsafe {
-                    I8_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    I8_CLASS = unwrap!(self, self.mark_class(id, ConstValue::I8(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassI16(id) => unsafe {
-                    I16_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    I16_CLASS = unwrap!(self, self.mark_class(id, ConstValue::I16(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassI32(id) => unsafe {
-                    I32_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    I32_CLASS = unwrap!(self, self.mark_class(id, ConstValue::I32(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassI64(id) => unsafe {
-                    I64_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    I64_CLASS = unwrap!(self, self.mark_class(id, ConstValue::I64(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassI128(id) => unsafe {
-                    I128_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    I128_CLASS = unwrap!(self, self.mark_class(id, ConstValue::I128(0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassIBig(id) => unsafe {
-                    IBIG_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    IBIG_CLASS =
+                        unwrap!(self, self.mark_class(id, ConstValue::IBig(BigInt::zero())));
                     self.pc += 1;
                 },
                 Instruction::MarkClassF32(id) => unsafe {
-                    F32_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    F32_CLASS = unwrap!(self, self.mark_class(id, ConstValue::F32(0.0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassF64(id) => unsafe {
-                    F64_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    F64_CLASS = unwrap!(self, self.mark_class(id, ConstValue::F64(0.0)));
                     self.pc += 1;
                 },
                 Instruction::MarkClassFBig(id) => unsafe {
-                    FBIG_CLASS = unwrap!(self, self.raw_class_ptr(id));
+                    FBIG_CLASS = unwrap!(
+                        self,
+                        self.mark_class(id, ConstValue::FBig(BigFraction::zero()))
+                    );
                     self.pc += 1;
                 },
 
@@ -476,50 +1277,280 @@ impl VM {
     }
 
     #[inline]
-    fn eval_lazy<M: Runtime>(&mut self, mut object: Arc<Object>) -> Option<Arc<Object>> {
-        loop {
-            match object.const_value {
-                ConstValue::Lazy(offset, ref call_stack, ref dependencies) => {
-                    for dep in dependencies.iter().cloned().rev() {
+    fn eval_lazy<M: Runtime>(&mut self, mut object: Arc<Object>) -> VMResult<Arc<Object>> {
+        // Every lazy object passed through on the way to the value is
+        // resolved, so that chains of lazy objects are only walked once.
+        let mut evaluated = vec![];
+        while let ConstValue::Lazy(offset, ref thunk) = object.const_value {
+            let thunk = thunk.clone();
+            if !self.tasks.is_empty() {
+                self.join_task(&object);
+            }
+
+            let result = match thunk.captured() {
+                None => expect!(self, thunk.value(), "failed to eval lazy"),
+                Some((call_stack, dependencies)) => {
+                    if let Some(max) = self.limits.max_lazy_depth {
+                        if self.lazy_depth >= max {
+                            let message =
+                                format!("lazy evaluation depth limit of {} exceeded", max);
+                            return self.fatal(message);
+                        }
+                    }
+
+                    for dep in dependencies.into_iter().rev() {
                         self.push(dep);
                     }
                     let return_offset = self.pc;
-                    let call_stack = std::mem::replace(&mut self.call_stack, call_stack.clone());
+                    let call_stack = std::mem::replace(&mut self.call_stack, call_stack);
 
                     self.pc = offset as usize;
-                    self.do_eval::<M>().report::<M>()?;
-                    let result = self.pop().report::<M>()?;
+                    self.lazy_depth += 1;
+                    let result = self.do_eval::<M>();
+                    self.lazy_depth -= 1;
+                    unwrap!(self, result);
+                    let result = unwrap!(self, self.pop());
 
                     self.pc = return_offset;
                     self.call_stack = call_stack;
+                    result
+                }
+            };
+
+            evaluated.push(thunk);
+            object = result;
+        }
+
+        for thunk in evaluated {
+            thunk.resolve(object.clone());
+        }
+        VMResult::Ok(object)
+    }
 
-                    object = result;
+    /// Calls a native method while recording or replaying. The receiver and
+    /// arguments are evaluated up front, which the native method would do
+    /// anyway, so that they can be recorded, or compared with the recording.
+    fn call_recorded_native<M: Runtime>(
+        &mut self,
+        method: NativeMethod,
+        native: NativeFn,
+    ) -> VMResult<()> {
+        let selector = method.name().rsplit('#').next().unwrap_or("");
+        let mut objects = vec![];
+        for _ in 0..=selector_arity(selector) {
+            objects.push(unwrap!(self, self.pop_eval::<M>()));
+        }
+        let inputs = objects.iter().map(|o| RecordedValue::of(o)).collect();
+        let mut call = NativeCall {
+            method: method.name().into(),
+            inputs,
+            outcome: Outcome::Panicked(String::new()),
+        };
+
+        let recorded = match self.recorder {
+            Some(Recorder::Replaying(ref mut calls, ref mut replayed)) => {
+                *replayed += 1;
+                Some((calls.pop_front(), *replayed))
+            }
+            _ => None,
+        };
+        let (recorded, index) = match recorded {
+            None => {
+                for object in objects.into_iter().rev() {
+                    self.push(object);
+                }
+                let result = native(self);
+                call.outcome = match result {
+                    VMResult::Ok(()) => {
+                        Outcome::Returned(RecordedValue::of(unwrap!(self, self.top())))
+                    }
+                    VMResult::Panic(ref message, _) => match self.exit_code {
+                        Some(code) => Outcome::Exited(code),
+                        None => Outcome::Panicked(message.clone()),
+                    },
+                };
+                if let Some(Recorder::Recording(ref mut recording)) = self.recorder {
+                    recording.calls.push(call);
                 }
-                _ => return Some(object),
+                return result;
+            }
+            Some((None, index)) => {
+                let message = format!("the program called {}, which wasn't recorded", call);
+                return self.diverge(index, message);
             }
+            Some((Some(recorded), index)) => (recorded, index),
+        };
+
+        if recorded.method != call.method || recorded.inputs != call.inputs {
+            let message = format!("the program called {}, but {} was recorded", call, recorded);
+            return self.diverge(index, message);
+        }
+        match recorded.outcome {
+            Outcome::Returned(value) => match value.revive(self) {
+                Some(object) => self.push(object),
+                // What can't be revived is returned by calling the method
+                // again, as long as that returns the same.
+                None => {
+                    for object in objects.into_iter().rev() {
+                        self.push(object);
+                    }
+                    unwrap!(self, native(self));
+                    let actual = RecordedValue::of(unwrap!(self, self.top()));
+                    if actual != value {
+                        let message =
+                            format!("{} returned {}, but {} was recorded", call, actual, value);
+                        return self.diverge(index, message);
+                    }
+                }
+            },
+            Outcome::Panicked(message) => return self.panic(message),
+            Outcome::Exited(code) => return self.exit(code),
         }
+        VMResult::Ok(())
     }
 
-    fn eval_catch<M: Runtime>(&mut self, instructions: Vec<Instruction>) -> bool {
+    /// Ends a replay that no longer matches its recording, with a panic that
+    /// can't be rescued.
+    fn diverge<T>(&mut self, index: usize, message: String) -> VMResult<T> {
+        self.recorder = None;
+        self.diverged = true;
+        self.fatal(format!(
+            "replay diverged at native call {}: {}",
+            index, message
+        ))
+    }
+
+    /// Appends instructions to the program, and prepares to evaluate them
+    /// within a fresh set of limits.
+    fn load(&mut self, instructions: Vec<Instruction>) {
         self.pc = self.program.len();
         self.program.extend(instructions);
-        self.do_eval::<M>().report::<M>().is_none()
+        self.exit_code = None;
+        self.diverged = false;
+        self.executed_instructions = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    /// Like `VMResult::report`, but passes on where the panic happened if
+    /// the program has a source map.
+    fn report<M: Runtime, T>(&self, result: VMResult<T>) -> Option<T> {
+        match (result, self.source_location(self.pc)) {
+            (VMResult::Panic(_, _), _) if self.exit_code.is_some() => None,
+            (VMResult::Panic(message, call_stack), Some(location)) => {
+                M::print_panic_at(message, call_stack, location);
+                None
+            }
+            (result, _) => result.report::<M>(),
+        }
+    }
+
+    fn eval_catch<M: Runtime>(&mut self, instructions: Vec<Instruction>) -> bool {
+        self.load(instructions);
+        let result = self.do_eval::<M>();
+        self.report::<M, _>(result).is_none()
+    }
+
+    /// Like `eval`, but hands panics back to the caller instead of reporting
+    /// them through the runtime.
+    pub fn run<M: Runtime>(&mut self, instructions: Vec<Instruction>) -> VMResult<()> {
+        self.load(instructions);
+        let result = self.do_eval::<M>();
+        self.stop_workers();
+        result
+    }
+
+    /// The class with the qualified name, if it has been declared.
+    pub fn class(&self, qualified_name: &str) -> Option<&Arc<Class>> {
+        self.classes
+            .values()
+            .find(|class| class.name == qualified_name)
+    }
+
+    /// Boxes a constant with the class this VM marked for its kind, if it
+    /// has marked one.
+    pub fn box_const(&self, value: ConstValue) -> Option<Arc<Object>> {
+        let address = self.constant_classes.get(&discriminant(&value))?;
+        let class = self.classes.get(address)?;
+        Some(Arc::new(Object {
+            class: Some(class.clone()),
+            const_value: value,
+        }))
+    }
+
+    /// The boolean object this VM marked for the value, if any.
+    pub fn box_bool(&self, value: bool) -> Option<Arc<Object>> {
+        if value {
+            self.booleans.0.clone()
+        } else {
+            self.booleans.1.clone()
+        }
+    }
+
+    /// Sends a message to the receiver, as if from a call site in the
+    /// program, and evaluates the result. The selector is the id of a
+    /// method or variable, as returned by `Class::selector`.
+    pub fn send<M: Runtime>(
+        &mut self,
+        receiver: Arc<Object>,
+        selector: u64,
+        arguments: Vec<Arc<Object>>,
+    ) -> VMResult<Arc<Object>> {
+        // Every selector gets a call site of its own, which is reused for
+        // every message sent by it.
+        let stub = match self.call_stubs.get(&selector) {
+            Some(stub) => *stub,
+            None => {
+                let stub = self.program.len();
+                self.program
+                    .push(Instruction::CallMethod(selector, "<host>".into(), 0, 0));
+                self.program.push(Instruction::Halt);
+                self.call_stubs.insert(selector, stub);
+                stub
+            }
+        };
+
+        let stack_size = self.stack.size();
+        for argument in arguments.into_iter().rev() {
+            self.push(argument);
+        }
+        self.push(receiver);
+
+        self.pc = stub;
+        self.executed_instructions = 0;
+        self.exit_code = None;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+        let result = match self.do_eval::<M>() {
+            VMResult::Ok(()) => self.pop_eval::<M>(),
+            VMResult::Panic(message, call_stack) => VMResult::Panic(message, call_stack),
+        };
+        if let VMResult::Panic(_, _) = result {
+            let size = self.stack.size();
+            self.stack.remove(stack_size.min(size), size);
+            self.call_stack = CallStack::new();
+            self.lazy_depth = 0;
+        }
+        self.stop_workers();
+        result
     }
 
     pub fn eval<M: Runtime>(&mut self, instructions: Vec<Instruction>) {
         self.eval_catch::<M>(instructions);
+        self.stop_workers();
     }
 
     pub fn eval_pop<M: Runtime>(&mut self, instructions: Vec<Instruction>) -> Option<Arc<Object>> {
-        if self.eval_catch::<M>(instructions) {
+        let result = if self.eval_catch::<M>(instructions) {
             None
         } else {
             let result = self.stack.pop();
             if self.stack.size() > 0 {
                 self.log_stack()
             }
-            self.eval_lazy::<M>(result?)
-        }
+            let result = self.eval_lazy::<M>(result?);
+            self.report::<M, _>(result)
+        };
+        self.stop_workers();
+        result
     }
 
     pub fn pop(&mut self) -> VMResult<Arc<Object>> {
@@ -528,7 +1559,7 @@ impl VM {
 
     pub fn pop_eval<M: Runtime>(&mut self) -> VMResult<Arc<Object>> {
         let o = unwrap!(self, self.pop());
-        let o = expect!(self, self.eval_lazy::<M>(o), "failed to eval lazy");
+        let o = unwrap!(self, self.eval_lazy::<M>(o));
         VMResult::Ok(o)
     }
 
@@ -546,15 +1577,43 @@ impl VM {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::assembly::*;
-    use crate::bytecode::{BytecodeEncoding, Instruction as BytecodeInstruction};
+enum Recorder {
+    Recording(Recording),
 
-    fn assert_evaluates(input: &str) {
-        let assembly = Parser::new().parse(input).unwrap();
-        let mut vm = VM::new();
+    /// The calls left to replay, and how many have been replayed so far.
+    Replaying(VecDeque<NativeCall>, usize),
+}
+
+/// What raised the last panic, which tells whether `Rescue` may recover from
+/// it.
+#[derive(Clone, Copy, PartialEq)]
+enum PanicOrigin {
+    /// A `Panic` instruction, or a native method failing.
+    Program,
+
+    /// The VM, because a limit was reached, the evaluation was cancelled or
+    /// exited, or the program is malformed.
+    VM,
+}
+
+fn is_unevaluated(object: &Arc<Object>) -> bool {
+    match object.const_value {
+        ConstValue::Lazy(_, ref thunk) => thunk.value().is_none(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::*;
+    use crate::bytecode::{BytecodeEncoding, Instruction as BytecodeInstruction};
+    use crate::optimization::Optimizable;
+    use crate::vm::Object;
+
+    fn assert_evaluates(input: &str) {
+        let assembly = Parser::new().parse(input).unwrap();
+        let mut vm = VM::new();
         let instructions: Vec<BytecodeInstruction> = assembly.into();
         vm.eval::<()>(instructions.rotate().unwrap());
     }
@@ -633,6 +1692,186 @@ mod tests {
         );
     }
 
+    #[test]
+    fn polymorphic_call_site() {
+        let assembly = Parser::new()
+            .parse(
+                r#"
+                @A$methods
+                    DeclareMethod "a" @A#a
+
+                @B$methods
+                    DeclareMethod "a" @B#a
+
+                @Main$methods
+                    DeclareMethod "of:" @Main#of:
+
+                @A
+                    DeclareClass "A"
+                    UseMethod @A#a
+
+                @B
+                    DeclareClass "B"
+                    OverrideMethod @A#a @B#a
+
+                @Main
+                    DeclareClass "Main"
+                    UseMethod @Main#of:
+
+                LoadObject @A
+                LoadObject @Main
+                CallMethod @Main#of: "call site" 1 1
+                DropLocal 0
+                LoadObject @B
+                LoadObject @Main
+                CallMethod @Main#of: "call site" 2 1
+                DropLocal 0
+                LoadObject @A
+                LoadObject @Main
+                CallMethod @Main#of: "call site" 3 1
+                Halt
+
+                @Main#of:
+                    LoadLocal 1
+                    CallMethod @A#a "call site" 4 1
+                    Return 2
+
+                @A#a
+                    LoadLocal 0
+                    Return 1
+
+                @B#a
+                    LoadLocal 0
+                    Return 1
+                "#,
+            )
+            .unwrap();
+
+        let mut vm = VM::new();
+        let result = vm.eval_pop::<()>(assembly.clone().into()).unwrap();
+        assert_eq!(result.to_string(), "A");
+        assert_eq!(
+            vm.inline_cache_stats(),
+            InlineCacheStats { hits: 1, misses: 5 }
+        );
+
+        let mut vm = VM::new();
+        vm.set_inline_caches(false);
+        let result = vm.eval_pop::<()>(assembly.into()).unwrap();
+        assert_eq!(result.to_string(), "A");
+        assert_eq!(vm.inline_cache_stats(), InlineCacheStats::default());
+    }
+
+    #[test]
+    fn parallel_arguments() {
+        let assembly = Parser::new()
+            .parse(
+                r#"
+                @UInt8$methods
+                    DeclareMethod "+" @UInt8#+
+
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+                    UseMethod @UInt8#+
+
+                LoadLazy 0 @right
+                LoadLazy 0 @left
+                CallMethod @UInt8#+ "call site" 1 1
+                Halt
+
+                @UInt8#+
+                    CallNative Loa/Number#+
+                    Return 0
+
+                @left
+                    LoadConstU8 1
+                    LoadConstU8 2
+                    CallMethod @UInt8#+ "left" 1 1
+                    ReturnLazy 0
+
+                @right
+                    LoadConstU8 3
+                    LoadConstU8 4
+                    CallMethod @UInt8#+ "right" 1 1
+                    ReturnLazy 0
+                "#,
+            )
+            .unwrap();
+
+        for workers in 0..3 {
+            let mut vm = VM::new();
+            vm.set_parallel_workers(workers);
+            let result = vm.eval_pop::<()>(assembly.clone().into()).unwrap();
+            assert_eq!(result.to_string(), "10");
+        }
+    }
+
+    struct CollectingRuntime;
+
+    impl Runtime for CollectingRuntime {
+        fn print_panic(message: String, call_stack: CallStack) {
+            <() as Runtime>::print_panic(message, call_stack)
+        }
+
+        fn register_natives(natives: &mut NativeRegistry) {
+            Self::register_standard_natives(natives);
+            natives.register("Host/Heap#collect", |vm: &mut VM| {
+                vm.collect_garbage();
+                vm.push(Object::box_u8(1));
+                VMResult::Ok(())
+            });
+            natives.register("Host/Slow#get", |vm: &mut VM| {
+                std::thread::sleep(std::time::Duration::from_millis(50));
+                vm.push(Object::box_u8(2));
+                VMResult::Ok(())
+            });
+        }
+    }
+
+    #[test]
+    fn collects_garbage_while_workers_evaluate() {
+        let assembly = Parser::new()
+            .parse(
+                r#"
+                @UInt8$methods
+                    DeclareMethod "+" @UInt8#+
+
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+                    UseMethod @UInt8#+
+
+                LoadLazy 0 @right
+                LoadLazy 0 @left
+                CallMethod @UInt8#+ "call site" 1 1
+                Halt
+
+                @UInt8#+
+                    CallNative Loa/Number#+
+                    Return 0
+
+                @left
+                    CallNative Host/Heap#collect
+                    ReturnLazy 0
+
+                @right
+                    CallNative Host/Slow#get
+                    ReturnLazy 0
+                "#,
+            )
+            .unwrap();
+
+        // The right operand is still being evaluated on a worker when the
+        // left one collects garbage.
+        let mut vm = VM::new();
+        vm.set_parallel_workers(2);
+        let result = vm.eval_pop::<CollectingRuntime>(assembly.into()).unwrap();
+
+        assert_eq!(result.to_string(), "3");
+        assert_eq!(vm.heap_stats().collections, 1);
+    }
+
     #[test]
     fn all_consts() {
         assert_evaluates_to(
@@ -739,6 +1978,127 @@ mod tests {
         );
     }
 
+    #[test]
+    fn native_number_promotion() {
+        assert_evaluates_to(
+            r#"
+            @UInt8
+                DeclareClass "UInt8"
+                MarkClassU8 @UInt8
+
+            @UInt16
+                DeclareClass "UInt16"
+                MarkClassU16 @UInt16
+
+            @Int32
+                DeclareClass "Int32"
+                MarkClassI32 @Int32
+
+            LoadConstU8 200
+            LoadConstU8 200
+            CallNative Loa/Number#*
+            LoadConstU8 0
+            CallNative Loa/Number#-
+
+            Halt
+            "#,
+            "-40000",
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "division by zero")]
+    fn native_number_division_by_zero() {
+        assert_evaluates(
+            r#"
+            @UInt8
+                DeclareClass "UInt8"
+                MarkClassU8 @UInt8
+
+            LoadConstU8 0
+            LoadConstU8 1
+            CallNative Loa/Number#/
+
+            Halt
+            "#,
+        );
+    }
+
+    #[test]
+    fn rescue_panic() {
+        assert_evaluates_to(
+            r#"
+            @A$methods
+                DeclareMethod "fail" @A#fail
+
+            @String
+                DeclareClass "String"
+                MarkClassString @String
+
+            @A
+                DeclareClass "A"
+                UseMethod @A#fail
+
+            LoadObject @A
+            LoadLazy 1 @expression
+            Rescue 0 @rescue
+            Halt
+
+            @expression
+                LoadConstString "left behind"
+                LoadLocal 1
+                CallMethod @A#fail "call site" 1 1
+                ReturnLazy 2
+
+            @rescue
+                LoadLocal 0
+                ReturnLazy 1
+
+            @A#fail
+                LoadConstString "oops"
+                Panic
+            "#,
+            "oops",
+        );
+    }
+
+    #[test]
+    fn rescue_native_panic() {
+        let program = |divisor: u8| {
+            format!(
+                r#"
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+
+                @String
+                    DeclareClass "String"
+                    MarkClassString @String
+
+                LoadConstU8 {}
+                LoadConstU8 6
+                LoadLazy 2 @expression
+                Rescue 0 @rescue
+                Halt
+
+                @expression
+                    LoadLocal 1
+                    LoadLocal 1
+                    CallNative Loa/Number#/
+                    ReturnLazy 2
+
+                @rescue
+                    LoadLocal 0
+                    ReturnLazy 1
+                "#,
+                divisor
+            )
+        };
+
+        assert_evaluates_to(&program(2), "3");
+        assert_evaluates_to(&program(0), "division by zero");
+    }
+
     #[test]
     fn binary_call() {
         assert_evaluates_to(
@@ -811,6 +2171,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn heap_stats_track_lazy_objects() {
+        let assembly = Parser::new()
+            .parse(
+                r#"
+            @SomeClass
+                DeclareClass "SomeClass"
+
+            LoadLazy 0 @lazy
+            LoadLazy 0 @lazy
+            Halt
+
+            @lazy
+                LoadObject @SomeClass
+                ReturnLazy 0
+            "#,
+            )
+            .unwrap();
+        let mut vm = VM::new();
+        let instructions: Vec<BytecodeInstruction> = assembly.into();
+        vm.eval::<()>(instructions.rotate().unwrap());
+
+        let stats = vm.heap_stats();
+        assert_eq!(stats.allocated_objects, 2);
+        assert_eq!(stats.live_objects, 2);
+
+        vm.stack.pop();
+        assert_eq!(vm.heap_stats().live_objects, 1);
+
+        assert_eq!(vm.collect_garbage(), 0);
+        assert_eq!(vm.heap_stats().collections, 1);
+        assert_eq!(vm.heap_stats().live_objects, 1);
+    }
+
     #[test]
     fn initializer() {
         assert_evaluates_to(
@@ -837,6 +2231,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn tail_call() {
+        assert_evaluates_to(
+            r#"
+            @A$methods
+                DeclareMethod "take:" @A#take:
+
+            @B$methods
+                DeclareMethod "yourself" @B#yourself
+
+            @A
+                DeclareClass "A"
+                UseMethod @A#take:
+
+            @B
+                DeclareClass "B"
+                UseMethod @B#yourself
+
+            LoadObject @B
+            LoadObject @A
+            CallMethod @A#take: "test:" 0 0
+            Halt
+
+            @A#take:
+                LoadLocal 1
+                TailCallMethod @B#yourself "test:" 1 1
+                Return 2
+
+            @B#yourself
+                LoadLocal 0
+                Return 1
+            "#,
+            "B",
+        );
+    }
+
     #[test]
     fn single_variable() {
         assert_evaluates_to(
@@ -882,6 +2312,243 @@ mod tests {
         );
     }
 
+    /// A method that sends itself to its receiver forever, either as a tail
+    /// call or as a regular one.
+    fn endless_recursion(call: &str) -> Vec<BytecodeInstruction> {
+        let assembly = Parser::new()
+            .parse(&format!(
+                r#"
+            @A$methods
+                DeclareMethod "loop" @A#loop
+
+            @A
+                DeclareClass "A"
+                UseMethod @A#loop
+
+            LoadObject @A
+            CallMethod @A#loop "test:" 0 0
+            Halt
+
+            @A#loop
+                LoadLocal 0
+                {} @A#loop "test:" 1 1
+                Return 1
+            "#,
+                call
+            ))
+            .unwrap();
+        let instructions: Vec<BytecodeInstruction> = assembly.into();
+        instructions.rotate().unwrap()
+    }
+
+    fn assert_panics_with_limits(
+        instructions: Vec<BytecodeInstruction>,
+        limits: VMLimits,
+        expected: &str,
+    ) {
+        let mut vm = VM::new();
+        vm.set_limits(limits);
+        vm.load(instructions);
+        match vm.do_eval::<()>() {
+            VMResult::Ok(()) => panic!("expected a panic"),
+            VMResult::Panic(message, _) => assert_eq!(message, expected),
+        }
+    }
+
+    #[test]
+    fn instruction_budget() {
+        assert_panics_with_limits(
+            endless_recursion("TailCallMethod"),
+            VMLimits {
+                max_instructions: Some(100),
+                ..VMLimits::unlimited()
+            },
+            "instruction budget of 100 exceeded",
+        );
+    }
+
+    #[test]
+    fn call_depth_limit() {
+        assert_panics_with_limits(
+            endless_recursion("CallMethod"),
+            VMLimits {
+                max_call_depth: Some(50),
+                ..VMLimits::unlimited()
+            },
+            "call depth limit of 50 exceeded",
+        );
+    }
+
+    #[test]
+    fn stack_size_limit() {
+        assert_panics_with_limits(
+            endless_recursion("CallMethod"),
+            VMLimits {
+                max_stack_size: Some(50),
+                ..VMLimits::unlimited()
+            },
+            "stack size limit of 50 exceeded",
+        );
+    }
+
+    #[test]
+    fn lazy_depth_limit() {
+        let assembly = Parser::new()
+            .parse(
+                r#"
+            @A$methods
+                DeclareMethod "x" @A#x
+
+            @A
+                DeclareClass "A"
+                UseMethod @A#x
+
+            LoadLazy 0 @lazy
+            CallMethod @A#x "test:" 0 0
+            Halt
+
+            @lazy
+                LoadLazy 0 @lazy
+                CallMethod @A#x "test:" 1 1
+                ReturnLazy 0
+
+            @A#x
+                LoadLocal 0
+                Return 1
+            "#,
+            )
+            .unwrap();
+        let instructions: Vec<BytecodeInstruction> = assembly.into();
+        assert_panics_with_limits(
+            instructions.rotate().unwrap(),
+            VMLimits {
+                max_lazy_depth: Some(20),
+                ..VMLimits::unlimited()
+            },
+            "lazy evaluation depth limit of 20 exceeded",
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        assert_panics_with_limits(
+            endless_recursion("TailCallMethod"),
+            VMLimits {
+                timeout: Some(std::time::Duration::from_millis(10)),
+                ..VMLimits::unlimited()
+            },
+            "evaluation timed out after 10ms",
+        );
+    }
+
+    struct RecordingDebugger {
+        command: DebugCommand,
+        pauses: Arc<Mutex<Vec<(usize, usize)>>>,
+    }
+
+    impl Debugger for RecordingDebugger {
+        fn paused(&mut self, vm: &mut VM, _reason: PauseReason) -> DebugCommand {
+            self.pauses
+                .lock()
+                .unwrap()
+                .push((vm.pc(), vm.call_stack().depth()));
+            self.command
+        }
+    }
+
+    fn debug(
+        input: &str,
+        command: DebugCommand,
+        breakpoint: Option<Breakpoint>,
+    ) -> Vec<(usize, usize)> {
+        let assembly = Parser::new().parse(input).unwrap();
+        let pauses = Arc::new(Mutex::new(vec![]));
+        let mut vm = VM::new();
+        vm.attach_debugger(RecordingDebugger {
+            command,
+            pauses: pauses.clone(),
+        });
+        match breakpoint {
+            Some(b) => vm.add_breakpoint(b),
+            None => vm.pause(),
+        }
+        vm.eval_pop::<()>(assembly.into()).unwrap();
+        drop(vm);
+        Arc::try_unwrap(pauses).unwrap().into_inner().unwrap()
+    }
+
+    const DEBUGGED_PROGRAM: &str = r#"
+        @SomeClass$methods
+            DeclareMethod "someMethod" @SomeClass#someMethod
+
+        @SomeClass
+            DeclareClass "SomeClass"
+            UseMethod @SomeClass#someMethod
+
+        LoadObject @SomeClass
+        CallMethod @SomeClass#someMethod "Main.loa" 3 1
+        Halt
+
+        @SomeClass#someMethod
+            LoadLocal 0
+            Return 1
+    "#;
+
+    #[test]
+    fn breakpoint_at_callsite() {
+        assert_eq!(
+            debug(
+                DEBUGGED_PROGRAM,
+                DebugCommand::Continue,
+                Some(Breakpoint::new("Main.loa", 3))
+            ),
+            vec![(4, 0)]
+        );
+    }
+
+    #[test]
+    fn step_instruction_enters_method() {
+        assert_eq!(
+            debug(DEBUGGED_PROGRAM, DebugCommand::StepInstruction, None),
+            vec![
+                (0, 0),
+                (1, 0),
+                (2, 0),
+                (3, 0),
+                (4, 0),
+                (6, 1),
+                (7, 1),
+                (5, 0)
+            ]
+        );
+    }
+
+    #[test]
+    fn step_over_skips_method() {
+        assert_eq!(
+            debug(DEBUGGED_PROGRAM, DebugCommand::StepOver, None),
+            vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0), (5, 0)]
+        );
+    }
+
+    #[test]
+    fn profile() {
+        let assembly = Parser::new().parse(DEBUGGED_PROGRAM).unwrap();
+        let mut vm = VM::new();
+        vm.start_profiling();
+        vm.eval_pop::<()>(assembly.into()).unwrap();
+        let profile = vm.stop_profiling().unwrap();
+
+        let mut collapsed = vec![];
+        profile.write_collapsed(&mut collapsed).unwrap();
+        assert_eq!(
+            String::from_utf8(collapsed).unwrap(),
+            "<main> 6\n<main>;SomeClass#someMethod 2\n"
+        );
+        assert_eq!(profile.methods()[0].name, "<main>");
+        assert_eq!(profile.methods()[0].total_instructions, 8);
+    }
+
     #[test]
     fn native_eq_method() {
         assert_evaluates_to(
@@ -908,4 +2575,375 @@ mod tests {
             "False",
         );
     }
+
+    struct HostRuntime;
+
+    impl Runtime for HostRuntime {
+        fn print_panic(message: String, call_stack: CallStack) {
+            <() as Runtime>::print_panic(message, call_stack)
+        }
+
+        fn register_natives(natives: &mut NativeRegistry) {
+            Self::register_standard_natives(natives);
+            natives.register("Host/Answer#get", |vm: &mut VM| {
+                vm.push(Object::box_u8(42));
+                VMResult::Ok(())
+            });
+            natives.register_boxing("Host/Greeting#get", &[ConstClass::String], |vm: &mut VM| {
+                vm.push(Object::box_string("hello".into()));
+                VMResult::Ok(())
+            });
+        }
+    }
+
+    const HOST_PROGRAM: &str = r#"
+        @UInt8
+            DeclareClass "UInt8"
+            MarkClassU8 @UInt8
+
+        CallNative Host/Answer#get
+        LoadConstU8 1
+        CallNative Loa/Number#+
+        Halt
+    "#;
+
+    #[test]
+    fn host_native_method() {
+        let assembly = Parser::new().parse(HOST_PROGRAM).unwrap();
+        let mut vm = VM::new();
+        let result = vm.eval_pop::<HostRuntime>(assembly.into()).unwrap();
+
+        assert_eq!(result.to_string(), "43");
+    }
+
+    #[test]
+    fn optimized_host_native_method() {
+        let mut assembly = Parser::new()
+            .parse(
+                r#"
+                @String
+                    DeclareClass "String"
+                    MarkClassString @String
+
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+
+                CallNative Host/Greeting#get
+                Halt
+                "#,
+            )
+            .unwrap();
+        assembly.optimize_for::<HostRuntime>();
+        let instructions: Vec<BytecodeInstruction> = assembly.into();
+        assert!(!instructions
+            .iter()
+            .any(|i| matches!(i, BytecodeInstruction::MarkClassU8(_))));
+
+        let result = VM::new().eval_pop::<HostRuntime>(instructions).unwrap();
+
+        assert_eq!(result.to_string(), "hello");
+    }
+
+    #[test]
+    fn boxes_constants_with_its_own_classes() {
+        let mut vm = VM::new();
+        let assembly = Parser::new()
+            .parse(
+                r#"
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+                Halt
+                "#,
+            )
+            .unwrap();
+        vm.eval::<()>(assembly.into());
+        let boxed = vm.box_const(ConstValue::U8(7)).unwrap();
+
+        assert_eq!(boxed.class.as_ref().unwrap().name, "UInt8");
+        assert!(Arc::ptr_eq(
+            boxed.class.as_ref().unwrap(),
+            vm.class("UInt8").unwrap()
+        ));
+        assert!(vm.box_const(ConstValue::U16(7)).is_none());
+        assert!(VM::new().box_const(ConstValue::U8(7)).is_none());
+        assert!(vm.box_bool(true).is_none());
+    }
+
+    #[test]
+    fn natives_follow_the_runtime() {
+        let declarations = Parser::new()
+            .parse(
+                r#"
+                @UInt8
+                    DeclareClass "UInt8"
+                    MarkClassU8 @UInt8
+                Halt
+                "#,
+            )
+            .unwrap();
+        let mut vm = VM::new();
+        vm.eval::<()>(declarations.into());
+
+        // The program comes after the declarations, so it can't use labels.
+        let program = Parser::new()
+            .parse(
+                r#"
+                CallNative Host/Answer#get
+                LoadConstU8 1
+                CallNative Loa/Number#+
+                Halt
+                "#,
+            )
+            .unwrap();
+        let result = vm.eval_pop::<HostRuntime>(program.into()).unwrap();
+
+        assert_eq!(result.to_string(), "43");
+    }
+
+    #[test]
+    #[should_panic(expected = "unknown native method: Host/Answer#get")]
+    fn unknown_native_method() {
+        assert_evaluates(HOST_PROGRAM);
+    }
+
+    struct IORuntime;
+
+    impl Runtime for IORuntime {
+        fn print_panic(message: String, call_stack: CallStack) {
+            <() as Runtime>::print_panic(message, call_stack)
+        }
+
+        fn capabilities() -> Capabilities {
+            Capabilities {
+                stdin: false,
+                ..Capabilities::all()
+            }
+        }
+    }
+
+    fn io_program(program: &str) -> Vec<BytecodeInstruction> {
+        let code = format!(
+            r#"
+            @String
+                DeclareClass "String"
+                MarkClassString @String
+
+            @UInt8
+                DeclareClass "UInt8"
+                MarkClassU8 @UInt8
+
+            @UInt64
+                DeclareClass "UInt64"
+                MarkClassU64 @UInt64
+
+            @Int32
+                DeclareClass "Int32"
+                MarkClassI32 @Int32
+
+            {}
+            "#,
+            program
+        );
+        Parser::new().parse(&code).unwrap().into()
+    }
+
+    #[test]
+    fn io_natives() {
+        let path = std::env::temp_dir().join(format!("loa-io-natives-{}", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut vm = VM::new();
+        vm.set_arguments(vec!["first".into(), " second".into()]);
+
+        let result = vm.eval_pop::<IORuntime>(io_program(&format!(
+            r#"
+            LoadConstString {path:?}
+            LoadConstU8 1
+            LoadConstString "Process"
+            CallNative Loa/Process#argumentAt:
+            LoadConstU8 0
+            LoadConstString "Process"
+            CallNative Loa/Process#argumentAt:
+            CallNative Loa/String#+
+            LoadConstString "File"
+            CallNative Loa/File#write:to:
+            LoadConstString {path:?}
+            LoadLocal 1
+            CallNative Loa/File#read:
+            DropLocal 1
+            Halt
+            "#,
+            path = path
+        )));
+        let _ = std::fs::remove_file(path);
+
+        assert_eq!(result.unwrap().to_string(), "first second");
+    }
+
+    #[test]
+    fn io_natives_need_capabilities() {
+        let program = || {
+            io_program(
+                r#"
+                LoadConstString "Process"
+                CallNative Loa/Process#argumentCount
+                Halt
+                "#,
+            )
+        };
+
+        let mut vm = VM::new();
+        vm.set_arguments(vec!["argument".into()]);
+        assert_eq!(
+            vm.eval_pop::<IORuntime>(program()).unwrap().to_string(),
+            "1"
+        );
+
+        match VM::new().run::<()>(program()) {
+            VMResult::Ok(()) => panic!("expected a panic"),
+            VMResult::Panic(message, _) => {
+                assert_eq!(message, "the runtime doesn't grant access to the arguments")
+            }
+        }
+
+        let mut worker = VM::new();
+        worker.worker = true;
+        match worker.run::<IORuntime>(program()) {
+            VMResult::Ok(()) => panic!("expected a panic"),
+            VMResult::Panic(message, _) => {
+                assert_eq!(message, "workers have no access to the arguments")
+            }
+        }
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let program = |operand: u8| {
+            io_program(&format!(
+                r#"
+                LoadConstU8 {}
+                LoadConstString "Process"
+                CallNative Loa/Process#argumentCount
+                CallNative Loa/Number#+
+                Halt
+                "#,
+                operand
+            ))
+        };
+
+        let mut vm = VM::new();
+        vm.set_arguments(vec!["argument".into()]);
+        vm.start_recording();
+        let result = vm.eval_pop::<IORuntime>(program(1)).unwrap();
+        let recording = vm.stop_recording().unwrap();
+        assert_eq!(result.to_string(), "2");
+        assert_eq!(recording.calls.len(), 2);
+
+        // The arguments aren't granted, but the recording remembers them.
+        let mut vm = VM::new();
+        vm.start_replaying(recording.clone());
+        let result = vm.eval_pop::<()>(program(1)).unwrap();
+        assert_eq!(result.to_string(), "2");
+        assert_eq!(vm.stop_replaying(), Some(0));
+
+        let mut vm = VM::new();
+        vm.start_replaying(recording);
+        match vm.run::<()>(program(2)) {
+            VMResult::Ok(()) => panic!("expected a panic"),
+            VMResult::Panic(message, _) => assert_eq!(
+                message,
+                "replay diverged at native call 2: the program called Loa/Number#+(1, 2), \
+                 but Loa/Number#+(1, 1) was recorded"
+            ),
+        }
+        assert_eq!(vm.stop_replaying(), None);
+    }
+
+    #[test]
+    fn exit_is_not_rescued() {
+        let mut vm = VM::new();
+        let result = vm.eval_pop::<IORuntime>(io_program(
+            r#"
+            LoadConstI32 3
+            LoadLazy 1 @expression
+            Rescue 0 @rescue
+            Halt
+
+            @expression
+                LoadLocal 0
+                LoadConstString "Process"
+                CallNative Loa/Process#exit:
+                ReturnLazy 1
+
+            @rescue
+                LoadLocal 0
+                ReturnLazy 1
+            "#,
+        ));
+
+        assert!(result.is_none());
+        assert_eq!(vm.exit_code(), Some(3));
+    }
+
+    fn rescued(expression: &str) -> Vec<BytecodeInstruction> {
+        let assembly = Parser::new()
+            .parse(&format!(
+                r#"
+            @A$methods
+                DeclareMethod "loop" @A#loop
+
+            @String
+                DeclareClass "String"
+                MarkClassString @String
+
+            @A
+                DeclareClass "A"
+                UseMethod @A#loop
+
+            LoadObject @A
+            LoadLazy 1 @expression
+            Rescue 0 @rescue
+            Halt
+
+            @expression
+                {}
+                ReturnLazy 1
+
+            @rescue
+                LoadLocal 0
+                ReturnLazy 1
+
+            @A#loop
+                LoadLocal 0
+                TailCallMethod @A#loop "test:" 1 1
+                Return 1
+            "#,
+                expression
+            ))
+            .unwrap();
+        let instructions: Vec<BytecodeInstruction> = assembly.into();
+        instructions.rotate().unwrap()
+    }
+
+    #[test]
+    fn limits_are_not_rescued() {
+        assert_panics_with_limits(
+            rescued("LoadLocal 0\nCallMethod @A#loop \"test:\" 1 1"),
+            VMLimits {
+                max_instructions: Some(100),
+                ..VMLimits::unlimited()
+            },
+            "instruction budget of 100 exceeded",
+        );
+    }
+
+    #[test]
+    fn malformed_programs_are_not_rescued() {
+        assert_panics_with_limits(
+            rescued("LoadLocal 100"),
+            VMLimits::unlimited(),
+            "not enough locals on the stack",
+        );
+    }
 }
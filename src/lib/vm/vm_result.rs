@@ -4,7 +4,12 @@ macro_rules! expect {
     ($vm:expr, $opt:expr, $($arg:tt)*) => {
         match $opt {
             Some(t) => t,
-            None => return VMResult::Panic(format!($($arg)*), $vm.call_stack.detach()),
+            // Only the fields are borrowed, rather than calling `fatal`,
+            // since the option may still borrow another field of the VM.
+            None => {
+                $vm.panic_origin = PanicOrigin::VM;
+                return VMResult::Panic(format!($($arg)*), $vm.call_stack.detach());
+            }
         }
     };
 }
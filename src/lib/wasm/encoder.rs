@@ -0,0 +1,519 @@
+/// The types of values in a WebAssembly module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ValueType {
+    I32,
+    I64,
+    F64,
+}
+
+impl ValueType {
+    fn code(self) -> u8 {
+        match self {
+            ValueType::I32 => 0x7f,
+            ValueType::I64 => 0x7e,
+            ValueType::F64 => 0x7c,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockType {
+    Empty,
+    Value(ValueType),
+}
+
+/// The subset of WebAssembly instructions used by the generated code. Loads
+/// and stores take the offset to add to the address on the stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    Unreachable,
+    Block(BlockType),
+    Loop(BlockType),
+    If(BlockType),
+    Else,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    CallIndirect(u32),
+    Drop,
+    Select,
+
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+
+    I32Load(u32),
+    I64Load(u32),
+    F64Load(u32),
+    I32Load8U(u32),
+    I64Load32U(u32),
+    I32Store(u32),
+    I64Store(u32),
+    I32Store8(u32),
+    I64Store32(u32),
+    MemorySize,
+    MemoryGrow,
+
+    I32Const(i32),
+    I64Const(i64),
+    F64Const(f64),
+
+    I32Eqz,
+    I32Eq,
+    I32Ne,
+    I32LtS,
+    I32LtU,
+    I32GtU,
+    I32LeS,
+    I32LeU,
+    I32GeU,
+    I64Eqz,
+    I64Eq,
+    I64Ne,
+    I64LtS,
+    I64LtU,
+    I64GtU,
+    I64LeS,
+    I64GeS,
+    F64Eq,
+    F64Ne,
+    F64Lt,
+    F64Le,
+    F64Ge,
+
+    I32Add,
+    I32Sub,
+    I32Mul,
+    I32And,
+    I32Or,
+    I32Shl,
+    I32ShrU,
+    I64Add,
+    I64Sub,
+    I64Mul,
+    I64DivS,
+    I64RemS,
+    I64And,
+    I64Or,
+    I64Xor,
+    I64Shl,
+    I64ShrS,
+    I64ShrU,
+    F64Abs,
+    F64Neg,
+    F64Trunc,
+    F64Add,
+    F64Sub,
+    F64Mul,
+    F64Div,
+    F64Copysign,
+
+    I32WrapI64,
+    I64ExtendI32U,
+    I64TruncF64S,
+    F32DemoteF64,
+    F64ConvertI64S,
+    F64PromoteF32,
+    I64ReinterpretF64,
+}
+
+impl Instr {
+    fn encode(self, out: &mut Vec<u8>) {
+        use Instr::*;
+
+        match self {
+            Unreachable => out.push(0x00),
+            Block(t) => block(out, 0x02, t),
+            Loop(t) => block(out, 0x03, t),
+            If(t) => block(out, 0x04, t),
+            Else => out.push(0x05),
+            End => out.push(0x0b),
+            Br(depth) => index(out, 0x0c, depth),
+            BrIf(depth) => index(out, 0x0d, depth),
+            Return => out.push(0x0f),
+            Call(function) => index(out, 0x10, function),
+            CallIndirect(signature) => {
+                index(out, 0x11, signature);
+                out.push(0x00);
+            }
+            Drop => out.push(0x1a),
+            Select => out.push(0x1b),
+
+            LocalGet(local) => index(out, 0x20, local),
+            LocalSet(local) => index(out, 0x21, local),
+            LocalTee(local) => index(out, 0x22, local),
+            GlobalGet(global) => index(out, 0x23, global),
+            GlobalSet(global) => index(out, 0x24, global),
+
+            I32Load(offset) => memory(out, 0x28, 2, offset),
+            I64Load(offset) => memory(out, 0x29, 3, offset),
+            F64Load(offset) => memory(out, 0x2b, 3, offset),
+            I32Load8U(offset) => memory(out, 0x2d, 0, offset),
+            I64Load32U(offset) => memory(out, 0x35, 2, offset),
+            I32Store(offset) => memory(out, 0x36, 2, offset),
+            I64Store(offset) => memory(out, 0x37, 3, offset),
+            I32Store8(offset) => memory(out, 0x3a, 0, offset),
+            I64Store32(offset) => memory(out, 0x3e, 2, offset),
+            MemorySize => out.extend_from_slice(&[0x3f, 0x00]),
+            MemoryGrow => out.extend_from_slice(&[0x40, 0x00]),
+
+            I32Const(value) => {
+                out.push(0x41);
+                signed(out, value.into());
+            }
+            I64Const(value) => {
+                out.push(0x42);
+                signed(out, value);
+            }
+            F64Const(value) => {
+                out.push(0x44);
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+
+            I32Eqz => out.push(0x45),
+            I32Eq => out.push(0x46),
+            I32Ne => out.push(0x47),
+            I32LtS => out.push(0x48),
+            I32LtU => out.push(0x49),
+            I32GtU => out.push(0x4b),
+            I32LeS => out.push(0x4c),
+            I32LeU => out.push(0x4d),
+            I32GeU => out.push(0x4f),
+            I64Eqz => out.push(0x50),
+            I64Eq => out.push(0x51),
+            I64Ne => out.push(0x52),
+            I64LtS => out.push(0x53),
+            I64LtU => out.push(0x54),
+            I64GtU => out.push(0x56),
+            I64LeS => out.push(0x57),
+            I64GeS => out.push(0x59),
+            F64Eq => out.push(0x61),
+            F64Ne => out.push(0x62),
+            F64Lt => out.push(0x63),
+            F64Le => out.push(0x65),
+            F64Ge => out.push(0x66),
+
+            I32Add => out.push(0x6a),
+            I32Sub => out.push(0x6b),
+            I32Mul => out.push(0x6c),
+            I32And => out.push(0x71),
+            I32Or => out.push(0x72),
+            I32Shl => out.push(0x74),
+            I32ShrU => out.push(0x76),
+            I64Add => out.push(0x7c),
+            I64Sub => out.push(0x7d),
+            I64Mul => out.push(0x7e),
+            I64DivS => out.push(0x7f),
+            I64RemS => out.push(0x81),
+            I64And => out.push(0x83),
+            I64Or => out.push(0x84),
+            I64Xor => out.push(0x85),
+            I64Shl => out.push(0x86),
+            I64ShrS => out.push(0x87),
+            I64ShrU => out.push(0x88),
+            F64Abs => out.push(0x99),
+            F64Neg => out.push(0x9a),
+            F64Trunc => out.push(0x9d),
+            F64Add => out.push(0xa0),
+            F64Sub => out.push(0xa1),
+            F64Mul => out.push(0xa2),
+            F64Div => out.push(0xa3),
+            F64Copysign => out.push(0xa6),
+
+            I32WrapI64 => out.push(0xa7),
+            I64ExtendI32U => out.push(0xad),
+            I64TruncF64S => out.push(0xb0),
+            F32DemoteF64 => out.push(0xb6),
+            F64ConvertI64S => out.push(0xb9),
+            F64PromoteF32 => out.push(0xbb),
+            I64ReinterpretF64 => out.push(0xbd),
+        }
+    }
+}
+
+fn block(out: &mut Vec<u8>, opcode: u8, block_type: BlockType) {
+    out.push(opcode);
+    match block_type {
+        BlockType::Empty => out.push(0x40),
+        BlockType::Value(t) => out.push(t.code()),
+    }
+}
+
+fn index(out: &mut Vec<u8>, opcode: u8, index: u32) {
+    out.push(opcode);
+    unsigned(out, index);
+}
+
+fn memory(out: &mut Vec<u8>, opcode: u8, alignment: u32, offset: u32) {
+    out.push(opcode);
+    unsigned(out, alignment);
+    unsigned(out, offset);
+}
+
+/// Writes an unsigned LEB128 number.
+pub fn unsigned(out: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+/// Writes a signed LEB128 number.
+pub fn signed(out: &mut Vec<u8>, mut value: i64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    unsigned(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+pub type Signature = (Vec<ValueType>, Vec<ValueType>);
+
+fn intern(signatures: &mut Vec<Signature>, signature: Signature) -> u32 {
+    match signatures.iter().position(|s| *s == signature) {
+        Some(index) => index as u32,
+        None => {
+            signatures.push(signature);
+            signatures.len() as u32 - 1
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub params: Vec<ValueType>,
+    pub results: Vec<ValueType>,
+    pub locals: Vec<ValueType>,
+    pub body: Vec<Instr>,
+}
+
+impl Function {
+    pub fn new(params: &[ValueType], results: &[ValueType]) -> Function {
+        Function {
+            params: params.to_vec(),
+            results: results.to_vec(),
+            locals: vec![],
+            body: vec![],
+        }
+    }
+
+    /// Adds a local, and returns its index.
+    pub fn local(&mut self, value_type: ValueType) -> u32 {
+        self.locals.push(value_type);
+        (self.params.len() + self.locals.len() - 1) as u32
+    }
+
+    pub fn emit(&mut self, instructions: &[Instr]) {
+        self.body.extend_from_slice(instructions);
+    }
+
+    fn signature(&self) -> Signature {
+        (self.params.clone(), self.results.clone())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Global {
+    pub value_type: ValueType,
+    pub mutable: bool,
+    pub initial: i64,
+}
+
+/// A WebAssembly module with a single memory and a single function table,
+/// without imports.
+#[derive(Debug, Clone, Default)]
+pub struct Module {
+    pub types: Vec<Signature>,
+    pub functions: Vec<Function>,
+    pub table: Vec<u32>,
+    pub memory_pages: u32,
+    pub globals: Vec<Global>,
+    pub exported_functions: Vec<(String, u32)>,
+    pub exported_memory: Option<String>,
+    pub data: Vec<(u32, Vec<u8>)>,
+}
+
+impl Module {
+    pub fn new() -> Module {
+        Module::default()
+    }
+
+    /// The index of the type of functions with this signature, as used by
+    /// `CallIndirect`.
+    pub fn signature(&mut self, params: &[ValueType], results: &[ValueType]) -> u32 {
+        intern(&mut self.types, (params.to_vec(), results.to_vec()))
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        let mut signatures = self.types.clone();
+        let indices = self
+            .functions
+            .iter()
+            .map(|f| intern(&mut signatures, f.signature()))
+            .collect::<Vec<_>>();
+
+        section(&mut out, 1, signatures.len(), |s| {
+            for (params, results) in signatures.iter() {
+                s.push(0x60);
+                unsigned(s, params.len() as u32);
+                s.extend(params.iter().map(|t| t.code()));
+                unsigned(s, results.len() as u32);
+                s.extend(results.iter().map(|t| t.code()));
+            }
+        });
+
+        section(&mut out, 3, self.functions.len(), |s| {
+            for index in indices.iter() {
+                unsigned(s, *index);
+            }
+        });
+
+        // Index 0 of the table is left empty, so that it can mean "no
+        // function".
+        section(&mut out, 4, 1, |s| {
+            s.push(0x70);
+            s.push(0x00);
+            unsigned(s, self.table.len() as u32 + 1);
+        });
+
+        section(&mut out, 5, 1, |s| {
+            s.push(0x00);
+            unsigned(s, self.memory_pages);
+        });
+
+        section(&mut out, 6, self.globals.len(), |s| {
+            for global in self.globals.iter() {
+                s.push(global.value_type.code());
+                s.push(global.mutable as u8);
+                match global.value_type {
+                    ValueType::I32 => Instr::I32Const(global.initial as i32).encode(s),
+                    ValueType::I64 => Instr::I64Const(global.initial).encode(s),
+                    ValueType::F64 => Instr::F64Const(global.initial as f64).encode(s),
+                }
+                Instr::End.encode(s);
+            }
+        });
+
+        let exports = self.exported_functions.len() + self.exported_memory.iter().count();
+        section(&mut out, 7, exports, |s| {
+            for (name, function) in self.exported_functions.iter() {
+                bytes(s, name.as_bytes());
+                s.push(0x00);
+                unsigned(s, *function);
+            }
+            if let Some(ref name) = self.exported_memory {
+                bytes(s, name.as_bytes());
+                s.push(0x02);
+                unsigned(s, 0);
+            }
+        });
+
+        section(&mut out, 9, 1, |s| {
+            s.push(0x00);
+            Instr::I32Const(1).encode(s);
+            Instr::End.encode(s);
+            unsigned(s, self.table.len() as u32);
+            for function in self.table.iter() {
+                unsigned(s, *function);
+            }
+        });
+
+        section(&mut out, 10, self.functions.len(), |s| {
+            for function in self.functions.iter() {
+                let mut code = vec![];
+                let mut groups: Vec<(u32, ValueType)> = vec![];
+                for local in function.locals.iter() {
+                    match groups.last_mut() {
+                        Some((count, t)) if t == local => *count += 1,
+                        _ => groups.push((1, *local)),
+                    }
+                }
+                unsigned(&mut code, groups.len() as u32);
+                for (count, t) in groups {
+                    unsigned(&mut code, count);
+                    code.push(t.code());
+                }
+                for instruction in function.body.iter() {
+                    instruction.encode(&mut code);
+                }
+                Instr::End.encode(&mut code);
+                bytes(s, &code);
+            }
+        });
+
+        section(&mut out, 11, self.data.len(), |s| {
+            for (offset, data) in self.data.iter() {
+                s.push(0x00);
+                Instr::I32Const(*offset as i32).encode(s);
+                Instr::End.encode(s);
+                bytes(s, data);
+            }
+        });
+
+        out
+    }
+}
+
+fn section<F: FnOnce(&mut Vec<u8>)>(out: &mut Vec<u8>, id: u8, count: usize, f: F) {
+    if count == 0 {
+        return;
+    }
+    let mut contents = vec![];
+    unsigned(&mut contents, count as u32);
+    f(&mut contents);
+    out.push(id);
+    bytes(out, &contents);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leb128<F: Fn(&mut Vec<u8>)>(f: F) -> Vec<u8> {
+        let mut out = vec![];
+        f(&mut out);
+        out
+    }
+
+    #[test]
+    fn unsigned_leb128() {
+        assert_eq!(leb128(|o| unsigned(o, 0)), vec![0x00]);
+        assert_eq!(leb128(|o| unsigned(o, 127)), vec![0x7f]);
+        assert_eq!(leb128(|o| unsigned(o, 624_485)), vec![0xe5, 0x8e, 0x26]);
+    }
+
+    #[test]
+    fn signed_leb128() {
+        assert_eq!(leb128(|o| signed(o, 63)), vec![0x3f]);
+        assert_eq!(leb128(|o| signed(o, 64)), vec![0xc0, 0x00]);
+        assert_eq!(leb128(|o| signed(o, -1)), vec![0x7f]);
+        assert_eq!(leb128(|o| signed(o, -123_456)), vec![0xc0, 0xbb, 0x78]);
+    }
+
+    #[test]
+    fn empty_module() {
+        let mut module = Module::new();
+        module.memory_pages = 1;
+        let bytes = module.encode();
+        assert_eq!(&bytes[..8], b"\0asm\x01\0\0\0");
+    }
+}
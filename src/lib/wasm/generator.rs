@@ -0,0 +1,847 @@
+use super::encoder::{Function, Instr, Instr::*, Module, ValueType::*};
+use super::layout::*;
+use super::runtime::{self, Builtin, Tables, UNSUPPORTED};
+use crate::assembly::Assembly;
+use crate::bytecode::Instruction;
+use crate::vm::{selector_arity, NumberKind, CONVERSIONS};
+use crate::wasm::*;
+use crate::*;
+use num_traits::ToPrimitive;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+
+/// Lowers assembly to a WebAssembly module, which exports its memory and a
+/// `main` function that runs the program. `main` returns the address of the
+/// length of the UTF-8 string that the result of the program formats as,
+/// followed by its bytes, or 0 if the program leaves nothing on the stack.
+/// When `main` traps, `panic_message` returns the address of the message in
+/// the same format, or 0 if the trap wasn't a panic.
+///
+/// Big floats, integers that don't fit in 64 bits, rescuing panics and the
+/// effects of `Loa/Process` and `Loa/File` aren't supported yet. Message
+/// sends that aren't in tail position use the call stack of the host, which
+/// limits how deep they can recurse.
+pub struct Generator {
+    stack_size: u32,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Entry {
+    Main,
+    Method,
+    Lazy,
+}
+
+struct Class {
+    name: String,
+    methods: HashMap<u64, u64>,
+    getters: HashMap<u64, u64>,
+    setters: HashMap<u64, u64>,
+}
+
+/// What the declarations along the entry point of the program declare.
+#[derive(Default)]
+struct Declarations {
+    classes: Vec<Class>,
+    class_indices: HashMap<u64, usize>,
+    methods: HashMap<u64, String>,
+    variables: Vec<String>,
+    variable_indices: HashMap<u64, usize>,
+    number_classes: [Option<usize>; 15],
+    string_class: Option<usize>,
+    character_class: Option<usize>,
+    symbol_class: Option<usize>,
+    true_class: Option<usize>,
+    false_class: Option<usize>,
+}
+
+impl Declarations {
+    fn collect(instructions: &[Instruction]) -> WasmResult<Declarations> {
+        let mut declarations = Declarations::default();
+        let mut variables = HashMap::new();
+        let mut declaring = None;
+
+        for (offset, instruction) in instructions.iter().enumerate() {
+            let declaring_class =
+                |declaring: Option<usize>| declaring.ok_or(WasmError::OutsideClass { offset });
+            match instruction {
+                Instruction::Halt
+                | Instruction::Panic
+                | Instruction::Return(_)
+                | Instruction::ReturnLazy(_) => break,
+
+                Instruction::DeclareClass(name) => {
+                    declaring = Some(declarations.classes.len());
+                    declarations
+                        .class_indices
+                        .insert(offset as u64, declarations.classes.len());
+                    declarations.classes.push(Class {
+                        name: name.clone(),
+                        methods: HashMap::new(),
+                        getters: HashMap::new(),
+                        setters: HashMap::new(),
+                    });
+                }
+
+                Instruction::DeclareVariable(name, id, getter_id, setter_id) => {
+                    if !declarations.variable_indices.contains_key(id) {
+                        declarations
+                            .variable_indices
+                            .insert(*id, declarations.variables.len());
+                        declarations.variables.push(name.clone());
+                    }
+                    variables.insert(*id, (*getter_id, *setter_id));
+                }
+
+                Instruction::UseVariable(id) => {
+                    let (getter_id, setter_id) =
+                        *variables.get(id).ok_or(WasmError::UnknownVariable {
+                            offset,
+                            variable: *id,
+                        })?;
+                    let class = &mut declarations.classes[declaring_class(declaring)?];
+                    class.getters.insert(getter_id, *id);
+                    class.setters.insert(setter_id, *id);
+                }
+
+                Instruction::DeclareMethod(name, method) => {
+                    declarations.methods.insert(*method, name.clone());
+                }
+
+                Instruction::UseMethod(method) | Instruction::OverrideMethod(method, _) => {
+                    let target = match instruction {
+                        Instruction::OverrideMethod(_, target) => target,
+                        _ => method,
+                    };
+                    if !declarations.methods.contains_key(target) {
+                        return Err(WasmError::UnknownMethod {
+                            offset,
+                            method: *target,
+                        });
+                    }
+                    declarations.classes[declaring_class(declaring)?]
+                        .methods
+                        .insert(*method, *target);
+                }
+
+                Instruction::MarkClassTrue(class) => {
+                    declarations.true_class = Some(declarations.class(offset, *class)?);
+                }
+                Instruction::MarkClassFalse(class) => {
+                    declarations.false_class = Some(declarations.class(offset, *class)?);
+                }
+                Instruction::MarkClassString(class) => {
+                    declarations.string_class = Some(declarations.class(offset, *class)?);
+                }
+                Instruction::MarkClassCharacter(class) => {
+                    declarations.character_class = Some(declarations.class(offset, *class)?);
+                }
+                Instruction::MarkClassSymbol(class) => {
+                    declarations.symbol_class = Some(declarations.class(offset, *class)?);
+                }
+
+                instruction => {
+                    if let Some((kind, class)) = number_mark(instruction) {
+                        declarations.number_classes[kind as usize] =
+                            Some(declarations.class(offset, class)?);
+                    }
+                }
+            }
+        }
+
+        Ok(declarations)
+    }
+
+    fn class(&self, offset: usize, class: u64) -> WasmResult<usize> {
+        self.class_indices
+            .get(&class)
+            .cloned()
+            .ok_or(WasmError::UnknownClass { offset, class })
+    }
+
+    fn class_of(slot: Option<usize>) -> i32 {
+        slot.map(|c| c as i32).unwrap_or(NO_CLASS)
+    }
+}
+
+fn number_mark(instruction: &Instruction) -> Option<(NumberKind, u64)> {
+    match instruction {
+        Instruction::MarkClassU8(c) => Some((NumberKind::U8, *c)),
+        Instruction::MarkClassU16(c) => Some((NumberKind::U16, *c)),
+        Instruction::MarkClassU32(c) => Some((NumberKind::U32, *c)),
+        Instruction::MarkClassU64(c) => Some((NumberKind::U64, *c)),
+        Instruction::MarkClassU128(c) => Some((NumberKind::U128, *c)),
+        Instruction::MarkClassUBig(c) => Some((NumberKind::UBig, *c)),
+        Instruction::MarkClassI8(c) => Some((NumberKind::I8, *c)),
+        Instruction::MarkClassI16(c) => Some((NumberKind::I16, *c)),
+        Instruction::MarkClassI32(c) => Some((NumberKind::I32, *c)),
+        Instruction::MarkClassI64(c) => Some((NumberKind::I64, *c)),
+        Instruction::MarkClassI128(c) => Some((NumberKind::I128, *c)),
+        Instruction::MarkClassIBig(c) => Some((NumberKind::IBig, *c)),
+        Instruction::MarkClassF32(c) => Some((NumberKind::F32, *c)),
+        Instruction::MarkClassF64(c) => Some((NumberKind::F64, *c)),
+        Instruction::MarkClassFBig(c) => Some((NumberKind::FBig, *c)),
+        _ => None,
+    }
+}
+
+/// The kind and value of an integer constant, or `None` if the value doesn't
+/// fit in an i64.
+fn integer_constant(instruction: &Instruction) -> Option<(NumberKind, Option<i64>)> {
+    match instruction {
+        Instruction::LoadConstU8(v) => Some((NumberKind::U8, Some(*v as i64))),
+        Instruction::LoadConstU16(v) => Some((NumberKind::U16, Some(*v as i64))),
+        Instruction::LoadConstU32(v) => Some((NumberKind::U32, Some(*v as i64))),
+        Instruction::LoadConstU64(v) => Some((NumberKind::U64, i64::try_from(*v).ok())),
+        Instruction::LoadConstU128(v) => Some((NumberKind::U128, i64::try_from(*v).ok())),
+        Instruction::LoadConstUBig(v) => Some((NumberKind::UBig, v.to_i64())),
+        Instruction::LoadConstI8(v) => Some((NumberKind::I8, Some(*v as i64))),
+        Instruction::LoadConstI16(v) => Some((NumberKind::I16, Some(*v as i64))),
+        Instruction::LoadConstI32(v) => Some((NumberKind::I32, Some(*v as i64))),
+        Instruction::LoadConstI64(v) => Some((NumberKind::I64, Some(*v))),
+        Instruction::LoadConstI128(v) => Some((NumberKind::I128, i64::try_from(*v).ok())),
+        Instruction::LoadConstIBig(v) => Some((NumberKind::IBig, v.to_i64())),
+        _ => None,
+    }
+}
+
+fn call(builtin: Builtin) -> Instr {
+    Call(builtin as u32)
+}
+
+impl Generator {
+    pub fn new() -> Generator {
+        Generator {
+            stack_size: STACK_SIZE,
+        }
+    }
+
+    /// Sets the size of the operand stack in bytes. Programs that need a
+    /// deeper stack panic.
+    pub fn with_stack_size(mut self, stack_size: u32) -> Generator {
+        self.stack_size = stack_size;
+        self
+    }
+
+    pub fn generate(self, assembly: Assembly) -> WasmResult<Vec<u8>> {
+        let instructions: Vec<Instruction> = assembly.into();
+        let declarations = Declarations::collect(&instructions)?;
+
+        let mut selectors = BTreeSet::new();
+        for class in declarations.classes.iter() {
+            selectors.extend(class.methods.keys());
+            selectors.extend(class.getters.keys());
+            selectors.extend(class.setters.keys());
+        }
+        for instruction in instructions.iter() {
+            if let Instruction::CallMethod(selector, _, _, _)
+            | Instruction::TailCallMethod(selector, _, _, _) = instruction
+            {
+                selectors.insert(*selector);
+            }
+        }
+        let selectors: Vec<u64> = selectors.into_iter().collect();
+
+        let mut lowering = Lowering {
+            instructions: &instructions,
+            selector_indices: selectors
+                .iter()
+                .enumerate()
+                .map(|(i, s)| (*s, i as i32))
+                .collect(),
+            data: Data::new(declarations.string_class),
+            declarations: &declarations,
+            queue: vec![],
+            table_indices: HashMap::new(),
+            global_indices: HashMap::new(),
+        };
+
+        // Every method a class responds to gets a function, whether or not
+        // any call to it is ever reached.
+        let mut methods: Vec<u64> = declarations
+            .classes
+            .iter()
+            .flat_map(|c| c.methods.values().cloned())
+            .collect();
+        methods.sort();
+        for method in methods {
+            lowering.function(method, Entry::Method);
+        }
+
+        let main = lowering.body(0, Entry::Main);
+        let mut code = vec![];
+        while code.len() < lowering.queue.len() {
+            let (offset, entry) = lowering.queue[code.len()];
+            code.push(lowering.body(offset as usize, entry));
+        }
+
+        let tables = lowering.tables(&selectors);
+        let Lowering { mut data, .. } = lowering;
+
+        let mut module = Module::new();
+        let code_signature = module.signature(&[], &[I32]);
+        debug_assert_eq!(code_signature, tables.code_signature);
+
+        module.functions = runtime::functions(&tables, &mut data);
+        let main_index = module.functions.len() as u32;
+        module.functions.push(main);
+        module.table = (0..code.len() as u32).map(|i| main_index + 1 + i).collect();
+        module.functions.extend(code);
+
+        let stack_start = (data.end() + 7) & !7;
+        let stack_end = stack_start + self.stack_size;
+        module.memory_pages = stack_end / PAGE_SIZE + 1;
+        for (mutable, initial) in &[
+            (true, stack_start),
+            (true, stack_end),
+            (true, 0),
+            (false, stack_start),
+            (false, stack_end),
+        ] {
+            module.globals.push(super::encoder::Global {
+                value_type: I32,
+                mutable: *mutable,
+                initial: *initial as i64,
+            });
+        }
+
+        module.exported_functions = vec![
+            ("main".into(), main_index),
+            ("panic_message".into(), Builtin::PanicMessage as u32),
+        ];
+        module.exported_memory = Some("memory".into());
+        module.data = vec![(data.start(), data.into_bytes())];
+
+        Ok(module.encode())
+    }
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator::new()
+    }
+}
+
+struct Lowering<'a> {
+    instructions: &'a [Instruction],
+    declarations: &'a Declarations,
+    selector_indices: HashMap<u64, i32>,
+    data: Data,
+    /// The functions in the function table, which starts at index 1.
+    queue: Vec<(u64, Entry)>,
+    table_indices: HashMap<(u64, Entry), u32>,
+    global_indices: HashMap<u64, i32>,
+}
+
+impl<'a> Lowering<'a> {
+    /// The table index of the function for the method or lazy object at the
+    /// offset, which is compiled later.
+    fn function(&mut self, offset: u64, entry: Entry) -> u32 {
+        if let Some(index) = self.table_indices.get(&(offset, entry)) {
+            return *index;
+        }
+        self.queue.push((offset, entry));
+        let index = self.queue.len() as u32;
+        self.table_indices.insert((offset, entry), index);
+        index
+    }
+
+    fn panic(&mut self, f: &mut Function, message: &str) {
+        let message = self.data.string(message) as i32;
+        f.emit(&[I32Const(message), call(Builtin::Panic), Unreachable]);
+    }
+
+    fn unsupported(&mut self, f: &mut Function, feature: &str) {
+        self.panic(f, &format!("{}: {}", UNSUPPORTED, feature));
+    }
+
+    /// Pushes a constant object of the class, or panics like the VM does
+    /// when the class isn't loaded.
+    fn constant(
+        &mut self,
+        f: &mut Function,
+        class: Option<usize>,
+        name: &str,
+        object: impl FnOnce(&mut Data, i32) -> u32,
+    ) -> bool {
+        match class {
+            None => {
+                self.panic(f, &format!("{} is not loaded", name));
+                false
+            }
+            Some(class) => {
+                let address = object(&mut self.data, class as i32);
+                f.emit(&[I32Const(address as i32), call(Builtin::Push)]);
+                true
+            }
+        }
+    }
+
+    /// Compiles the instructions from the offset until the end of the
+    /// method, lazy object or entry point.
+    fn body(&mut self, start: usize, entry: Entry) -> Function {
+        let mut f = Function::new(&[], &[I32]);
+
+        let base = f.local(I32);
+        let tail = f.local(I32);
+        if entry == Entry::Method {
+            let arity = self
+                .declarations
+                .methods
+                .get(&(start as u64))
+                .map(|name| selector_arity(name))
+                .unwrap_or(0);
+            f.emit(&[
+                GlobalGet(runtime::SP),
+                I32Const(4 * (arity as i32 + 1)),
+                I32Sub,
+                LocalSet(base),
+            ]);
+        }
+
+        let mut pc = start;
+        loop {
+            let instruction = match self.instructions.get(pc) {
+                Some(instruction) => instruction,
+                None => {
+                    self.unsupported(&mut f, "running past the end of the program");
+                    break;
+                }
+            };
+            pc += 1;
+
+            match instruction {
+                Instruction::Noop | Instruction::DumpStack => {}
+
+                Instruction::Halt => {
+                    if entry != Entry::Main {
+                        self.unsupported(&mut f, "halting outside of the entry point");
+                        break;
+                    }
+                    f.emit(&[
+                        GlobalGet(runtime::SP),
+                        GlobalGet(runtime::STACK_START),
+                        I32Eq,
+                        If(super::encoder::BlockType::Empty),
+                        I32Const(0),
+                        Return,
+                        End,
+                        call(Builtin::PopEval),
+                        call(Builtin::Display),
+                        I32Const(VALUE as i32),
+                        I32Add,
+                        Return,
+                    ]);
+                    break;
+                }
+
+                Instruction::Panic => {
+                    f.emit(&[
+                        call(Builtin::Pop),
+                        call(Builtin::Display),
+                        call(Builtin::DebugString),
+                        call(Builtin::Panic),
+                        Unreachable,
+                    ]);
+                    break;
+                }
+
+                Instruction::DeclareClass(_)
+                | Instruction::DeclareVariable(_, _, _, _)
+                | Instruction::UseVariable(_)
+                | Instruction::DeclareMethod(_, _)
+                | Instruction::UseMethod(_)
+                | Instruction::OverrideMethod(_, _)
+                | Instruction::MarkClassTrue(_)
+                | Instruction::MarkClassFalse(_)
+                | Instruction::MarkClassString(_)
+                | Instruction::MarkClassCharacter(_)
+                | Instruction::MarkClassSymbol(_)
+                | Instruction::MarkClassU8(_)
+                | Instruction::MarkClassU16(_)
+                | Instruction::MarkClassU32(_)
+                | Instruction::MarkClassU64(_)
+                | Instruction::MarkClassU128(_)
+                | Instruction::MarkClassUBig(_)
+                | Instruction::MarkClassI8(_)
+                | Instruction::MarkClassI16(_)
+                | Instruction::MarkClassI32(_)
+                | Instruction::MarkClassI64(_)
+                | Instruction::MarkClassI128(_)
+                | Instruction::MarkClassIBig(_)
+                | Instruction::MarkClassF32(_)
+                | Instruction::MarkClassF64(_)
+                | Instruction::MarkClassFBig(_) => {
+                    // Declarations along the entry point have already been
+                    // collected.
+                    if entry != Entry::Main {
+                        self.unsupported(&mut f, "declarations outside of the entry point");
+                        break;
+                    }
+                }
+
+                Instruction::LoadObject(class) => {
+                    match self.declarations.class_indices.get(class) {
+                        Some(index) => {
+                            let object = self.data.object(*index as i32, NOTHING, &[]);
+                            f.emit(&[I32Const(object as i32), call(Builtin::Push)]);
+                        }
+                        None => {
+                            self.panic(&mut f, "unknown class");
+                            break;
+                        }
+                    }
+                }
+
+                Instruction::CallMethod(selector, _, _, _) => {
+                    f.emit(&[
+                        I32Const(self.selector_indices[selector]),
+                        call(Builtin::Send),
+                    ]);
+                }
+
+                Instruction::TailCallMethod(selector, _, _, _) => {
+                    let selector = self.selector_indices[selector];
+                    if entry == Entry::Method {
+                        f.emit(&[
+                            I32Const(selector),
+                            LocalGet(base),
+                            call(Builtin::SendTail),
+                            LocalTee(tail),
+                            If(super::encoder::BlockType::Empty),
+                            LocalGet(tail),
+                            Return,
+                            End,
+                        ]);
+                    } else {
+                        f.emit(&[I32Const(selector), call(Builtin::Send)]);
+                    }
+                }
+
+                Instruction::CallNative(method) => match runtime::native(method.name()) {
+                    Some(instructions) => f.emit(&instructions),
+                    None if method.name().starts_with("Loa/Process#")
+                        || method.name().starts_with("Loa/File#") =>
+                    {
+                        self.unsupported(&mut f, "effects");
+                        break;
+                    }
+                    None => {
+                        self.panic(&mut f, &format!("unknown native method: {}", method));
+                        break;
+                    }
+                },
+
+                Instruction::LoadLocal(index) => {
+                    f.emit(&[I32Const(*index as i32), call(Builtin::LoadLocal)]);
+                }
+                Instruction::DropLocal(index) => {
+                    f.emit(&[I32Const(*index as i32), call(Builtin::DropLocal)]);
+                }
+
+                Instruction::StoreGlobal(offset) | Instruction::LoadGlobal(offset) => {
+                    let next = self.global_indices.len() as i32;
+                    let index = *self.global_indices.entry(*offset).or_insert(next);
+                    let builtin = match instruction {
+                        Instruction::StoreGlobal(_) => Builtin::StoreGlobal,
+                        _ => Builtin::LoadGlobal,
+                    };
+                    f.emit(&[I32Const(index), call(builtin)]);
+                }
+
+                Instruction::LoadLazy(arity, offset) => {
+                    let function = self.function(*offset, Entry::Lazy);
+                    f.emit(&[
+                        I32Const(function as i32),
+                        I32Const(*arity as i32),
+                        call(Builtin::MakeLazy),
+                    ]);
+                }
+
+                Instruction::Return(arity) | Instruction::ReturnLazy(arity) => {
+                    let expected = match instruction {
+                        Instruction::Return(_) => Entry::Method,
+                        _ => Entry::Lazy,
+                    };
+                    if entry == Entry::Main {
+                        self.panic(&mut f, "empty call stack");
+                    } else if entry != expected {
+                        self.unsupported(&mut f, "returning from a different kind of frame");
+                    } else {
+                        f.emit(&[
+                            I32Const(*arity as i32),
+                            call(Builtin::Ret),
+                            I32Const(0),
+                            Return,
+                        ]);
+                    }
+                    break;
+                }
+
+                Instruction::LoadConstString(s) => {
+                    if !self.constant(
+                        &mut f,
+                        self.declarations.string_class,
+                        "Loa/String",
+                        |d, c| d.text(c, STRING, s),
+                    ) {
+                        break;
+                    }
+                }
+                Instruction::LoadConstSymbol(s) => {
+                    if !self.constant(
+                        &mut f,
+                        self.declarations.symbol_class,
+                        "Loa/Symbol",
+                        |d, c| d.text(c, SYMBOL, s),
+                    ) {
+                        break;
+                    }
+                }
+                Instruction::LoadConstCharacter(c) => {
+                    let class = self.declarations.character_class;
+                    let unit = *c as i32;
+                    if !self.constant(&mut f, class, "Loa/Character", |d, c| {
+                        d.object(c, CHARACTER, &unit.to_le_bytes())
+                    }) {
+                        break;
+                    }
+                }
+
+                Instruction::Rescue(_, _) => {
+                    self.unsupported(&mut f, "rescuing panics");
+                    break;
+                }
+
+                Instruction::LoadConstF32(_) | Instruction::LoadConstF64(_) => {
+                    let (kind, value) = match instruction {
+                        Instruction::LoadConstF32(v) => (NumberKind::F32, *v as f64),
+                        Instruction::LoadConstF64(v) => (NumberKind::F64, *v),
+                        _ => unreachable!(),
+                    };
+                    let class = self.declarations.number_classes[kind as usize];
+                    if !self.constant(&mut f, class, kind.class_name(), |d, c| {
+                        d.object(c, kind as i32, &value.to_le_bytes())
+                    }) {
+                        break;
+                    }
+                }
+                Instruction::LoadConstFBig(_) => {
+                    self.unsupported(&mut f, "big floats");
+                    break;
+                }
+
+                instruction => {
+                    let (kind, value) = match integer_constant(instruction) {
+                        Some(constant) => constant,
+                        None => unreachable!("every instruction is lowered"),
+                    };
+                    let value = match value {
+                        Some(value) => value,
+                        None => {
+                            self.unsupported(&mut f, "integers that don't fit in 64 bits");
+                            break;
+                        }
+                    };
+                    let class = self.declarations.number_classes[kind as usize];
+                    if !self.constant(&mut f, class, kind.class_name(), |d, c| {
+                        d.object(c, kind as i32, &value.to_le_bytes())
+                    }) {
+                        break;
+                    }
+                }
+            }
+        }
+
+        f.emit(&[Unreachable]);
+        f
+    }
+
+    fn tables(&mut self, selectors: &[u64]) -> Tables {
+        let declarations = self.declarations;
+        let data = &mut self.data;
+        let strings = |data: &mut Data, names: Vec<String>| {
+            let addresses: Vec<i32> = names.iter().map(|n| data.string(n) as i32).collect();
+            data.alloc_words(addresses)
+        };
+
+        let class_names = strings(
+            data,
+            declarations
+                .classes
+                .iter()
+                .map(|c| c.name.clone())
+                .collect(),
+        );
+        let selector_names = strings(
+            data,
+            selectors
+                .iter()
+                .map(|s| {
+                    declarations
+                        .methods
+                        .get(s)
+                        .cloned()
+                        .unwrap_or_else(|| "?".into())
+                })
+                .collect(),
+        );
+        let variable_names = strings(data, declarations.variables.clone());
+        let kind_names = strings(
+            data,
+            CONVERSIONS
+                .iter()
+                .map(|(_, k)| k.class_name().to_string())
+                .collect(),
+        );
+
+        let table_indices = &self.table_indices;
+        let mut dispatch = vec![];
+        for class in declarations.classes.iter() {
+            for selector in selectors {
+                dispatch.push(if let Some(v) = class.getters.get(selector) {
+                    (declarations.variable_indices[v] as i32) << 2 | 2
+                } else if let Some(v) = class.setters.get(selector) {
+                    (declarations.variable_indices[v] as i32) << 2 | 3
+                } else if let Some(method) = class.methods.get(selector) {
+                    (table_indices[&(*method, Entry::Method)] as i32) << 2 | 1
+                } else {
+                    0
+                });
+            }
+        }
+        let dispatch = data.alloc_words(dispatch);
+
+        let arities = data.alloc_words(std::iter::once(0).chain(self.queue.iter().map(
+            |(offset, entry)| {
+                match entry {
+                    Entry::Method => declarations
+                        .methods
+                        .get(offset)
+                        .map(|name| selector_arity(name) as i32)
+                        .unwrap_or(0),
+                    _ => 0,
+                }
+            },
+        )));
+        let globals = data.alloc_words(vec![0; self.global_indices.len().max(1)]);
+
+        let kinds: Vec<NumberKind> = CONVERSIONS.iter().map(|(_, k)| *k).collect();
+        let index = |kind: NumberKind| kinds.iter().position(|k| *k == kind).unwrap() as u8;
+        let join: Vec<u8> = kinds
+            .iter()
+            .flat_map(|a| kinds.iter().map(move |b| (*a, *b)))
+            .map(|(a, b)| index(a.join(b)))
+            .collect();
+        let join = data.alloc(&join);
+        let signed: Vec<u8> = kinds.iter().map(|k| index(k.signed())).collect();
+        let signed = data.alloc(&signed);
+        let wider: Vec<u8> = kinds.iter().map(|k| index(k.wider())).collect();
+        let wider = data.alloc(&wider);
+
+        let bounds = |natural: bool, bits: Option<usize>| -> (i64, i64) {
+            match (natural, bits) {
+                (true, Some(bits)) if bits < 64 => (0, (1 << bits) - 1),
+                (true, _) => (0, std::i64::MAX),
+                (false, Some(bits)) if bits < 64 => (-(1 << (bits - 1)), (1 << (bits - 1)) - 1),
+                (false, _) => (std::i64::MIN, std::i64::MAX),
+            }
+        };
+        let (min, max): (Vec<i64>, Vec<i64>) = kinds
+            .iter()
+            .map(|k| bounds(k.is_natural(), k.bits()))
+            .unzip();
+        let i64_bytes = |values: Vec<i64>| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect()
+        };
+        let min = data.alloc(&i64_bytes(min));
+        let max = data.alloc(&i64_bytes(max));
+
+        // The bounds of the integers that floats are truncated to, which are
+        // checked before whether they fit in an i64.
+        let float_bounds = |natural: bool, bits: Option<usize>| -> (f64, f64) {
+            match (natural, bits) {
+                (true, Some(bits)) => (0.0, 2f64.powi(bits as i32)),
+                (true, None) => (0.0, std::f64::INFINITY),
+                (false, Some(bits)) => {
+                    let bound = 2f64.powi(bits as i32 - 1);
+                    (-bound, bound)
+                }
+                (false, None) => (std::f64::NEG_INFINITY, std::f64::INFINITY),
+            }
+        };
+        let (float_min, float_max): (Vec<f64>, Vec<f64>) = kinds
+            .iter()
+            .map(|k| float_bounds(k.is_natural(), k.bits()))
+            .unzip();
+        let f64_bytes = |values: Vec<f64>| -> Vec<u8> {
+            values
+                .iter()
+                .flat_map(|v| v.to_le_bytes().to_vec())
+                .collect()
+        };
+        let float_min = data.alloc(&f64_bytes(float_min));
+        let float_max = data.alloc(&f64_bytes(float_max));
+
+        let number_classes = data.alloc_words(
+            declarations
+                .number_classes
+                .iter()
+                .map(|c| c.map(|c| c as i32).unwrap_or(-1)),
+        );
+
+        let escapes: Vec<i32> = (0..128u8)
+            .map(|b| {
+                let c = b as char;
+                let escaped = format!("{:?}", c.to_string());
+                let escaped = &escaped[1..escaped.len() - 1];
+                if escaped.len() == 1 {
+                    0
+                } else {
+                    data.string(escaped) as i32
+                }
+            })
+            .collect();
+        let escapes = data.alloc_words(escapes);
+        let scratch = data.alloc(&[0; runtime::SCRATCH_SIZE as usize]);
+
+        let true_object = declarations
+            .true_class
+            .map(|c| data.object(c as i32, NOTHING, &[]))
+            .unwrap_or(0);
+        let false_object = declarations
+            .false_class
+            .map(|c| data.object(c as i32, NOTHING, &[]))
+            .unwrap_or(0);
+
+        Tables {
+            code_signature: 0,
+            selectors: selectors.len() as u32,
+            dispatch,
+            selector_names,
+            class_names,
+            variable_names,
+            arities,
+            globals,
+            join,
+            signed,
+            wider,
+            min,
+            max,
+            float_min,
+            float_max,
+            number_classes,
+            kind_names,
+            escapes,
+            scratch,
+            true_object,
+            false_object,
+            string_class: Declarations::class_of(declarations.string_class),
+            character_class: Declarations::class_of(declarations.character_class),
+            symbol_class: Declarations::class_of(declarations.symbol_class),
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::*;
+
+// Objects live in linear memory, and start with the index of their class and
+// the kind of value they hold:
+//
+//   [class i32][kind i32][value...]
+//
+// Integers hold an i64 at `VALUE` and floats an f64, strings and symbols
+// hold their length at `VALUE` followed by their UTF-8 bytes at `BYTES`,
+// characters hold their UTF-16 code unit at `VALUE`, and objects with
+// instance variables hold the number of variables at `VALUE`, followed by
+// pairs of variable indices and values, sorted by index. Lazy objects have no class, and hold the table
+// index of the function that evaluates them at `VALUE`, their value once
+// they've been evaluated at `LAZY_VALUE`, and the objects they depend on.
+
+pub const CLASS: u32 = 0;
+pub const KIND: u32 = 4;
+pub const VALUE: u32 = 8;
+pub const BYTES: u32 = 12;
+pub const PAIRS: u32 = 12;
+pub const LAZY_VALUE: u32 = 12;
+pub const LAZY_ARITY: u32 = 16;
+pub const LAZY_DEPENDENCIES: u32 = 20;
+
+/// The class of lazy objects.
+pub const LAZY_CLASS: i32 = -1;
+/// The class of strings that are only used by the runtime, in programs that
+/// never mark a string class.
+pub const NO_CLASS: i32 = -2;
+
+// Kinds 0 to 14 are the indices of `NumberKind`s.
+pub const NOTHING: i32 = 16;
+pub const INSTANCE_VARIABLES: i32 = 17;
+pub const STRING: i32 = 18;
+pub const SYMBOL: i32 = 19;
+pub const CHARACTER: i32 = 20;
+pub const LAZY: i32 = 21;
+
+/// Integers are held as i64s, so kinds past `I64` are only supported as far
+/// as their values fit.
+pub const INTEGER_KINDS: i32 = 12;
+/// Floats of both sizes are held as f64s, and big floats aren't supported.
+pub const NUMBER_KINDS: i32 = 15;
+
+pub const PAGE_SIZE: u32 = 65536;
+pub const STACK_SIZE: u32 = 1 << 20;
+
+/// Address 0 is never used, so that it can mean "no object".
+const STATIC_START: u32 = 16;
+
+/// The static part of memory, which holds constants and the tables the
+/// runtime looks things up in.
+pub struct Data {
+    bytes: Vec<u8>,
+    objects: HashMap<Vec<u8>, u32>,
+    string_class: i32,
+}
+
+impl Data {
+    pub fn new(string_class: Option<usize>) -> Data {
+        Data {
+            bytes: vec![],
+            objects: HashMap::new(),
+            string_class: string_class.map(|c| c as i32).unwrap_or(NO_CLASS),
+        }
+    }
+
+    /// Places the bytes in memory, aligned to 8 bytes, and returns their
+    /// address.
+    pub fn alloc(&mut self, bytes: &[u8]) -> u32 {
+        while self.bytes.len() % 8 != 0 {
+            self.bytes.push(0);
+        }
+        let address = self.end();
+        self.bytes.extend_from_slice(bytes);
+        address
+    }
+
+    pub fn alloc_words<I: IntoIterator<Item = i32>>(&mut self, words: I) -> u32 {
+        let bytes: Vec<u8> = words
+            .into_iter()
+            .flat_map(|w| w.to_le_bytes().to_vec())
+            .collect();
+        self.alloc(&bytes)
+    }
+
+    /// Places an object in memory, unless an identical one is already
+    /// there. Objects are immutable, so they can be shared.
+    pub fn object(&mut self, class: i32, kind: i32, value: &[u8]) -> u32 {
+        let mut bytes = vec![];
+        bytes.extend_from_slice(&class.to_le_bytes());
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(value);
+        if let Some(address) = self.objects.get(&bytes) {
+            return *address;
+        }
+        let address = self.alloc(&bytes);
+        self.objects.insert(bytes, address);
+        address
+    }
+
+    pub fn string(&mut self, s: &str) -> u32 {
+        let class = self.string_class;
+        self.text(class, STRING, s)
+    }
+
+    pub fn text(&mut self, class: i32, kind: i32, s: &str) -> u32 {
+        let mut value = (s.len() as u32).to_le_bytes().to_vec();
+        value.extend_from_slice(s.as_bytes());
+        self.object(class, kind, &value)
+    }
+
+    pub fn start(&self) -> u32 {
+        STATIC_START
+    }
+
+    pub fn end(&self) -> u32 {
+        STATIC_START + self.bytes.len() as u32
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
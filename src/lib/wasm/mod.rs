@@ -0,0 +1,266 @@
+mod encoder;
+mod layout;
+mod runtime;
+
+pub use self::runtime::UNSUPPORTED;
+
+mod wasm_error;
+pub use self::wasm_error::*;
+
+mod generator;
+pub use self::generator::*;
+
+/// Runs a generated module in an interpreter, and returns what the program
+/// formats its result as, or the message it panics with.
+#[cfg(test)]
+pub(crate) fn run(module: &[u8]) -> Result<Option<String>, String> {
+    use wasmi::{ImportsBuilder, ModuleInstance, NopExternals, RuntimeValue};
+
+    let module = wasmi::Module::from_buffer(module).map_err(|e| e.to_string())?;
+    let instance = ModuleInstance::new(&module, &ImportsBuilder::default())
+        .map_err(|e| e.to_string())?
+        .assert_no_start();
+    let memory = instance
+        .export_by_name("memory")
+        .and_then(|e| e.as_memory().cloned())
+        .ok_or("no memory exported")?;
+
+    let read = |address: Option<RuntimeValue>| -> Result<Option<String>, String> {
+        match address {
+            Some(RuntimeValue::I32(0)) => Ok(None),
+            Some(RuntimeValue::I32(address)) => {
+                let length: u32 = memory
+                    .get_value(address as u32)
+                    .map_err(|e| e.to_string())?;
+                let bytes = memory
+                    .get(address as u32 + 4, length as usize)
+                    .map_err(|e| e.to_string())?;
+                String::from_utf8(bytes)
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            }
+            value => Err(format!("unexpected result: {:?}", value)),
+        }
+    };
+
+    match instance.invoke_export("main", &[], &mut NopExternals) {
+        Ok(result) => read(result),
+        Err(error) => {
+            let message = instance
+                .invoke_export("panic_message", &[], &mut NopExternals)
+                .map_err(|e| e.to_string())?;
+            Err(read(message)?.unwrap_or_else(|| error.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembly::{Assembly, Parser};
+    use crate::vm::VM;
+
+    const DECLARATIONS: &str = r#"
+        DeclareMethod "add:" @Counter#add:
+        @Int
+          DeclareClass "Loa/Int32"
+          MarkClassI32 @Int
+        @Float32
+          DeclareClass "Loa/Float32"
+          MarkClassF32 @Float32
+        @Float64
+          DeclareClass "Loa/Float64"
+          MarkClassF64 @Float64
+        @True
+          DeclareClass "Loa/True"
+          MarkClassTrue @True
+        @False
+          DeclareClass "Loa/False"
+          MarkClassFalse @False
+        @String
+          DeclareClass "Loa/String"
+          MarkClassString @String
+        @Counter
+          DeclareClass "Counter"
+          DeclareVariable "count" @count @count_get @count_set
+          UseVariable @count
+          UseMethod @Counter#add:
+    "#;
+
+    const DEFINITIONS: &str = r#"
+        @Counter#add:
+          LoadLocal 1
+          LoadLocal 1
+          CallMethod @count_get "test.loa" 1 1
+          CallNative Loa/Number#+
+          LoadLocal 1
+          TailCallMethod @count_set "test.loa" 1 1
+          Return 2
+        @lazy
+          LoadConstI32 3
+          LoadLocal 1
+          CallNative Loa/Number#*
+          ReturnLazy 1
+        @count
+          Noop
+        @count_get
+          Noop
+        @count_set
+          Noop
+    "#;
+
+    fn generate(main: &str) -> (Assembly, WasmResult<Vec<u8>>) {
+        let code = format!("{}{}\nHalt\n{}", DECLARATIONS, main, DEFINITIONS);
+        let assembly = Parser::new().parse(&code).unwrap();
+        (assembly.clone(), Generator::new().generate(assembly))
+    }
+
+    fn assert_runs_like_vm(main: &str) {
+        let (assembly, module) = generate(main);
+        let expected = VM::new().eval_pop::<()>(assembly.into()).unwrap();
+        assert_eq!(run(&module.unwrap()), Ok(Some(expected.to_string())));
+    }
+
+    #[test]
+    fn methods_and_variables() {
+        assert_runs_like_vm(
+            r#"
+            LoadConstI32 1
+            LoadObject @Counter
+            CallMethod @count_set "test.loa" 1 1
+            LoadConstI32 41
+            LoadLocal 1
+            CallMethod @Counter#add: "test.loa" 1 1
+            DropLocal 1
+            "#,
+        );
+    }
+
+    #[test]
+    fn lazy_objects() {
+        assert_runs_like_vm(
+            r#"
+            LoadConstI32 2
+            LoadLazy 1 @lazy
+            "#,
+        );
+    }
+
+    #[test]
+    fn strings_and_panics() {
+        assert_runs_like_vm(r#"LoadConstString "caf\u{e9}""#);
+
+        let (_, module) = generate("LoadConstString \"a \\\"b\\\"\"\nPanic");
+        assert_eq!(run(&module.unwrap()), Err(r#""a \"b\"""#.into()));
+    }
+
+    #[test]
+    fn floats() {
+        assert_runs_like_vm(
+            r#"
+            LoadConstI32 3
+            LoadConstF32 0.1
+            CallNative Loa/Number#+
+            "#,
+        );
+        assert_runs_like_vm(
+            r#"
+            LoadConstF64 2.5
+            LoadConstF64 -7.25
+            CallNative Loa/Number#rem:
+            "#,
+        );
+        assert_runs_like_vm(
+            r#"
+            LoadConstF32 3e38
+            LoadConstF32 3e38
+            CallNative Loa/Number#*
+            "#,
+        );
+        assert_runs_like_vm(
+            r#"
+            LoadConstF64 0.1
+            CallNative Loa/Number#asFloat32
+            "#,
+        );
+        assert_runs_like_vm(
+            r#"
+            LoadConstF64 -41.9
+            CallNative Loa/Number#asInt32
+            "#,
+        );
+        assert_runs_like_vm(
+            r#"
+            LoadConstF32 1.5
+            LoadConstI32 2
+            CallNative Loa/Number#<
+            "#,
+        );
+
+        let (_, module) = generate("LoadConstF64 1e300\nCallNative Loa/Number#asFloat32");
+        assert_eq!(
+            run(&module.unwrap()),
+            Err(format!("{} is out of bounds for Loa/Float32", 1e300))
+        );
+    }
+
+    #[test]
+    fn formats_floats_like_rust() {
+        let mut seed = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut doubles = vec![
+            0.1,
+            -0.0,
+            1.0,
+            1e21,
+            1e-7,
+            123_456.789,
+            std::f64::MAX,
+            std::f64::MIN_POSITIVE,
+            5e-324,
+            std::f64::NAN,
+            std::f64::NEG_INFINITY,
+        ];
+        let mut singles = vec![0.1f32, 16_777_216.0, std::f32::MAX, 1e-45, 1.1754942e-38];
+        for _ in 0..40 {
+            doubles.push(f64::from_bits(next()));
+            singles.push(f32::from_bits(next() as u32));
+        }
+
+        let values = doubles
+            .iter()
+            .map(|d| (format!("LoadConstF64 {}", d), d.to_string()))
+            .chain(
+                singles
+                    .iter()
+                    .map(|s| (format!("LoadConstF32 {}", s), s.to_string())),
+            );
+        for (main, expected) in values {
+            let (_, module) = generate(&main);
+            assert_eq!(run(&module.unwrap()), Ok(Some(expected)), "{}", main);
+        }
+    }
+
+    #[test]
+    fn unsupported_features() {
+        let (_, module) = generate("LoadConstU128 18446744073709551616");
+        assert!(run(&module.unwrap()).unwrap_err().starts_with(UNSUPPORTED));
+    }
+
+    #[test]
+    fn unknown_declarations() {
+        let assembly = Parser::new().parse("UseMethod @a\n@a\nHalt").unwrap();
+        assert_eq!(
+            Generator::new().generate(assembly),
+            Err(WasmError::UnknownMethod {
+                offset: 0,
+                method: 1
+            })
+        );
+    }
+}
@@ -0,0 +1,3971 @@
+use super::encoder::{BlockType, Function, Instr, Instr::*, ValueType::*};
+use super::layout::*;
+use strum::IntoEnumIterator;
+
+/// The prefix of the messages that generated modules panic with when they
+/// reach a feature that isn't supported in WebAssembly.
+pub const UNSUPPORTED: &str = "unsupported in WebAssembly";
+
+// The globals of every generated module.
+pub const SP: u32 = 0;
+pub const HEAP: u32 = 1;
+pub const PANIC: u32 = 2;
+pub const STACK_START: u32 = 3;
+pub const STACK_END: u32 = 4;
+
+pub const ADD: i32 = 0;
+pub const SUBTRACT: i32 = 1;
+pub const MULTIPLY: i32 = 2;
+pub const DIVIDE: i32 = 3;
+pub const REMAINDER: i32 = 4;
+
+pub const AND: i32 = 0;
+pub const OR: i32 = 1;
+pub const XOR: i32 = 2;
+
+pub const LESS: i32 = 1;
+pub const EQUAL: i32 = 2;
+pub const GREATER: i32 = 4;
+
+pub const LETTER: i32 = 0;
+pub const DIGIT: i32 = 1;
+pub const WHITESPACE: i32 = 2;
+
+const U16: i32 = 1;
+const U64: i32 = 3;
+const F32_KIND: i32 = 12;
+const F64_KIND: i32 = 13;
+const FBIG_KIND: i32 = 14;
+
+/// The number of 32 bit limbs in the big integers that formatting floats
+/// takes, which is enough for the largest and smallest `f64`s.
+const LIMBS: i32 = 40;
+const BIG_SIZE: i32 = LIMBS * 4;
+/// The size of the memory that `Tables::scratch` points to, which holds
+/// five big integers followed by the digits of a float.
+pub const SCRATCH_SIZE: u32 = 5 * BIG_SIZE as u32 + 32;
+
+const E: BlockType = BlockType::Empty;
+const R32: BlockType = BlockType::Value(I32);
+const R64: BlockType = BlockType::Value(I64);
+const RF64: BlockType = BlockType::Value(F64);
+
+/// Where the tables that the runtime looks things up in are placed in
+/// memory, along with what else it needs to know about the program.
+pub struct Tables {
+    /// The type of the functions that methods and lazy objects are compiled
+    /// to, which take no arguments and return the table index of the method
+    /// to tail call, or 0.
+    pub code_signature: u32,
+    pub selectors: u32,
+    /// The method, getter or setter that each class responds to each
+    /// selector with, as the index of its function or variable, shifted
+    /// left by 2 and tagged with 1, 2 or 3 respectively.
+    pub dispatch: u32,
+    pub selector_names: u32,
+    pub class_names: u32,
+    pub variable_names: u32,
+    /// The arity of each method in the function table.
+    pub arities: u32,
+    pub globals: u32,
+    pub join: u32,
+    pub signed: u32,
+    pub wider: u32,
+    pub min: u32,
+    pub max: u32,
+    /// The bounds, as f64s, that a truncated float must be at least and
+    /// less than to be converted to each integer kind.
+    pub float_min: u32,
+    pub float_max: u32,
+    pub number_classes: u32,
+    pub kind_names: u32,
+    /// The escaped form of every ASCII character in debug strings, or 0 for
+    /// characters that aren't escaped.
+    pub escapes: u32,
+    /// Memory for `FloatToString` to work in.
+    pub scratch: u32,
+    pub true_object: u32,
+    pub false_object: u32,
+    pub string_class: i32,
+    pub character_class: i32,
+    pub symbol_class: i32,
+}
+
+/// The functions every generated module starts with, in the order of their
+/// indices. The natives take their receiver and arguments from the operand
+/// stack, and push their result, like in the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumIter)]
+pub enum Builtin {
+    Alloc,
+    Copy,
+    Panic,
+    PanicMessage,
+    Push,
+    Pop,
+    LoadLocal,
+    DropLocal,
+    Ret,
+    StoreGlobal,
+    LoadGlobal,
+
+    StringNew,
+    Retag,
+    Concat,
+    IntToString,
+    FloatToString,
+    FloatDigits,
+    BigSet,
+    BigAdd,
+    BigSub,
+    BigMulSmall,
+    BigCmp,
+    CharToString,
+    Display,
+    DisplayVariables,
+    DebugString,
+    StrCmp,
+    Utf16Length,
+    Utf16At,
+    AsciiCase,
+
+    Force,
+    PopEval,
+    Equals,
+    BoxBool,
+    NewInt,
+    NewCharacter,
+    Fits,
+    BoxInt,
+    IntegerKind,
+    NumberKind,
+    IntArithmetic,
+    FloatArithmetic,
+    FloatRemainder,
+    GetVar,
+    SetVar,
+    MakeLazy,
+    Lookup,
+    Send,
+    SendTail,
+
+    ObjectEq,
+    ObjectAsString,
+    NumberArithmetic,
+    NumberCompare,
+    NumberNegated,
+    NumberConvert,
+    IntegerBitwise,
+    IntegerInvert,
+    IntegerShift,
+    StringConcat,
+    StringSize,
+    StringAt,
+    StringAsSymbol,
+    StringMap,
+    CharacterCodePoint,
+    CharacterTest,
+    CharacterMap,
+    SymbolName,
+}
+
+fn call(builtin: Builtin) -> Instr {
+    Call(builtin as u32)
+}
+
+/// The instructions that call the native method with the qualified name, if
+/// it's supported.
+pub fn native(name: &str) -> Option<Vec<Instr>> {
+    use self::Builtin::*;
+
+    let with = |argument: i32, builtin: Builtin| Some(vec![I32Const(argument), call(builtin)]);
+
+    if let Some(selector) = name.strip_prefix("Loa/Number#as") {
+        return crate::vm::CONVERSIONS
+            .iter()
+            .position(|(s, _)| s[2..] == *selector)
+            .and_then(|kind| with(kind as i32, NumberConvert));
+    }
+
+    match name {
+        "Loa/Object#==" => Some(vec![call(ObjectEq)]),
+        "Loa/Object#asString" => Some(vec![call(ObjectAsString)]),
+
+        "Loa/Number#+" => with(ADD, NumberArithmetic),
+        "Loa/Number#-" => with(SUBTRACT, NumberArithmetic),
+        "Loa/Number#*" => with(MULTIPLY, NumberArithmetic),
+        "Loa/Number#/" => with(DIVIDE, NumberArithmetic),
+        "Loa/Number#rem:" => with(REMAINDER, NumberArithmetic),
+
+        "Loa/Number#==" => with(EQUAL, NumberCompare),
+        "Loa/Number#<" => with(LESS, NumberCompare),
+        "Loa/Number#>" => with(GREATER, NumberCompare),
+        "Loa/Number#<=" => with(LESS | EQUAL, NumberCompare),
+        "Loa/Number#>=" => with(GREATER | EQUAL, NumberCompare),
+
+        "Loa/Number#negated" => Some(vec![call(NumberNegated)]),
+
+        "Loa/Integer#bitAnd:" => with(AND, IntegerBitwise),
+        "Loa/Integer#bitOr:" => with(OR, IntegerBitwise),
+        "Loa/Integer#bitXor:" => with(XOR, IntegerBitwise),
+        "Loa/Integer#bitInvert" => Some(vec![call(IntegerInvert)]),
+        "Loa/Integer#bitShiftLeft:" => with(1, IntegerShift),
+        "Loa/Integer#bitShiftRight:" => with(0, IntegerShift),
+
+        "Loa/String#+" => Some(vec![call(StringConcat)]),
+        "Loa/String#size" => Some(vec![call(StringSize)]),
+        "Loa/String#at:" => Some(vec![call(StringAt)]),
+        "Loa/String#asSymbol" => Some(vec![call(StringAsSymbol)]),
+        "Loa/String#asUppercase" => with(1, StringMap),
+        "Loa/String#asLowercase" => with(0, StringMap),
+
+        "Loa/Character#codePoint" => Some(vec![call(CharacterCodePoint)]),
+        "Loa/Character#isLetter" => with(LETTER, CharacterTest),
+        "Loa/Character#isDigit" => with(DIGIT, CharacterTest),
+        "Loa/Character#isWhitespace" => with(WHITESPACE, CharacterTest),
+        "Loa/Character#asUppercase" => with(1, CharacterMap),
+        "Loa/Character#asLowercase" => with(0, CharacterMap),
+
+        "Loa/Symbol#name" => Some(vec![call(SymbolName)]),
+
+        _ => None,
+    }
+}
+
+/// Builds the functions of the runtime, in the order of `Builtin`.
+pub fn functions(tables: &Tables, data: &mut Data) -> Vec<Function> {
+    let mut builder = Builder { t: tables, data };
+    Builtin::iter().map(|b| builder.function(b)).collect()
+}
+
+struct Builder<'a> {
+    t: &'a Tables,
+    data: &'a mut Data,
+}
+
+impl<'a> Builder<'a> {
+    fn string(&mut self, s: &str) -> Instr {
+        I32Const(self.data.string(s) as i32)
+    }
+
+    fn panic(&mut self, message: &str) -> Vec<Instr> {
+        vec![self.string(message), call(Builtin::Panic), Unreachable]
+    }
+
+    fn unsupported(&mut self, feature: &str) -> Vec<Instr> {
+        self.panic(&format!("{}: {}", UNSUPPORTED, feature))
+    }
+
+    fn overflow(&mut self) -> Vec<Instr> {
+        self.unsupported("integers that don't fit in 64 bits")
+    }
+
+    /// Panics with the displayed object in the local, followed by the
+    /// suffix, unless the object is of the kind.
+    fn expect_kind(&mut self, local: u32, kind: i32, suffix: &str) -> Vec<Instr> {
+        vec![
+            LocalGet(local),
+            I32Load(KIND),
+            I32Const(kind),
+            I32Ne,
+            If(E),
+            LocalGet(local),
+            call(Builtin::Display),
+            self.string(suffix),
+            call(Builtin::Concat),
+            call(Builtin::Panic),
+            Unreachable,
+            End,
+        ]
+    }
+
+    /// Loads the i32 at index `local` of the table.
+    fn entry(table: u32, local: u32) -> Vec<Instr> {
+        vec![
+            I32Const(table as i32),
+            LocalGet(local),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            I32Load(0),
+        ]
+    }
+
+    fn increment(local: u32) -> Vec<Instr> {
+        vec![LocalGet(local), I32Const(1), I32Add, LocalSet(local)]
+    }
+
+    /// Loads the f64 at index `local` of the table.
+    fn float_entry(table: u32, local: u32) -> Vec<Instr> {
+        vec![
+            I32Const(table as i32),
+            LocalGet(local),
+            I32Const(3),
+            I32Shl,
+            I32Add,
+            F64Load(0),
+        ]
+    }
+
+    /// Loads the number in the local as an f64.
+    fn float(local: u32) -> Vec<Instr> {
+        vec![
+            LocalGet(local),
+            I32Load(KIND),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(RF64),
+            LocalGet(local),
+            F64Load(VALUE),
+            Else,
+            LocalGet(local),
+            I64Load(VALUE),
+            F64ConvertI64S,
+            End,
+        ]
+    }
+
+    fn is_infinite() -> Vec<Instr> {
+        vec![F64Abs, F64Const(std::f64::INFINITY), F64Eq]
+    }
+
+    /// The kind of the object, or -1 unless it's below the bound.
+    fn kind_below(bound: i32) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        let kind = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            I32Load(KIND),
+            LocalTee(kind),
+            I32Const(-1),
+            LocalGet(kind),
+            I32Const(bound),
+            I32LtU,
+            Select,
+        ]);
+        f
+    }
+
+    fn function(&mut self, builtin: Builtin) -> Function {
+        use self::Builtin::*;
+
+        match builtin {
+            Alloc => self.alloc(),
+            Copy => self.copy(),
+            Panic => {
+                let mut f = Function::new(&[I32], &[]);
+                f.emit(&[LocalGet(0), GlobalSet(PANIC), Unreachable]);
+                f
+            }
+            PanicMessage => {
+                let mut f = Function::new(&[], &[I32]);
+                f.emit(&[
+                    GlobalGet(PANIC),
+                    If(R32),
+                    GlobalGet(PANIC),
+                    I32Const(VALUE as i32),
+                    I32Add,
+                    Else,
+                    I32Const(0),
+                    End,
+                ]);
+                f
+            }
+            Push => self.push(),
+            Pop => self.pop(),
+            LoadLocal => self.load_local(),
+            DropLocal => self.drop_local(),
+            Ret => self.ret(),
+            StoreGlobal => {
+                let mut f = Function::new(&[I32], &[]);
+                f.emit(&[
+                    I32Const(self.t.globals as i32),
+                    LocalGet(0),
+                    I32Const(2),
+                    I32Shl,
+                    I32Add,
+                    call(Pop),
+                    I32Store(0),
+                ]);
+                f
+            }
+            LoadGlobal => {
+                let mut f = Function::new(&[I32], &[]);
+                let value = f.local(I32);
+                f.emit(&Self::entry(self.t.globals, 0));
+                f.emit(&[LocalTee(value), I32Eqz, If(E)]);
+                f.emit(&self.panic("global not found"));
+                f.emit(&[End, LocalGet(value), call(Push)]);
+                f
+            }
+
+            StringNew => self.string_new(),
+            Retag => self.retag(),
+            Concat => self.concat(),
+            IntToString => self.int_to_string(),
+            FloatToString => self.float_to_string(),
+            FloatDigits => self.float_digits(),
+            BigSet => self.big_set(),
+            BigAdd => self.big_add(),
+            BigSub => self.big_sub(),
+            BigMulSmall => self.big_mul_small(),
+            BigCmp => self.big_cmp(),
+            CharToString => self.char_to_string(),
+            Display => self.display(),
+            DisplayVariables => self.display_variables(),
+            DebugString => self.debug_string(),
+            StrCmp => self.str_cmp(),
+            Utf16Length => self.utf16_length(),
+            Utf16At => self.utf16_at(),
+            AsciiCase => self.ascii_case(),
+
+            Force => self.force(),
+            PopEval => {
+                let mut f = Function::new(&[], &[I32]);
+                f.emit(&[call(Pop), call(Force)]);
+                f
+            }
+            Equals => self.equals(),
+            BoxBool => self.box_bool(),
+            NewInt => self.new_int(),
+            NewCharacter => {
+                let mut f = Function::new(&[I32], &[I32]);
+                let object = f.local(I32);
+                f.emit(&[
+                    I32Const(12),
+                    call(Alloc),
+                    LocalTee(object),
+                    I32Const(self.t.character_class),
+                    I32Store(CLASS),
+                    LocalGet(object),
+                    I32Const(CHARACTER),
+                    I32Store(KIND),
+                    LocalGet(object),
+                    LocalGet(0),
+                    I32Store(VALUE),
+                    LocalGet(object),
+                ]);
+                f
+            }
+            Fits => {
+                let mut f = Function::new(&[I32, I64], &[I32]);
+                f.emit(&[
+                    LocalGet(1),
+                    I32Const(self.t.min as i32),
+                    LocalGet(0),
+                    I32Const(3),
+                    I32Shl,
+                    I32Add,
+                    I64Load(0),
+                    I64GeS,
+                    LocalGet(1),
+                    I32Const(self.t.max as i32),
+                    LocalGet(0),
+                    I32Const(3),
+                    I32Shl,
+                    I32Add,
+                    I64Load(0),
+                    I64LeS,
+                    I32And,
+                ]);
+                f
+            }
+            BoxInt => self.box_int(),
+            IntegerKind => Self::kind_below(INTEGER_KINDS),
+            NumberKind => Self::kind_below(NUMBER_KINDS),
+            IntArithmetic => self.int_arithmetic(),
+            FloatArithmetic => self.float_arithmetic(),
+            FloatRemainder => self.float_remainder(),
+            GetVar => self.get_var(),
+            SetVar => self.set_var(),
+            MakeLazy => self.make_lazy(),
+            Lookup => self.lookup(),
+            Send => {
+                let mut f = Function::new(&[I32], &[]);
+                let function = f.local(I32);
+                f.emit(&[
+                    LocalGet(0),
+                    call(Lookup),
+                    LocalSet(function),
+                    Block(E),
+                    Loop(E),
+                    LocalGet(function),
+                    I32Eqz,
+                    BrIf(1),
+                    LocalGet(function),
+                    CallIndirect(self.t.code_signature),
+                    LocalSet(function),
+                    Br(0),
+                    End,
+                    End,
+                ]);
+                f
+            }
+            SendTail => self.send_tail(),
+
+            ObjectEq => {
+                let mut f = Function::new(&[], &[]);
+                let receiver = f.local(I32);
+                f.emit(&[
+                    call(PopEval),
+                    LocalSet(receiver),
+                    LocalGet(receiver),
+                    call(PopEval),
+                    call(Equals),
+                    call(BoxBool),
+                    call(Push),
+                ]);
+                f
+            }
+            ObjectAsString => {
+                let mut f = Function::new(&[], &[]);
+                f.emit(&[call(PopEval), call(Display), call(Push)]);
+                f
+            }
+            NumberArithmetic => self.number_arithmetic(),
+            NumberCompare => self.number_compare(),
+            NumberNegated => self.number_negated(),
+            NumberConvert => self.number_convert(),
+            IntegerBitwise => self.integer_bitwise(),
+            IntegerInvert => self.integer_invert(),
+            IntegerShift => self.integer_shift(),
+            StringConcat => self.string_concat(),
+            StringSize => {
+                let mut f = Function::new(&[], &[]);
+                let receiver = f.local(I32);
+                f.emit(&[call(PopEval), LocalSet(receiver)]);
+                f.emit(&self.expect_kind(receiver, STRING, " is not a string"));
+                f.emit(&[
+                    I32Const(U64),
+                    LocalGet(receiver),
+                    call(Utf16Length),
+                    call(NewInt),
+                    call(Push),
+                ]);
+                f
+            }
+            StringAt => self.string_at(),
+            StringAsSymbol => {
+                let mut f = Function::new(&[], &[]);
+                let receiver = f.local(I32);
+                f.emit(&[call(PopEval), LocalSet(receiver)]);
+                f.emit(&self.expect_kind(receiver, STRING, " is not a string"));
+                f.emit(&[
+                    LocalGet(receiver),
+                    I32Const(self.t.symbol_class),
+                    I32Const(SYMBOL),
+                    call(Retag),
+                    call(Push),
+                ]);
+                f
+            }
+            StringMap => self.string_map(),
+            CharacterCodePoint => {
+                let mut f = Function::new(&[], &[]);
+                let receiver = f.local(I32);
+                f.emit(&[call(PopEval), LocalSet(receiver)]);
+                f.emit(&self.expect_kind(receiver, CHARACTER, " is not a character"));
+                f.emit(&[
+                    I32Const(U16),
+                    LocalGet(receiver),
+                    I32Load(VALUE),
+                    I64ExtendI32U,
+                    call(NewInt),
+                    call(Push),
+                ]);
+                f
+            }
+            CharacterTest => self.character_test(),
+            CharacterMap => self.character_map(),
+            SymbolName => {
+                let mut f = Function::new(&[], &[]);
+                let receiver = f.local(I32);
+                f.emit(&[call(PopEval), LocalSet(receiver)]);
+                f.emit(&self.expect_kind(receiver, SYMBOL, " is not a symbol"));
+                f.emit(&[
+                    LocalGet(receiver),
+                    I32Const(self.t.string_class),
+                    I32Const(STRING),
+                    call(Retag),
+                    call(Push),
+                ]);
+                f
+            }
+        }
+    }
+
+    /// Bump allocates memory, which is never freed, and grows the memory
+    /// when it runs out.
+    fn alloc(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        let pointer = f.local(I32);
+        f.emit(&[
+            GlobalGet(HEAP),
+            LocalTee(pointer),
+            LocalGet(0),
+            I32Const(7),
+            I32Add,
+            I32Const(-8),
+            I32And,
+            I32Add,
+            GlobalSet(HEAP),
+            Block(E),
+            GlobalGet(HEAP),
+            MemorySize,
+            I32Const(16),
+            I32Shl,
+            I32LeU,
+            BrIf(0),
+            GlobalGet(HEAP),
+            MemorySize,
+            I32Const(16),
+            I32Shl,
+            I32Sub,
+            I32Const(PAGE_SIZE as i32 - 1),
+            I32Add,
+            I32Const(16),
+            I32ShrU,
+            MemoryGrow,
+            I32Const(-1),
+            I32Ne,
+            BrIf(0),
+        ]);
+        f.emit(&self.unsupported("running out of memory"));
+        f.emit(&[End, LocalGet(pointer)]);
+        f
+    }
+
+    fn copy(&mut self) -> Function {
+        let (destination, source, length) = (0, 1, 2);
+        let mut f = Function::new(&[I32, I32, I32], &[]);
+        f.emit(&[
+            Block(E),
+            Loop(E),
+            LocalGet(length),
+            I32Eqz,
+            BrIf(1),
+            LocalGet(destination),
+            LocalGet(source),
+            I32Load8U(0),
+            I32Store8(0),
+        ]);
+        f.emit(&Self::increment(destination));
+        f.emit(&Self::increment(source));
+        f.emit(&[
+            LocalGet(length),
+            I32Const(1),
+            I32Sub,
+            LocalSet(length),
+            Br(0),
+            End,
+            End,
+        ]);
+        f
+    }
+
+    fn push(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        f.emit(&[
+            Block(E),
+            GlobalGet(SP),
+            GlobalGet(STACK_END),
+            I32LtU,
+            BrIf(0),
+        ]);
+        f.emit(&self.unsupported("operand stacks this deep"));
+        f.emit(&[
+            End,
+            GlobalGet(SP),
+            LocalGet(0),
+            I32Store(0),
+            GlobalGet(SP),
+            I32Const(4),
+            I32Add,
+            GlobalSet(SP),
+        ]);
+        f
+    }
+
+    fn pop(&mut self) -> Function {
+        let mut f = Function::new(&[], &[I32]);
+        f.emit(&[
+            Block(E),
+            GlobalGet(SP),
+            GlobalGet(STACK_START),
+            I32GtU,
+            BrIf(0),
+        ]);
+        f.emit(&self.panic("tried to pop empty stack"));
+        f.emit(&[
+            End,
+            GlobalGet(SP),
+            I32Const(4),
+            I32Sub,
+            GlobalSet(SP),
+            GlobalGet(SP),
+            I32Load(0),
+        ]);
+        f
+    }
+
+    fn load_local(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let address = f.local(I32);
+        f.emit(&[
+            GlobalGet(SP),
+            LocalGet(0),
+            I32Const(1),
+            I32Add,
+            I32Const(2),
+            I32Shl,
+            I32Sub,
+            LocalTee(address),
+            GlobalGet(STACK_START),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic("not enough locals on the stack"));
+        f.emit(&[End, LocalGet(address), I32Load(0), call(Builtin::Push)]);
+        f
+    }
+
+    /// Removes the object at the depth, and moves the objects above it down.
+    fn drop_local(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let address = f.local(I32);
+        let next = f.local(I32);
+        f.emit(&[
+            GlobalGet(SP),
+            LocalGet(0),
+            I32Const(1),
+            I32Add,
+            I32Const(2),
+            I32Shl,
+            I32Sub,
+            LocalSet(address),
+            Block(E),
+            Loop(E),
+            LocalGet(address),
+            I32Const(4),
+            I32Add,
+            LocalTee(next),
+            GlobalGet(SP),
+            I32GeU,
+            BrIf(1),
+            LocalGet(address),
+            LocalGet(next),
+            I32Load(0),
+            I32Store(0),
+            LocalGet(next),
+            LocalSet(address),
+            Br(0),
+            End,
+            End,
+            GlobalGet(SP),
+            I32Const(4),
+            I32Sub,
+            GlobalSet(SP),
+        ]);
+        f
+    }
+
+    /// Pops the result and the arguments, and pushes the result back.
+    fn ret(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let result = f.local(I32);
+        f.emit(&[
+            call(Builtin::Pop),
+            LocalSet(result),
+            GlobalGet(SP),
+            LocalGet(0),
+            I32Const(2),
+            I32Shl,
+            I32Sub,
+            GlobalSet(SP),
+            LocalGet(result),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    /// Allocates a string of the length, with its bytes left to be filled
+    /// in.
+    fn string_new(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        let string = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            I32Const(BYTES as i32),
+            I32Add,
+            call(Builtin::Alloc),
+            LocalTee(string),
+            I32Const(self.t.string_class),
+            I32Store(CLASS),
+            LocalGet(string),
+            I32Const(STRING),
+            I32Store(KIND),
+            LocalGet(string),
+            LocalGet(0),
+            I32Store(VALUE),
+            LocalGet(string),
+        ]);
+        f
+    }
+
+    /// Copies a string or symbol, with another class and kind.
+    fn retag(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I32, I32], &[I32]);
+        let copy = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            I32Load(VALUE),
+            call(Builtin::StringNew),
+            LocalTee(copy),
+            LocalGet(1),
+            I32Store(CLASS),
+            LocalGet(copy),
+            LocalGet(2),
+            I32Store(KIND),
+            LocalGet(copy),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(0),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(0),
+            I32Load(VALUE),
+            call(Builtin::Copy),
+            LocalGet(copy),
+        ]);
+        f
+    }
+
+    fn concat(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        let string = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            I32Load(VALUE),
+            LocalGet(1),
+            I32Load(VALUE),
+            I32Add,
+            call(Builtin::StringNew),
+            LocalTee(string),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(0),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(0),
+            I32Load(VALUE),
+            call(Builtin::Copy),
+            LocalGet(string),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(0),
+            I32Load(VALUE),
+            I32Add,
+            LocalGet(1),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(1),
+            I32Load(VALUE),
+            call(Builtin::Copy),
+            LocalGet(string),
+        ]);
+        f
+    }
+
+    fn int_to_string(&mut self) -> Function {
+        let mut f = Function::new(&[I64], &[I32]);
+        let rest = f.local(I64);
+        let length = f.local(I32);
+        let string = f.local(I32);
+        let pointer = f.local(I32);
+        let digit = f.local(I32);
+        f.emit(&[
+            I32Const(0),
+            LocalSet(length),
+            LocalGet(0),
+            LocalSet(rest),
+            Loop(E),
+        ]);
+        f.emit(&Self::increment(length));
+        f.emit(&[
+            LocalGet(rest),
+            I64Const(10),
+            I64DivS,
+            LocalTee(rest),
+            I64Eqz,
+            I32Eqz,
+            BrIf(0),
+            End,
+            LocalGet(length),
+            LocalGet(0),
+            I64Const(0),
+            I64LtS,
+            I32Add,
+            LocalTee(length),
+            call(Builtin::StringNew),
+            LocalTee(string),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(length),
+            I32Add,
+            LocalSet(pointer),
+            LocalGet(0),
+            LocalSet(rest),
+            Loop(E),
+            LocalGet(pointer),
+            I32Const(1),
+            I32Sub,
+            LocalSet(pointer),
+            LocalGet(rest),
+            I64Const(10),
+            I64RemS,
+            I32WrapI64,
+            LocalSet(digit),
+            LocalGet(pointer),
+            I32Const(0),
+            LocalGet(digit),
+            I32Sub,
+            LocalGet(digit),
+            LocalGet(digit),
+            I32Const(0),
+            I32LtS,
+            Select,
+            I32Const(b'0' as i32),
+            I32Add,
+            I32Store8(0),
+            LocalGet(rest),
+            I64Const(10),
+            I64DivS,
+            LocalTee(rest),
+            I64Eqz,
+            I32Eqz,
+            BrIf(0),
+            End,
+            LocalGet(0),
+            I64Const(0),
+            I64LtS,
+            If(E),
+            LocalGet(string),
+            I32Const(b'-' as i32),
+            I32Store8(BYTES),
+            End,
+            LocalGet(string),
+        ]);
+        f
+    }
+
+    /// Formats a float of the kind like `Display` does in Rust, which is what
+    /// the VM formats floats with.
+    fn float_to_string(&mut self) -> Function {
+        let mut f = Function::new(&[F64, I32], &[I32]);
+        let magnitude = f.local(F64);
+        let string = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            LocalGet(0),
+            F64Ne,
+            If(E),
+            self.string("NaN"),
+            Return,
+            End,
+            LocalGet(0),
+            F64Abs,
+            LocalTee(magnitude),
+            F64Const(std::f64::INFINITY),
+            F64Eq,
+            If(R32),
+            self.string("inf"),
+            Else,
+            LocalGet(magnitude),
+            F64Const(0.0),
+            F64Eq,
+            If(R32),
+            self.string("0"),
+            Else,
+            LocalGet(magnitude),
+            LocalGet(1),
+            call(Builtin::FloatDigits),
+            End,
+            End,
+            LocalSet(string),
+            LocalGet(0),
+            I64ReinterpretF64,
+            I64Const(0),
+            I64LtS,
+            If(R32),
+            self.string("-"),
+            LocalGet(string),
+            call(Builtin::Concat),
+            Else,
+            LocalGet(string),
+            End,
+        ]);
+        f
+    }
+
+    /// Formats a positive, finite float of the kind with the fewest digits
+    /// that still read back as the same float, following `format_shortest`
+    /// in Rust's `flt2dec::strategy::dragon`, down to how it decodes floats.
+    fn float_digits(&mut self) -> Function {
+        use self::Builtin::*;
+
+        let (x, kind) = (0, 1);
+        let mut f = Function::new(&[F64, I32], &[I32]);
+        let mantissa = f.local(I64);
+        let normal = f.local(I64);
+        let exponent = f.local(I32);
+        let plus = f.local(I64);
+        let inclusive = f.local(I32);
+        let k = f.local(I32);
+        let n = f.local(I32);
+        let digit = f.local(I32);
+        let down = f.local(I32);
+        let up = f.local(I32);
+        let i = f.local(I32);
+        let length = f.local(I32);
+        let string = f.local(I32);
+
+        let scratch = self.t.scratch as i32;
+        let (mant, minus, plus_big, scale, high) = (
+            scratch,
+            scratch + BIG_SIZE,
+            scratch + 2 * BIG_SIZE,
+            scratch + 3 * BIG_SIZE,
+            scratch + 4 * BIG_SIZE,
+        );
+        let digits = scratch + 5 * BIG_SIZE;
+        let times_ten = |bigs: &[i32]| -> Vec<Instr> {
+            bigs.iter()
+                .flat_map(|b| vec![I32Const(*b), I32Const(10), call(BigMulSmall)])
+                .collect()
+        };
+        let double = |bigs: &[i32]| -> Vec<Instr> {
+            bigs.iter()
+                .flat_map(|b| vec![I32Const(*b), I32Const(*b), I32Const(*b), call(BigAdd)])
+                .collect()
+        };
+        // Whether `mant + plus`, times ten if asked, reaches the scale.
+        let too_high = |ten: bool| -> Vec<Instr> {
+            let mut instrs = vec![
+                I32Const(high),
+                I32Const(mant),
+                I32Const(plus_big),
+                call(BigAdd),
+            ];
+            if ten {
+                instrs.extend(times_ten(&[high]));
+            }
+            instrs.extend_from_slice(&[
+                I32Const(0),
+                I32Const(high),
+                I32Const(scale),
+                call(BigCmp),
+                LocalGet(inclusive),
+                I32Add,
+                I32LtS,
+            ]);
+            instrs
+        };
+        let digit_at = |index: u32| vec![I32Const(digits), LocalGet(index), I32Add];
+
+        // Decode the float into `mantissa * 2^exponent`, with the distances
+        // to its neighbours in `plus` and 1, like `decode` in Rust.
+        f.emit(&[
+            LocalGet(x),
+            I64ReinterpretF64,
+            I64Const((1 << 52) - 1),
+            I64And,
+            LocalSet(mantissa),
+            LocalGet(x),
+            I64ReinterpretF64,
+            I64Const(52),
+            I64ShrU,
+            I32WrapI64,
+            LocalTee(exponent),
+            I32Eqz,
+            If(E),
+            I32Const(1),
+            LocalSet(exponent),
+            Else,
+            LocalGet(mantissa),
+            I64Const(1 << 52),
+            I64Or,
+            LocalSet(mantissa),
+            End,
+            LocalGet(exponent),
+            I32Const(1075),
+            I32Sub,
+            LocalSet(exponent),
+            I64Const(1 << 52),
+            LocalSet(normal),
+            LocalGet(kind),
+            I32Const(F32_KIND),
+            I32Eq,
+            If(E),
+            LocalGet(mantissa),
+            I64Const(29),
+            I64ShrU,
+            LocalSet(mantissa),
+            LocalGet(exponent),
+            I32Const(29),
+            I32Add,
+            LocalSet(exponent),
+            I64Const(1 << 23),
+            LocalSet(normal),
+            LocalGet(exponent),
+            I32Const(-149),
+            I32LtS,
+            If(E),
+            LocalGet(mantissa),
+            I32Const(-149),
+            LocalGet(exponent),
+            I32Sub,
+            I64ExtendI32U,
+            I64ShrU,
+            LocalSet(mantissa),
+            I32Const(-149),
+            LocalSet(exponent),
+            End,
+            End,
+            LocalGet(mantissa),
+            LocalGet(normal),
+            I64LtU,
+            LocalGet(mantissa),
+            I64Const(1),
+            I64And,
+            I64Eqz,
+            I32Or,
+            LocalSet(inclusive),
+            LocalGet(mantissa),
+            LocalGet(normal),
+            I64Eq,
+            If(E),
+            LocalGet(mantissa),
+            I64Const(2),
+            I64Shl,
+            LocalSet(mantissa),
+            LocalGet(exponent),
+            I32Const(2),
+            I32Sub,
+            LocalSet(exponent),
+            I64Const(2),
+            LocalSet(plus),
+            Else,
+            LocalGet(mantissa),
+            I64Const(1),
+            I64Shl,
+            LocalSet(mantissa),
+            LocalGet(exponent),
+            I32Const(1),
+            I32Sub,
+            LocalSet(exponent),
+            I64Const(1),
+            LocalSet(plus),
+            End,
+            I32Const(mant),
+            LocalGet(mantissa),
+            call(BigSet),
+            I32Const(minus),
+            I64Const(1),
+            call(BigSet),
+            I32Const(plus_big),
+            LocalGet(plus),
+            call(BigSet),
+            I32Const(scale),
+            I64Const(1),
+            call(BigSet),
+        ]);
+
+        // Scale the mantissa and the distances to its neighbours by powers
+        // of two and ten, so that they are relative to `scale` and
+        // `mant + plus` is between a tenth of it and all of it.
+        f.emit(&[
+            Block(E),
+            Loop(E),
+            LocalGet(exponent),
+            I32Eqz,
+            BrIf(1),
+            LocalGet(exponent),
+            I32Const(0),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&double(&[scale]));
+        f.emit(&Self::increment(exponent));
+        f.emit(&[Else]);
+        f.emit(&double(&[mant, minus, plus_big]));
+        f.emit(&[
+            LocalGet(exponent),
+            I32Const(1),
+            I32Sub,
+            LocalSet(exponent),
+            End,
+            Br(0),
+            End,
+            End,
+            Block(E),
+            Loop(E),
+        ]);
+        f.emit(&too_high(false));
+        f.emit(&[I32Eqz, BrIf(1)]);
+        f.emit(&times_ten(&[scale]));
+        f.emit(&Self::increment(k));
+        f.emit(&[Br(0), End, End, Block(E), Loop(E)]);
+        f.emit(&too_high(true));
+        f.emit(&[BrIf(1)]);
+        f.emit(&times_ten(&[mant, minus, plus_big]));
+        f.emit(&[
+            LocalGet(k),
+            I32Const(1),
+            I32Sub,
+            LocalSet(k),
+            Br(0),
+            End,
+            End,
+        ]);
+
+        // Generate digits until the rest is within the distance to either
+        // neighbour.
+        f.emit(&[Loop(E)]);
+        f.emit(&times_ten(&[mant, minus, plus_big]));
+        f.emit(&[
+            I32Const(0),
+            LocalSet(digit),
+            Block(E),
+            Loop(E),
+            I32Const(mant),
+            I32Const(scale),
+            call(BigCmp),
+            I32Const(0),
+            I32LtS,
+            BrIf(1),
+            I32Const(mant),
+            I32Const(mant),
+            I32Const(scale),
+            call(BigSub),
+        ]);
+        f.emit(&Self::increment(digit));
+        f.emit(&[Br(0), End, End]);
+        f.emit(&digit_at(n));
+        f.emit(&[LocalGet(digit), I32Const(b'0' as i32), I32Add, I32Store8(0)]);
+        f.emit(&Self::increment(n));
+        f.emit(&[
+            I32Const(mant),
+            I32Const(minus),
+            call(BigCmp),
+            LocalGet(inclusive),
+            I32LtS,
+            LocalSet(down),
+        ]);
+        f.emit(&too_high(false));
+        f.emit(&[LocalTee(up), LocalGet(down), I32Or, I32Eqz, BrIf(0), End]);
+
+        // Round the last digit up if the rest is closer to the upper
+        // neighbour, or only within the distance to it.
+        f.emit(&[
+            LocalGet(up),
+            LocalGet(down),
+            I32Eqz,
+            I32Const(high),
+            I32Const(mant),
+            I32Const(mant),
+            call(BigAdd),
+            I32Const(high),
+            I32Const(scale),
+            call(BigCmp),
+            I32Const(-1),
+            I32Ne,
+            I32Or,
+            I32And,
+            If(E),
+            LocalGet(n),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            I32Const(1),
+            I32Sub,
+            LocalSet(i),
+        ]);
+        f.emit(&digit_at(i));
+        f.emit(&[I32Load8U(0), I32Const(b'9' as i32), I32Ne, If(E)]);
+        f.emit(&digit_at(i));
+        f.emit(&digit_at(i));
+        f.emit(&[I32Load8U(0), I32Const(1), I32Add, I32Store8(0), Br(2), End]);
+        f.emit(&digit_at(i));
+        f.emit(&[
+            I32Const(b'0' as i32),
+            I32Store8(0),
+            LocalGet(i),
+            I32Eqz,
+            If(E),
+            I32Const(digits),
+            I32Const(b'1' as i32),
+            I32Store8(0),
+        ]);
+        f.emit(&digit_at(n));
+        f.emit(&[I32Const(b'0' as i32), I32Store8(0)]);
+        f.emit(&Self::increment(n));
+        f.emit(&Self::increment(k));
+        f.emit(&[Br(2), End, Br(0), End, End, End]);
+
+        // Place the decimal point `k` digits in, like `digits_to_dec_str`.
+        f.emit(&[
+            LocalGet(k),
+            I32Const(0),
+            I32LeS,
+            If(R32),
+            I32Const(2),
+            LocalGet(k),
+            I32Sub,
+            LocalGet(n),
+            I32Add,
+            Else,
+            LocalGet(k),
+            LocalGet(n),
+            I32LtS,
+            If(R32),
+            LocalGet(n),
+            I32Const(1),
+            I32Add,
+            Else,
+            LocalGet(k),
+            End,
+            End,
+            LocalTee(length),
+            call(StringNew),
+            LocalSet(string),
+            I32Const(0),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(length),
+            I32GeU,
+            BrIf(1),
+            LocalGet(string),
+            LocalGet(i),
+            I32Add,
+            I32Const(b'0' as i32),
+            I32Store8(BYTES),
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[
+            Br(0),
+            End,
+            End,
+            LocalGet(k),
+            I32Const(0),
+            I32LeS,
+            If(E),
+            LocalGet(string),
+            I32Const(b'.' as i32),
+            I32Store8(BYTES + 1),
+            LocalGet(string),
+            I32Const(BYTES as i32 + 2),
+            I32Add,
+            LocalGet(k),
+            I32Sub,
+            I32Const(digits),
+            LocalGet(n),
+            call(Copy),
+            Else,
+            LocalGet(k),
+            LocalGet(n),
+            I32LtS,
+            If(E),
+            LocalGet(string),
+            I32Const(BYTES as i32),
+            I32Add,
+            I32Const(digits),
+            LocalGet(k),
+            call(Copy),
+            LocalGet(string),
+            LocalGet(k),
+            I32Add,
+            I32Const(b'.' as i32),
+            I32Store8(BYTES),
+            LocalGet(string),
+            I32Const(BYTES as i32 + 1),
+            I32Add,
+            LocalGet(k),
+            I32Add,
+            I32Const(digits),
+            LocalGet(k),
+            I32Add,
+            LocalGet(n),
+            LocalGet(k),
+            I32Sub,
+            call(Copy),
+            Else,
+            LocalGet(string),
+            I32Const(BYTES as i32),
+            I32Add,
+            I32Const(digits),
+            LocalGet(n),
+            call(Copy),
+            End,
+            End,
+            LocalGet(string),
+        ]);
+        f
+    }
+
+    /// Runs the body for the offset of each limb of a big integer, starting
+    /// from the least significant one.
+    fn each_limb(f: &mut Function, offset: u32, body: &[Instr]) {
+        f.emit(&[
+            Block(E),
+            Loop(E),
+            LocalGet(offset),
+            I32Const(BIG_SIZE),
+            I32GeU,
+            BrIf(1),
+        ]);
+        f.emit(body);
+        f.emit(&[
+            LocalGet(offset),
+            I32Const(4),
+            I32Add,
+            LocalSet(offset),
+            Br(0),
+            End,
+            End,
+        ]);
+    }
+
+    fn limb(big: u32, offset: u32) -> Vec<Instr> {
+        vec![LocalGet(big), LocalGet(offset), I32Add]
+    }
+
+    /// Sets the big integer at the address to a positive integer.
+    fn big_set(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I64], &[]);
+        let offset = f.local(I32);
+        let mut body = Self::limb(0, offset);
+        body.extend_from_slice(&[I32Const(0), I32Store(0)]);
+        Self::each_limb(&mut f, offset, &body);
+        f.emit(&[LocalGet(0), LocalGet(1), I64Store(0)]);
+        f
+    }
+
+    /// Adds two big integers, into the first address.
+    fn big_add(&mut self) -> Function {
+        let (destination, a, b) = (0, 1, 2);
+        let mut f = Function::new(&[I32, I32, I32], &[]);
+        let offset = f.local(I32);
+        let carry = f.local(I64);
+        let sum = f.local(I64);
+        let mut body = Self::limb(destination, offset);
+        body.extend(Self::limb(a, offset));
+        body.push(I64Load32U(0));
+        body.extend(Self::limb(b, offset));
+        body.extend_from_slice(&[
+            I64Load32U(0),
+            I64Add,
+            LocalGet(carry),
+            I64Add,
+            LocalTee(sum),
+            I64Store32(0),
+            LocalGet(sum),
+            I64Const(32),
+            I64ShrU,
+            LocalSet(carry),
+        ]);
+        Self::each_limb(&mut f, offset, &body);
+        f
+    }
+
+    /// Subtracts a big integer from one that's at least as large, into the
+    /// first address.
+    fn big_sub(&mut self) -> Function {
+        let (destination, a, b) = (0, 1, 2);
+        let mut f = Function::new(&[I32, I32, I32], &[]);
+        let offset = f.local(I32);
+        let borrow = f.local(I64);
+        let difference = f.local(I64);
+        let mut body = Self::limb(destination, offset);
+        body.extend(Self::limb(a, offset));
+        body.push(I64Load32U(0));
+        body.extend(Self::limb(b, offset));
+        body.extend_from_slice(&[
+            I64Load32U(0),
+            I64Sub,
+            LocalGet(borrow),
+            I64Sub,
+            LocalTee(difference),
+            I64Store32(0),
+            LocalGet(difference),
+            I64Const(63),
+            I64ShrU,
+            LocalSet(borrow),
+        ]);
+        Self::each_limb(&mut f, offset, &body);
+        f
+    }
+
+    /// Multiplies the big integer at the address by a small integer.
+    fn big_mul_small(&mut self) -> Function {
+        let (big, factor) = (0, 1);
+        let mut f = Function::new(&[I32, I32], &[]);
+        let offset = f.local(I32);
+        let carry = f.local(I64);
+        let product = f.local(I64);
+        let mut body = Self::limb(big, offset);
+        body.extend(Self::limb(big, offset));
+        body.extend_from_slice(&[
+            I64Load32U(0),
+            LocalGet(factor),
+            I64ExtendI32U,
+            I64Mul,
+            LocalGet(carry),
+            I64Add,
+            LocalTee(product),
+            I64Store32(0),
+            LocalGet(product),
+            I64Const(32),
+            I64ShrU,
+            LocalSet(carry),
+        ]);
+        Self::each_limb(&mut f, offset, &body);
+        f
+    }
+
+    /// Compares two big integers, returning -1, 0 or 1.
+    fn big_cmp(&mut self) -> Function {
+        let (a, b) = (0, 1);
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        let offset = f.local(I32);
+        let x = f.local(I64);
+        let y = f.local(I64);
+        f.emit(&[
+            I32Const(BIG_SIZE),
+            LocalSet(offset),
+            Block(E),
+            Loop(E),
+            LocalGet(offset),
+            I32Eqz,
+            BrIf(1),
+            LocalGet(offset),
+            I32Const(4),
+            I32Sub,
+            LocalSet(offset),
+        ]);
+        f.emit(&Self::limb(a, offset));
+        f.emit(&[I64Load32U(0), LocalSet(x)]);
+        f.emit(&Self::limb(b, offset));
+        f.emit(&[
+            I64Load32U(0),
+            LocalTee(y),
+            LocalGet(x),
+            I64Ne,
+            If(E),
+            I32Const(1),
+            I32Const(-1),
+            LocalGet(x),
+            LocalGet(y),
+            I64GtU,
+            Select,
+            Return,
+            End,
+            Br(0),
+            End,
+            End,
+            I32Const(0),
+        ]);
+        f
+    }
+
+    /// Encodes a UTF-16 code unit as UTF-8. Unpaired surrogates are replaced
+    /// with U+FFFD.
+    fn char_to_string(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        let string = f.local(I32);
+        let continuation = |shift: i32, offset: u32| {
+            vec![
+                LocalGet(string),
+                LocalGet(0),
+                I32Const(shift),
+                I32ShrU,
+                I32Const(0x3f),
+                I32And,
+                I32Const(0x80),
+                I32Or,
+                I32Store8(BYTES + offset),
+            ]
+        };
+        f.emit(&[
+            LocalGet(0),
+            I32Const(0xd800),
+            I32Sub,
+            I32Const(0x800),
+            I32LtU,
+            If(E),
+            I32Const(0xfffd),
+            LocalSet(0),
+            End,
+            LocalGet(0),
+            I32Const(0x80),
+            I32LtU,
+            If(R32),
+            I32Const(1),
+            call(Builtin::StringNew),
+            LocalTee(string),
+            LocalGet(0),
+            I32Store8(BYTES),
+            LocalGet(string),
+            Else,
+            LocalGet(0),
+            I32Const(0x800),
+            I32LtU,
+            If(R32),
+            I32Const(2),
+            call(Builtin::StringNew),
+            LocalTee(string),
+            LocalGet(0),
+            I32Const(6),
+            I32ShrU,
+            I32Const(0xc0),
+            I32Or,
+            I32Store8(BYTES),
+        ]);
+        f.emit(&continuation(0, 1));
+        f.emit(&[
+            LocalGet(string),
+            Else,
+            I32Const(3),
+            call(Builtin::StringNew),
+            LocalTee(string),
+            LocalGet(0),
+            I32Const(12),
+            I32ShrU,
+            I32Const(0xe0),
+            I32Or,
+            I32Store8(BYTES),
+        ]);
+        f.emit(&continuation(6, 1));
+        f.emit(&continuation(0, 2));
+        f.emit(&[LocalGet(string), End, End]);
+        f
+    }
+
+    /// Formats an object like `Display` does in the VM.
+    fn display(&mut self) -> Function {
+        use self::Builtin::*;
+
+        let mut f = Function::new(&[I32], &[I32]);
+        let kind = f.local(I32);
+        let is = |k: i32| vec![LocalGet(kind), I32Const(k), I32Eq, If(E)];
+        f.emit(&[
+            LocalGet(0),
+            I32Load(CLASS),
+            I32Const(LAZY_CLASS),
+            I32Eq,
+            If(E),
+            self.string("$lazy"),
+            Return,
+            End,
+            LocalGet(0),
+            I32Load(KIND),
+            LocalSet(kind),
+        ]);
+        f.emit(&is(NOTHING));
+        f.emit(&[
+            I32Const(self.t.class_names as i32),
+            LocalGet(0),
+            I32Load(CLASS),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            I32Load(0),
+            Return,
+            End,
+        ]);
+        f.emit(&is(INSTANCE_VARIABLES));
+        f.emit(&[LocalGet(0), call(DisplayVariables), Return, End]);
+        f.emit(&is(STRING));
+        f.emit(&[LocalGet(0), Return, End]);
+        f.emit(&is(SYMBOL));
+        f.emit(&[self.string("#"), LocalGet(0), call(Concat), Return, End]);
+        f.emit(&is(CHARACTER));
+        f.emit(&[
+            LocalGet(0),
+            I32Load(VALUE),
+            call(CharToString),
+            Return,
+            End,
+            LocalGet(kind),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+            LocalGet(0),
+            F64Load(VALUE),
+            LocalGet(kind),
+            call(FloatToString),
+            Return,
+            End,
+            LocalGet(0),
+            I64Load(VALUE),
+            call(IntToString),
+        ]);
+        f
+    }
+
+    /// Formats an object with instance variables as `a Class(a=1, b=2)`,
+    /// with the variables sorted and deduplicated like in the VM.
+    fn display_variables(&mut self) -> Function {
+        use self::Builtin::*;
+
+        let mut f = Function::new(&[I32], &[I32]);
+        let count = f.local(I32);
+        let strings = f.local(I32);
+        let i = f.local(I32);
+        let j = f.local(I32);
+        let string = f.local(I32);
+        let pair = f.local(I32);
+        let result = f.local(I32);
+        let skip = f.local(I32);
+        let at = |index: u32| {
+            vec![
+                LocalGet(strings),
+                LocalGet(index),
+                I32Const(2),
+                I32Shl,
+                I32Add,
+            ]
+        };
+
+        f.emit(&[
+            LocalGet(0),
+            I32Load(VALUE),
+            LocalTee(count),
+            I32Const(2),
+            I32Shl,
+            call(Alloc),
+            LocalSet(strings),
+            I32Const(0),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(count),
+            I32GeU,
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(i),
+            I32Const(3),
+            I32Shl,
+            I32Add,
+            LocalTee(pair),
+            I32Load(PAIRS),
+            LocalSet(string),
+        ]);
+        f.emit(&Self::entry(self.t.variable_names, string));
+        f.emit(&[
+            self.string("="),
+            call(Concat),
+            LocalGet(pair),
+            I32Load(PAIRS + 4),
+            call(Display),
+            call(Concat),
+            LocalSet(string),
+            // Insertion sort, since there are only ever a few variables.
+            LocalGet(i),
+            LocalSet(j),
+            Block(E),
+            Loop(E),
+            LocalGet(j),
+            I32Eqz,
+            BrIf(1),
+        ]);
+        f.emit(&at(j));
+        f.emit(&[
+            I32Const(4),
+            I32Sub,
+            I32Load(0),
+            LocalGet(string),
+            call(StrCmp),
+            I32Const(0),
+            I32LeS,
+            BrIf(1),
+        ]);
+        f.emit(&at(j));
+        f.emit(&at(j));
+        f.emit(&[
+            I32Const(4),
+            I32Sub,
+            I32Load(0),
+            I32Store(0),
+            LocalGet(j),
+            I32Const(1),
+            I32Sub,
+            LocalSet(j),
+            Br(0),
+            End,
+            End,
+        ]);
+        f.emit(&at(j));
+        f.emit(&[LocalGet(string), I32Store(0)]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End, self.string("a ")]);
+        f.emit(&[
+            I32Const(self.t.class_names as i32),
+            LocalGet(0),
+            I32Load(CLASS),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            I32Load(0),
+            call(Concat),
+            self.string("("),
+            call(Concat),
+            LocalSet(result),
+            I32Const(0),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(count),
+            I32GeU,
+            BrIf(1),
+        ]);
+        f.emit(&at(i));
+        f.emit(&[
+            I32Load(0),
+            LocalSet(string),
+            I32Const(0),
+            LocalSet(skip),
+            LocalGet(i),
+            If(E),
+        ]);
+        f.emit(&at(i));
+        f.emit(&[
+            I32Const(4),
+            I32Sub,
+            I32Load(0),
+            LocalGet(string),
+            call(StrCmp),
+            I32Eqz,
+            LocalTee(skip),
+            I32Eqz,
+            If(E),
+            LocalGet(result),
+            self.string(", "),
+            call(Concat),
+            LocalSet(result),
+            End,
+            End,
+            LocalGet(skip),
+            I32Eqz,
+            If(E),
+            LocalGet(result),
+            LocalGet(string),
+            call(Concat),
+            LocalSet(result),
+            End,
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[
+            Br(0),
+            End,
+            End,
+            LocalGet(result),
+            self.string(")"),
+            call(Concat),
+        ]);
+        f
+    }
+
+    /// Quotes and escapes a string, like formatting it with `{:?}`.
+    fn debug_string(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        let length = f.local(I32);
+        let i = f.local(I32);
+        let escape = f.local(I32);
+        let string = f.local(I32);
+        let pointer = f.local(I32);
+        let escapes = self.t.escapes as i32;
+        let next = |f: &mut Function| {
+            f.emit(&[
+                I32Const(0),
+                LocalSet(escape),
+                LocalGet(0),
+                LocalGet(i),
+                I32Add,
+                I32Load8U(BYTES),
+                LocalTee(pointer),
+                I32Const(0x80),
+                I32LtU,
+                If(E),
+                I32Const(escapes),
+                LocalGet(pointer),
+                I32Const(2),
+                I32Shl,
+                I32Add,
+                I32Load(0),
+                LocalSet(escape),
+                End,
+            ]);
+        };
+
+        f.emit(&[
+            I32Const(2),
+            LocalSet(length),
+            I32Const(0),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(0),
+            I32Load(VALUE),
+            I32GeU,
+            BrIf(1),
+        ]);
+        next(&mut f);
+        f.emit(&[
+            LocalGet(length),
+            LocalGet(escape),
+            If(R32),
+            LocalGet(escape),
+            I32Load(VALUE),
+            Else,
+            I32Const(1),
+            End,
+            I32Add,
+            LocalSet(length),
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[
+            Br(0),
+            End,
+            End,
+            LocalGet(length),
+            call(Builtin::StringNew),
+            LocalTee(string),
+            I32Const(b'"' as i32),
+            I32Store8(BYTES),
+            LocalGet(string),
+            I32Const(BYTES as i32 + 1),
+            I32Add,
+            LocalSet(length),
+            I32Const(0),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(0),
+            I32Load(VALUE),
+            I32GeU,
+            BrIf(1),
+        ]);
+        next(&mut f);
+        f.emit(&[
+            LocalGet(escape),
+            If(E),
+            LocalGet(length),
+            LocalGet(escape),
+            I32Const(BYTES as i32),
+            I32Add,
+            LocalGet(escape),
+            I32Load(VALUE),
+            call(Builtin::Copy),
+            LocalGet(length),
+            LocalGet(escape),
+            I32Load(VALUE),
+            I32Add,
+            LocalSet(length),
+            Else,
+            LocalGet(length),
+            LocalGet(pointer),
+            I32Store8(0),
+        ]);
+        f.emit(&Self::increment(length));
+        f.emit(&[End]);
+        f.emit(&Self::increment(i));
+        f.emit(&[
+            Br(0),
+            End,
+            End,
+            LocalGet(length),
+            I32Const(b'"' as i32),
+            I32Store8(0),
+            LocalGet(string),
+        ]);
+        f
+    }
+
+    /// Compares the bytes of two strings, returning -1, 0 or 1.
+    fn str_cmp(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        let length = f.local(I32);
+        let i = f.local(I32);
+        let a = f.local(I32);
+        let b = f.local(I32);
+        let lengths = vec![LocalGet(0), I32Load(VALUE), LocalGet(1), I32Load(VALUE)];
+        f.emit(&lengths);
+        f.emit(&lengths);
+        f.emit(&[
+            I32LtU,
+            Select,
+            LocalSet(length),
+            I32Const(0),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(length),
+            I32GeU,
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(BYTES),
+            LocalTee(a),
+            LocalGet(1),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(BYTES),
+            LocalTee(b),
+            I32Ne,
+            If(E),
+            I32Const(-1),
+            I32Const(1),
+            LocalGet(a),
+            LocalGet(b),
+            I32LtU,
+            Select,
+            Return,
+            End,
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End]);
+        f.emit(&lengths);
+        f.emit(&[I32GtU]);
+        f.emit(&lengths);
+        f.emit(&[I32LtU, I32Sub]);
+        f
+    }
+
+    fn utf16_length(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I64]);
+        let i = f.local(I32);
+        let count = f.local(I32);
+        let byte = f.local(I32);
+        f.emit(&[
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(0),
+            I32Load(VALUE),
+            I32GeU,
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(BYTES),
+            LocalSet(byte),
+            // Every character starts with a byte that isn't a continuation,
+            // and those encoded in 4 bytes take two UTF-16 code units.
+            LocalGet(count),
+            LocalGet(byte),
+            I32Const(0xc0),
+            I32And,
+            I32Const(0x80),
+            I32Ne,
+            I32Add,
+            LocalGet(byte),
+            I32Const(0xf0),
+            I32GeU,
+            I32Add,
+            LocalSet(count),
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End, LocalGet(count), I64ExtendI32U]);
+        f
+    }
+
+    /// The UTF-16 code unit at the index of a string, or -1 if the index is
+    /// out of bounds.
+    fn utf16_at(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I64], &[I32]);
+        let index = f.local(I32);
+        let position = f.local(I32);
+        let i = f.local(I32);
+        let byte = f.local(I32);
+        let code_point = f.local(I32);
+        let size = f.local(I32);
+        let continuation = |offset: u32, shift: i32| {
+            vec![
+                LocalGet(0),
+                LocalGet(i),
+                I32Add,
+                I32Load8U(BYTES + offset),
+                I32Const(0x3f),
+                I32And,
+                I32Const(shift),
+                I32Shl,
+                I32Or,
+            ]
+        };
+        let lead = |mask: i32, shift: i32| {
+            vec![
+                LocalGet(byte),
+                I32Const(mask),
+                I32And,
+                I32Const(shift),
+                I32Shl,
+            ]
+        };
+
+        f.emit(&[
+            LocalGet(1),
+            I64Const(0x7fff_ffff),
+            I64GtU,
+            If(E),
+            I32Const(-1),
+            Return,
+            End,
+            LocalGet(1),
+            I32WrapI64,
+            LocalSet(index),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(0),
+            I32Load(VALUE),
+            I32GeU,
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(BYTES),
+            LocalSet(byte),
+            LocalGet(byte),
+            I32Const(0x80),
+            I32LtU,
+            If(E),
+            LocalGet(byte),
+            LocalSet(code_point),
+            I32Const(1),
+            LocalSet(size),
+            Else,
+            LocalGet(byte),
+            I32Const(0xe0),
+            I32LtU,
+            If(E),
+        ]);
+        f.emit(&lead(0x1f, 6));
+        f.emit(&continuation(1, 0));
+        f.emit(&[
+            LocalSet(code_point),
+            I32Const(2),
+            LocalSet(size),
+            Else,
+            LocalGet(byte),
+            I32Const(0xf0),
+            I32LtU,
+            If(E),
+        ]);
+        f.emit(&lead(0x0f, 12));
+        f.emit(&continuation(1, 6));
+        f.emit(&continuation(2, 0));
+        f.emit(&[LocalSet(code_point), I32Const(3), LocalSet(size), Else]);
+        f.emit(&lead(0x07, 18));
+        f.emit(&continuation(1, 12));
+        f.emit(&continuation(2, 6));
+        f.emit(&continuation(3, 0));
+        f.emit(&[
+            LocalSet(code_point),
+            I32Const(4),
+            LocalSet(size),
+            End,
+            End,
+            End,
+            LocalGet(code_point),
+            I32Const(0x10000),
+            I32GeU,
+            If(E),
+            LocalGet(position),
+            LocalGet(index),
+            I32Eq,
+            If(E),
+            LocalGet(code_point),
+            I32Const(0x10000),
+            I32Sub,
+            I32Const(10),
+            I32ShrU,
+            I32Const(0xd800),
+            I32Add,
+            Return,
+            End,
+            LocalGet(position),
+            I32Const(1),
+            I32Add,
+            LocalGet(index),
+            I32Eq,
+            If(E),
+            LocalGet(code_point),
+            I32Const(0x3ff),
+            I32And,
+            I32Const(0xdc00),
+            I32Add,
+            Return,
+            End,
+            LocalGet(position),
+            I32Const(2),
+            I32Add,
+            LocalSet(position),
+            Else,
+            LocalGet(position),
+            LocalGet(index),
+            I32Eq,
+            If(E),
+            LocalGet(code_point),
+            Return,
+            End,
+        ]);
+        f.emit(&Self::increment(position));
+        f.emit(&[
+            End,
+            LocalGet(i),
+            LocalGet(size),
+            I32Add,
+            LocalSet(i),
+            Br(0),
+            End,
+            End,
+            I32Const(-1),
+        ]);
+        f
+    }
+
+    /// Maps an ASCII character to upper or lower case.
+    fn ascii_case(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        f.emit(&[LocalGet(0), I32Const(0x80), I32GeU, If(E)]);
+        f.emit(&self.unsupported("changing the case of non-ASCII characters"));
+        f.emit(&[
+            End,
+            LocalGet(1),
+            If(R32),
+            LocalGet(0),
+            I32Const(32),
+            I32Sub,
+            LocalGet(0),
+            LocalGet(0),
+            I32Const(b'a' as i32),
+            I32Sub,
+            I32Const(26),
+            I32LtU,
+            Select,
+            Else,
+            LocalGet(0),
+            I32Const(32),
+            I32Add,
+            LocalGet(0),
+            LocalGet(0),
+            I32Const(b'A' as i32),
+            I32Sub,
+            I32Const(26),
+            I32LtU,
+            Select,
+            End,
+        ]);
+        f
+    }
+
+    /// Evaluates a lazy object, and every lazy object it evaluates to, and
+    /// resolves them all to the final value.
+    fn force(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        let start = f.local(I32);
+        let value = f.local(I32);
+        let i = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            LocalSet(start),
+            Block(E),
+            Loop(E),
+            LocalGet(0),
+            I32Load(CLASS),
+            I32Const(LAZY_CLASS),
+            I32Ne,
+            BrIf(1),
+            LocalGet(0),
+            I32Load(LAZY_VALUE),
+            LocalTee(value),
+            If(E),
+            LocalGet(value),
+            LocalSet(0),
+            Br(1),
+            End,
+            LocalGet(0),
+            I32Load(LAZY_ARITY),
+            LocalSet(i),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            I32Eqz,
+            BrIf(1),
+            LocalGet(i),
+            I32Const(1),
+            I32Sub,
+            LocalSet(i),
+            LocalGet(0),
+            LocalGet(i),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            I32Load(LAZY_DEPENDENCIES),
+            call(Builtin::Push),
+            Br(0),
+            End,
+            End,
+            LocalGet(0),
+            I32Load(VALUE),
+            CallIndirect(self.t.code_signature),
+            Drop,
+            LocalGet(0),
+            call(Builtin::Pop),
+            I32Store(LAZY_VALUE),
+            LocalGet(0),
+            I32Load(LAZY_VALUE),
+            LocalSet(0),
+            Br(0),
+            End,
+            End,
+            Block(E),
+            Loop(E),
+            LocalGet(start),
+            LocalGet(0),
+            I32Eq,
+            BrIf(1),
+            LocalGet(start),
+            I32Load(LAZY_VALUE),
+            LocalSet(value),
+            LocalGet(start),
+            LocalGet(0),
+            I32Store(LAZY_VALUE),
+            LocalGet(value),
+            LocalSet(start),
+            Br(0),
+            End,
+            End,
+            LocalGet(0),
+        ]);
+        f
+    }
+
+    /// Compares objects like `PartialEq` does in the VM, where lazy objects
+    /// are never equal to anything.
+    fn equals(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        let kind = f.local(I32);
+        let count = f.local(I32);
+        let i = f.local(I32);
+        let a = f.local(I32);
+        let b = f.local(I32);
+        let unless = |mut condition: Vec<Instr>| {
+            condition.extend_from_slice(&[If(E), I32Const(0), Return, End]);
+            condition
+        };
+        let is = |k: i32| vec![LocalGet(kind), I32Const(k), I32Eq];
+
+        f.emit(&unless(vec![
+            LocalGet(0),
+            I32Load(CLASS),
+            I32Const(LAZY_CLASS),
+            I32Eq,
+            LocalGet(1),
+            I32Load(CLASS),
+            I32Const(LAZY_CLASS),
+            I32Eq,
+            I32Or,
+            LocalGet(0),
+            I32Load(CLASS),
+            LocalGet(1),
+            I32Load(CLASS),
+            I32Ne,
+            I32Or,
+            LocalGet(0),
+            I32Load(KIND),
+            LocalTee(kind),
+            LocalGet(1),
+            I32Load(KIND),
+            I32Ne,
+            I32Or,
+        ]));
+        f.emit(&is(NOTHING));
+        f.emit(&[If(E), I32Const(1), Return, End]);
+        f.emit(&is(STRING));
+        f.emit(&is(SYMBOL));
+        f.emit(&[
+            I32Or,
+            If(E),
+            LocalGet(0),
+            LocalGet(1),
+            call(Builtin::StrCmp),
+            I32Eqz,
+            Return,
+            End,
+        ]);
+        f.emit(&is(CHARACTER));
+        f.emit(&[
+            If(E),
+            LocalGet(0),
+            I32Load(VALUE),
+            LocalGet(1),
+            I32Load(VALUE),
+            I32Eq,
+            Return,
+            End,
+        ]);
+        f.emit(&is(INSTANCE_VARIABLES));
+        f.emit(&[If(E)]);
+        f.emit(&unless(vec![
+            LocalGet(0),
+            I32Load(VALUE),
+            LocalTee(count),
+            LocalGet(1),
+            I32Load(VALUE),
+            I32Ne,
+        ]));
+        f.emit(&[
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(count),
+            I32GeU,
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(i),
+            I32Const(3),
+            I32Shl,
+            I32Add,
+            LocalSet(a),
+            LocalGet(1),
+            LocalGet(i),
+            I32Const(3),
+            I32Shl,
+            I32Add,
+            LocalSet(b),
+        ]);
+        f.emit(&unless(vec![
+            LocalGet(a),
+            I32Load(PAIRS),
+            LocalGet(b),
+            I32Load(PAIRS),
+            I32Ne,
+        ]));
+        f.emit(&unless(vec![
+            LocalGet(a),
+            I32Load(PAIRS + 4),
+            LocalGet(b),
+            I32Load(PAIRS + 4),
+            call(Builtin::Equals),
+            I32Eqz,
+        ]));
+        f.emit(&Self::increment(i));
+        f.emit(&[
+            Br(0),
+            End,
+            End,
+            I32Const(1),
+            Return,
+            End,
+            LocalGet(kind),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+            LocalGet(0),
+            F64Load(VALUE),
+            LocalGet(1),
+            F64Load(VALUE),
+            F64Eq,
+            Return,
+            End,
+            LocalGet(0),
+            I64Load(VALUE),
+            LocalGet(1),
+            I64Load(VALUE),
+            I64Eq,
+        ]);
+        f
+    }
+
+    fn box_bool(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[I32]);
+        f.emit(&[LocalGet(0), If(R32)]);
+        match self.t.true_object {
+            0 => f.emit(&self.panic("True not loaded")),
+            object => f.emit(&[I32Const(object as i32)]),
+        }
+        f.emit(&[Else]);
+        match self.t.false_object {
+            0 => f.emit(&self.panic("False not loaded")),
+            object => f.emit(&[I32Const(object as i32)]),
+        }
+        f.emit(&[End]);
+        f
+    }
+
+    fn new_int(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I64], &[I32]);
+        let class = f.local(I32);
+        let object = f.local(I32);
+        f.emit(&Self::entry(self.t.number_classes, 0));
+        f.emit(&[LocalTee(class), I32Const(-1), I32Eq, If(E)]);
+        f.emit(&Self::entry(self.t.kind_names, 0));
+        f.emit(&[
+            self.string(" is not loaded"),
+            call(Builtin::Concat),
+            call(Builtin::Panic),
+            Unreachable,
+            End,
+            I32Const(16),
+            call(Builtin::Alloc),
+            LocalTee(object),
+            LocalGet(class),
+            I32Store(CLASS),
+            LocalGet(object),
+            LocalGet(0),
+            I32Store(KIND),
+            LocalGet(object),
+            LocalGet(1),
+            I64Store(VALUE),
+            LocalGet(object),
+        ]);
+        f
+    }
+
+    /// Boxes an integer in the narrowest kind that can hold it, starting
+    /// from the kind, like `box_integer` in the VM.
+    fn box_int(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I64], &[I32]);
+        f.emit(&[
+            LocalGet(1),
+            I64Const(0),
+            I64LtS,
+            If(E),
+            I32Const(self.t.signed as i32),
+            LocalGet(0),
+            I32Add,
+            I32Load8U(0),
+            LocalSet(0),
+            End,
+            Block(E),
+            Loop(E),
+            LocalGet(0),
+            LocalGet(1),
+            call(Builtin::Fits),
+            BrIf(1),
+            I32Const(self.t.wider as i32),
+            LocalGet(0),
+            I32Add,
+            I32Load8U(0),
+            LocalSet(0),
+            Br(0),
+            End,
+            End,
+            LocalGet(0),
+            LocalGet(1),
+            call(Builtin::NewInt),
+        ]);
+        f
+    }
+
+    /// Performs an operation on two integers, and boxes the result in the
+    /// kind.
+    fn int_arithmetic(&mut self) -> Function {
+        let (operation, kind, a, b) = (0, 1, 2, 3);
+        let mut f = Function::new(&[I32, I32, I64, I64], &[I32]);
+        let result = f.local(I64);
+        let is = |op: i32| vec![LocalGet(operation), I32Const(op), I32Eq, If(E)];
+
+        f.emit(&[
+            LocalGet(operation),
+            I32Const(DIVIDE),
+            I32GeU,
+            LocalGet(b),
+            I64Eqz,
+            I32And,
+            If(E),
+        ]);
+        f.emit(&self.panic("division by zero"));
+        f.emit(&[End]);
+
+        f.emit(&is(ADD));
+        f.emit(&[
+            LocalGet(a),
+            LocalGet(b),
+            I64Add,
+            LocalSet(result),
+            LocalGet(a),
+            LocalGet(result),
+            I64Xor,
+            LocalGet(b),
+            LocalGet(result),
+            I64Xor,
+            I64And,
+            I64Const(0),
+            I64LtS,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[End, End]);
+
+        f.emit(&is(SUBTRACT));
+        f.emit(&[
+            LocalGet(a),
+            LocalGet(b),
+            I64Sub,
+            LocalSet(result),
+            LocalGet(a),
+            LocalGet(b),
+            I64Xor,
+            LocalGet(a),
+            LocalGet(result),
+            I64Xor,
+            I64And,
+            I64Const(0),
+            I64LtS,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[End, End]);
+
+        f.emit(&is(MULTIPLY));
+        f.emit(&[
+            LocalGet(a),
+            I64Const(-1),
+            I64Eq,
+            LocalGet(b),
+            I64Const(std::i64::MIN),
+            I64Eq,
+            I32And,
+            LocalGet(b),
+            I64Const(-1),
+            I64Eq,
+            LocalGet(a),
+            I64Const(std::i64::MIN),
+            I64Eq,
+            I32And,
+            I32Or,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[
+            End,
+            LocalGet(a),
+            LocalGet(b),
+            I64Mul,
+            LocalSet(result),
+            LocalGet(a),
+            I64Eqz,
+            I32Eqz,
+            If(E),
+            LocalGet(result),
+            LocalGet(a),
+            I64DivS,
+            LocalGet(b),
+            I64Ne,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[End, End, End]);
+
+        f.emit(&is(DIVIDE));
+        f.emit(&[
+            LocalGet(a),
+            I64Const(std::i64::MIN),
+            I64Eq,
+            LocalGet(b),
+            I64Const(-1),
+            I64Eq,
+            I32And,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[
+            End,
+            LocalGet(a),
+            LocalGet(b),
+            I64DivS,
+            LocalSet(result),
+            End,
+        ]);
+
+        f.emit(&is(REMAINDER));
+        f.emit(&[
+            LocalGet(a),
+            LocalGet(b),
+            I64RemS,
+            LocalSet(result),
+            End,
+            LocalGet(kind),
+            LocalGet(result),
+            call(Builtin::BoxInt),
+        ]);
+        f
+    }
+
+    /// Performs an operation on two floats, and boxes the result in the
+    /// kind. Results that only overflow a `Float32` are boxed as `Float64`s,
+    /// like in the VM.
+    fn float_arithmetic(&mut self) -> Function {
+        let (operation, kind, a, b) = (0, 1, 2, 3);
+        let mut f = Function::new(&[I32, I32, F64, F64], &[I32]);
+        let result = f.local(F64);
+        let narrowed = f.local(F64);
+        let is = |op: i32| vec![LocalGet(operation), I32Const(op), I32Eq, If(E)];
+        let finite = vec![
+            LocalGet(a),
+            F64Abs,
+            F64Const(std::f64::INFINITY),
+            F64Lt,
+            LocalGet(b),
+            F64Abs,
+            F64Const(std::f64::INFINITY),
+            F64Lt,
+            I32And,
+        ];
+
+        f.emit(&[
+            LocalGet(operation),
+            I32Const(DIVIDE),
+            I32GeU,
+            LocalGet(b),
+            F64Const(0.0),
+            F64Eq,
+            I32And,
+            If(E),
+        ]);
+        f.emit(&self.panic("division by zero"));
+        f.emit(&[End]);
+
+        for (op, instr) in &[
+            (ADD, F64Add),
+            (SUBTRACT, F64Sub),
+            (MULTIPLY, F64Mul),
+            (DIVIDE, F64Div),
+        ] {
+            f.emit(&is(*op));
+            f.emit(&[LocalGet(a), LocalGet(b), *instr, LocalSet(result), End]);
+        }
+        f.emit(&is(REMAINDER));
+        f.emit(&[
+            LocalGet(a),
+            LocalGet(b),
+            call(Builtin::FloatRemainder),
+            LocalSet(result),
+            End,
+        ]);
+
+        f.emit(&[
+            LocalGet(kind),
+            I32Const(F32_KIND),
+            I32Eq,
+            If(E),
+            LocalGet(result),
+            F32DemoteF64,
+            F64PromoteF32,
+            LocalTee(narrowed),
+        ]);
+        f.emit(&Self::is_infinite());
+        f.emit(&finite);
+        f.emit(&[
+            I32And,
+            I32Eqz,
+            If(E),
+            I32Const(F32_KIND),
+            LocalGet(narrowed),
+            I64ReinterpretF64,
+            call(Builtin::NewInt),
+            Return,
+            End,
+            I32Const(F64_KIND),
+            LocalSet(kind),
+            End,
+            LocalGet(result),
+        ]);
+        f.emit(&Self::is_infinite());
+        f.emit(&finite);
+        f.emit(&[I32And, If(E)]);
+        f.emit(&self.unsupported("big floats"));
+        f.emit(&[
+            End,
+            LocalGet(kind),
+            LocalGet(result),
+            I64ReinterpretF64,
+            call(Builtin::NewInt),
+        ]);
+        f
+    }
+
+    /// The remainder of dividing two floats, which is exact and has the sign
+    /// of the dividend, like `%` in Rust. The divisor isn't 0.
+    fn float_remainder(&mut self) -> Function {
+        let (a, b) = (0, 1);
+        let mut f = Function::new(&[F64, F64], &[F64]);
+        let rest = f.local(F64);
+        let divisor = f.local(F64);
+        f.emit(&[
+            LocalGet(a),
+            LocalGet(a),
+            F64Ne,
+            LocalGet(b),
+            LocalGet(b),
+            F64Ne,
+            I32Or,
+            LocalGet(a),
+        ]);
+        f.emit(&Self::is_infinite());
+        f.emit(&[
+            I32Or,
+            If(E),
+            F64Const(std::f64::NAN),
+            Return,
+            End,
+            LocalGet(a),
+            F64Abs,
+            LocalTee(rest),
+            LocalGet(b),
+            F64Abs,
+            LocalTee(b),
+            F64Lt,
+            If(E),
+            LocalGet(a),
+            Return,
+            End,
+            LocalGet(b),
+            LocalSet(divisor),
+            Block(E),
+            Loop(E),
+            LocalGet(divisor),
+            F64Const(2.0),
+            F64Mul,
+            LocalGet(rest),
+            F64Le,
+            I32Eqz,
+            BrIf(1),
+            LocalGet(divisor),
+            F64Const(2.0),
+            F64Mul,
+            LocalSet(divisor),
+            Br(0),
+            End,
+            End,
+            Block(E),
+            Loop(E),
+            LocalGet(rest),
+            LocalGet(divisor),
+            F64Ge,
+            If(E),
+            LocalGet(rest),
+            LocalGet(divisor),
+            F64Sub,
+            LocalSet(rest),
+            End,
+            LocalGet(divisor),
+            LocalGet(b),
+            F64Eq,
+            BrIf(1),
+            LocalGet(divisor),
+            F64Const(0.5),
+            F64Mul,
+            LocalSet(divisor),
+            Br(0),
+            End,
+            End,
+            LocalGet(rest),
+            LocalGet(a),
+            F64Copysign,
+        ]);
+        f
+    }
+
+    fn get_var(&mut self) -> Function {
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        let count = f.local(I32);
+        let i = f.local(I32);
+        let pair = f.local(I32);
+        f.emit(&[
+            LocalGet(0),
+            I32Load(KIND),
+            I32Const(INSTANCE_VARIABLES),
+            I32Ne,
+            If(E),
+            I32Const(0),
+            Return,
+            End,
+            LocalGet(0),
+            I32Load(VALUE),
+            LocalSet(count),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(count),
+            I32GeU,
+            BrIf(1),
+            LocalGet(0),
+            LocalGet(i),
+            I32Const(3),
+            I32Shl,
+            I32Add,
+            LocalTee(pair),
+            I32Load(PAIRS),
+            LocalGet(1),
+            I32Eq,
+            If(E),
+            LocalGet(pair),
+            I32Load(PAIRS + 4),
+            Return,
+            End,
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End, I32Const(0)]);
+        f
+    }
+
+    /// Copies an object with the variable set to the value, keeping the
+    /// variables sorted.
+    fn set_var(&mut self) -> Function {
+        let (object, variable, value) = (0, 1, 2);
+        let mut f = Function::new(&[I32, I32, I32], &[I32]);
+        let count = f.local(I32);
+        let copy = f.local(I32);
+        let i = f.local(I32);
+        let j = f.local(I32);
+        let pair = f.local(I32);
+        let inserted = f.local(I32);
+        let existing = f.local(I32);
+        let target = f.local(I32);
+        let write = |var: Vec<Instr>, val: Vec<Instr>| {
+            let mut instructions = vec![
+                LocalGet(copy),
+                LocalGet(j),
+                I32Const(3),
+                I32Shl,
+                I32Add,
+                LocalTee(target),
+            ];
+            instructions.extend(var);
+            instructions.extend_from_slice(&[I32Store(PAIRS), LocalGet(target)]);
+            instructions.extend(val);
+            instructions.extend_from_slice(&[I32Store(PAIRS + 4)]);
+            instructions.extend(Self::increment(j));
+            instructions
+        };
+
+        f.emit(&[
+            LocalGet(object),
+            I32Load(KIND),
+            I32Const(INSTANCE_VARIABLES),
+            I32Eq,
+            If(E),
+            LocalGet(object),
+            I32Load(VALUE),
+            LocalSet(count),
+            End,
+            LocalGet(count),
+            I32Const(1),
+            I32Add,
+            I32Const(3),
+            I32Shl,
+            I32Const(PAIRS as i32),
+            I32Add,
+            call(Builtin::Alloc),
+            LocalTee(copy),
+            LocalGet(object),
+            I32Load(CLASS),
+            I32Store(CLASS),
+            LocalGet(copy),
+            I32Const(INSTANCE_VARIABLES),
+            I32Store(KIND),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(count),
+            I32GeU,
+            BrIf(1),
+            LocalGet(object),
+            LocalGet(i),
+            I32Const(3),
+            I32Shl,
+            I32Add,
+            LocalTee(pair),
+            I32Load(PAIRS),
+            LocalSet(existing),
+            LocalGet(inserted),
+            I32Eqz,
+            LocalGet(existing),
+            LocalGet(variable),
+            I32GeU,
+            I32And,
+            If(E),
+        ]);
+        f.emit(&write(vec![LocalGet(variable)], vec![LocalGet(value)]));
+        f.emit(&[
+            I32Const(1),
+            LocalSet(inserted),
+            End,
+            LocalGet(existing),
+            LocalGet(variable),
+            I32Ne,
+            If(E),
+        ]);
+        f.emit(&write(
+            vec![LocalGet(existing)],
+            vec![LocalGet(pair), I32Load(PAIRS + 4)],
+        ));
+        f.emit(&[End]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End, LocalGet(inserted), I32Eqz, If(E)]);
+        f.emit(&write(vec![LocalGet(variable)], vec![LocalGet(value)]));
+        f.emit(&[
+            End,
+            LocalGet(copy),
+            LocalGet(j),
+            I32Store(VALUE),
+            LocalGet(copy),
+        ]);
+        f
+    }
+
+    /// Pops the dependencies of a lazy object, and pushes the object.
+    fn make_lazy(&mut self) -> Function {
+        let (function, arity) = (0, 1);
+        let mut f = Function::new(&[I32, I32], &[]);
+        let object = f.local(I32);
+        let i = f.local(I32);
+        f.emit(&[
+            LocalGet(arity),
+            I32Const(2),
+            I32Shl,
+            I32Const(LAZY_DEPENDENCIES as i32),
+            I32Add,
+            call(Builtin::Alloc),
+            LocalTee(object),
+            I32Const(LAZY_CLASS),
+            I32Store(CLASS),
+            LocalGet(object),
+            I32Const(LAZY),
+            I32Store(KIND),
+            LocalGet(object),
+            LocalGet(function),
+            I32Store(VALUE),
+            LocalGet(object),
+            I32Const(0),
+            I32Store(LAZY_VALUE),
+            LocalGet(object),
+            LocalGet(arity),
+            I32Store(LAZY_ARITY),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(arity),
+            I32GeU,
+            BrIf(1),
+            LocalGet(object),
+            LocalGet(i),
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            call(Builtin::Pop),
+            I32Store(LAZY_DEPENDENCIES),
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End, LocalGet(object), call(Builtin::Push)]);
+        f
+    }
+
+    /// Dispatches a message to the receiver on top of the stack. Getters and
+    /// setters are called right away, and 0 is returned. Otherwise the table
+    /// index of the method is returned, for the caller to call.
+    fn lookup(&mut self) -> Function {
+        use self::Builtin::*;
+
+        let mut f = Function::new(&[I32], &[I32]);
+        let receiver = f.local(I32);
+        let entry = f.local(I32);
+        let value = f.local(I32);
+        let tag = |t: i32| {
+            vec![
+                LocalGet(entry),
+                I32Const(3),
+                I32And,
+                I32Const(t),
+                I32Eq,
+                If(E),
+            ]
+        };
+        let index = vec![LocalGet(entry), I32Const(2), I32ShrU];
+
+        f.emit(&[
+            Block(E),
+            GlobalGet(SP),
+            GlobalGet(STACK_START),
+            I32GtU,
+            BrIf(0),
+        ]);
+        f.emit(&self.panic("empty stack"));
+        f.emit(&[
+            End,
+            GlobalGet(SP),
+            I32Const(4),
+            I32Sub,
+            I32Load(0),
+            call(Force),
+            LocalSet(receiver),
+            I32Const(self.t.dispatch as i32),
+            LocalGet(receiver),
+            I32Load(CLASS),
+            I32Const(self.t.selectors as i32),
+            I32Mul,
+            LocalGet(0),
+            I32Add,
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            I32Load(0),
+            LocalSet(entry),
+        ]);
+
+        f.emit(&tag(1));
+        f.emit(&index);
+        f.emit(&[Return, End]);
+
+        f.emit(&tag(2));
+        f.emit(&[call(Pop), Drop, LocalGet(receiver)]);
+        f.emit(&index);
+        f.emit(&[
+            call(GetVar),
+            LocalTee(value),
+            I32Eqz,
+            If(E),
+            LocalGet(receiver),
+            call(Display),
+            self.string(" has no value in variable "),
+            call(Concat),
+            I32Const(self.t.variable_names as i32),
+        ]);
+        f.emit(&index);
+        f.emit(&[
+            I32Const(2),
+            I32Shl,
+            I32Add,
+            I32Load(0),
+            call(Concat),
+            call(Panic),
+            Unreachable,
+            End,
+            LocalGet(value),
+            call(Push),
+            I32Const(0),
+            Return,
+            End,
+        ]);
+
+        f.emit(&tag(3));
+        f.emit(&[
+            call(Pop),
+            Drop,
+            call(Pop),
+            LocalSet(value),
+            LocalGet(receiver),
+        ]);
+        f.emit(&index);
+        f.emit(&[
+            LocalGet(value),
+            call(SetVar),
+            call(Push),
+            I32Const(0),
+            Return,
+            End,
+            self.string("message #"),
+        ]);
+        f.emit(&Self::entry(self.t.selector_names, 0));
+        f.emit(&[
+            call(Concat),
+            self.string(" not understood by "),
+            call(Concat),
+            LocalGet(receiver),
+            call(Display),
+            call(Concat),
+            call(Panic),
+            Unreachable,
+        ]);
+        f
+    }
+
+    /// Dispatches a message sent in tail position, by moving the receiver
+    /// and arguments down to the base of the frame of the caller, which
+    /// then returns the method for its own caller to call.
+    fn send_tail(&mut self) -> Function {
+        let (selector, base) = (0, 1);
+        let mut f = Function::new(&[I32, I32], &[I32]);
+        let function = f.local(I32);
+        let size = f.local(I32);
+        f.emit(&[
+            LocalGet(selector),
+            call(Builtin::Lookup),
+            LocalTee(function),
+            If(E),
+        ]);
+        f.emit(&Self::entry(self.t.arities, function));
+        f.emit(&[
+            I32Const(1),
+            I32Add,
+            I32Const(2),
+            I32Shl,
+            LocalSet(size),
+            LocalGet(base),
+            GlobalGet(SP),
+            LocalGet(size),
+            I32Sub,
+            LocalGet(size),
+            call(Builtin::Copy),
+            LocalGet(base),
+            LocalGet(size),
+            I32Add,
+            GlobalSet(SP),
+            End,
+            LocalGet(function),
+        ]);
+        f
+    }
+
+    /// Pops the receiver and operand of a binary native, and panics unless
+    /// they are both integers.
+    fn binary_integers(&mut self, f: &mut Function, message: &str) -> (u32, u32, u32, u32) {
+        self.binary(f, Builtin::IntegerKind, message)
+    }
+
+    /// Pops and evaluates a receiver and an operand, and panics with the
+    /// message unless the builtin finds a kind for both.
+    fn binary(
+        &mut self,
+        f: &mut Function,
+        kind_of: Builtin,
+        message: &str,
+    ) -> (u32, u32, u32, u32) {
+        let receiver = f.local(I32);
+        let operand = f.local(I32);
+        let receiver_kind = f.local(I32);
+        let operand_kind = f.local(I32);
+        f.emit(&[
+            call(Builtin::PopEval),
+            LocalSet(receiver),
+            call(Builtin::PopEval),
+            LocalSet(operand),
+            LocalGet(receiver),
+            call(kind_of),
+            LocalTee(receiver_kind),
+            LocalGet(operand),
+            call(kind_of),
+            LocalTee(operand_kind),
+            I32Or,
+            I32Const(0),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic(message));
+        f.emit(&[End]);
+        (receiver, operand, receiver_kind, operand_kind)
+    }
+
+    fn join(&self, receiver_kind: u32, operand_kind: u32) -> Vec<Instr> {
+        vec![
+            I32Const(self.t.join as i32),
+            LocalGet(receiver_kind),
+            I32Const(15),
+            I32Mul,
+            I32Add,
+            LocalGet(operand_kind),
+            I32Add,
+            I32Load8U(0),
+        ]
+    }
+
+    fn number_arithmetic(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let (receiver, operand, receiver_kind, operand_kind) =
+            self.binary(&mut f, Builtin::NumberKind, "not a number");
+        let kind = f.local(I32);
+        f.emit(&self.join(receiver_kind, operand_kind));
+        f.emit(&[LocalTee(kind), I32Const(FBIG_KIND), I32Eq, If(E)]);
+        f.emit(&self.unsupported("big floats"));
+        f.emit(&[
+            End,
+            LocalGet(kind),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+            LocalGet(0),
+            LocalGet(kind),
+        ]);
+        f.emit(&Self::float(receiver));
+        f.emit(&Self::float(operand));
+        f.emit(&[
+            call(Builtin::FloatArithmetic),
+            call(Builtin::Push),
+            Return,
+            End,
+            LocalGet(0),
+            LocalGet(kind),
+            LocalGet(receiver),
+            I64Load(VALUE),
+            LocalGet(operand),
+            I64Load(VALUE),
+            call(Builtin::IntArithmetic),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    /// Compares two numbers, and pushes whether the ordering is in the
+    /// mask. Comparing with something that isn't a number, or with NaN, is
+    /// never true.
+    fn number_compare(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let receiver = f.local(I32);
+        let operand = f.local(I32);
+        let receiver_kind = f.local(I32);
+        let operand_kind = f.local(I32);
+        let x = f.local(F64);
+        let y = f.local(F64);
+        let values = vec![
+            LocalGet(receiver),
+            I64Load(VALUE),
+            LocalGet(operand),
+            I64Load(VALUE),
+        ];
+        let floats = vec![LocalGet(x), LocalGet(y)];
+        let push = vec![
+            I32Const(0),
+            I32Ne,
+            call(Builtin::BoxBool),
+            call(Builtin::Push),
+        ];
+        f.emit(&[
+            call(Builtin::PopEval),
+            LocalSet(receiver),
+            call(Builtin::PopEval),
+            LocalSet(operand),
+            LocalGet(receiver),
+            call(Builtin::NumberKind),
+            LocalTee(receiver_kind),
+            LocalGet(operand),
+            call(Builtin::NumberKind),
+            LocalTee(operand_kind),
+            I32Or,
+            I32Const(0),
+            I32LtS,
+            If(E),
+            I32Const(0),
+            call(Builtin::BoxBool),
+            call(Builtin::Push),
+            Return,
+            End,
+        ]);
+        f.emit(&self.join(receiver_kind, operand_kind));
+        f.emit(&[LocalTee(receiver_kind), I32Const(FBIG_KIND), I32Eq, If(E)]);
+        f.emit(&self.unsupported("big floats"));
+        f.emit(&[
+            End,
+            LocalGet(receiver_kind),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+        ]);
+        f.emit(&Self::float(receiver));
+        f.emit(&[LocalSet(x)]);
+        f.emit(&Self::float(operand));
+        f.emit(&[
+            LocalSet(y),
+            LocalGet(0),
+            I32Const(LESS),
+            I32Const(EQUAL),
+            I32Const(GREATER),
+        ]);
+        f.emit(&floats);
+        f.emit(&[F64Eq, Select]);
+        f.emit(&floats);
+        f.emit(&[
+            F64Lt,
+            Select,
+            I32Const(0),
+            LocalGet(x),
+            LocalGet(x),
+            F64Eq,
+            LocalGet(y),
+            LocalGet(y),
+            F64Eq,
+            I32And,
+            Select,
+            I32And,
+        ]);
+        f.emit(&push);
+        f.emit(&[
+            Return,
+            End,
+            LocalGet(0),
+            I32Const(LESS),
+            I32Const(EQUAL),
+            I32Const(GREATER),
+        ]);
+        f.emit(&values);
+        f.emit(&[I64Eq, Select]);
+        f.emit(&values);
+        f.emit(&[I64LtS, Select, I32And]);
+        f.emit(&push);
+        f
+    }
+
+    fn number_negated(&mut self) -> Function {
+        let mut f = Function::new(&[], &[]);
+        let receiver = f.local(I32);
+        let kind = f.local(I32);
+        f.emit(&[
+            call(Builtin::PopEval),
+            LocalTee(receiver),
+            call(Builtin::NumberKind),
+            LocalTee(kind),
+            I32Const(0),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic("not an integer"));
+        f.emit(&[
+            End,
+            LocalGet(kind),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+            LocalGet(kind),
+            LocalGet(receiver),
+            F64Load(VALUE),
+            F64Neg,
+            I64ReinterpretF64,
+            call(Builtin::NewInt),
+            call(Builtin::Push),
+            Return,
+            End,
+            I32Const(SUBTRACT),
+            LocalGet(kind),
+            I64Const(0),
+            LocalGet(receiver),
+            I64Load(VALUE),
+            call(Builtin::IntArithmetic),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    /// Converts a number to the kind, panicking like the VM does when it's
+    /// out of bounds for it.
+    fn number_convert(&mut self) -> Function {
+        let target = 0;
+        let mut f = Function::new(&[I32], &[]);
+        let receiver = f.local(I32);
+        let kind = f.local(I32);
+        let value = f.local(I64);
+        let float = f.local(F64);
+        let mut out_of_bounds = vec![
+            If(E),
+            LocalGet(receiver),
+            call(Builtin::Display),
+            self.string(" is out of bounds for "),
+            call(Builtin::Concat),
+        ];
+        out_of_bounds.extend(Self::entry(self.t.kind_names, target));
+        out_of_bounds.extend_from_slice(&[
+            call(Builtin::Concat),
+            call(Builtin::Panic),
+            Unreachable,
+            End,
+        ]);
+
+        f.emit(&[
+            call(Builtin::PopEval),
+            LocalTee(receiver),
+            call(Builtin::NumberKind),
+            LocalTee(kind),
+            I32Const(0),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic("not a number"));
+        f.emit(&[End, LocalGet(target), I32Const(FBIG_KIND), I32Eq, If(E)]);
+        f.emit(&self.unsupported("big floats"));
+        f.emit(&[
+            End,
+            LocalGet(target),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+        ]);
+        f.emit(&Self::float(receiver));
+        f.emit(&[
+            LocalSet(float),
+            LocalGet(target),
+            I32Const(F32_KIND),
+            I32Eq,
+            If(E),
+            LocalGet(float),
+            F32DemoteF64,
+            F64PromoteF32,
+            LocalSet(float),
+            End,
+            LocalGet(float),
+        ]);
+        f.emit(&Self::is_infinite());
+        f.emit(&Self::float(receiver));
+        f.emit(&Self::is_infinite());
+        f.emit(&[I32Eqz, I32And]);
+        f.emit(&out_of_bounds);
+        f.emit(&[
+            LocalGet(target),
+            LocalGet(float),
+            I64ReinterpretF64,
+            call(Builtin::NewInt),
+            call(Builtin::Push),
+            Return,
+            End,
+            LocalGet(kind),
+            I32Const(INTEGER_KINDS),
+            I32GeU,
+            If(E),
+            LocalGet(receiver),
+            F64Load(VALUE),
+            F64Trunc,
+            LocalTee(float),
+        ]);
+        f.emit(&Self::float_entry(self.t.float_min, target));
+        f.emit(&[F64Ge, LocalGet(float)]);
+        f.emit(&Self::float_entry(self.t.float_max, target));
+        f.emit(&[F64Lt, I32And, I32Eqz]);
+        f.emit(&out_of_bounds);
+        f.emit(&[
+            LocalGet(float),
+            F64Const(std::i64::MIN as f64),
+            F64Ge,
+            LocalGet(float),
+            F64Const(-(std::i64::MIN as f64)),
+            F64Lt,
+            I32And,
+            I32Eqz,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[
+            End,
+            LocalGet(float),
+            I64TruncF64S,
+            LocalSet(value),
+            Else,
+            LocalGet(receiver),
+            I64Load(VALUE),
+            LocalSet(value),
+            LocalGet(target),
+            LocalGet(value),
+            call(Builtin::Fits),
+            I32Eqz,
+        ]);
+        f.emit(&out_of_bounds);
+        f.emit(&[
+            End,
+            LocalGet(target),
+            LocalGet(value),
+            call(Builtin::NewInt),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    fn integer_bitwise(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let (receiver, operand, receiver_kind, operand_kind) =
+            self.binary_integers(&mut f, "not an integer");
+        let a = f.local(I64);
+        let b = f.local(I64);
+        f.emit(&self.join(receiver_kind, operand_kind));
+        f.emit(&[
+            LocalGet(receiver),
+            I64Load(VALUE),
+            LocalSet(a),
+            LocalGet(operand),
+            I64Load(VALUE),
+            LocalSet(b),
+            LocalGet(a),
+            LocalGet(b),
+            I64Xor,
+            LocalGet(a),
+            LocalGet(b),
+            I64Or,
+            LocalGet(a),
+            LocalGet(b),
+            I64And,
+            LocalGet(0),
+            I32Const(OR),
+            I32Eq,
+            Select,
+            LocalGet(0),
+            I32Const(XOR),
+            I32Eq,
+            Select,
+            call(Builtin::BoxInt),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    /// Inverts the bits of an integer. Fixed size naturals are inverted
+    /// within their size, and all other integers as if they were in two's
+    /// complement.
+    fn integer_invert(&mut self) -> Function {
+        let mut f = Function::new(&[], &[]);
+        let receiver = f.local(I32);
+        let kind = f.local(I32);
+        f.emit(&[
+            call(Builtin::PopEval),
+            LocalTee(receiver),
+            call(Builtin::IntegerKind),
+            LocalTee(kind),
+            I32Const(0),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic("not an integer"));
+        f.emit(&[
+            End,
+            // The inverse of a 64 or 128 bit natural doesn't fit in an i64.
+            LocalGet(kind),
+            I32Const(U64),
+            I32Eq,
+            LocalGet(kind),
+            I32Const(U64 + 1),
+            I32Eq,
+            I32Or,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[
+            End,
+            LocalGet(kind),
+            LocalGet(kind),
+            I32Const(U64),
+            I32LtU,
+            If(R64),
+            I64Const(1),
+            I32Const(8),
+            LocalGet(kind),
+            I32Shl,
+            I64ExtendI32U,
+            I64Shl,
+            I64Const(1),
+            I64Sub,
+            LocalGet(receiver),
+            I64Load(VALUE),
+            I64Sub,
+            Else,
+            LocalGet(receiver),
+            I64Load(VALUE),
+            I64Const(-1),
+            I64Xor,
+            End,
+            call(Builtin::BoxInt),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    fn integer_shift(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let (receiver, operand, kind, _) = self.binary_integers(&mut f, "not an integer");
+        let amount = f.local(I64);
+        let value = f.local(I64);
+        let result = f.local(I64);
+        f.emit(&[
+            LocalGet(operand),
+            I64Load(VALUE),
+            LocalTee(amount),
+            I64Const(0),
+            I64LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic("invalid shift amount"));
+        f.emit(&[
+            End,
+            LocalGet(receiver),
+            I64Load(VALUE),
+            LocalSet(value),
+            LocalGet(0),
+            If(E),
+            LocalGet(value),
+            I64Eqz,
+            I32Eqz,
+            If(E),
+            LocalGet(amount),
+            I64Const(63),
+            I64GeS,
+            LocalGet(value),
+            LocalGet(amount),
+            I64Shl,
+            LocalTee(result),
+            LocalGet(amount),
+            I64ShrS,
+            LocalGet(value),
+            I64Ne,
+            I32Or,
+            If(E),
+        ]);
+        f.emit(&self.overflow());
+        f.emit(&[
+            End,
+            End,
+            Else,
+            // Shifting right rounds towards negative infinity.
+            LocalGet(value),
+            I64Const(63),
+            I64ShrS,
+            LocalGet(value),
+            LocalGet(amount),
+            I64ShrS,
+            LocalGet(amount),
+            I64Const(64),
+            I64GeS,
+            Select,
+            LocalSet(result),
+            End,
+            LocalGet(kind),
+            LocalGet(result),
+            call(Builtin::BoxInt),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    fn string_concat(&mut self) -> Function {
+        use self::Builtin::*;
+
+        let mut f = Function::new(&[], &[]);
+        let receiver = f.local(I32);
+        let operand = f.local(I32);
+        f.emit(&[
+            call(PopEval),
+            LocalSet(receiver),
+            call(PopEval),
+            LocalSet(operand),
+            LocalGet(receiver),
+            I32Load(KIND),
+            I32Const(STRING),
+            I32Ne,
+            LocalGet(operand),
+            I32Load(KIND),
+            I32Const(STRING),
+            I32Ne,
+            I32Or,
+            If(E),
+            self.string("cannot concatenate "),
+            LocalGet(receiver),
+            call(Display),
+            call(Concat),
+            self.string(" and "),
+            call(Concat),
+            LocalGet(operand),
+            call(Display),
+            call(Concat),
+            call(Panic),
+            Unreachable,
+            End,
+            LocalGet(receiver),
+            LocalGet(operand),
+            call(Concat),
+            call(Push),
+        ]);
+        f
+    }
+
+    fn string_at(&mut self) -> Function {
+        use self::Builtin::*;
+
+        let mut f = Function::new(&[], &[]);
+        let receiver = f.local(I32);
+        let operand = f.local(I32);
+        let index = f.local(I64);
+        let unit = f.local(I32);
+        f.emit(&[
+            call(PopEval),
+            LocalSet(receiver),
+            call(PopEval),
+            LocalSet(operand),
+        ]);
+        f.emit(&self.expect_kind(receiver, STRING, " is not a string"));
+        f.emit(&[
+            LocalGet(operand),
+            call(IntegerKind),
+            I32Const(0),
+            I32LtS,
+            If(E),
+        ]);
+        f.emit(&self.panic("not a number"));
+        f.emit(&[
+            End,
+            LocalGet(operand),
+            I64Load(VALUE),
+            LocalTee(index),
+            I64Const(0),
+            I64LtS,
+            If(E),
+            LocalGet(index),
+            call(IntToString),
+            self.string(" is out of bounds for Loa/UInt64"),
+            call(Concat),
+            call(Panic),
+            Unreachable,
+            End,
+            LocalGet(receiver),
+            LocalGet(index),
+            call(Utf16At),
+            LocalTee(unit),
+            I32Const(0),
+            I32LtS,
+            If(E),
+            self.string("index "),
+            LocalGet(index),
+            call(IntToString),
+            call(Concat),
+            self.string(" is out of bounds"),
+            call(Concat),
+            call(Panic),
+            Unreachable,
+            End,
+            LocalGet(unit),
+            call(NewCharacter),
+            call(Push),
+        ]);
+        f
+    }
+
+    fn string_map(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let receiver = f.local(I32);
+        let string = f.local(I32);
+        let i = f.local(I32);
+        f.emit(&[call(Builtin::PopEval), LocalSet(receiver)]);
+        f.emit(&self.expect_kind(receiver, STRING, " is not a string"));
+        f.emit(&[
+            LocalGet(receiver),
+            I32Const(self.t.string_class),
+            I32Const(STRING),
+            call(Builtin::Retag),
+            LocalSet(string),
+            Block(E),
+            Loop(E),
+            LocalGet(i),
+            LocalGet(string),
+            I32Load(VALUE),
+            I32GeU,
+            BrIf(1),
+            LocalGet(string),
+            LocalGet(i),
+            I32Add,
+            LocalGet(string),
+            LocalGet(i),
+            I32Add,
+            I32Load8U(BYTES),
+            LocalGet(0),
+            call(Builtin::AsciiCase),
+            I32Store8(BYTES),
+        ]);
+        f.emit(&Self::increment(i));
+        f.emit(&[Br(0), End, End, LocalGet(string), call(Builtin::Push)]);
+        f
+    }
+
+    /// Pops a character, and leaves its code unit in the local, unless it's
+    /// an unpaired surrogate.
+    fn character(&mut self, f: &mut Function, surrogate: Vec<Instr>) -> u32 {
+        let receiver = f.local(I32);
+        let unit = f.local(I32);
+        f.emit(&[call(Builtin::PopEval), LocalSet(receiver)]);
+        f.emit(&self.expect_kind(receiver, CHARACTER, " is not a character"));
+        f.emit(&[
+            LocalGet(receiver),
+            I32Load(VALUE),
+            LocalTee(unit),
+            I32Const(0xd800),
+            I32Sub,
+            I32Const(0x800),
+            I32LtU,
+            If(E),
+        ]);
+        f.emit(&surrogate);
+        f.emit(&[Return, End]);
+        unit
+    }
+
+    fn character_test(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        // Unpaired surrogates are neither letters, digits nor whitespace.
+        let unit = self.character(
+            &mut f,
+            vec![I32Const(0), call(Builtin::BoxBool), call(Builtin::Push)],
+        );
+        f.emit(&[LocalGet(unit), I32Const(0x80), I32GeU, If(E)]);
+        f.emit(&self.unsupported("classifying non-ASCII characters"));
+        f.emit(&[
+            End,
+            LocalGet(unit),
+            I32Const(32),
+            I32Or,
+            I32Const(b'a' as i32),
+            I32Sub,
+            I32Const(26),
+            I32LtU,
+            LocalGet(unit),
+            I32Const(b'0' as i32),
+            I32Sub,
+            I32Const(10),
+            I32LtU,
+            LocalGet(unit),
+            I32Const(b' ' as i32),
+            I32Eq,
+            LocalGet(unit),
+            I32Const(b'\t' as i32),
+            I32Sub,
+            I32Const(5),
+            I32LtU,
+            I32Or,
+            LocalGet(0),
+            I32Const(DIGIT),
+            I32Eq,
+            Select,
+            LocalGet(0),
+            I32Const(LETTER),
+            I32Eq,
+            Select,
+            call(Builtin::BoxBool),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+
+    fn character_map(&mut self) -> Function {
+        let mut f = Function::new(&[I32], &[]);
+        let receiver = f.local(I32);
+        let unit = self.character(&mut f, vec![LocalGet(receiver), call(Builtin::Push)]);
+        f.emit(&[
+            LocalGet(unit),
+            LocalGet(0),
+            call(Builtin::AsciiCase),
+            call(Builtin::NewCharacter),
+            call(Builtin::Push),
+        ]);
+        f
+    }
+}
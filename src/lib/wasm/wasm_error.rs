@@ -0,0 +1,42 @@
+use crate::*;
+
+/// Why a program couldn't be lowered to WebAssembly. Features that the
+/// generated module doesn't support aren't errors, but panic when they're
+/// reached at runtime, with a message starting with `UNSUPPORTED`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WasmError {
+    /// A class declaration at the offset refers to a method that hasn't been
+    /// declared.
+    UnknownMethod { offset: usize, method: u64 },
+    /// A class declaration at the offset refers to a variable that hasn't
+    /// been declared.
+    UnknownVariable { offset: usize, variable: u64 },
+    /// The instruction at the offset refers to a class that hasn't been
+    /// declared.
+    UnknownClass { offset: usize, class: u64 },
+    /// The instruction at the offset declares a member outside of a class.
+    OutsideClass { offset: usize },
+}
+
+pub type WasmResult<T> = Result<T, WasmError>;
+
+impl fmt::Display for WasmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WasmError::UnknownMethod { offset, method } => {
+                write!(f, "unknown method {} used at {}", method, offset)
+            }
+            WasmError::UnknownVariable { offset, variable } => {
+                write!(f, "unknown variable {} used at {}", variable, offset)
+            }
+            WasmError::UnknownClass { offset, class } => {
+                write!(f, "unknown class {} used at {}", class, offset)
+            }
+            WasmError::OutsideClass { offset } => {
+                write!(f, "member declared outside of a class at {}", offset)
+            }
+        }
+    }
+}
+
+impl Error for WasmError {}
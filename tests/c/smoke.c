@@ -0,0 +1,111 @@
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "loa.h"
+
+#define CHECK(condition)                                                       \
+  if (!(condition)) {                                                          \
+    fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__,          \
+            #condition);                                                       \
+    return 1;                                                                  \
+  }
+
+static uint8_t *read_file(const char *path, size_t *length) {
+  FILE *file = fopen(path, "rb");
+  if (file == NULL) {
+    return NULL;
+  }
+  fseek(file, 0, SEEK_END);
+  *length = (size_t)ftell(file);
+  fseek(file, 0, SEEK_SET);
+  uint8_t *bytes = malloc(*length);
+  if (bytes != NULL && fread(bytes, 1, *length, file) != *length) {
+    free(bytes);
+    bytes = NULL;
+  }
+  fclose(file);
+  return bytes;
+}
+
+int main(int argc, char **argv) {
+  CHECK(argc == 2);
+  size_t length;
+  uint8_t *bytecode = read_file(argv[1], &length);
+  CHECK(bytecode != NULL);
+
+  LoaVM *vm = loa_vm_new();
+
+  // The entry point
+  LoaValue *result = loa_vm_load(vm, bytecode, length);
+  CHECK(result != NULL);
+  CHECK(loa_value_kind(result) == LOA_VALUE_KIND_INTEGER);
+  uint64_t sum;
+  CHECK(loa_value_as_u64(result, &sum) && sum == 42);
+  char *string = loa_value_to_string(result);
+  printf("entry point: %s\n", string);
+  loa_string_free(string);
+  loa_value_free(result);
+
+  // A named method
+  LoaValue *calculator = loa_vm_class(vm, "Example/Calculator");
+  CHECK(calculator != NULL);
+  LoaValue *dividend = loa_value_u64(vm, 84);
+  LoaValue *divisor = loa_value_u64(vm, 2);
+  LoaValue *zero = loa_value_u64(vm, 0);
+  CHECK(dividend != NULL && divisor != NULL && zero != NULL);
+
+  const LoaValue *arguments[] = {dividend, divisor};
+  result = loa_vm_send(vm, calculator, "divide:by:", arguments, 2);
+  CHECK(result != NULL);
+  int64_t quotient;
+  CHECK(loa_value_as_i64(result, &quotient) && quotient == 42);
+  double approximate;
+  CHECK(loa_value_as_f64(result, &approximate) && approximate == 42.0);
+  bool boolean;
+  CHECK(!loa_value_as_bool(result, &boolean));
+  printf("84 / 2: %lld\n", (long long)quotient);
+  loa_value_free(result);
+
+  // A panic
+  const LoaValue *by_zero[] = {dividend, zero};
+  result = loa_vm_send(vm, calculator, "divide:by:", by_zero, 2);
+  CHECK(result == NULL);
+  const char *error = loa_vm_last_error(vm);
+  CHECK(error != NULL && strstr(error, "division by zero") != NULL);
+  printf("84 / 0: panicked\n");
+
+  // Errors
+  CHECK(loa_vm_class(vm, "Example/Missing") == NULL);
+  CHECK(strstr(loa_vm_last_error(vm), "Example/Missing") != NULL);
+  CHECK(loa_vm_send(vm, calculator, "divide:by:", arguments, 1) == NULL);
+  CHECK(loa_value_i64(vm, 1) == NULL);
+  CHECK(strstr(loa_vm_last_error(vm), "Loa/Int64") != NULL);
+
+  // Booleans of another VM, which outlive it
+  LoaVM *other = loa_vm_new();
+  result = loa_vm_load(other, bytecode, length);
+  CHECK(result != NULL);
+  loa_value_free(result);
+  LoaValue *yes = loa_value_bool(vm, true);
+  LoaValue *no = loa_value_bool(other, false);
+  CHECK(yes != NULL && no != NULL);
+  loa_vm_free(other);
+  CHECK(loa_value_kind(yes) == LOA_VALUE_KIND_BOOLEAN);
+  CHECK(loa_value_kind(no) == LOA_VALUE_KIND_BOOLEAN);
+  bool yes_value, no_value;
+  CHECK(loa_value_as_bool(yes, &yes_value) && yes_value);
+  CHECK(loa_value_as_bool(no, &no_value) && !no_value);
+  printf("booleans: %s %s\n", yes_value ? "true" : "false",
+         no_value ? "true" : "false");
+  loa_value_free(yes);
+  loa_value_free(no);
+  free(bytecode);
+
+  loa_value_free(dividend);
+  loa_value_free(divisor);
+  loa_value_free(zero);
+  loa_value_free(calculator);
+  loa_vm_free(vm);
+  return 0;
+}
@@ -0,0 +1,116 @@
+extern crate loa;
+
+use loa::assembly::Parser;
+use loa::bytecode::Binary;
+use std::path::PathBuf;
+use std::process::Command;
+
+const PROGRAM: &str = r#"
+    @Loa/UInt64$methods
+        DeclareMethod "+" @Loa/UInt64#+
+        DeclareMethod "/" @Loa/UInt64#/
+
+    @Example/Calculator$methods
+        DeclareMethod "add:to:" @Example/Calculator#add:to:
+        DeclareMethod "divide:by:" @Example/Calculator#divide:by:
+
+    @Loa/UInt64
+        DeclareClass "Loa/UInt64"
+        MarkClassU64 @Loa/UInt64
+        UseMethod @Loa/UInt64#+
+        UseMethod @Loa/UInt64#/
+
+    @Example/Calculator
+        DeclareClass "Example/Calculator"
+        UseMethod @Example/Calculator#add:to:
+        UseMethod @Example/Calculator#divide:by:
+
+    @Loa/True
+        DeclareClass "Loa/True"
+        MarkClassTrue @Loa/True
+
+    @Loa/False
+        DeclareClass "Loa/False"
+        MarkClassFalse @Loa/False
+
+    LoadConstU64 30
+    LoadConstU64 12
+    LoadObject @Example/Calculator
+    CallMethod @Example/Calculator#add:to: "Main" 1 1
+    Halt
+
+    @Loa/UInt64#+
+        CallNative Loa/Number#+
+        Return 0
+
+    @Loa/UInt64#/
+        CallNative Loa/Number#/
+        Return 0
+
+    @Example/Calculator#add:to:
+        LoadLocal 2
+        LoadLocal 2
+        CallMethod @Loa/UInt64#+ "Example/Calculator" 1 1
+        Return 3
+
+    @Example/Calculator#divide:by:
+        LoadLocal 2
+        LoadLocal 2
+        CallMethod @Loa/UInt64#/ "Example/Calculator" 2 1
+        Return 3
+"#;
+
+/// Builds tests/c/smoke.c against the shared library and include/loa.h,
+/// and runs it on a small program.
+#[test]
+fn c_smoke_test() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // The test executable is in target/<profile>/deps, next to which cargo
+    // puts the shared library.
+    let library_dir = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .and_then(|deps| deps.parent())
+        .unwrap()
+        .to_path_buf();
+    let out_dir = std::env::temp_dir().join(format!("loa-c-api-{}", std::process::id()));
+    std::fs::create_dir_all(&out_dir).unwrap();
+
+    let program = out_dir.join("program.loabin");
+    let assembly = Parser::new().parse(PROGRAM).unwrap();
+    Binary::new(assembly.into())
+        .write(std::fs::File::create(&program).unwrap())
+        .unwrap();
+
+    let smoke = out_dir.join("smoke");
+    let status = Command::new(std::env::var("CC").unwrap_or_else(|_| "cc".into()))
+        .arg(manifest_dir.join("tests/c/smoke.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(&library_dir)
+        .arg("-lloa")
+        .arg("-o")
+        .arg(&smoke)
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "failed to compile tests/c/smoke.c");
+
+    let output = Command::new(&smoke)
+        .arg(&program)
+        .env("LD_LIBRARY_PATH", &library_dir)
+        .env("DYLD_LIBRARY_PATH", &library_dir)
+        .output()
+        .unwrap();
+    std::fs::remove_dir_all(&out_dir).unwrap();
+
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "entry point: 42\n84 / 2: 42\n84 / 0: panicked\nbooleans: true false\n"
+    );
+}